@@ -0,0 +1,215 @@
+//! Command-line entry point for the compiler.
+
+use eoc::cir;
+use eoc::driver::{drive_cir_to, drive_program_to, Stage};
+use eoc::options::Options;
+use eoc::repl::{Repl, Reply};
+use eoc::rir::parse::parse_file_program;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::process;
+
+const USAGE: &str = "usage: eoc [--emit=STAGE] [--overflow=MODE] [--typing=MODE] [-o OUTPUT] INPUT
+       eoc repl
+
+Compiles the R program in INPUT to x86 assembly. An INPUT ending in `.cir`
+is read as a CIR program and compiled from the CIR stage onward.
+
+options:
+  --emit=STAGE  stop after STAGE and print the program at that point, one of
+                rir, rir-casts, rir-uniq, rir-assign, rir-closure,
+                rir-alloc, rir-simple, cir, pxir, pxir-live, pxir-homes,
+                asm
+                (default: asm)
+  --overflow=MODE
+                what integer overflow does, `wrap` around or `trap` with a
+                runtime error (default: wrap)
+  --typing=MODE whether the program is type checked with its annotations,
+                `static`, or every value is tagged and checked at run time,
+                `dynamic` (default: static)
+  -o OUTPUT     write to OUTPUT instead of standard output
+  -h, --help    print this message
+
+`eoc repl` starts an interactive session that evaluates one expression at a
+time and can show the output of each stage.";
+
+/// Options given on the command line.
+#[derive(Debug, PartialEq)]
+struct Args {
+    input: String,
+    output: Option<String>,
+    stage: Stage,
+    options: Options,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut stage = Stage::Asm;
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(name) = arg.strip_prefix("--emit=") {
+            stage = name.parse()?;
+        } else if let Some(mode) = arg.strip_prefix("--overflow=") {
+            options.overflow = mode.parse()?;
+        } else if let Some(mode) = arg.strip_prefix("--typing=") {
+            options.typing = mode.parse()?;
+        } else if arg == "-o" {
+            let path = args.next().ok_or("`-o` expects a file name")?;
+            output = Some(path.clone());
+        } else if arg.starts_with('-') && arg != "-" {
+            return Err(format!("unknown option `{}`", arg));
+        } else if input.is_none() {
+            input = Some(arg.clone());
+        } else {
+            return Err(format!("unexpected argument `{}`", arg));
+        }
+    }
+    Ok(Args {
+        input: input.ok_or("no input file")?,
+        output,
+        stage,
+        options,
+    })
+}
+
+/// Compiles the input file. Returns the message to print on failure.
+fn run(args: &Args) -> Result<(), String> {
+    let src = fs::read_to_string(&args.input)
+        .map_err(|e| format!("cannot read `{}`: {}", args.input, e))?;
+    let out = if args.input.ends_with(".cir") {
+        if args.stage < Stage::Cir {
+            return Err(format!(
+                "cannot emit `{}` from CIR input",
+                args.stage.name()
+            ));
+        }
+        let prog = cir::parse::parse_file_program(&args.input, &src).map_err(|e| e.render(&src))?;
+        drive_cir_to(prog, args.stage, &args.options)
+    } else {
+        let prog = parse_file_program(&args.input, &src).map_err(|e| e.render(&src))?;
+        drive_program_to(prog, args.stage, &args.options)
+    }
+    .map_err(|e| e.render(&src))?;
+    match &args.output {
+        Some(path) => fs::write(path, out).map_err(|e| format!("cannot write `{}`: {}", path, e)),
+        None => {
+            print!("{}", out);
+            Ok(())
+        }
+    }
+}
+
+/// Runs the REPL on standard input until it is closed or the user quits.
+fn run_repl() -> io::Result<()> {
+    println!("eoc repl, enter `:help` for commands");
+    let mut repl = Repl::new();
+    let mut entry = String::new();
+    loop {
+        print!("{}", if entry.is_empty() { "> " } else { ". " });
+        io::stdout().flush()?;
+        if io::stdin().read_line(&mut entry)? == 0 {
+            println!();
+            return Ok(());
+        }
+        if entry.trim().is_empty() {
+            entry.clear();
+            continue;
+        }
+        if !Repl::is_complete(&entry) {
+            continue;
+        }
+        match repl.eval(&entry) {
+            Reply::Print(text) => println!("{}", text.trim_end()),
+            Reply::Quit => return Ok(()),
+        }
+        entry.clear();
+    }
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    if args.len() == 1 && args[0] == "repl" {
+        if let Err(e) = run_repl() {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    let args = match parse_args(&args) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(message) = run(&args) {
+        eprint!("error: {}", message);
+        if !message.ends_with('\n') {
+            eprintln!();
+        }
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_args, Args};
+    use eoc::driver::Stage;
+    use eoc::options::{Options, Overflow, Typing};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn defaults_to_asm() {
+        let expected = Args {
+            input: "prog.rkt".to_string(),
+            output: None,
+            stage: Stage::Asm,
+            options: Options::default(),
+        };
+        assert_eq!(parse_args(&args(&["prog.rkt"])), Ok(expected));
+    }
+
+    #[test]
+    fn emit_and_output() {
+        let expected = Args {
+            input: "prog.rkt".to_string(),
+            output: Some("prog.s".to_string()),
+            stage: Stage::Cir,
+            options: Options::default(),
+        };
+        let actual = parse_args(&args(&["--emit=cir", "prog.rkt", "-o", "prog.s"]));
+        assert_eq!(actual, Ok(expected));
+    }
+
+    #[test]
+    fn overflow_mode() {
+        let actual = parse_args(&args(&["--overflow=trap", "prog.rkt"])).unwrap();
+        assert_eq!(actual.options.overflow, Overflow::Trapping);
+        assert!(parse_args(&args(&["--overflow=saturate", "prog.rkt"])).is_err());
+    }
+
+    #[test]
+    fn typing_mode() {
+        let actual = parse_args(&args(&["--typing=dynamic", "prog.rkt"])).unwrap();
+        assert_eq!(actual.options.typing, Typing::Dynamic);
+        assert!(parse_args(&args(&["--typing=gradual", "prog.rkt"])).is_err());
+    }
+
+    #[test]
+    fn bad_arguments() {
+        assert!(parse_args(&args(&[])).is_err());
+        assert!(parse_args(&args(&["--emit=llvm", "prog.rkt"])).is_err());
+        assert!(parse_args(&args(&["prog.rkt", "-o"])).is_err());
+        assert!(parse_args(&args(&["a.rkt", "b.rkt"])).is_err());
+    }
+}
@@ -1,10 +1,14 @@
 //! CIR (C-like Intermediate Representation)
 
+pub mod parse;
 pub mod select_instr;
 pub mod uncover;
+mod write;
 
 use std::collections::{HashMap, HashSet};
 
+pub use super::rir::typecheck::Type;
+
 /// Symbol used for variable names.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Symbol {
@@ -22,6 +26,7 @@ impl Symbol {
 #[derive(Clone, Debug, PartialEq)]
 pub enum Arg {
     Int(i64),
+    Bool(bool),
     Var(Box<Symbol>),
 }
 
@@ -30,17 +35,93 @@ impl Arg {
         Box::new(Arg::Int(i))
     }
 
+    pub fn bool(b: bool) -> Box<Arg> {
+        Box::new(Arg::Bool(b))
+    }
+
     pub fn var(s: &str) -> Box<Arg> {
         Box::new(Arg::Var(Box::new(Symbol::new(s))))
     }
 }
 
+/// Comparison between two operands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CmpOp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    pub const ALL: [CmpOp; 5] = [CmpOp::Eq, CmpOp::Lt, CmpOp::Le, CmpOp::Gt, CmpOp::Ge];
+
+    /// Name of the comparison in source text.
+    pub fn name(self) -> &'static str {
+        match self {
+            CmpOp::Eq => "eq?",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CmpOp> {
+        CmpOp::ALL.iter().copied().find(|op| op.name() == name)
+    }
+
+    /// Applies the comparison to two integers.
+    pub fn apply(self, i1: i64, i2: i64) -> bool {
+        match self {
+            CmpOp::Eq => i1 == i2,
+            CmpOp::Lt => i1 < i2,
+            CmpOp::Le => i1 <= i2,
+            CmpOp::Gt => i1 > i2,
+            CmpOp::Ge => i1 >= i2,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expr {
     Read,
     Arg(Box<Arg>),
     Neg(Box<Arg>),
     Add(Box<Arg>, Box<Arg>),
+    Sub(Box<Arg>, Box<Arg>),
+    Mul(Box<Arg>, Box<Arg>),
+    Quotient(Box<Arg>, Box<Arg>),
+    Remainder(Box<Arg>, Box<Arg>),
+    Not(Box<Arg>),
+    Cmp(CmpOp, Box<Arg>, Box<Arg>),
+    Void,
+    /// Runs the garbage collector so that at least the given number of bytes
+    /// are free.
+    Collect(i64),
+    /// Reserves space on the heap for a vector of the given length and type.
+    Allocate(usize, Type),
+    /// Reads a global variable of the runtime.
+    GlobalValue(String),
+    VectorRef(Box<Arg>, usize),
+    VectorSet(Box<Arg>, usize, Box<Arg>),
+    /// Tags the operand, which has the given type, as an `Any`.
+    Inject(Box<Arg>, Type),
+    /// Removes the tag from an `Any`, failing at run time unless the tag is
+    /// that of the given type.
+    Project(Box<Arg>, Type),
+    /// Reads an element of a vector held in an `Any`.
+    AnyVectorRef(Box<Arg>, usize),
+    /// Writes an element of a vector held in an `Any`.
+    AnyVectorSet(Box<Arg>, usize, Box<Arg>),
+    /// Writes the operand followed by a newline to standard output, in the
+    /// format of its type.
+    Print(Box<Arg>),
+    /// Address of a function defined at the top level.
+    FunRef(String),
+    /// Calls the function the first operand points to.
+    Call(Box<Arg>, Vec<Arg>),
 }
 
 impl Expr {
@@ -59,6 +140,82 @@ impl Expr {
     pub fn add(op1: Box<Arg>, op2: Box<Arg>) -> Box<Expr> {
         Box::new(Expr::Add(op1, op2))
     }
+
+    pub fn sub(op1: Box<Arg>, op2: Box<Arg>) -> Box<Expr> {
+        Box::new(Expr::Sub(op1, op2))
+    }
+
+    pub fn mul(op1: Box<Arg>, op2: Box<Arg>) -> Box<Expr> {
+        Box::new(Expr::Mul(op1, op2))
+    }
+
+    pub fn quotient(op1: Box<Arg>, op2: Box<Arg>) -> Box<Expr> {
+        Box::new(Expr::Quotient(op1, op2))
+    }
+
+    pub fn remainder(op1: Box<Arg>, op2: Box<Arg>) -> Box<Expr> {
+        Box::new(Expr::Remainder(op1, op2))
+    }
+
+    pub fn not(arg: Box<Arg>) -> Box<Expr> {
+        Box::new(Expr::Not(arg))
+    }
+
+    pub fn cmp(op: CmpOp, op1: Box<Arg>, op2: Box<Arg>) -> Box<Expr> {
+        Box::new(Expr::Cmp(op, op1, op2))
+    }
+
+    pub fn void() -> Box<Expr> {
+        Box::new(Expr::Void)
+    }
+
+    pub fn collect(bytes: i64) -> Box<Expr> {
+        Box::new(Expr::Collect(bytes))
+    }
+
+    pub fn allocate(len: usize, ty: Type) -> Box<Expr> {
+        Box::new(Expr::Allocate(len, ty))
+    }
+
+    pub fn global_value(name: &str) -> Box<Expr> {
+        Box::new(Expr::GlobalValue(name.to_string()))
+    }
+
+    pub fn vector_ref(v: Box<Arg>, index: usize) -> Box<Expr> {
+        Box::new(Expr::VectorRef(v, index))
+    }
+
+    pub fn vector_set(v: Box<Arg>, index: usize, arg: Box<Arg>) -> Box<Expr> {
+        Box::new(Expr::VectorSet(v, index, arg))
+    }
+
+    pub fn inject(arg: Box<Arg>, ty: Type) -> Box<Expr> {
+        Box::new(Expr::Inject(arg, ty))
+    }
+
+    pub fn project(arg: Box<Arg>, ty: Type) -> Box<Expr> {
+        Box::new(Expr::Project(arg, ty))
+    }
+
+    pub fn any_vector_ref(v: Box<Arg>, index: usize) -> Box<Expr> {
+        Box::new(Expr::AnyVectorRef(v, index))
+    }
+
+    pub fn any_vector_set(v: Box<Arg>, index: usize, arg: Box<Arg>) -> Box<Expr> {
+        Box::new(Expr::AnyVectorSet(v, index, arg))
+    }
+
+    pub fn print(arg: Box<Arg>) -> Box<Expr> {
+        Box::new(Expr::Print(arg))
+    }
+
+    pub fn fun_ref(name: &str) -> Box<Expr> {
+        Box::new(Expr::FunRef(name.to_string()))
+    }
+
+    pub fn call(f: Box<Arg>, args: Vec<Arg>) -> Box<Expr> {
+        Box::new(Expr::Call(f, args))
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -77,6 +234,10 @@ impl Stmt {
 pub enum Tail {
     Seq(Box<Stmt>, Box<Tail>),
     Ret(Box<Expr>),
+    Goto(Label),
+    /// Continues with the first block if the condition, usually a
+    /// comparison, is true and with the second otherwise.
+    If(Box<Expr>, Label, Label),
 }
 
 impl Tail {
@@ -87,6 +248,18 @@ impl Tail {
     pub fn ret(expr: Box<Expr>) -> Box<Tail> {
         Box::new(Tail::Ret(expr))
     }
+
+    pub fn goto(label: &str) -> Box<Tail> {
+        Box::new(Tail::Goto(Label::new(label)))
+    }
+
+    pub fn if_goto(cond: Box<Expr>, then_label: &str, else_label: &str) -> Box<Tail> {
+        Box::new(Tail::If(
+            cond,
+            Label::new(then_label),
+            Label::new(else_label),
+        ))
+    }
 }
 
 /// Label for a tail definition.
@@ -103,20 +276,48 @@ impl Label {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Info {
     pub symbols: HashSet<Symbol>,
+
+    /// Type of each assigned variable whose type follows from its
+    /// assignments.
+    pub types: HashMap<Symbol, Type>,
+
+    /// Type of the value the blocks return, if known.
+    pub result: Option<Type>,
 }
 
-impl Default for Info {
-    fn default() -> Self {
-        Info {
-            symbols: HashSet::new(),
-        }
+/// A function defined at the top level of a program. Its blocks are labeled
+/// with its name and an underscore, starting with `NAME_start`, so that they
+/// do not clash with the blocks of other functions.
+#[derive(Clone, Debug)]
+pub struct Def {
+    pub name: String,
+    pub params: Vec<(Symbol, Type)>,
+    pub result: Type,
+    pub info: Info,
+    pub tails: HashMap<Label, Tail>,
+}
+
+impl Def {
+    /// Label of the block the function starts with.
+    pub fn start_label(&self) -> Label {
+        Label::new(&format!("{}_start", self.name))
+    }
+
+    /// Type of the function.
+    pub fn ty(&self) -> Type {
+        let params = self.params.iter().map(|(_, ty)| ty.clone()).collect();
+        Type::Function(params, Box::new(self.result.clone()))
     }
 }
 
+/// The blocks of the program's final expression, starting with `start`, and
+/// the functions it defines.
+#[derive(Clone, Debug)]
 pub struct Program {
     pub info: Info,
     pub tails: HashMap<Label, Tail>,
+    pub defs: Vec<Def>,
 }
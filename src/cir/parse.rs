@@ -0,0 +1,655 @@
+//! Reader for the textual form of CIR written by `Display for Program`.
+//!
+//! A program is a sequence of labeled blocks, each a list of assignments
+//! ending in a return, a `goto` or a conditional `goto`:
+//!
+//! ```text
+//! start:
+//!   x = (read);
+//!   if (< x 0) goto neg; else goto pos;
+//!
+//! neg:
+//!   return (- x);
+//!
+//! pos:
+//!   return x;
+//! ```
+//!
+//! Functions follow the blocks of the program, each introduced by a line
+//! giving its name, parameters and result type:
+//!
+//! ```text
+//! define add1 (x Integer) -> Integer:
+//!
+//! add1_start:
+//!   return (+ x 1);
+//! ```
+//!
+//! The parsed program has no symbol info; run `uncover` to fill it in.
+
+use super::super::diagnostic::{Diagnostic, Span};
+use super::*;
+use std::rc::Rc;
+
+/// Error produced when text is not a well-formed CIR program.
+pub type ParseError = Diagnostic;
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Open,
+    Close,
+    Colon,
+    Semi,
+    Atom(String),
+    Eof,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    span: Span,
+}
+
+fn tokenize(file: Rc<str>, src: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = src.char_indices().peekable();
+    let (mut line, mut column) = (1, 1);
+    loop {
+        // Skip whitespace and `//` comments.
+        while let Some(&(i, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                if c == '\n' {
+                    line += 1;
+                    column = 1;
+                } else {
+                    column += 1;
+                }
+            } else if src[i..].starts_with("//") {
+                while let Some(&(_, c)) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                    column += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        let (lo, c) = match chars.peek() {
+            Some(&(lo, c)) => (lo, c),
+            None => {
+                let span = Span::new(file, line, column, src.len(), src.len());
+                tokens.push(Token {
+                    kind: TokenKind::Eof,
+                    span,
+                });
+                return tokens;
+            }
+        };
+        let start_column = column;
+        let kind = match c {
+            '(' | ')' | ':' | ';' => {
+                chars.next();
+                column += 1;
+                match c {
+                    '(' => TokenKind::Open,
+                    ')' => TokenKind::Close,
+                    ':' => TokenKind::Colon,
+                    _ => TokenKind::Semi,
+                }
+            }
+            _ => {
+                let mut text = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || "():;".contains(c) {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                    column += 1;
+                }
+                TokenKind::Atom(text)
+            }
+        };
+        let hi = chars.peek().map_or(src.len(), |&(i, _)| i);
+        let span = Span::new(file.clone(), line, start_column, lo, hi);
+        tokens.push(Token { kind, span });
+    }
+}
+
+/// Builds a program from a stream of tokens.
+struct Reader {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Reader {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, kind: TokenKind, what: &str) -> Result<(), ParseError> {
+        let token = self.next();
+        if token.kind == kind {
+            Ok(())
+        } else {
+            Err(ParseError::new(format!("expected {}", what), token.span))
+        }
+    }
+
+    fn read_name(&mut self, what: &str) -> Result<String, ParseError> {
+        let token = self.next();
+        match token.kind {
+            TokenKind::Atom(name) if is_name(&name) => Ok(name),
+            _ => Err(ParseError::new(format!("expected {}", what), token.span)),
+        }
+    }
+
+    fn read_arg(&mut self) -> Result<Box<Arg>, ParseError> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Atom(text) => {
+                if let Ok(i) = text.parse() {
+                    Ok(Arg::int(i))
+                } else if text == "#t" || text == "#f" {
+                    Ok(Arg::bool(text == "#t"))
+                } else if is_name(text) {
+                    Ok(Arg::var(text))
+                } else {
+                    Err(ParseError::new(
+                        format!("`{}` is not a variable or integer", text),
+                        token.span,
+                    ))
+                }
+            }
+            _ => Err(ParseError::new(
+                "expected a variable or integer".to_string(),
+                token.span,
+            )),
+        }
+    }
+
+    fn read_number<T: std::str::FromStr>(&mut self, what: &str) -> Result<T, ParseError> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Atom(text) => text.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| ParseError::new(format!("expected {}", what), token.span))
+    }
+
+    fn read_type(&mut self) -> Result<Type, ParseError> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Atom(name) if name == "Integer" => Ok(Type::Integer),
+            TokenKind::Atom(name) if name == "Boolean" => Ok(Type::Boolean),
+            TokenKind::Atom(name) if name == "Void" => Ok(Type::Void),
+            TokenKind::Atom(name) if name == "Any" => Ok(Type::Any),
+            TokenKind::Open if self.peek().kind == TokenKind::Atom("Vector".to_string()) => {
+                self.next();
+                let mut elems = vec![];
+                while self.peek().kind != TokenKind::Close {
+                    elems.push(self.read_type()?);
+                }
+                self.next();
+                Ok(Type::Vector(elems))
+            }
+            TokenKind::Open => {
+                let arrow = TokenKind::Atom("->".to_string());
+                let mut params = vec![];
+                while self.peek().kind != arrow {
+                    params.push(self.read_type()?);
+                }
+                self.next();
+                let result = self.read_type()?;
+                self.expect(TokenKind::Close, "`)`")?;
+                Ok(Type::Function(params, Box::new(result)))
+            }
+            _ => Err(ParseError::new("expected a type".to_string(), token.span)),
+        }
+    }
+
+    fn read_expr(&mut self) -> Result<Box<Expr>, ParseError> {
+        if self.peek().kind != TokenKind::Open {
+            return Ok(Expr::arg(self.read_arg()?));
+        }
+        self.next();
+        let op = self.next();
+        let expr = match &op.kind {
+            TokenKind::Atom(name) if name == "read" => Expr::read(),
+            TokenKind::Atom(name) if name == "-" => {
+                let arg1 = self.read_arg()?;
+                if self.peek().kind == TokenKind::Close {
+                    Expr::neg(arg1)
+                } else {
+                    Expr::sub(arg1, self.read_arg()?)
+                }
+            }
+            TokenKind::Atom(name) if name == "+" => {
+                let arg1 = self.read_arg()?;
+                Expr::add(arg1, self.read_arg()?)
+            }
+            TokenKind::Atom(name) if name == "*" => {
+                let arg1 = self.read_arg()?;
+                Expr::mul(arg1, self.read_arg()?)
+            }
+            TokenKind::Atom(name) if name == "quotient" => {
+                let arg1 = self.read_arg()?;
+                Expr::quotient(arg1, self.read_arg()?)
+            }
+            TokenKind::Atom(name) if name == "remainder" => {
+                let arg1 = self.read_arg()?;
+                Expr::remainder(arg1, self.read_arg()?)
+            }
+            TokenKind::Atom(name) if name == "not" => Expr::not(self.read_arg()?),
+            TokenKind::Atom(name) if name == "void" => Expr::void(),
+            TokenKind::Atom(name) if name == "collect" => {
+                Expr::collect(self.read_number("a number of bytes")?)
+            }
+            TokenKind::Atom(name) if name == "allocate" => {
+                let len = self.read_number("a vector length")?;
+                Expr::allocate(len, self.read_type()?)
+            }
+            TokenKind::Atom(name) if name == "global-value" => {
+                Expr::global_value(&self.read_name("a global variable")?)
+            }
+            TokenKind::Atom(name) if name == "vector-ref" => {
+                let v = self.read_arg()?;
+                Expr::vector_ref(v, self.read_number("an index")?)
+            }
+            TokenKind::Atom(name) if name == "vector-set!" => {
+                let v = self.read_arg()?;
+                let index = self.read_number("an index")?;
+                Expr::vector_set(v, index, self.read_arg()?)
+            }
+            TokenKind::Atom(name) if name == "inject" => {
+                let arg = self.read_arg()?;
+                Expr::inject(arg, self.read_type()?)
+            }
+            TokenKind::Atom(name) if name == "project" => {
+                let arg = self.read_arg()?;
+                Expr::project(arg, self.read_type()?)
+            }
+            TokenKind::Atom(name) if name == "any-vector-ref" => {
+                let v = self.read_arg()?;
+                Expr::any_vector_ref(v, self.read_number("an index")?)
+            }
+            TokenKind::Atom(name) if name == "any-vector-set!" => {
+                let v = self.read_arg()?;
+                let index = self.read_number("an index")?;
+                Expr::any_vector_set(v, index, self.read_arg()?)
+            }
+            TokenKind::Atom(name) if name == "print" => Expr::print(self.read_arg()?),
+            TokenKind::Atom(name) if name == "fun-ref" => {
+                Expr::fun_ref(&self.read_name("a function name")?)
+            }
+            TokenKind::Atom(name) if name == "call" => {
+                let func = self.read_arg()?;
+                let mut args = vec![];
+                while self.peek().kind != TokenKind::Close {
+                    args.push(*self.read_arg()?);
+                }
+                Expr::call(func, args)
+            }
+            TokenKind::Atom(name) if CmpOp::from_name(name).is_some() => {
+                let arg1 = self.read_arg()?;
+                Expr::cmp(CmpOp::from_name(name).unwrap(), arg1, self.read_arg()?)
+            }
+            _ => return Err(ParseError::new("expected an operator".to_string(), op.span)),
+        };
+        self.expect(TokenKind::Close, "`)`")?;
+        Ok(expr)
+    }
+
+    fn read_tail(&mut self) -> Result<Box<Tail>, ParseError> {
+        let mut stmts = vec![];
+        loop {
+            let token = self.next();
+            match token.kind {
+                TokenKind::Atom(keyword) if keyword == "return" => {
+                    let expr = self.read_expr()?;
+                    self.expect(TokenKind::Semi, "`;`")?;
+                    let tail = stmts
+                        .into_iter()
+                        .rev()
+                        .fold(Tail::ret(expr), |tail, stmt| Tail::seq(stmt, tail));
+                    return Ok(tail);
+                }
+                TokenKind::Atom(keyword) if keyword == "goto" || keyword == "if" => {
+                    let end = if keyword == "goto" {
+                        let label = self.read_name("a block label")?;
+                        Tail::goto(&label)
+                    } else {
+                        let cond = self.read_expr()?;
+                        self.expect(TokenKind::Atom("goto".to_string()), "`goto`")?;
+                        let then_label = self.read_name("a block label")?;
+                        self.expect(TokenKind::Semi, "`;`")?;
+                        self.expect(TokenKind::Atom("else".to_string()), "`else`")?;
+                        self.expect(TokenKind::Atom("goto".to_string()), "`goto`")?;
+                        let else_label = self.read_name("a block label")?;
+                        Tail::if_goto(cond, &then_label, &else_label)
+                    };
+                    self.expect(TokenKind::Semi, "`;`")?;
+                    let tail = stmts
+                        .into_iter()
+                        .rev()
+                        .fold(end, |tail, stmt| Tail::seq(stmt, tail));
+                    return Ok(tail);
+                }
+                TokenKind::Atom(name) if is_name(&name) => {
+                    self.expect(TokenKind::Atom("=".to_string()), "`=`")?;
+                    let expr = self.read_expr()?;
+                    self.expect(TokenKind::Semi, "`;`")?;
+                    stmts.push(Stmt::assign(&name, expr));
+                }
+                _ => {
+                    return Err(ParseError::new(
+                        "expected an assignment, `return`, `goto` or `if`".to_string(),
+                        token.span,
+                    ))
+                }
+            }
+        }
+    }
+
+    fn at_define(&self) -> bool {
+        self.peek().kind == TokenKind::Atom("define".to_string())
+    }
+
+    /// Reads labeled blocks up to the next `define` or the end of the text.
+    fn read_tails(&mut self) -> Result<HashMap<Label, Tail>, ParseError> {
+        let mut tails = HashMap::new();
+        while self.peek().kind != TokenKind::Eof && !self.at_define() {
+            let label_span = self.peek().span.clone();
+            let label = Label::new(&self.read_name("a block label")?);
+            self.expect(TokenKind::Colon, "`:`")?;
+            let tail = self.read_tail()?;
+            if tails.contains_key(&label) {
+                return Err(ParseError::new(
+                    format!("duplicate block `{}`", label.value),
+                    label_span,
+                ));
+            }
+            tails.insert(label, *tail);
+        }
+        Ok(tails)
+    }
+
+    /// Reads `define f (x T) ... -> R:` followed by the function's blocks.
+    fn read_def(&mut self) -> Result<Def, ParseError> {
+        self.next();
+        let name = self.read_name("a function name")?;
+        let mut params = vec![];
+        while self.peek().kind == TokenKind::Open {
+            self.next();
+            let param = self.read_name("a parameter name")?;
+            params.push((Symbol::new(&param), self.read_type()?));
+            self.expect(TokenKind::Close, "`)`")?;
+        }
+        self.expect(TokenKind::Atom("->".to_string()), "`->`")?;
+        let result = self.read_type()?;
+        self.expect(TokenKind::Colon, "`:`")?;
+        Ok(Def {
+            name,
+            params,
+            result,
+            info: Info::default(),
+            tails: self.read_tails()?,
+        })
+    }
+
+    fn read_program(&mut self) -> Result<Program, ParseError> {
+        let tails = self.read_tails()?;
+        let mut defs = vec![];
+        while self.at_define() {
+            defs.push(self.read_def()?);
+        }
+        Ok(Program {
+            info: Info::default(),
+            tails,
+            defs,
+        })
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    !matches!(
+        text,
+        "=" | "return"
+            | "goto"
+            | "if"
+            | "else"
+            | "read"
+            | "+"
+            | "-"
+            | "*"
+            | "quotient"
+            | "remainder"
+            | "not"
+            | "void"
+            | "collect"
+            | "allocate"
+            | "global-value"
+            | "vector-ref"
+            | "vector-set!"
+            | "print"
+            | "fun-ref"
+            | "call"
+            | "define"
+            | "->"
+    ) && CmpOp::from_name(text).is_none()
+        && !text.starts_with('#')
+        && text.parse::<i64>().is_err()
+        && !text.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Parses a CIR program from text read from the named file.
+pub fn parse_file_program(file: &str, src: &str) -> Result<Program, ParseError> {
+    let tokens = tokenize(file.into(), src);
+    Reader { tokens, pos: 0 }.read_program()
+}
+
+/// Parses a CIR program from text.
+pub fn parse_program(src: &str) -> Result<Program, ParseError> {
+    parse_file_program("", src)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::parse_program;
+
+    #[test]
+    fn parse_blocks() {
+        let src = "\
+start:
+  x = (read);
+  y = (- x);  // negate
+  z = (- y 2);
+  return (+ z 1);
+
+other:
+  return 42;
+";
+        let program = parse_program(src).unwrap();
+        let start = Tail::seq(
+            Stmt::assign("x", Expr::read()),
+            Tail::seq(
+                Stmt::assign("y", Expr::neg(Arg::var("x"))),
+                Tail::seq(
+                    Stmt::assign("z", Expr::sub(Arg::var("y"), Arg::int(2))),
+                    Tail::ret(Expr::add(Arg::var("z"), Arg::int(1))),
+                ),
+            ),
+        );
+        let other = Tail::ret(Expr::arg(Arg::int(42)));
+        assert_eq!(program.tails.len(), 2);
+        assert_eq!(program.tails[&Label::new("start")], *start);
+        assert_eq!(program.tails[&Label::new("other")], *other);
+    }
+
+    #[test]
+    fn round_trip() {
+        let start = Tail::seq(
+            Stmt::assign("v200000", Expr::neg(Arg::int(-10))),
+            Tail::seq(
+                Stmt::assign("x.1", Expr::arg(Arg::var("v200000"))),
+                Tail::ret(Expr::add(Arg::int(52), Arg::var("x.1"))),
+            ),
+        );
+        let mut tails = HashMap::new();
+        tails.insert(Label::new("start"), *start);
+        let program = Program {
+            info: Info::default(),
+            tails,
+            defs: vec![],
+        };
+        let printed = program.to_string();
+        let expected = "\
+start:
+  v200000 = (- -10);
+  x.1 = v200000;
+  return (+ 52 x.1);
+";
+        assert_eq!(printed, expected);
+        assert_eq!(parse_program(&printed).unwrap().tails, program.tails);
+    }
+
+    #[test]
+    fn branches() {
+        let src = "\
+start:
+  x = (read);
+  b = (not #f);
+  if (<= x 0) goto neg; else goto pos;
+
+neg:
+  goto pos;
+
+pos:
+  return (eq? x 1);
+";
+        let program = parse_program(src).unwrap();
+        let start = Tail::seq(
+            Stmt::assign("x", Expr::read()),
+            Tail::seq(
+                Stmt::assign("b", Expr::not(Arg::bool(false))),
+                Tail::if_goto(
+                    Expr::cmp(CmpOp::Le, Arg::var("x"), Arg::int(0)),
+                    "neg",
+                    "pos",
+                ),
+            ),
+        );
+        assert_eq!(program.tails[&Label::new("start")], *start);
+        assert_eq!(program.tails[&Label::new("neg")], *Tail::goto("pos"));
+        let pos = Tail::ret(Expr::cmp(CmpOp::Eq, Arg::var("x"), Arg::int(1)));
+        assert_eq!(program.tails[&Label::new("pos")], *pos);
+        assert_eq!(program.to_string(), src);
+    }
+
+    #[test]
+    fn vectors() {
+        let src = "\
+start:
+  c = (collect 24);
+  end = (global-value fromspace_end);
+  v = (allocate 2 (Vector Integer (Vector Boolean)));
+  s = (vector-set! v 0 end);
+  return (vector-ref v 1);
+";
+        let program = parse_program(src).unwrap();
+        let ty = Type::Vector(vec![Type::Integer, Type::Vector(vec![Type::Boolean])]);
+        let start = Tail::seq(
+            Stmt::assign("c", Expr::collect(24)),
+            Tail::seq(
+                Stmt::assign("end", Expr::global_value("fromspace_end")),
+                Tail::seq(
+                    Stmt::assign("v", Expr::allocate(2, ty)),
+                    Tail::seq(
+                        Stmt::assign("s", Expr::vector_set(Arg::var("v"), 0, Arg::var("end"))),
+                        Tail::ret(Expr::vector_ref(Arg::var("v"), 1)),
+                    ),
+                ),
+            ),
+        );
+        assert_eq!(program.tails[&Label::new("start")], *start);
+        assert_eq!(program.to_string(), src);
+        let err = parse_program("start:\n  return (allocate 1 (Vec Integer));").unwrap_err();
+        assert_eq!(err.to_string(), "expected a type at 2:23");
+    }
+
+    #[test]
+    fn casts() {
+        let src = "\
+start:
+  x = (inject 1 Integer);
+  v = (project y (Vector Any));
+  s = (any-vector-set! v 0 x);
+  return (any-vector-ref v 0);
+";
+        let program = parse_program(src).unwrap();
+        let start = Tail::seq(
+            Stmt::assign("x", Expr::inject(Arg::int(1), Type::Integer)),
+            Tail::seq(
+                Stmt::assign(
+                    "v",
+                    Expr::project(Arg::var("y"), Type::Vector(vec![Type::Any])),
+                ),
+                Tail::seq(
+                    Stmt::assign("s", Expr::any_vector_set(Arg::var("v"), 0, Arg::var("x"))),
+                    Tail::ret(Expr::any_vector_ref(Arg::var("v"), 0)),
+                ),
+            ),
+        );
+        assert_eq!(program.tails[&Label::new("start")], *start);
+        assert_eq!(program.to_string(), src);
+    }
+
+    #[test]
+    fn print() {
+        let src = "\
+start:
+  u = (print x);
+  return (print #t);
+";
+        let program = parse_program(src).unwrap();
+        let start = Tail::seq(
+            Stmt::assign("u", Expr::print(Arg::var("x"))),
+            Tail::ret(Expr::print(Arg::bool(true))),
+        );
+        assert_eq!(program.tails[&Label::new("start")], *start);
+        assert_eq!(program.to_string(), src);
+    }
+
+    #[test]
+    fn missing_semicolon() {
+        let err = parse_program("start:\n  x = 1\n  return x;").unwrap_err();
+        assert_eq!(err.to_string(), "expected `;` at 3:3");
+    }
+
+    #[test]
+    fn missing_return() {
+        let err = parse_program("start:\n  x = 1;\n").unwrap_err();
+        assert_eq!(
+            err.message,
+            "expected an assignment, `return`, `goto` or `if`"
+        );
+    }
+
+    #[test]
+    fn duplicate_label() {
+        let err = parse_program("a:\n  return 1;\na:\n  return 2;\n").unwrap_err();
+        assert_eq!(err.to_string(), "duplicate block `a` at 3:1");
+    }
+}
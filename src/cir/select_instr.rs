@@ -1,18 +1,169 @@
+use super::super::error::CompileError;
+use super::super::options::Overflow;
 use super::super::pxir;
+use super::super::pxir::ARG_REGISTERS;
 use super::*;
 
-/// Folds the CIR argument into a PXIR argument.
+/// Label of the block that reports integer overflow. The block calls the
+/// runtime's `integer_overflow`, which exits.
+pub const OVERFLOW_LABEL: &str = "overflow_error";
+
+/// Label of the block that reports division by zero. The block calls the
+/// runtime's `divide_by_zero`, which exits.
+pub const DIVIDE_BY_ZERO_LABEL: &str = "divide_by_zero_error";
+
+/// Label of the block that reports a projection from `Any` whose tag does
+/// not match. The block calls the runtime's `type_mismatch`, which exits.
+pub const TYPE_MISMATCH_LABEL: &str = "type_mismatch_error";
+
+/// Number of low bits of an `Any` that hold its tag. The value is shifted
+/// left past them, or for vectors and closures the pointer, which is 8-byte
+/// aligned, already has them clear.
+pub const ANY_TAG_BITS: i64 = 3;
+
+/// Bit of a closure's tag word from which the number of parameters of its
+/// code, not counting the closure itself, is stored. It is above the pointer
+/// mask of a vector of the longest length.
+const ARITY_SHIFT: i64 = 57;
+
+/// Folds the CIR argument into a PXIR argument. Booleans are represented as
+/// 1 for true and 0 for false.
 fn fold_arg(arg: Arg) -> Box<pxir::Arg> {
     match arg {
         Arg::Int(i) => pxir::Arg::int(i),
+        Arg::Bool(b) => pxir::Arg::int(b as i64),
         Arg::Var(sym) => pxir::Arg::var(&sym.value),
     }
 }
 
+/// Condition code that holds after `cmpq op2, op1` when the comparison of
+/// `op1` with `op2` is true.
+fn cond_code(op: CmpOp) -> pxir::CondCode {
+    match op {
+        CmpOp::Eq => pxir::CondCode::E,
+        CmpOp::Lt => pxir::CondCode::L,
+        CmpOp::Le => pxir::CondCode::Le,
+        CmpOp::Gt => pxir::CondCode::G,
+        CmpOp::Ge => pxir::CondCode::Ge,
+    }
+}
+
+/// Number of parameters of the code of a closure of the given type, not
+/// counting the closure itself, or `None` if the type is not that of a
+/// closure. Closures are vectors that hold their code first.
+fn closure_arity(ty: &Type) -> Option<usize> {
+    match ty {
+        Type::Vector(elems) => match elems.first() {
+            Some(Type::Function(params, _)) => Some(params.len().saturating_sub(1)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Tag word at the start of a vector of the given type, in the runtime's
+/// format: bit 0 is set to mark the vector as not yet copied by the
+/// collector, bits 1 to 6 hold the length and bit 7 + i is set when element
+/// i is itself a vector, or an `Any` that may hold one. The tag word of a
+/// closure also holds its arity from `ARITY_SHIFT`, which the runtime
+/// ignores.
+fn vector_tag(ty: &Type) -> i64 {
+    let elems = match ty {
+        Type::Vector(elems) => elems.as_slice(),
+        _ => &[],
+    };
+    let pointer_mask = elems
+        .iter()
+        .enumerate()
+        .filter(|(_, elem)| matches!(elem, Type::Vector(_) | Type::Any))
+        .fold(0, |mask, (i, _)| mask | 1 << i);
+    let arity = closure_arity(ty).unwrap_or(0) as i64;
+    1 | (elems.len() as i64) << 1 | pointer_mask << 7 | arity << ARITY_SHIFT
+}
+
+/// Tag in the low bits of an `Any` holding a value of the given ground type,
+/// in the runtime's format.
+fn any_tag(ty: &Type) -> i64 {
+    match ty {
+        Type::Integer => 1,
+        Type::Vector(_) if closure_arity(ty).is_some() => 3,
+        Type::Vector(_) => 2,
+        Type::Boolean => 4,
+        Type::Void => 5,
+        Type::Function(_, _) | Type::Any => {
+            unreachable!("closure conversion leaves only ground types in casts")
+        }
+    }
+}
+
+/// Offset from the start of a vector of the element at the given index,
+/// which follows the tag word.
+fn element_offset(index: usize) -> i64 {
+    8 * (index as i64 + 1)
+}
+
+/// Creates PXIR instructions that write the value in `%rdi`, of the given
+/// type, to standard output with the runtime's printers. While its elements
+/// are printed, a vector is kept in two stack slots, which keeps the stack
+/// 16-byte aligned for the calls.
+fn print_instrs(ty: &Type) -> Vec<pxir::Instr> {
+    let rdi = || pxir::Arg::reg(pxir::Register::Rdi);
+    match ty {
+        Type::Integer => vec![pxir::Instr::callq("print_int")],
+        Type::Boolean => vec![pxir::Instr::callq("print_bool")],
+        Type::Void => vec![pxir::Instr::callq("print_void")],
+        Type::Any => vec![pxir::Instr::callq("print_any")],
+        Type::Function(_, _) => vec![pxir::Instr::callq("print_procedure")],
+        Type::Vector(_) if closure_arity(ty).is_some() => {
+            vec![pxir::Instr::callq("print_procedure")]
+        }
+        Type::Vector(elems) => {
+            let mut instrs = vec![
+                pxir::Instr::pushq(rdi()),
+                pxir::Instr::pushq(rdi()),
+                pxir::Instr::callq("print_vecbegin"),
+            ];
+            for (i, elem) in elems.iter().enumerate() {
+                if i > 0 {
+                    instrs.push(pxir::Instr::callq("print_space"));
+                }
+                instrs.push(pxir::Instr::movq(
+                    pxir::Arg::deref(pxir::Register::Rsp, 0),
+                    rdi(),
+                ));
+                instrs.push(pxir::Instr::movq(
+                    pxir::Arg::deref(pxir::Register::Rdi, element_offset(i)),
+                    rdi(),
+                ));
+                instrs.extend(print_instrs(elem));
+            }
+            instrs.extend(vec![
+                pxir::Instr::callq("print_vecend"),
+                pxir::Instr::addq(pxir::Arg::int(16), pxir::Arg::reg(pxir::Register::Rsp)),
+            ]);
+            instrs
+        }
+    }
+}
+
+/// Creates PXIR instructions that write the source, a value of the given
+/// type, and a newline to standard output.
+pub fn print_line_instrs(src: Box<pxir::Arg>, ty: &Type) -> Vec<pxir::Instr> {
+    let mut instrs = vec![pxir::Instr::movq(src, pxir::Arg::reg(pxir::Register::Rdi))];
+    instrs.extend(print_instrs(ty));
+    instrs.push(pxir::Instr::callq("print_newline"));
+    instrs
+}
+
 mod assign {
+    use super::super::super::options::Overflow;
     use super::super::super::pxir;
+    use super::super::super::pxir::ARG_REGISTERS;
     use super::super::*;
-    use super::fold_arg;
+    use super::{
+        any_tag, closure_arity, cond_code, element_offset, fold_arg, print_line_instrs, vector_tag,
+        ANY_TAG_BITS, ARITY_SHIFT, DIVIDE_BY_ZERO_LABEL, OVERFLOW_LABEL, TYPE_MISMATCH_LABEL,
+    };
 
     /// Creates PXIR instructions that read and assign the parsed input to the
     /// destination.
@@ -42,9 +193,11 @@ mod assign {
         vec![pxir::Instr::movq(op, dst.clone()), pxir::Instr::negq(dst)]
     }
 
-    /// Creates PXIR instructions that add the given operands and assign the
-    /// result to the destination.
-    fn add_instrs(
+    /// Creates PXIR instructions that apply a commutative operation, such as
+    /// `addq`, to the given operands and assign the result to the
+    /// destination.
+    fn commutative_instrs(
+        instr: fn(Box<pxir::Arg>, Box<pxir::Arg>) -> pxir::Instr,
         op1: Box<pxir::Arg>,
         op2: Box<pxir::Arg>,
         dst: Box<pxir::Arg>,
@@ -52,26 +205,365 @@ mod assign {
         if let pxir::Arg::Var(sym) = &*op1 {
             if let pxir::Arg::Var(dst_sym) = &*dst {
                 if sym.value == dst_sym.value {
-                    return vec![pxir::Instr::addq(op2, dst)];
+                    return vec![instr(op2, dst)];
                 }
             }
         } else if let pxir::Arg::Var(sym) = &*op2 {
             if let pxir::Arg::Var(dst_sym) = &*dst {
                 if sym.value == dst_sym.value {
-                    return vec![pxir::Instr::addq(op1, dst)];
+                    return vec![instr(op1, dst)];
+                }
+            }
+        }
+        vec![pxir::Instr::movq(op1, dst.clone()), instr(op2, dst)]
+    }
+
+    /// Creates PXIR instructions that subtract the second operand from the
+    /// first and assign the result to the destination.
+    fn sub_instrs(
+        op1: Box<pxir::Arg>,
+        op2: Box<pxir::Arg>,
+        dst: Box<pxir::Arg>,
+    ) -> Vec<pxir::Instr> {
+        if let pxir::Arg::Var(dst_sym) = &*dst {
+            if let pxir::Arg::Var(sym) = &*op1 {
+                if sym.value == dst_sym.value {
+                    return vec![pxir::Instr::subq(op2, dst)];
+                }
+            }
+            if let pxir::Arg::Var(sym) = &*op2 {
+                if sym.value == dst_sym.value {
+                    // Moving the first operand into the destination would
+                    // overwrite the second, so subtract in `%rax` instead.
+                    let rax = pxir::Arg::reg(pxir::Register::Rax);
+                    return vec![
+                        pxir::Instr::movq(op1, rax.clone()),
+                        pxir::Instr::subq(op2, rax.clone()),
+                        pxir::Instr::movq(rax, dst),
+                    ];
                 }
             }
         }
         vec![
             pxir::Instr::movq(op1, dst.clone()),
-            pxir::Instr::addq(op2, dst),
+            pxir::Instr::subq(op2, dst),
         ]
     }
 
+    /// Creates PXIR instructions that assign the logical negation of the given
+    /// operand to the destination.
+    fn not_instrs(op: Box<pxir::Arg>, dst: Box<pxir::Arg>) -> Vec<pxir::Instr> {
+        if op == dst {
+            return vec![pxir::Instr::xorq(pxir::Arg::int(1), dst)];
+        }
+        vec![
+            pxir::Instr::movq(op, dst.clone()),
+            pxir::Instr::xorq(pxir::Arg::int(1), dst),
+        ]
+    }
+
+    /// Creates PXIR instructions that compare the given operands and assign
+    /// the boolean result to the destination.
+    fn cmp_instrs(
+        op: CmpOp,
+        op1: Box<pxir::Arg>,
+        op2: Box<pxir::Arg>,
+        dst: Box<pxir::Arg>,
+    ) -> Vec<pxir::Instr> {
+        vec![
+            pxir::Instr::cmpq(op2, op1),
+            pxir::Instr::set(cond_code(op), pxir::Arg::byte_reg(pxir::Register::Rax)),
+            pxir::Instr::movzbq(pxir::Arg::byte_reg(pxir::Register::Rax), dst),
+        ]
+    }
+
+    /// Creates PXIR instructions that call the garbage collector with the
+    /// root stack pointer and the number of bytes needed, then assign void to
+    /// the destination.
+    fn collect_instrs(bytes: i64, dst: Box<pxir::Arg>) -> Vec<pxir::Instr> {
+        vec![
+            pxir::Instr::movq(
+                pxir::Arg::reg(pxir::Register::R15),
+                pxir::Arg::reg(pxir::Register::Rdi),
+            ),
+            pxir::Instr::movq(pxir::Arg::int(bytes), pxir::Arg::reg(pxir::Register::Rsi)),
+            pxir::Instr::callq("collect"),
+            pxir::Instr::movq(pxir::Arg::int(0), dst),
+        ]
+    }
+
+    /// Creates PXIR instructions that take space for a vector from the
+    /// runtime's `free_ptr`, write its tag and assign it to the destination.
+    /// `%r11` holds the vector while it is set up.
+    fn allocate_instrs(len: usize, ty: &Type, dst: Box<pxir::Arg>) -> Vec<pxir::Instr> {
+        let r11 = pxir::Arg::reg(pxir::Register::R11);
+        let free_ptr = pxir::Arg::global("free_ptr");
+        let mut instrs = vec![
+            pxir::Instr::movq(free_ptr.clone(), r11.clone()),
+            pxir::Instr::addq(pxir::Arg::int(element_offset(len)), free_ptr),
+        ];
+        let tag = vector_tag(ty);
+        let tag_dst = pxir::Arg::deref(pxir::Register::R11, 0);
+        if tag > i64::from(i32::MAX) {
+            // Only a move to a register can take a 64-bit immediate.
+            let rax = pxir::Arg::reg(pxir::Register::Rax);
+            instrs.push(pxir::Instr::movq(pxir::Arg::int(tag), rax.clone()));
+            instrs.push(pxir::Instr::movq(rax, tag_dst));
+        } else {
+            instrs.push(pxir::Instr::movq(pxir::Arg::int(tag), tag_dst));
+        }
+        instrs.push(pxir::Instr::movq(r11, dst));
+        instrs
+    }
+
+    /// Creates PXIR instructions that assign the element of a vector at the
+    /// given index to the destination.
+    fn vector_ref_instrs(v: Box<pxir::Arg>, index: usize, dst: Box<pxir::Arg>) -> Vec<pxir::Instr> {
+        vec![
+            pxir::Instr::movq(v, pxir::Arg::reg(pxir::Register::R11)),
+            pxir::Instr::movq(
+                pxir::Arg::deref(pxir::Register::R11, element_offset(index)),
+                dst,
+            ),
+        ]
+    }
+
+    /// Creates PXIR instructions that write the element of a vector at the
+    /// given index and assign void to the destination.
+    fn vector_set_instrs(
+        v: Box<pxir::Arg>,
+        index: usize,
+        arg: Box<pxir::Arg>,
+        dst: Box<pxir::Arg>,
+    ) -> Vec<pxir::Instr> {
+        vec![
+            pxir::Instr::movq(v, pxir::Arg::reg(pxir::Register::R11)),
+            pxir::Instr::movq(
+                arg,
+                pxir::Arg::deref(pxir::Register::R11, element_offset(index)),
+            ),
+            pxir::Instr::movq(pxir::Arg::int(0), dst),
+        ]
+    }
+
+    /// Creates PXIR instructions that tag the operand, a value of the given
+    /// type, as an `Any` and assign it to the destination. When trapping on
+    /// overflow, an integer too wide for the bits left beside the tag jumps
+    /// to the overflow error block; otherwise its top bits are lost.
+    fn inject_instrs(
+        op: Box<pxir::Arg>,
+        ty: &Type,
+        dst: Box<pxir::Arg>,
+        overflow: Overflow,
+    ) -> Vec<pxir::Instr> {
+        let tag = pxir::Arg::int(any_tag(ty));
+        let shift = pxir::Arg::int(ANY_TAG_BITS);
+        match ty {
+            Type::Integer if overflow == Overflow::Trapping => vec![
+                pxir::Instr::movq(op, dst.clone()),
+                pxir::Instr::imulq(pxir::Arg::int(1 << ANY_TAG_BITS), dst.clone()),
+                pxir::Instr::jump_if(pxir::CondCode::O, OVERFLOW_LABEL),
+                pxir::Instr::orq(tag, dst),
+            ],
+            Type::Integer | Type::Boolean => vec![
+                pxir::Instr::movq(op, dst.clone()),
+                pxir::Instr::salq(shift, dst.clone()),
+                pxir::Instr::orq(tag, dst),
+            ],
+            Type::Void => vec![pxir::Instr::movq(tag, dst)],
+            _ => vec![
+                pxir::Instr::movq(op, dst.clone()),
+                pxir::Instr::orq(tag, dst),
+            ],
+        }
+    }
+
+    /// Creates PXIR instructions that load the `Any` operand into `%rax` and
+    /// jump to the type mismatch error block unless its tag is the given one.
+    fn tag_check_instrs(op: Box<pxir::Arg>, tag: i64) -> Vec<pxir::Instr> {
+        let rax = pxir::Arg::reg(pxir::Register::Rax);
+        vec![
+            pxir::Instr::movq(op, rax.clone()),
+            pxir::Instr::andq(pxir::Arg::int((1 << ANY_TAG_BITS) - 1), rax.clone()),
+            pxir::Instr::cmpq(pxir::Arg::int(tag), rax),
+            pxir::Instr::jump_if(pxir::CondCode::Ne, TYPE_MISMATCH_LABEL),
+        ]
+    }
+
+    /// Creates PXIR instructions that remove the tag from the `Any` operand
+    /// and assign the value to the destination, after checking that it has
+    /// the given type. Vectors must also have the type's length and closures
+    /// the type's arity, which `%r11` is used to read from the tag word.
+    fn project_instrs(op: Box<pxir::Arg>, ty: &Type, dst: Box<pxir::Arg>) -> Vec<pxir::Instr> {
+        let tag = any_tag(ty);
+        let mut instrs = tag_check_instrs(op.clone(), tag);
+        let rax = pxir::Arg::reg(pxir::Register::Rax);
+        let r11 = pxir::Arg::reg(pxir::Register::R11);
+        match ty {
+            Type::Integer | Type::Boolean => instrs.extend(vec![
+                pxir::Instr::movq(op, dst.clone()),
+                pxir::Instr::sarq(pxir::Arg::int(ANY_TAG_BITS), dst),
+            ]),
+            Type::Void => instrs.push(pxir::Instr::movq(pxir::Arg::int(0), dst)),
+            Type::Vector(elems) => {
+                instrs.extend(vec![
+                    pxir::Instr::movq(op, r11.clone()),
+                    pxir::Instr::subq(pxir::Arg::int(tag), r11.clone()),
+                    pxir::Instr::movq(pxir::Arg::deref(pxir::Register::R11, 0), rax.clone()),
+                ]);
+                let expected = match closure_arity(ty) {
+                    Some(arity) => {
+                        instrs.push(pxir::Instr::sarq(pxir::Arg::int(ARITY_SHIFT), rax.clone()));
+                        arity as i64
+                    }
+                    None => {
+                        instrs.push(pxir::Instr::andq(pxir::Arg::int(126), rax.clone()));
+                        2 * elems.len() as i64
+                    }
+                };
+                instrs.extend(vec![
+                    pxir::Instr::cmpq(pxir::Arg::int(expected), rax),
+                    pxir::Instr::jump_if(pxir::CondCode::Ne, TYPE_MISMATCH_LABEL),
+                    pxir::Instr::movq(r11, dst),
+                ]);
+            }
+            Type::Function(_, _) | Type::Any => unreachable!("`any_tag` rejects these"),
+        }
+        instrs
+    }
+
+    /// Creates PXIR instructions that check that the `Any` operand holds a
+    /// vector longer than the index, leaving the vector in `%r11`.
+    fn any_vector_check_instrs(v: Box<pxir::Arg>, index: usize) -> Vec<pxir::Instr> {
+        let tag = any_tag(&Type::Vector(vec![]));
+        let mut instrs = tag_check_instrs(v.clone(), tag);
+        let rax = pxir::Arg::reg(pxir::Register::Rax);
+        let r11 = pxir::Arg::reg(pxir::Register::R11);
+        instrs.extend(vec![
+            pxir::Instr::movq(v, r11.clone()),
+            pxir::Instr::subq(pxir::Arg::int(tag), r11),
+            pxir::Instr::movq(pxir::Arg::deref(pxir::Register::R11, 0), rax.clone()),
+            pxir::Instr::andq(pxir::Arg::int(126), rax.clone()),
+            pxir::Instr::cmpq(pxir::Arg::int(2 * index as i64), rax),
+            pxir::Instr::jump_if(pxir::CondCode::Le, TYPE_MISMATCH_LABEL),
+        ]);
+        instrs
+    }
+
+    /// Creates PXIR instructions that call the function `f` points to and
+    /// assign its result to the destination. Arguments past those passed in
+    /// registers are pushed onto the stack, with padding first when there is
+    /// an odd number of them so that the stack stays 16-byte aligned.
+    fn call_instrs(f: Box<pxir::Arg>, args: Vec<Arg>, dst: Box<pxir::Arg>) -> Vec<pxir::Instr> {
+        let args = args.into_iter().map(fold_arg).collect::<Vec<_>>();
+        let rsp = pxir::Arg::reg(pxir::Register::Rsp);
+        let stack_args = args.len().saturating_sub(ARG_REGISTERS.len()) as i64;
+        let padding = 8 * (stack_args % 2);
+        let mut instrs = vec![];
+        if padding > 0 {
+            instrs.push(pxir::Instr::subq(pxir::Arg::int(padding), rsp.clone()));
+        }
+        for arg in args.iter().skip(ARG_REGISTERS.len()).rev() {
+            instrs.push(pxir::Instr::pushq(arg.clone()));
+        }
+        for (arg, reg) in args.into_iter().zip(ARG_REGISTERS.iter()) {
+            instrs.push(pxir::Instr::movq(arg, pxir::Arg::reg(*reg)));
+        }
+        instrs.push(pxir::Instr::indirect_callq(f));
+        if stack_args > 0 {
+            let bytes = 8 * stack_args + padding;
+            instrs.push(pxir::Instr::addq(pxir::Arg::int(bytes), rsp));
+        }
+        instrs.push(pxir::Instr::movq(pxir::Arg::reg(pxir::Register::Rax), dst));
+        instrs
+    }
+
+    /// Creates PXIR instructions that jump to an error block when the division
+    /// of the first operand by the second would fault: when the divisor is
+    /// zero, or when the most negative integer is divided by -1. Checks that a
+    /// literal divisor makes unnecessary are left out.
+    fn division_check_instrs(op1: Box<pxir::Arg>, op2: Box<pxir::Arg>) -> Vec<pxir::Instr> {
+        let divisor = match *op2 {
+            pxir::Arg::Int(i) => Some(i),
+            _ => None,
+        };
+        let mut instrs = vec![];
+        if divisor.is_none() || divisor == Some(0) {
+            instrs.push(pxir::Instr::cmpq(pxir::Arg::int(0), op2.clone()));
+            instrs.push(pxir::Instr::jump_if(
+                pxir::CondCode::E,
+                DIVIDE_BY_ZERO_LABEL,
+            ));
+        }
+        if divisor.is_none() || divisor == Some(-1) {
+            // Computes -1 in `%rdx` if the divisor is -1 and 0 otherwise, then
+            // adds the dividend, which overflows only for the most negative
+            // integer. `%rdx` is free until `cqto` writes it.
+            let rdx = pxir::Arg::reg(pxir::Register::Rdx);
+            instrs.extend(vec![
+                pxir::Instr::movq(op2, rdx.clone()),
+                pxir::Instr::addq(pxir::Arg::int(1), rdx.clone()),
+                pxir::Instr::cmpq(pxir::Arg::int(1), rdx.clone()),
+                pxir::Instr::sbbq(rdx.clone(), rdx.clone()),
+                pxir::Instr::addq(op1, rdx),
+                pxir::Instr::jump_if(pxir::CondCode::O, OVERFLOW_LABEL),
+            ]);
+        }
+        instrs
+    }
+
+    /// Creates PXIR instructions that divide the first operand by the second
+    /// and assign the quotient, which `idivq` leaves in `%rax`, or the
+    /// remainder, which it leaves in `%rdx`, to the destination.
+    fn division_instrs(
+        op1: Box<pxir::Arg>,
+        op2: Box<pxir::Arg>,
+        result: pxir::Register,
+        dst: Box<pxir::Arg>,
+    ) -> Vec<pxir::Instr> {
+        let mut instrs = division_check_instrs(op1.clone(), op2.clone());
+        instrs.extend(vec![
+            pxir::Instr::movq(op1, pxir::Arg::reg(pxir::Register::Rax)),
+            pxir::Instr::cqto(),
+            pxir::Instr::idivq(op2),
+            pxir::Instr::movq(pxir::Arg::reg(result), dst),
+        ]);
+        instrs
+    }
+
+    /// Creates PXIR instructions that print the operand, whose type is found
+    /// in `types`, and assign void to the destination. Operands of unknown
+    /// type, which only hand-written CIR has, are printed as integers.
+    fn print_instrs(
+        op: Arg,
+        types: &HashMap<Symbol, Type>,
+        dst: Box<pxir::Arg>,
+    ) -> Vec<pxir::Instr> {
+        let ty = match &op {
+            Arg::Int(_) => Type::Integer,
+            Arg::Bool(_) => Type::Boolean,
+            Arg::Var(sym) => types.get(sym).cloned().unwrap_or(Type::Integer),
+        };
+        let mut instrs = print_line_instrs(fold_arg(op), &ty);
+        instrs.push(pxir::Instr::movq(pxir::Arg::int(0), dst));
+        instrs
+    }
+
     /// Creates PXIR instructions that evaluate the given expresion and assign
-    /// the result to the destination.
-    pub fn expr_instrs(expr: Expr, dst: Box<pxir::Arg>) -> Vec<pxir::Instr> {
-        match expr {
+    /// the result to the destination. `types` holds the types of the
+    /// variables, by which values are printed. When trapping on overflow,
+    /// arithmetic is followed by a jump to the overflow error block.
+    pub fn expr_instrs(
+        expr: Expr,
+        dst: Box<pxir::Arg>,
+        types: &HashMap<Symbol, Type>,
+        overflow: Overflow,
+    ) -> Vec<pxir::Instr> {
+        let checked = matches!(
+            expr,
+            Expr::Neg(_) | Expr::Add(_, _) | Expr::Sub(_, _) | Expr::Mul(_, _)
+        );
+        let mut instrs = match expr {
             Expr::Read => read_instrs(dst),
             Expr::Arg(arg) => {
                 let arg = fold_arg(*arg);
@@ -84,60 +576,211 @@ mod assign {
             Expr::Add(op1, op2) => {
                 let op1 = fold_arg(*op1);
                 let op2 = fold_arg(*op2);
-                add_instrs(op1, op2, dst)
+                commutative_instrs(pxir::Instr::addq, op1, op2, dst)
+            }
+            Expr::Sub(op1, op2) => {
+                let op1 = fold_arg(*op1);
+                let op2 = fold_arg(*op2);
+                sub_instrs(op1, op2, dst)
+            }
+            Expr::Mul(op1, op2) => {
+                let op1 = fold_arg(*op1);
+                let op2 = fold_arg(*op2);
+                commutative_instrs(pxir::Instr::imulq, op1, op2, dst)
             }
+            Expr::Quotient(op1, op2) => {
+                let op1 = fold_arg(*op1);
+                let op2 = fold_arg(*op2);
+                division_instrs(op1, op2, pxir::Register::Rax, dst)
+            }
+            Expr::Remainder(op1, op2) => {
+                let op1 = fold_arg(*op1);
+                let op2 = fold_arg(*op2);
+                division_instrs(op1, op2, pxir::Register::Rdx, dst)
+            }
+            Expr::Not(op) => {
+                let op = fold_arg(*op);
+                not_instrs(op, dst)
+            }
+            Expr::Cmp(op, op1, op2) => {
+                let op1 = fold_arg(*op1);
+                let op2 = fold_arg(*op2);
+                cmp_instrs(op, op1, op2, dst)
+            }
+            // Void is represented as 0.
+            Expr::Void => arg_move_instrs(pxir::Arg::int(0), dst),
+            Expr::Collect(bytes) => collect_instrs(bytes, dst),
+            Expr::Allocate(len, ty) => allocate_instrs(len, &ty, dst),
+            Expr::GlobalValue(name) => arg_move_instrs(pxir::Arg::global(&name), dst),
+            Expr::VectorRef(v, index) => vector_ref_instrs(fold_arg(*v), index, dst),
+            Expr::VectorSet(v, index, arg) => {
+                vector_set_instrs(fold_arg(*v), index, fold_arg(*arg), dst)
+            }
+            Expr::Inject(op, ty) => inject_instrs(fold_arg(*op), &ty, dst, overflow),
+            Expr::Project(op, ty) => project_instrs(fold_arg(*op), &ty, dst),
+            Expr::AnyVectorRef(v, index) => {
+                let mut instrs = any_vector_check_instrs(fold_arg(*v), index);
+                instrs.push(pxir::Instr::movq(
+                    pxir::Arg::deref(pxir::Register::R11, element_offset(index)),
+                    dst,
+                ));
+                instrs
+            }
+            Expr::AnyVectorSet(v, index, arg) => {
+                let mut instrs = any_vector_check_instrs(fold_arg(*v), index);
+                instrs.extend(vec![
+                    pxir::Instr::movq(
+                        fold_arg(*arg),
+                        pxir::Arg::deref(pxir::Register::R11, element_offset(index)),
+                    ),
+                    pxir::Instr::movq(pxir::Arg::int(0), dst),
+                ]);
+                instrs
+            }
+            Expr::Print(op) => print_instrs(*op, types, dst),
+            Expr::FunRef(name) => vec![pxir::Instr::leaq(pxir::Arg::global(&name), dst)],
+            Expr::Call(f, args) => call_instrs(fold_arg(*f), args, dst),
+        };
+        if checked && overflow == Overflow::Trapping {
+            instrs.push(pxir::Instr::jump_if(pxir::CondCode::O, OVERFLOW_LABEL));
         }
+        instrs
     }
 }
 
 /// Folds the CIR statment into PXIR instructions.
-fn fold_stmt(stmt: Stmt) -> Vec<pxir::Instr> {
+fn fold_stmt(stmt: Stmt, types: &HashMap<Symbol, Type>, overflow: Overflow) -> Vec<pxir::Instr> {
     match stmt {
         Stmt::Assign(dst_sym, expr) => {
             let dst = pxir::Arg::var(&dst_sym.value);
-            assign::expr_instrs(*expr, dst)
+            assign::expr_instrs(*expr, dst, types, overflow)
         }
     }
 }
 
 /// Folds the CIR tail into PXIR instructions that return by jumping to the
-/// given conclusion label.
-fn fold_tail(tail: Tail, conclusion_label: &str) -> Vec<pxir::Instr> {
+/// given conclusion label. `types` holds the types of the variables.
+fn fold_tail(
+    tail: Tail,
+    conclusion_label: &str,
+    types: &HashMap<Symbol, Type>,
+    overflow: Overflow,
+) -> Vec<pxir::Instr> {
     match tail {
         Tail::Seq(stmt, tail) => {
-            let mut instrs = fold_stmt(*stmt);
-            instrs.extend(fold_tail(*tail, conclusion_label));
+            let mut instrs = fold_stmt(*stmt, types, overflow);
+            instrs.extend(fold_tail(*tail, conclusion_label, types, overflow));
             instrs
         }
         Tail::Ret(expr) => {
-            let mut instrs = assign::expr_instrs(*expr, pxir::Arg::reg(pxir::Register::Rax));
+            let rax = pxir::Arg::reg(pxir::Register::Rax);
+            let mut instrs = assign::expr_instrs(*expr, rax, types, overflow);
             instrs.push(pxir::Instr::jumpq(conclusion_label));
             instrs
         }
+        Tail::Goto(label) => vec![pxir::Instr::jumpq(&label.value)],
+        Tail::If(cond, then_label, else_label) => {
+            let mut instrs = match *cond {
+                Expr::Cmp(op, op1, op2) => vec![
+                    pxir::Instr::cmpq(fold_arg(*op2), fold_arg(*op1)),
+                    pxir::Instr::jump_if(cond_code(op), &then_label.value),
+                ],
+                expr => {
+                    // Any other condition is evaluated to a boolean first.
+                    let rax = pxir::Arg::reg(pxir::Register::Rax);
+                    let mut instrs = assign::expr_instrs(expr, rax.clone(), types, overflow);
+                    instrs.push(pxir::Instr::cmpq(pxir::Arg::int(1), rax));
+                    instrs.push(pxir::Instr::jump_if(pxir::CondCode::E, &then_label.value));
+                    instrs
+                }
+            };
+            instrs.push(pxir::Instr::jumpq(&else_label.value));
+            instrs
+        }
     }
 }
 
-/// Folds the CIR program into a PXIR program.
-pub fn fold_program(program: Program) -> pxir::Program {
+/// Folds the blocks of the program's expression or of a function, which
+/// return by jumping to the given conclusion label.
+fn fold_tails(
+    tails: HashMap<Label, Tail>,
+    info: &Info,
+    conclusion_label: &str,
+    overflow: Overflow,
+) -> pxir::Program {
+    let vector_vars = info
+        .types
+        .iter()
+        .filter(|(_, ty)| matches!(ty, Type::Vector(_) | Type::Any))
+        .map(|(sym, _)| pxir::Symbol::new(&sym.value))
+        .collect();
     let mut blocks = HashMap::new();
-    for (label, tail) in program.tails {
+    for (label, tail) in tails {
         let label = pxir::Label { value: label.value };
-        // FIXME: Different blocks may need different conclusion labels.
-        let instrs = fold_tail(tail, "conclusion");
+        let instrs = fold_tail(tail, conclusion_label, &info.types, overflow);
         let block = pxir::Block::new(instrs);
         blocks.insert(label, block);
     }
     pxir::Program {
-        info: pxir::ProgramInfo {},
+        info: pxir::ProgramInfo {
+            vector_vars,
+            root_stack_space: 0,
+            stack_space: 0,
+            callee_saved: vec![],
+        },
         blocks,
     }
 }
 
+/// Folds the blocks of the CIR program's expression into a PXIR program that
+/// returns through `conclusion`. Run `uncover` first so that the variables
+/// holding vectors are known. The program's functions are folded separately
+/// by `fold_def`.
+pub fn fold_program(program: Program, overflow: Overflow) -> pxir::Program {
+    fold_tails(program.tails, &program.info, "conclusion", overflow)
+}
+
+/// Folds a CIR function into a PXIR program that returns through
+/// `NAME_conclusion`. The start block first moves the arguments from where
+/// the caller passed them into the parameters: the registers of
+/// `ARG_REGISTERS`, then the stack above the return address and the saved
+/// `%rbp`. A hand-written function may lack its `NAME_start` block, which is
+/// reported as an error.
+pub fn fold_def(def: Def, overflow: Overflow) -> Result<pxir::Program, CompileError> {
+    let start_label = def.start_label();
+    let conclusion_label = format!("{}_conclusion", def.name);
+    let mut prog = fold_tails(def.tails, &def.info, &conclusion_label, overflow);
+    let param_moves = def.params.iter().enumerate().map(|(i, (param, _))| {
+        let src = match ARG_REGISTERS.get(i) {
+            Some(reg) => pxir::Arg::reg(*reg),
+            None => {
+                let offset = 16 + 8 * (i - ARG_REGISTERS.len()) as i64;
+                pxir::Arg::deref(pxir::Register::Rbp, offset)
+            }
+        };
+        pxir::Instr::movq(src, pxir::Arg::var(&param.value))
+    });
+    let start = prog
+        .blocks
+        .get_mut(&pxir::Label {
+            value: start_label.value.clone(),
+        })
+        .ok_or_else(|| {
+            CompileError::Emit(format!("program has no `{}` block", start_label.value))
+        })?;
+    start.instrs.splice(0..0, param_moves);
+    Ok(prog)
+}
+
 #[cfg(test)]
 mod tests {
+    use super::super::super::options::Overflow;
     use super::super::super::pxir;
     use super::super::*;
-    use super::fold_tail;
+    use super::{
+        fold_program, fold_tail, vector_tag, DIVIDE_BY_ZERO_LABEL, OVERFLOW_LABEL,
+        TYPE_MISMATCH_LABEL,
+    };
 
     #[test]
     fn read() {
@@ -151,7 +794,12 @@ mod tests {
             pxir::Instr::movq(pxir::Arg::var("x"), pxir::Arg::reg(pxir::Register::Rax)),
             pxir::Instr::jumpq("read_conclusion"),
         ];
-        let actual = fold_tail(*tail, "read_conclusion");
+        let actual = fold_tail(
+            *tail,
+            "read_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
         assert_eq!(actual, expected);
     }
 
@@ -171,7 +819,12 @@ mod tests {
             ),
             pxir::Instr::jumpq("basic_add_and_neg_conclusion"),
         ];
-        let actual = fold_tail(*tail, "basic_add_and_neg_conclusion");
+        let actual = fold_tail(
+            *tail,
+            "basic_add_and_neg_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
         assert_eq!(actual, expected);
     }
 
@@ -195,7 +848,7 @@ mod tests {
             pxir::Instr::movq(pxir::Arg::var("y"), pxir::Arg::reg(pxir::Register::Rax)),
             pxir::Instr::jumpq("add_conclusion"),
         ];
-        let actual = fold_tail(*tail, "add_conclusion");
+        let actual = fold_tail(*tail, "add_conclusion", &HashMap::new(), Overflow::Wrapping);
         assert_eq!(actual, expected);
     }
 
@@ -214,7 +867,12 @@ mod tests {
             pxir::Instr::movq(pxir::Arg::var("x"), pxir::Arg::reg(pxir::Register::Rax)),
             pxir::Instr::jumpq("add_in_place_left_op_conclusion"),
         ];
-        let actual = fold_tail(*tail, "add_in_place_left_op_conclusion");
+        let actual = fold_tail(
+            *tail,
+            "add_in_place_left_op_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
         assert_eq!(actual, expected);
     }
 
@@ -233,7 +891,12 @@ mod tests {
             pxir::Instr::movq(pxir::Arg::var("x"), pxir::Arg::reg(pxir::Register::Rax)),
             pxir::Instr::jumpq("add_in_place_right_op_conclusion"),
         ];
-        let actual = fold_tail(*tail, "add_in_place_right_op_conclusion");
+        let actual = fold_tail(
+            *tail,
+            "add_in_place_right_op_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
         assert_eq!(actual, expected);
     }
 
@@ -252,7 +915,12 @@ mod tests {
             pxir::Instr::movq(pxir::Arg::var("x"), pxir::Arg::reg(pxir::Register::Rax)),
             pxir::Instr::jumpq("add_in_place_both_ops_conclusion"),
         ];
-        let actual = fold_tail(*tail, "add_in_place_both_ops_conclusion");
+        let actual = fold_tail(
+            *tail,
+            "add_in_place_both_ops_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
         assert_eq!(actual, expected);
     }
 
@@ -272,7 +940,7 @@ mod tests {
             pxir::Instr::movq(pxir::Arg::var("y"), pxir::Arg::reg(pxir::Register::Rax)),
             pxir::Instr::jumpq("neg_conclusion"),
         ];
-        let actual = fold_tail(*tail, "neg_conclusion");
+        let actual = fold_tail(*tail, "neg_conclusion", &HashMap::new(), Overflow::Wrapping);
         assert_eq!(actual, expected);
     }
 
@@ -291,7 +959,315 @@ mod tests {
             pxir::Instr::movq(pxir::Arg::var("x"), pxir::Arg::reg(pxir::Register::Rax)),
             pxir::Instr::jumpq("neg_in_place_conclusion"),
         ];
-        let actual = fold_tail(*tail, "neg_in_place_conclusion");
+        let actual = fold_tail(
+            *tail,
+            "neg_in_place_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn sub() {
+        let tail = Tail::seq(
+            Stmt::assign("x", Expr::arg(Arg::int(20))),
+            Tail::seq(
+                Stmt::assign("y", Expr::sub(Arg::int(22), Arg::var("x"))),
+                Tail::ret(Expr::sub(Arg::var("y"), Arg::int(1))),
+            ),
+        );
+        let expected = vec![
+            pxir::Instr::movq(pxir::Arg::int(20), pxir::Arg::var("x")),
+            pxir::Instr::movq(pxir::Arg::int(22), pxir::Arg::var("y")),
+            pxir::Instr::subq(pxir::Arg::var("x"), pxir::Arg::var("y")),
+            pxir::Instr::movq(pxir::Arg::var("y"), pxir::Arg::reg(pxir::Register::Rax)),
+            pxir::Instr::subq(pxir::Arg::int(1), pxir::Arg::reg(pxir::Register::Rax)),
+            pxir::Instr::jumpq("sub_conclusion"),
+        ];
+        let actual = fold_tail(*tail, "sub_conclusion", &HashMap::new(), Overflow::Wrapping);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn sub_in_place_left_op() {
+        let tail = Tail::seq(
+            Stmt::assign("x", Expr::arg(Arg::int(20))),
+            Tail::seq(
+                Stmt::assign("x", Expr::sub(Arg::var("x"), Arg::int(22))),
+                Tail::ret(Expr::arg(Arg::var("x"))),
+            ),
+        );
+        let expected = vec![
+            pxir::Instr::movq(pxir::Arg::int(20), pxir::Arg::var("x")),
+            pxir::Instr::subq(pxir::Arg::int(22), pxir::Arg::var("x")),
+            pxir::Instr::movq(pxir::Arg::var("x"), pxir::Arg::reg(pxir::Register::Rax)),
+            pxir::Instr::jumpq("sub_in_place_left_op_conclusion"),
+        ];
+        let actual = fold_tail(
+            *tail,
+            "sub_in_place_left_op_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn sub_in_place_right_op() {
+        let tail = Tail::seq(
+            Stmt::assign("x", Expr::arg(Arg::int(20))),
+            Tail::seq(
+                Stmt::assign("x", Expr::sub(Arg::int(22), Arg::var("x"))),
+                Tail::ret(Expr::arg(Arg::var("x"))),
+            ),
+        );
+        let expected = vec![
+            pxir::Instr::movq(pxir::Arg::int(20), pxir::Arg::var("x")),
+            pxir::Instr::movq(pxir::Arg::int(22), pxir::Arg::reg(pxir::Register::Rax)),
+            pxir::Instr::subq(pxir::Arg::var("x"), pxir::Arg::reg(pxir::Register::Rax)),
+            pxir::Instr::movq(pxir::Arg::reg(pxir::Register::Rax), pxir::Arg::var("x")),
+            pxir::Instr::movq(pxir::Arg::var("x"), pxir::Arg::reg(pxir::Register::Rax)),
+            pxir::Instr::jumpq("sub_in_place_right_op_conclusion"),
+        ];
+        let actual = fold_tail(
+            *tail,
+            "sub_in_place_right_op_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn mul_in_place() {
+        let tail = Tail::seq(
+            Stmt::assign("x", Expr::arg(Arg::int(20))),
+            Tail::seq(
+                Stmt::assign("x", Expr::mul(Arg::int(3), Arg::var("x"))),
+                Tail::ret(Expr::mul(Arg::var("x"), Arg::var("x"))),
+            ),
+        );
+        let expected = vec![
+            pxir::Instr::movq(pxir::Arg::int(20), pxir::Arg::var("x")),
+            pxir::Instr::imulq(pxir::Arg::int(3), pxir::Arg::var("x")),
+            pxir::Instr::movq(pxir::Arg::var("x"), pxir::Arg::reg(pxir::Register::Rax)),
+            pxir::Instr::imulq(pxir::Arg::var("x"), pxir::Arg::reg(pxir::Register::Rax)),
+            pxir::Instr::jumpq("mul_in_place_conclusion"),
+        ];
+        let actual = fold_tail(
+            *tail,
+            "mul_in_place_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn division() {
+        let tail = Tail::seq(
+            Stmt::assign("q", Expr::quotient(Arg::var("x"), Arg::int(7))),
+            Tail::ret(Expr::remainder(Arg::int(10), Arg::var("q"))),
+        );
+        let rax = || pxir::Arg::reg(pxir::Register::Rax);
+        let rdx = || pxir::Arg::reg(pxir::Register::Rdx);
+        let expected = vec![
+            pxir::Instr::movq(pxir::Arg::var("x"), rax()),
+            pxir::Instr::cqto(),
+            pxir::Instr::idivq(pxir::Arg::int(7)),
+            pxir::Instr::movq(rax(), pxir::Arg::var("q")),
+            pxir::Instr::cmpq(pxir::Arg::int(0), pxir::Arg::var("q")),
+            pxir::Instr::jump_if(pxir::CondCode::E, DIVIDE_BY_ZERO_LABEL),
+            pxir::Instr::movq(pxir::Arg::var("q"), rdx()),
+            pxir::Instr::addq(pxir::Arg::int(1), rdx()),
+            pxir::Instr::cmpq(pxir::Arg::int(1), rdx()),
+            pxir::Instr::sbbq(rdx(), rdx()),
+            pxir::Instr::addq(pxir::Arg::int(10), rdx()),
+            pxir::Instr::jump_if(pxir::CondCode::O, OVERFLOW_LABEL),
+            pxir::Instr::movq(pxir::Arg::int(10), rax()),
+            pxir::Instr::cqto(),
+            pxir::Instr::idivq(pxir::Arg::var("q")),
+            pxir::Instr::movq(rdx(), rax()),
+            pxir::Instr::jumpq("division_conclusion"),
+        ];
+        let actual = fold_tail(
+            *tail,
+            "division_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn not_and_cmp() {
+        let tail = Tail::seq(
+            Stmt::assign("x", Expr::not(Arg::bool(true))),
+            Tail::ret(Expr::cmp(CmpOp::Lt, Arg::var("x"), Arg::int(1))),
+        );
+        let al = || pxir::Arg::byte_reg(pxir::Register::Rax);
+        let expected = vec![
+            pxir::Instr::movq(pxir::Arg::int(1), pxir::Arg::var("x")),
+            pxir::Instr::xorq(pxir::Arg::int(1), pxir::Arg::var("x")),
+            pxir::Instr::cmpq(pxir::Arg::int(1), pxir::Arg::var("x")),
+            pxir::Instr::set(pxir::CondCode::L, al()),
+            pxir::Instr::movzbq(al(), pxir::Arg::reg(pxir::Register::Rax)),
+            pxir::Instr::jumpq("not_and_cmp_conclusion"),
+        ];
+        let actual = fold_tail(
+            *tail,
+            "not_and_cmp_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn branch() {
+        let tail = Tail::if_goto(
+            Expr::cmp(CmpOp::Ge, Arg::var("x"), Arg::int(0)),
+            "block_1",
+            "block_2",
+        );
+        let expected = vec![
+            pxir::Instr::cmpq(pxir::Arg::int(0), pxir::Arg::var("x")),
+            pxir::Instr::jump_if(pxir::CondCode::Ge, "block_1"),
+            pxir::Instr::jumpq("block_2"),
+        ];
+        let actual = fold_tail(
+            *tail,
+            "branch_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn trapping() {
+        let tail = Tail::seq(
+            Stmt::assign("x", Expr::neg(Arg::int(20))),
+            Tail::seq(
+                Stmt::assign("y", Expr::arg(Arg::var("x"))),
+                Tail::ret(Expr::add(Arg::var("y"), Arg::int(1))),
+            ),
+        );
+        let expected = vec![
+            pxir::Instr::movq(pxir::Arg::int(20), pxir::Arg::var("x")),
+            pxir::Instr::negq(pxir::Arg::var("x")),
+            pxir::Instr::jump_if(pxir::CondCode::O, OVERFLOW_LABEL),
+            pxir::Instr::movq(pxir::Arg::var("x"), pxir::Arg::var("y")),
+            pxir::Instr::movq(pxir::Arg::var("y"), pxir::Arg::reg(pxir::Register::Rax)),
+            pxir::Instr::addq(pxir::Arg::int(1), pxir::Arg::reg(pxir::Register::Rax)),
+            pxir::Instr::jump_if(pxir::CondCode::O, OVERFLOW_LABEL),
+            pxir::Instr::jumpq("trapping_conclusion"),
+        ];
+        let actual = fold_tail(
+            *tail,
+            "trapping_conclusion",
+            &HashMap::new(),
+            Overflow::Trapping,
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn vectors() {
+        let ty = Type::Vector(vec![Type::Integer, Type::Vector(vec![])]);
+        let tail = Tail::seq(
+            Stmt::assign("c", Expr::collect(24)),
+            Tail::seq(
+                Stmt::assign("v", Expr::allocate(2, ty.clone())),
+                Tail::seq(
+                    Stmt::assign("s", Expr::vector_set(Arg::var("v"), 0, Arg::bool(true))),
+                    Tail::ret(Expr::vector_ref(Arg::var("v"), 1)),
+                ),
+            ),
+        );
+        let r11 = || pxir::Arg::reg(pxir::Register::R11);
+        let expected = vec![
+            pxir::Instr::movq(
+                pxir::Arg::reg(pxir::Register::R15),
+                pxir::Arg::reg(pxir::Register::Rdi),
+            ),
+            pxir::Instr::movq(pxir::Arg::int(24), pxir::Arg::reg(pxir::Register::Rsi)),
+            pxir::Instr::callq("collect"),
+            pxir::Instr::movq(pxir::Arg::int(0), pxir::Arg::var("c")),
+            pxir::Instr::movq(pxir::Arg::global("free_ptr"), r11()),
+            pxir::Instr::addq(pxir::Arg::int(24), pxir::Arg::global("free_ptr")),
+            pxir::Instr::movq(
+                pxir::Arg::int(261),
+                pxir::Arg::deref(pxir::Register::R11, 0),
+            ),
+            pxir::Instr::movq(r11(), pxir::Arg::var("v")),
+            pxir::Instr::movq(pxir::Arg::var("v"), r11()),
+            pxir::Instr::movq(pxir::Arg::int(1), pxir::Arg::deref(pxir::Register::R11, 8)),
+            pxir::Instr::movq(pxir::Arg::int(0), pxir::Arg::var("s")),
+            pxir::Instr::movq(pxir::Arg::var("v"), r11()),
+            pxir::Instr::movq(
+                pxir::Arg::deref(pxir::Register::R11, 16),
+                pxir::Arg::reg(pxir::Register::Rax),
+            ),
+            pxir::Instr::jumpq("vectors_conclusion"),
+        ];
+        assert_eq!(vector_tag(&ty), 0b1_0000_0101);
+        let actual = fold_tail(
+            *tail.clone(),
+            "vectors_conclusion",
+            &HashMap::new(),
+            Overflow::Wrapping,
+        );
+        assert_eq!(actual, expected);
+
+        let mut tails = HashMap::new();
+        tails.insert(Label::new("start"), *tail);
+        let program = uncover::fold_program(Program {
+            info: Info::default(),
+            tails,
+            defs: vec![],
+        });
+        let program = fold_program(program, Overflow::Wrapping);
+        let vector_vars = program.info.vector_vars.into_iter().collect::<Vec<_>>();
+        assert_eq!(vector_vars, vec![pxir::Symbol::new("v")]);
+    }
+
+    #[test]
+    fn casts() {
+        let tail = Tail::seq(
+            Stmt::assign("x", Expr::inject(Arg::int(5), Type::Integer)),
+            Tail::ret(Expr::project(Arg::var("x"), Type::Boolean)),
+        );
+        let x = || pxir::Arg::var("x");
+        let rax = || pxir::Arg::reg(pxir::Register::Rax);
+        let expected = vec![
+            pxir::Instr::movq(pxir::Arg::int(5), x()),
+            pxir::Instr::imulq(pxir::Arg::int(8), x()),
+            pxir::Instr::jump_if(pxir::CondCode::O, OVERFLOW_LABEL),
+            pxir::Instr::orq(pxir::Arg::int(1), x()),
+            pxir::Instr::movq(x(), rax()),
+            pxir::Instr::andq(pxir::Arg::int(7), rax()),
+            pxir::Instr::cmpq(pxir::Arg::int(4), rax()),
+            pxir::Instr::jump_if(pxir::CondCode::Ne, TYPE_MISMATCH_LABEL),
+            pxir::Instr::movq(x(), rax()),
+            pxir::Instr::sarq(pxir::Arg::int(3), rax()),
+            pxir::Instr::jumpq("casts_conclusion"),
+        ];
+        let actual = fold_tail(
+            *tail,
+            "casts_conclusion",
+            &HashMap::new(),
+            Overflow::Trapping,
+        );
+        assert_eq!(actual, expected);
+
+        // Elements that are `Any`s may hold vectors, and a closure's tag word
+        // holds its arity.
+        let any_pair = Type::Vector(vec![Type::Any, Type::Integer]);
+        assert_eq!(vector_tag(&any_pair), 0b1000_0101);
+        let code = Type::Function(vec![Type::Vector(vec![]), Type::Any], Box::new(Type::Any));
+        let closure = Type::Vector(vec![code]);
+        assert_eq!(vector_tag(&closure), 1 << 57 | 0b11);
+    }
 }
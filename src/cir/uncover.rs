@@ -1,17 +1,88 @@
 use super::*;
 use std::collections::HashSet;
 
-struct Ctx {
+struct Ctx<'a> {
     symbols: HashSet<Symbol>,
+    types: HashMap<Symbol, Type>,
+
+    /// Type of each function defined at the top level, by name.
+    funs: &'a HashMap<String, Type>,
+}
+
+/// Type of the argument, if known.
+fn arg_type(arg: &Arg, types: &HashMap<Symbol, Type>) -> Option<Type> {
+    match arg {
+        Arg::Int(_) => Some(Type::Integer),
+        Arg::Bool(_) => Some(Type::Boolean),
+        Arg::Var(sym) => types.get(sym).cloned(),
+    }
+}
+
+/// Type of the expression's value, if known.
+fn expr_type(
+    expr: &Expr,
+    types: &HashMap<Symbol, Type>,
+    funs: &HashMap<String, Type>,
+) -> Option<Type> {
+    match expr {
+        Expr::Arg(arg) => arg_type(arg, types),
+        Expr::Read
+        | Expr::Neg(_)
+        | Expr::Add(_, _)
+        | Expr::Sub(_, _)
+        | Expr::Mul(_, _)
+        | Expr::Quotient(_, _)
+        | Expr::Remainder(_, _)
+        | Expr::GlobalValue(_) => Some(Type::Integer),
+        Expr::Not(_) | Expr::Cmp(_, _, _) => Some(Type::Boolean),
+        Expr::Void
+        | Expr::Collect(_)
+        | Expr::VectorSet(_, _, _)
+        | Expr::AnyVectorSet(_, _, _)
+        | Expr::Print(_) => Some(Type::Void),
+        Expr::Inject(_, _) | Expr::AnyVectorRef(_, _) => Some(Type::Any),
+        Expr::Project(_, ty) => Some(ty.clone()),
+        Expr::Allocate(_, ty) => Some(ty.clone()),
+        Expr::VectorRef(v, index) => match arg_type(v, types)? {
+            Type::Vector(elems) => elems.get(*index).cloned(),
+            _ => None,
+        },
+        Expr::FunRef(name) => funs.get(name).cloned(),
+        Expr::Call(f, _) => match arg_type(f, types)? {
+            Type::Function(_, result) => Some(*result),
+            _ => None,
+        },
+    }
 }
 
-impl Ctx {
-    fn new() -> Ctx {
+impl<'a> Ctx<'a> {
+    /// Starts with the types of the parameters, if the blocks are those of a
+    /// function.
+    fn new(params: &[(Symbol, Type)], funs: &'a HashMap<String, Type>) -> Ctx<'a> {
         Ctx {
             symbols: HashSet::new(),
+            types: params.iter().cloned().collect(),
+            funs,
         }
     }
 
+    /// Records the types of the variables assigned in the tail. Returns
+    /// whether a type was found that was not known before.
+    fn infer_types(&mut self, mut tail: &Tail) -> bool {
+        let mut changed = false;
+        while let Tail::Seq(stmt, t) = tail {
+            let Stmt::Assign(sym, expr) = &**stmt;
+            if !self.types.contains_key(sym) {
+                if let Some(ty) = expr_type(expr, &self.types, self.funs) {
+                    self.types.insert(*sym.clone(), ty);
+                    changed = true;
+                }
+            }
+            tail = t;
+        }
+        changed
+    }
+
     fn fold_stmt(&mut self, stmt: &Stmt) {
         match stmt {
             Stmt::Assign(sym, _) => {
@@ -26,19 +97,68 @@ impl Ctx {
             self.fold_tail(t);
         }
     }
+
+    /// Type of the value the tail returns, if it returns one of known type.
+    fn result_type(&self, mut tail: &Tail) -> Option<Type> {
+        while let Tail::Seq(_, t) = tail {
+            tail = t;
+        }
+        match tail {
+            Tail::Ret(expr) => expr_type(expr, &self.types, self.funs),
+            _ => None,
+        }
+    }
 }
 
-pub fn fold_program(p: Program) -> Program {
-    let mut ctx = Ctx::new();
-    for t in p.tails.values() {
+/// Finds the info for the blocks of the program's expression or of one of its
+/// functions.
+fn uncover_tails(
+    tails: &HashMap<Label, Tail>,
+    params: &[(Symbol, Type)],
+    funs: &HashMap<String, Type>,
+) -> Info {
+    let mut ctx = Ctx::new(params, funs);
+    for t in tails.values() {
         ctx.fold_tail(t);
     }
+    // A variable may be assigned from one defined in another block, so the
+    // blocks are visited until no more types are found.
+    loop {
+        let mut changed = false;
+        for t in tails.values() {
+            changed |= ctx.infer_types(t);
+        }
+        if !changed {
+            break;
+        }
+    }
+    let result = tails.values().find_map(|t| ctx.result_type(t));
+    Info {
+        symbols: ctx.symbols,
+        types: ctx.types,
+        result,
+    }
+}
 
+pub fn fold_program(p: Program) -> Program {
+    let funs = p
+        .defs
+        .iter()
+        .map(|def| (def.name.clone(), def.ty()))
+        .collect::<HashMap<String, Type>>();
+    let info = uncover_tails(&p.tails, &[], &funs);
+    let defs = p
+        .defs
+        .into_iter()
+        .map(|def| Def {
+            info: uncover_tails(&def.tails, &def.params, &funs),
+            ..def
+        })
+        .collect();
     Program {
-        info: Info {
-            symbols: ctx.symbols,
-        },
+        info,
         tails: p.tails,
+        defs,
     }
 }
 
@@ -62,6 +182,7 @@ mod tests {
         let program = Program {
             info: Info::default(),
             tails,
+            defs: vec![],
         };
         let program = fold_program(program);
 
@@ -93,6 +214,7 @@ mod tests {
         let program = Program {
             info: Info::default(),
             tails,
+            defs: vec![],
         };
         let program = fold_program(program);
 
@@ -105,4 +227,50 @@ mod tests {
         };
         assert_eq!(program.info.symbols, expected_symbols);
     }
+
+    #[test]
+    fn types_across_blocks() {
+        let src = "\
+start:
+  v = (allocate 2 (Vector Integer (Vector Boolean)));
+  goto next;
+
+next:
+  w = (vector-ref u 1);
+  b = (vector-ref w 0);
+  return b;
+
+other:
+  u = v;
+  goto next;
+";
+        let program = fold_program(parse::parse_program(src).unwrap());
+        let vector = Type::Vector(vec![Type::Boolean]);
+        assert_eq!(program.info.types[&Symbol::new("w")], vector);
+        assert_eq!(program.info.types[&Symbol::new("b")], Type::Boolean);
+        assert_eq!(program.info.types.len(), 4);
+        assert_eq!(program.info.result, Some(Type::Boolean));
+    }
+
+    #[test]
+    fn types_of_functions() {
+        let src = "\
+start:
+  f = (fun-ref id);
+  v = (call f w);
+  return v;
+
+define id (x (Vector Integer)) -> (Vector Integer):
+
+id_start:
+  return x;
+";
+        let program = fold_program(parse::parse_program(src).unwrap());
+        let vector = Type::Vector(vec![Type::Integer]);
+        let id_type = Type::Function(vec![vector.clone()], Box::new(vector.clone()));
+        assert_eq!(program.info.types[&Symbol::new("f")], id_type);
+        assert_eq!(program.info.types[&Symbol::new("v")], vector);
+        assert_eq!(program.defs[0].info.types[&Symbol::new("x")], vector);
+        assert!(program.defs[0].info.symbols.is_empty());
+    }
 }
@@ -0,0 +1,116 @@
+use super::*;
+use std::fmt;
+
+/// Writes each tail as a labeled block. The start block comes first and the
+/// rest are ordered by label so the output is stable.
+fn write_tails(
+    f: &mut fmt::Formatter<'_>,
+    tails: &HashMap<Label, Tail>,
+    start: &Label,
+) -> fmt::Result {
+    let mut labels = tails.keys().collect::<Vec<&Label>>();
+    labels.sort_by_key(|l| (*l != start, &l.value));
+    for (i, label) in labels.into_iter().enumerate() {
+        if i > 0 {
+            writeln!(f)?;
+        }
+        writeln!(f, "{}:", label.value)?;
+        write!(f, "{}", tails[label])?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Program {
+    /// Writes the blocks of the final expression, then each function as a
+    /// `define` line giving its name, parameters and result type followed by
+    /// its blocks.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_tails(f, &self.tails, &Label::new("start"))?;
+        for def in &self.defs {
+            write!(f, "\ndefine {}", def.name)?;
+            for (param, ty) in &def.params {
+                write!(f, " ({} {})", param.value, ty)?;
+            }
+            writeln!(f, " -> {}:\n", def.result)?;
+            write_tails(f, &def.tails, &def.start_label())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Tail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tail::Seq(stmt, tail) => {
+                writeln!(f, "  {}", stmt)?;
+                write!(f, "{}", tail)
+            }
+            Tail::Ret(expr) => writeln!(f, "  return {};", expr),
+            Tail::Goto(label) => writeln!(f, "  goto {};", label.value),
+            Tail::If(cond, then_label, else_label) => writeln!(
+                f,
+                "  if {} goto {}; else goto {};",
+                cond, then_label.value, else_label.value
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stmt::Assign(sym, expr) => write!(f, "{} = {};", sym.value, expr),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Read => write!(f, "(read)"),
+            Expr::Arg(arg) => write!(f, "{}", arg),
+            Expr::Neg(arg) => write!(f, "(- {})", arg),
+            Expr::Add(arg1, arg2) => write!(f, "(+ {} {})", arg1, arg2),
+            Expr::Sub(arg1, arg2) => write!(f, "(- {} {})", arg1, arg2),
+            Expr::Mul(arg1, arg2) => write!(f, "(* {} {})", arg1, arg2),
+            Expr::Quotient(arg1, arg2) => write!(f, "(quotient {} {})", arg1, arg2),
+            Expr::Remainder(arg1, arg2) => write!(f, "(remainder {} {})", arg1, arg2),
+            Expr::Not(arg) => write!(f, "(not {})", arg),
+            Expr::Cmp(op, arg1, arg2) => write!(f, "({} {} {})", op.name(), arg1, arg2),
+            Expr::Void => write!(f, "(void)"),
+            Expr::Collect(bytes) => write!(f, "(collect {})", bytes),
+            Expr::Allocate(len, ty) => write!(f, "(allocate {} {})", len, ty),
+            Expr::GlobalValue(name) => write!(f, "(global-value {})", name),
+            Expr::VectorRef(v, index) => write!(f, "(vector-ref {} {})", v, index),
+            Expr::VectorSet(v, index, arg) => {
+                write!(f, "(vector-set! {} {} {})", v, index, arg)
+            }
+            Expr::Inject(arg, ty) => write!(f, "(inject {} {})", arg, ty),
+            Expr::Project(arg, ty) => write!(f, "(project {} {})", arg, ty),
+            Expr::AnyVectorRef(v, index) => write!(f, "(any-vector-ref {} {})", v, index),
+            Expr::AnyVectorSet(v, index, arg) => {
+                write!(f, "(any-vector-set! {} {} {})", v, index, arg)
+            }
+            Expr::Print(arg) => write!(f, "(print {})", arg),
+            Expr::FunRef(name) => write!(f, "(fun-ref {})", name),
+            Expr::Call(func, args) => {
+                write!(f, "(call {}", func)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arg::Int(i) => write!(f, "{}", i),
+            Arg::Bool(true) => write!(f, "#t"),
+            Arg::Bool(false) => write!(f, "#f"),
+            Arg::Var(sym) => write!(f, "{}", sym.value),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Source locations and the diagnostics that point at them.
+
+use std::fmt;
+use std::rc::Rc;
+
+/// Location of a piece of source text.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    /// Name of the file the text came from. Empty when the text was not read
+    /// from a file.
+    pub file: Rc<str>,
+
+    /// Line of the first character, starting at 1.
+    pub line: usize,
+
+    /// Column of the first character, starting at 1.
+    pub column: usize,
+
+    /// Byte offset of the first character.
+    pub lo: usize,
+
+    /// Byte offset one past the last character.
+    pub hi: usize,
+}
+
+impl Span {
+    pub fn new(file: Rc<str>, line: usize, column: usize, lo: usize, hi: usize) -> Span {
+        Span {
+            file,
+            line,
+            column,
+            lo,
+            hi,
+        }
+    }
+
+    /// Creates a span that starts where `self` starts and ends where `other`
+    /// ends.
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            hi: other.hi,
+            ..self.clone()
+        }
+    }
+
+    /// Whether the span points at real source text. Nodes built directly in
+    /// Rust rather than parsed from source have no location.
+    pub fn is_known(&self) -> bool {
+        self.line > 0
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.file.is_empty() {
+            write!(f, "{}:", self.file)?;
+        }
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A message about a specific location in the source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(message: String, span: Span) -> Diagnostic {
+        Diagnostic { message, span }
+    }
+
+    /// Renders the diagnostic together with the offending line of `src` and a
+    /// caret underneath the located text.
+    pub fn render(&self, src: &str) -> String {
+        let line_text = match src.lines().nth(self.span.line.wrapping_sub(1)) {
+            Some(line_text) if self.span.is_known() => line_text,
+            _ => return format!("{}\n", self),
+        };
+        let line_no = self.span.line.to_string();
+        let gutter = " ".repeat(line_no.len());
+        let indent: String = line_text
+            .chars()
+            .take(self.span.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let remaining = line_text
+            .chars()
+            .count()
+            .saturating_sub(self.span.column - 1);
+        let width = src
+            .get(self.span.lo..self.span.hi)
+            .map_or(1, |text| text.chars().count())
+            .min(remaining)
+            .max(1);
+        format!(
+            "{}\n{} |\n{} | {}\n{} | {}{}\n",
+            self,
+            gutter,
+            line_no,
+            line_text,
+            gutter,
+            indent,
+            "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.span.is_known() {
+            write!(f, "{} at {}", self.message, self.span)
+        } else {
+            write!(f, "{}", self.message)
+        }
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, Span};
+
+    #[test]
+    fn render_points_at_span() {
+        let src = "(let ([x 1])\n  (+ x\n     y))";
+        let diagnostic = Diagnostic::new(
+            "unbound variable `y`".to_string(),
+            Span::new("".into(), 3, 6, 25, 26),
+        );
+        let expected = "unbound variable `y` at 3:6\n  |\n3 |      y))\n  |      ^\n";
+        assert_eq!(diagnostic.render(src), expected);
+    }
+
+    #[test]
+    fn render_underlines_whole_span() {
+        let src = "(+ foo 1)";
+        let diagnostic = Diagnostic::new(
+            "unbound variable `foo`".to_string(),
+            Span::new("prog.rkt".into(), 1, 4, 3, 6),
+        );
+        let expected = "unbound variable `foo` at prog.rkt:1:4\n  |\n1 | (+ foo 1)\n  |    ^^^\n";
+        assert_eq!(diagnostic.render(src), expected);
+    }
+
+    #[test]
+    fn render_without_location() {
+        let diagnostic = Diagnostic::new("unbound variable `y`".to_string(), Span::default());
+        assert_eq!(diagnostic.render("y"), "unbound variable `y`\n");
+    }
+}
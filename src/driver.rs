@@ -1,100 +1,688 @@
 use super::cir;
+use super::cir::Type;
+pub use super::error::CompileError;
+use super::options::{Options, Typing};
 use super::pxir;
 use super::rir;
-use super::rir::ExprFolder;
 
-pub fn drive(expr: rir::Expr) -> String {
+/// Point in the pass pipeline at which to stop and print the program. Stages
+/// are ordered by when they run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// RIR as parsed.
+    Rir,
+    /// RIR after cast insertion, which changes only dynamically typed
+    /// programs.
+    RirCasts,
+    /// RIR after uniquify.
+    RirUniq,
+    /// RIR after assignment conversion.
+    RirAssign,
+    /// RIR after closure conversion.
+    RirClosure,
+    /// RIR after expose_allocation.
+    RirAlloc,
+    /// RIR after argument simplification.
+    RirSimple,
+    /// CIR after explicate and uncover.
+    Cir,
+    /// PXIR after instruction selection.
+    Pxir,
+    /// PXIR after instruction selection with the variables live after each
+    /// instruction.
+    PxirLive,
+    /// PXIR after assigning homes to variables.
+    PxirHomes,
+    /// Final x86 assembly.
+    Asm,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 12] = [
+        Stage::Rir,
+        Stage::RirCasts,
+        Stage::RirUniq,
+        Stage::RirAssign,
+        Stage::RirClosure,
+        Stage::RirAlloc,
+        Stage::RirSimple,
+        Stage::Cir,
+        Stage::Pxir,
+        Stage::PxirLive,
+        Stage::PxirHomes,
+        Stage::Asm,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Rir => "rir",
+            Stage::RirCasts => "rir-casts",
+            Stage::RirUniq => "rir-uniq",
+            Stage::RirAssign => "rir-assign",
+            Stage::RirClosure => "rir-closure",
+            Stage::RirAlloc => "rir-alloc",
+            Stage::RirSimple => "rir-simple",
+            Stage::Cir => "cir",
+            Stage::Pxir => "pxir",
+            Stage::PxirLive => "pxir-live",
+            Stage::PxirHomes => "pxir-homes",
+            Stage::Asm => "asm",
+        }
+    }
+}
+
+impl std::str::FromStr for Stage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Stage::ALL
+            .iter()
+            .find(|stage| stage.name() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown stage `{}`", s))
+    }
+}
+
+/// Compiles the expression to x86 assembly.
+pub fn drive(expr: rir::Expr) -> Result<String, CompileError> {
+    drive_to(expr, Stage::Asm, &Options::default())
+}
+
+/// Runs the pass pipeline on a program made of just the expression up to and
+/// including the given stage and returns the program at that point as text.
+pub fn drive_to(expr: rir::Expr, stage: Stage, options: &Options) -> Result<String, CompileError> {
+    drive_program_to(rir::Program::new(Box::new(expr)), stage, options)
+}
+
+/// Runs the pass pipeline up to and including the given stage and returns the
+/// program at that point as text.
+pub fn drive_program_to(
+    prog: rir::Program,
+    stage: Stage,
+    options: &Options,
+) -> Result<String, CompileError> {
+    if stage == Stage::Rir {
+        return Ok(prog.to_string());
+    }
+
     // RIR folds
+    let prog = match options.typing {
+        Typing::Static => prog,
+        Typing::Dynamic => rir::insert_casts::CastInserter::new().fold_program(prog),
+    };
+    let mut typecheck_ctx = rir::typecheck::TypeChecker::new();
+    typecheck_ctx.check_program(&prog);
+    if !typecheck_ctx.diagnostics.is_empty() {
+        return Err(CompileError::Typecheck(typecheck_ctx.diagnostics));
+    }
+    if stage == Stage::RirCasts {
+        return Ok(prog.to_string());
+    }
     let mut uniquify_ctx = rir::uniquify::ExprUniquifier::new(12345);
-    let expr = uniquify_ctx.fold(Box::new(expr));
-    let mut arg_simplify_ctx = rir::arg_simplify::ExprArgSimplifier::new(uniquify_ctx.counter);
-    let expr = arg_simplify_ctx.fold(expr);
-    let prog = rir::Program::new(expr);
+    let prog = uniquify_ctx.fold_program(prog);
+    if !uniquify_ctx.diagnostics.is_empty() {
+        return Err(CompileError::Uniquify(uniquify_ctx.diagnostics));
+    }
+    if stage == Stage::RirUniq {
+        return Ok(prog.to_string());
+    }
+    let mut assign_ctx = rir::convert_assignments::AssignmentConverter::new(uniquify_ctx.counter);
+    let prog = assign_ctx.fold_program(prog);
+    if stage == Stage::RirAssign {
+        return Ok(prog.to_string());
+    }
+    let mut convert_ctx = rir::convert_closures::ClosureConverter::new(assign_ctx.counter);
+    let prog = convert_ctx.fold_program(prog);
+    if stage == Stage::RirClosure {
+        return Ok(prog.to_string());
+    }
+    let mut expose_ctx = rir::expose_allocation::ExprAllocationExposer::new(convert_ctx.counter);
+    let prog = expose_ctx.fold_program(prog);
+    if stage == Stage::RirAlloc {
+        return Ok(prog.to_string());
+    }
+    let mut arg_simplify_ctx = rir::arg_simplify::ExprArgSimplifier::new(expose_ctx.counter);
+    arg_simplify_ctx.assigned = assign_ctx.assigned;
+    let prog = prog.fold_exprs(&mut arg_simplify_ctx);
+    if stage == Stage::RirSimple {
+        return Ok(prog.to_string());
+    }
 
     // CIR folds
-    let prog = rir::explicate::fold_program(prog);
-    let prog = cir::uncover::fold_program(prog);
+    let prog = rir::explicate::fold_program(prog)?;
+    drive_cir_to(prog, stage, options)
+}
+
+/// The code of the program's expression or of one of its functions, with the
+/// labels of its entry point, first block and conclusion.
+struct Unit {
+    entry: pxir::Label,
+    start: pxir::Label,
+    conclusion: pxir::Label,
+    prog: pxir::Program,
+}
+
+impl Unit {
+    fn new(entry: &str, start: &str, conclusion: &str, prog: pxir::Program) -> Unit {
+        let label = |value: &str| pxir::Label {
+            value: value.to_string(),
+        };
+        Unit {
+            entry: label(entry),
+            start: label(start),
+            conclusion: label(conclusion),
+            prog,
+        }
+    }
+
+    /// Applies a pass to the unit's code.
+    fn map(self, pass: impl Fn(pxir::Program) -> pxir::Program) -> Unit {
+        Unit {
+            prog: pass(self.prog),
+            ..self
+        }
+    }
+}
+
+/// Runs the passes that follow explicate on a CIR program, such as one read
+/// with `cir::parse`, up to and including the given stage. Stages before
+/// `Stage::Cir` print the CIR program.
+pub fn drive_cir_to(
+    prog: cir::Program,
+    stage: Stage,
+    options: &Options,
+) -> Result<String, CompileError> {
+    let mut prog = cir::uncover::fold_program(prog);
+    if stage <= Stage::Cir {
+        return Ok(prog.to_string());
+    }
+    // Values of unknown type, which only hand-written CIR has, are printed
+    // as integers.
+    let result = prog.info.result.clone().unwrap_or(Type::Integer);
 
     // PXIR folds
-    let prog = cir::select_instr::fold_program(prog);
-    // let expected = vec![
-    //     pxir::Instr::movq(pxir::Arg::int(10), pxir::Arg::var("v12345")),
-    //     pxir::Instr::negq(pxir::Arg::var("v12345")),
-    //     pxir::Instr::movq(pxir::Arg::int(52), pxir::Arg::reg(pxir::Register::Rax)),
-    //     pxir::Instr::addq(
-    //         pxir::Arg::var("v12345"),
-    //         pxir::Arg::reg(pxir::Register::Rax),
-    //     ),
-    //     pxir::Instr::jumpq("conclusion"),
-    // ];
-    // let label = pxir::Label {
-    //     value: "start".to_string(),
-    // };
-    // assert_eq!(prog.blocks.get(&label).unwrap().instrs, expected);
-
-    let prog = pxir::assign_homes::fold_program(prog);
-    let prog = pxir::patch::fold_program(prog);
-
-    // Prepare to write.
-    let start_label = pxir::Label {
-        value: "start".to_string(),
-    };
-    let start_block = prog.blocks.get(&start_label).unwrap();
-    let start_stack_space = adjusted_stack_space(start_block.info.stack_space);
-    let main_label = pxir::Label {
-        value: "main".to_string(),
-    };
-    let main_block = build_main_block(start_stack_space, &start_label);
-    let conclusion_label = pxir::Label {
-        value: "conclusion".to_string(),
-    };
-    let conclusion_block = build_conclusion_block(start_stack_space);
+    let defs = std::mem::take(&mut prog.defs);
+    let mut units = vec![Unit::new(
+        "main",
+        "start",
+        "conclusion",
+        cir::select_instr::fold_program(prog, options.overflow),
+    )];
+    for def in defs {
+        let name = def.name.clone();
+        let prog = cir::select_instr::fold_def(def, options.overflow)?;
+        let start = format!("{}_start", name);
+        let conclusion = format!("{}_conclusion", name);
+        units.push(Unit::new(&name, &start, &conclusion, prog));
+    }
+    if stage == Stage::Pxir {
+        return write_units(&units, write_pxir);
+    }
+    if stage == Stage::PxirLive {
+        return write_units(&units, write_pxir_live);
+    }
+    let units = units
+        .into_iter()
+        .map(|unit| unit.map(pxir::assign_homes::fold_program))
+        .collect::<Vec<Unit>>();
+    if stage == Stage::PxirHomes {
+        return write_units(&units, write_pxir);
+    }
+    let units = units
+        .into_iter()
+        .map(|unit| unit.map(pxir::patch::fold_program))
+        .collect::<Vec<Unit>>();
+
+    // Prepare to write. The runtime is set up once, in `main`, if any
+    // function uses the heap.
+    let heap = units.iter().any(|unit| uses_heap(&unit.prog));
+    let mut frames = vec![];
+    for (i, unit) in units.iter().enumerate() {
+        if !unit.prog.blocks.contains_key(&unit.start) {
+            return Err(CompileError::Emit(format!(
+                "program has no `{}` block",
+                unit.start.value
+            )));
+        }
+        let saved = &unit.prog.info.callee_saved;
+        // `main` also saves `%r15` when it points it at the root stack.
+        let pushed = saved.len() + if i == 0 && heap { 1 } else { 0 };
+        let stack_space = adjusted_stack_space(unit.prog.info.stack_space, pushed);
+        let root_stack_space = if heap {
+            Some(unit.prog.info.root_stack_space)
+        } else {
+            None
+        };
+        let entry_block = if i == 0 {
+            build_main_block(stack_space, saved, root_stack_space, &unit.start)
+        } else {
+            let root_stack_space = root_stack_space.unwrap_or(0);
+            build_function_block(stack_space, saved, root_stack_space, &unit.start)
+        };
+        let printed = if i == 0 { Some(&result) } else { None };
+        let conclusion_block =
+            build_conclusion_block(stack_space, saved, root_stack_space, printed);
+        frames.push((entry_block, conclusion_block));
+    }
+    let error_blocks = build_error_blocks(&units);
+    let mut blocks = units[0]
+        .prog
+        .labels()
+        .into_iter()
+        .map(|label| (label, &units[0].prog.blocks[label]))
+        .collect::<Vec<(&pxir::Label, &pxir::Block)>>();
+    for (unit, (entry_block, conclusion_block)) in units.iter().zip(&frames).skip(1) {
+        blocks.push((&unit.entry, entry_block));
+        for label in unit.prog.labels() {
+            blocks.push((label, &unit.prog.blocks[label]));
+        }
+        blocks.push((&unit.conclusion, conclusion_block));
+    }
 
     // Write x86
-    use pxir::write_block;
-    use std::fmt::Write;
     let mut out = "".to_string();
-    write_block(&mut out, &start_label, start_block).unwrap();
-    writeln!(&mut out).unwrap();
-    writeln!(&mut out, "\t.globl main").unwrap();
-    write_block(&mut out, &main_label, &main_block).unwrap();
-    write_block(&mut out, &conclusion_label, &conclusion_block).unwrap();
+    write_program(
+        &mut out,
+        &blocks,
+        (&units[0].entry, &frames[0].0),
+        (&units[0].conclusion, &frames[0].1),
+        &error_blocks,
+    )
+    .map_err(|e| CompileError::Emit(e.to_string()))?;
+
+    Ok(out)
+}
+
+/// Writes the code of every unit, the program's expression first, with the
+/// given writer.
+fn write_units(
+    units: &[Unit],
+    write: fn(&pxir::Program) -> Result<String, CompileError>,
+) -> Result<String, CompileError> {
+    let mut out = "".to_string();
+    for unit in units {
+        out.push_str(&write(&unit.prog)?);
+    }
+    Ok(out)
+}
+
+/// Writes every block of an intermediate PXIR program, the start block first
+/// and the rest ordered by label.
+fn write_pxir(prog: &pxir::Program) -> Result<String, CompileError> {
+    let mut out = "".to_string();
+    for label in prog.labels() {
+        pxir::write_block(&mut out, label, &prog.blocks[label])
+            .map_err(|e| CompileError::Emit(e.to_string()))?;
+    }
+    Ok(out)
+}
+
+/// Writes every block of an intermediate PXIR program, as `write_pxir` does,
+/// with the variables live after each instruction.
+fn write_pxir_live(prog: &pxir::Program) -> Result<String, CompileError> {
+    let live = pxir::uncover_live::uncover_live_program(prog);
+    let mut out = "".to_string();
+    for label in prog.labels() {
+        let block = &prog.blocks[label];
+        pxir::write_block_live(&mut out, label, block, &live[label][1..])
+            .map_err(|e| CompileError::Emit(e.to_string()))?;
+    }
+    Ok(out)
+}
 
-    out
+fn write_program(
+    out: &mut String,
+    blocks: &[(&pxir::Label, &pxir::Block)],
+    main: (&pxir::Label, &pxir::Block),
+    conclusion: (&pxir::Label, &pxir::Block),
+    error_blocks: &[(pxir::Label, pxir::Block)],
+) -> std::fmt::Result {
+    use pxir::write_block;
+    use std::fmt::Write;
+    for (label, block) in blocks {
+        write_block(out, label, block)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "\t.globl main")?;
+    write_block(out, main.0, main.1)?;
+    write_block(out, conclusion.0, conclusion.1)?;
+    for (label, block) in error_blocks {
+        write_block(out, label, block)?;
+    }
+    Ok(())
 }
 
-fn adjusted_stack_space(stack_size: i64) -> i64 {
-    if stack_size % 16 == 0 {
+/// Rounds the space for stack variables up so that, with `%rbp` and the
+/// `saved` callee-saved registers pushed, the frame keeps `%rsp` 16-byte
+/// aligned for calls.
+fn adjusted_stack_space(stack_size: i64, saved: usize) -> i64 {
+    if (stack_size + 8 * saved as i64) % 16 == 0 {
         stack_size
     } else {
         stack_size + 8
     }
 }
 
-fn build_main_block(stack_size: i64, jump_to: &pxir::Label) -> pxir::Block {
-    let instrs = vec![
+/// Size in bytes of the root stack the runtime sets up. Every `Any` variable
+/// of a dynamically typed program takes a slot, so recursive functions need
+/// far more than with static types.
+const ROOT_STACK_SIZE: i64 = 65536;
+
+/// Size in bytes of each half of the heap the runtime sets up. It is kept
+/// small so that the collector runs often.
+const HEAP_SIZE: i64 = 16384;
+
+/// Whether the program allocates vectors, so that the runtime must set up the
+/// heap and root stack before it runs.
+fn uses_heap(prog: &pxir::Program) -> bool {
+    let is_global = |arg: &pxir::Arg| matches!(arg, pxir::Arg::Global(_));
+    prog.info.root_stack_space > 0
+        || prog.blocks.values().any(|block| {
+            block.instrs.iter().any(|instr| match instr {
+                pxir::Instr::Movq { src, dst } | pxir::Instr::Addq { src, dst } => {
+                    is_global(src) || is_global(dst)
+                }
+                pxir::Instr::Callq(label) => label.value == "collect",
+                _ => false,
+            })
+        })
+}
+
+/// Pushes `%rbp` and the callee-saved registers the code uses, then
+/// reserves the stack frame.
+fn frame_instrs(stack_size: i64, saved: &[pxir::Register]) -> Vec<pxir::Instr> {
+    let mut instrs = vec![
         pxir::Instr::pushq(pxir::Arg::reg(pxir::Register::Rbp)),
         pxir::Instr::movq(
             pxir::Arg::reg(pxir::Register::Rsp),
             pxir::Arg::reg(pxir::Register::Rbp),
         ),
-        pxir::Instr::subq(
-            pxir::Arg::int(stack_size),
-            pxir::Arg::reg(pxir::Register::Rsp),
-        ),
-        pxir::Instr::jumpq(&jump_to.value),
     ];
+    for reg in saved {
+        instrs.push(pxir::Instr::pushq(pxir::Arg::reg(*reg)));
+    }
+    instrs.push(pxir::Instr::subq(
+        pxir::Arg::int(stack_size),
+        pxir::Arg::reg(pxir::Register::Rsp),
+    ));
+    instrs
+}
+
+/// Zeroes the root stack slots, so the collector never follows a stale
+/// pointer, and pushes them onto the root stack in `%r15`.
+fn root_frame_instrs(root_stack_space: i64) -> Vec<pxir::Instr> {
+    let mut instrs = vec![];
+    for offset in (0..root_stack_space).step_by(8) {
+        instrs.push(pxir::Instr::movq(
+            pxir::Arg::int(0),
+            pxir::Arg::deref(pxir::Register::R15, offset),
+        ));
+    }
+    if root_stack_space > 0 {
+        instrs.push(pxir::Instr::addq(
+            pxir::Arg::int(root_stack_space),
+            pxir::Arg::reg(pxir::Register::R15),
+        ));
+    }
+    instrs
+}
+
+/// Builds the entry point. When `root_stack_space` is given, the program uses
+/// the heap: the runtime is initialized and the root stack frame is set up.
+/// The root stack pointer lives in `%r15`, which is callee-saved, so its
+/// caller's value is pushed first, above the frame, and the slots below
+/// `%rbp` stay where `assign_homes` put them.
+fn build_main_block(
+    stack_size: i64,
+    saved: &[pxir::Register],
+    root_stack_space: Option<i64>,
+    jump_to: &pxir::Label,
+) -> pxir::Block {
+    let mut instrs = vec![];
+    if root_stack_space.is_some() {
+        instrs.push(pxir::Instr::pushq(pxir::Arg::reg(pxir::Register::R15)));
+    }
+    instrs.extend(frame_instrs(stack_size, saved));
+    if let Some(root_stack_space) = root_stack_space {
+        instrs.extend(vec![
+            pxir::Instr::movq(
+                pxir::Arg::int(ROOT_STACK_SIZE),
+                pxir::Arg::reg(pxir::Register::Rdi),
+            ),
+            pxir::Instr::movq(
+                pxir::Arg::int(HEAP_SIZE),
+                pxir::Arg::reg(pxir::Register::Rsi),
+            ),
+            pxir::Instr::callq("initialize"),
+            pxir::Instr::movq(
+                pxir::Arg::global("rootstack_begin"),
+                pxir::Arg::reg(pxir::Register::R15),
+            ),
+        ]);
+        instrs.extend(root_frame_instrs(root_stack_space));
+    }
+    instrs.push(pxir::Instr::jumpq(&jump_to.value));
     pxir::Block::new(instrs)
 }
 
-fn build_conclusion_block(stack_size: i64) -> pxir::Block {
-    let instrs = vec![
-        pxir::Instr::addq(
-            pxir::Arg::int(stack_size),
-            pxir::Arg::reg(pxir::Register::Rsp),
-        ),
-        pxir::Instr::popq(pxir::Arg::reg(pxir::Register::Rbp)),
-        pxir::Instr::retq(),
-    ];
+/// Builds the entry point of a function, which sets up its stack frame and
+/// root stack frame like `main` does but leaves the runtime alone.
+fn build_function_block(
+    stack_size: i64,
+    saved: &[pxir::Register],
+    root_stack_space: i64,
+    jump_to: &pxir::Label,
+) -> pxir::Block {
+    let mut instrs = frame_instrs(stack_size, saved);
+    instrs.extend(root_frame_instrs(root_stack_space));
+    instrs.push(pxir::Instr::jumpq(&jump_to.value));
+    pxir::Block::new(instrs)
+}
+
+/// Builds the block that pops the stack frames, restores the callee-saved
+/// registers in reverse order and returns. The conclusion of `main` is given
+/// the type of the program's value, which it prints before exiting with
+/// status 0, and restores `%r15` if it set up the root stack.
+fn build_conclusion_block(
+    stack_size: i64,
+    saved: &[pxir::Register],
+    root_stack_space: Option<i64>,
+    printed: Option<&Type>,
+) -> pxir::Block {
+    let mut instrs = vec![];
+    if let Some(ty) = printed {
+        let rax = || pxir::Arg::reg(pxir::Register::Rax);
+        instrs.extend(cir::select_instr::print_line_instrs(rax(), ty));
+        instrs.push(pxir::Instr::movq(pxir::Arg::int(0), rax()));
+    }
+    if let Some(root_stack_space) = root_stack_space.filter(|&space| space > 0) {
+        instrs.push(pxir::Instr::subq(
+            pxir::Arg::int(root_stack_space),
+            pxir::Arg::reg(pxir::Register::R15),
+        ));
+    }
+    instrs.push(pxir::Instr::addq(
+        pxir::Arg::int(stack_size),
+        pxir::Arg::reg(pxir::Register::Rsp),
+    ));
+    for reg in saved.iter().rev() {
+        instrs.push(pxir::Instr::popq(pxir::Arg::reg(*reg)));
+    }
+    instrs.push(pxir::Instr::popq(pxir::Arg::reg(pxir::Register::Rbp)));
+    if printed.is_some() && root_stack_space.is_some() {
+        instrs.push(pxir::Instr::popq(pxir::Arg::reg(pxir::Register::R15)));
+    }
+    instrs.push(pxir::Instr::retq());
     pxir::Block::new(instrs)
 }
+
+/// Runtime routines that report an error and exit, by the label of the block
+/// that checks jump to.
+const ERROR_ROUTINES: [(&str, &str); 3] = [
+    (cir::select_instr::OVERFLOW_LABEL, "integer_overflow"),
+    (cir::select_instr::DIVIDE_BY_ZERO_LABEL, "divide_by_zero"),
+    (cir::select_instr::TYPE_MISMATCH_LABEL, "type_mismatch"),
+];
+
+/// Builds a block calling the runtime's error routine for each error label the
+/// program jumps to. The routines exit, so the blocks do not return.
+fn build_error_blocks(units: &[Unit]) -> Vec<(pxir::Label, pxir::Block)> {
+    let blocks = || units.iter().flat_map(|unit| unit.prog.blocks.values());
+    ERROR_ROUTINES
+        .iter()
+        .filter(|(label, _)| {
+            blocks().any(|block| {
+                block.instrs.iter().any(|instr| match instr {
+                    pxir::Instr::JumpIf(_, target) => target.value == *label,
+                    _ => false,
+                })
+            })
+        })
+        .map(|(label, routine)| {
+            let label = pxir::Label {
+                value: label.to_string(),
+            };
+            (label, pxir::Block::new(vec![pxir::Instr::callq(routine)]))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cir;
+    use super::super::options::{Options, Overflow, Typing};
+    use super::super::rir::parse::{parse_expr, parse_program};
+    use super::{drive, drive_cir_to, drive_program_to, drive_to, CompileError, Stage};
+
+    #[test]
+    fn unbound_variable() {
+        let expr = parse_expr("(let ([x 1]) (+ x y))").unwrap();
+        let actual = drive(*expr).unwrap_err();
+        assert!(matches!(actual, CompileError::Uniquify(_)));
+        assert_eq!(actual.pass(), "uniquify");
+        assert_eq!(actual.to_string(), "uniquify: unbound variable `y` at 1:19");
+    }
+
+    #[test]
+    fn compiles() {
+        let expr = parse_expr("(let ([x (read)]) (+ x (- 10)))").unwrap();
+        let out = drive(*expr).unwrap();
+        assert!(out.contains("callq read_int"));
+    }
+
+    #[test]
+    fn stops_at_stage() {
+        let expr = parse_expr("(+ 52 (- 10))").unwrap();
+        let out = drive_to(*expr, Stage::Pxir, &Options::default()).unwrap();
+        let expected = "start:\n\
+                        \tmovq $10, var<v12345>\n\
+                        \tnegq var<v12345>\n\
+                        \tmovq $52, %rax\n\
+                        \taddq var<v12345>, %rax\n\
+                        \tjmp conclusion\n";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn traps_overflow() {
+        let expr = parse_expr("(+ (read) 1)").unwrap();
+        let options = Options {
+            overflow: Overflow::Trapping,
+            ..Options::default()
+        };
+        let out = drive_to(*expr, Stage::Asm, &options).unwrap();
+        assert!(out.contains("\tjo overflow_error\n"));
+        assert!(out.contains("overflow_error:\n\tcallq integer_overflow\n"));
+        let expr = parse_expr("(+ (read) 1)").unwrap();
+        assert!(!drive(*expr).unwrap().contains("overflow"));
+    }
+
+    #[test]
+    fn checks_division() {
+        let expr = parse_expr("(quotient 10 (read))").unwrap();
+        let out = drive(*expr).unwrap();
+        assert!(out.contains("\tje divide_by_zero_error\n"));
+        assert!(out.contains("divide_by_zero_error:\n\tcallq divide_by_zero\n"));
+        assert!(out.contains("overflow_error:\n\tcallq integer_overflow\n"));
+    }
+
+    #[test]
+    fn writes_every_block() {
+        let expr = parse_expr("(if (< (read) 0) (- 1) (quotient 1 (read)))").unwrap();
+        let out = drive(*expr).unwrap();
+        assert!(out.starts_with("start:\n"));
+        assert!(out.contains("\tjl block_1\n\tjmp block_2\n"));
+        assert!(out.contains("block_1:\n"));
+        assert!(out.contains("block_2:\n"));
+        assert!(out.contains("divide_by_zero_error:\n"));
+    }
+
+    #[test]
+    fn calls_functions() {
+        let src = "(define (inc [x : Integer]) : Integer (+ x 1)) (inc 41)";
+        let prog = parse_program(src).unwrap();
+        let out = drive_program_to(prog, Stage::Asm, &Options::default()).unwrap();
+        assert!(out.contains("\tleaq f12345(%rip), "));
+        assert!(out.contains("\tcallq *"));
+        assert!(out.contains("f12345:\n\tpushq %rbp\n"));
+        assert!(out.contains("f12345_start:\n"));
+        assert!(out.contains("f12345_conclusion:\n"));
+    }
+
+    #[test]
+    fn saves_root_stack_pointer() {
+        let expr = parse_expr("(vector-ref (vector (read)) 0)").unwrap();
+        let out = drive(*expr).unwrap();
+        assert!(out.contains("main:\n\tpushq %r15\n\tpushq %rbp\n"));
+        assert!(out.contains("\tpopq %rbp\n\tpopq %r15\n\tretq\n"));
+        // Nothing else touches `%r15`, so it is left alone.
+        let out = drive(*parse_expr("(+ (read) 1)").unwrap()).unwrap();
+        assert!(!out.contains("%r15"));
+    }
+
+    #[test]
+    fn checks_tags() {
+        let prog = parse_program("(+ (vector-ref (read) 0) 1)").unwrap();
+        let options = Options {
+            typing: Typing::Dynamic,
+            ..Options::default()
+        };
+        let out = drive_program_to(prog.clone(), Stage::Asm, &options).unwrap();
+        assert!(out.contains("\tjne type_mismatch_error\n"));
+        assert!(out.contains("\tjle type_mismatch_error\n"));
+        assert!(out.contains("type_mismatch_error:\n\tcallq type_mismatch\n"));
+        assert!(out.contains("conclusion:\n\tmovq %rax, %rdi\n\tcallq print_any\n"));
+        // Statically, `(read)` is not a vector.
+        assert!(drive_program_to(prog, Stage::Asm, &Options::default()).is_err());
+    }
+
+    #[test]
+    fn ill_typed() {
+        let expr = parse_expr("(if (+ 1 2) 3 4)").unwrap();
+        let actual = drive(*expr).unwrap_err();
+        assert_eq!(
+            actual.to_string(),
+            "typecheck: expected Boolean, found Integer in condition of if at 1:5"
+        );
+    }
+
+    #[test]
+    fn missing_start_block() {
+        let src =
+            "start:\n  return 1;\n\ndefine f (x Integer) -> Integer:\n\nf_body:\n  return x;\n";
+        let prog = cir::parse::parse_program(src).unwrap();
+        let actual = drive_cir_to(prog, Stage::Asm, &Options::default()).unwrap_err();
+        assert_eq!(
+            actual,
+            CompileError::Emit("program has no `f_start` block".to_string())
+        );
+    }
+
+    #[test]
+    fn stage_names() {
+        for stage in Stage::ALL.iter() {
+            assert_eq!(stage.name().parse::<Stage>(), Ok(*stage));
+        }
+        assert!("llvm".parse::<Stage>().is_err());
+    }
+}
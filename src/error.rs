@@ -0,0 +1,75 @@
+//! Errors reported by the compiler passes.
+
+use super::diagnostic::Diagnostic;
+use std::fmt;
+
+/// Reason a program could not be compiled. Each variant names the pass that
+/// rejected the program.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CompileError {
+    /// Typecheck found operations applied to values of the wrong type.
+    Typecheck(Vec<Diagnostic>),
+
+    /// Uniquify found references to variables that are not in scope.
+    Uniquify(Vec<Diagnostic>),
+
+    /// Explicate found an operand that argument simplification should have
+    /// turned into a variable or literal, or a condition that is not a
+    /// boolean. Neither happens for well-typed programs.
+    Explicate(Diagnostic),
+
+    /// The final program could not be written out as assembly.
+    Emit(String),
+}
+
+impl CompileError {
+    /// Name of the pass that failed.
+    pub fn pass(&self) -> &'static str {
+        match self {
+            CompileError::Typecheck(_) => "typecheck",
+            CompileError::Uniquify(_) => "uniquify",
+            CompileError::Explicate(_) => "explicate",
+            CompileError::Emit(_) => "emit",
+        }
+    }
+
+    /// Diagnostics that point at the source of the error.
+    pub fn diagnostics(&self) -> Vec<&Diagnostic> {
+        match self {
+            CompileError::Typecheck(diagnostics) => diagnostics.iter().collect(),
+            CompileError::Uniquify(diagnostics) => diagnostics.iter().collect(),
+            CompileError::Explicate(diagnostic) => vec![diagnostic],
+            CompileError::Emit(_) => vec![],
+        }
+    }
+
+    /// Renders the error with the offending lines of `src` underlined.
+    pub fn render(&self, src: &str) -> String {
+        let diagnostics = self.diagnostics();
+        if diagnostics.is_empty() {
+            return format!("{}\n", self);
+        }
+        diagnostics
+            .iter()
+            .map(|d| format!("{}: {}", self.pass(), d.render(src)))
+            .collect()
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Emit(reason) => write!(f, "{}: {}", self.pass(), reason),
+            _ => {
+                let reasons = self
+                    .diagnostics()
+                    .iter()
+                    .map(|d| d.to_string())
+                    .collect::<Vec<String>>();
+                write!(f, "{}: {}", self.pass(), reasons.join("; "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
@@ -0,0 +1,56 @@
+//! Options that change the meaning or the output of a compiled program.
+
+/// What happens when integer arithmetic overflows 64 bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Results wrap around in two's complement. Dividing the most negative
+    /// integer by -1 is still an error, as `idivq` has no wrapped result.
+    #[default]
+    Wrapping,
+
+    /// The program stops with an integer overflow error.
+    Trapping,
+}
+
+impl std::str::FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrap" => Ok(Overflow::Wrapping),
+            "trap" => Ok(Overflow::Trapping),
+            _ => Err(format!("unknown overflow mode `{}`", s)),
+        }
+    }
+}
+
+/// How the types of a program are found.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Typing {
+    /// The program is type checked with the types it is annotated with.
+    #[default]
+    Static,
+
+    /// Every value has type `Any` and carries a tag that is checked when the
+    /// value is used. Annotations are ignored.
+    Dynamic,
+}
+
+impl std::str::FromStr for Typing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "static" => Ok(Typing::Static),
+            "dynamic" => Ok(Typing::Dynamic),
+            _ => Err(format!("unknown typing `{}`", s)),
+        }
+    }
+}
+
+/// Options shared by the interpreter and the compiler.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    pub overflow: Overflow,
+    pub typing: Typing,
+}
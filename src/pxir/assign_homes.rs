@@ -1,30 +1,65 @@
 use super::*;
 
+use super::build_interference::{build_interference, build_move_graph};
+use super::color_graph::color_graph;
+use super::uncover_live::uncover_live_program;
+
 struct Ctx {
     /// Space needed for stack variables in bytes.
     stack_space: i64,
 
-    /// Maps symbols to its storage location in the stack frame. Storage
-    /// location is represented as an offset in bytes from the base pointer.
-    sym_to_home: HashMap<Symbol, i64>,
+    /// Space needed for root stack variables in bytes.
+    root_stack_space: i64,
+
+    /// Space below `%rbp` taken by the callee-saved registers the frame
+    /// saves. Stack variables go below it.
+    saved_space: i64,
+
+    /// Variables that hold vectors, which get homes on the root stack when
+    /// they do not fit in registers.
+    vector_vars: HashSet<Symbol>,
+
+    /// Color of each variable, as `color_graph` gives it.
+    colors: HashMap<Symbol, usize>,
+
+    /// Maps the colors that did not fit in registers to their storage
+    /// location, either in the stack frame as an offset from the base pointer
+    /// or, for vector variables, on the root stack as an offset from the root
+    /// stack pointer in `%r15`. Variables of the same color that cannot share
+    /// a slot because of where they live get a slot each.
+    slots: HashMap<(usize, bool), Box<Arg>>,
 }
 
 impl Ctx {
-    fn new() -> Ctx {
+    fn new(colors: HashMap<Symbol, usize>, vector_vars: HashSet<Symbol>, saved_space: i64) -> Ctx {
         Ctx {
             stack_space: 0,
-            sym_to_home: HashMap::new(),
+            root_stack_space: 0,
+            saved_space,
+            vector_vars,
+            colors,
+            slots: HashMap::new(),
         }
     }
 
     fn get_home(&mut self, sym: &Symbol) -> Box<Arg> {
-        if let Some(offset) = self.sym_to_home.get(sym) {
-            return Arg::deref(Register::Rbp, *offset);
+        let color = self.colors[sym];
+        if let Some(reg) = ALLOCATABLE_REGISTERS.get(color) {
+            return Arg::reg(*reg);
         }
-        self.stack_space += 8;
-        let offset = -self.stack_space;
-        self.sym_to_home.insert(sym.clone(), offset);
-        Arg::deref(Register::Rbp, offset)
+        let on_root_stack = self.vector_vars.contains(sym);
+        if let Some(home) = self.slots.get(&(color, on_root_stack)) {
+            return home.clone();
+        }
+        let home = if on_root_stack {
+            self.root_stack_space += 8;
+            Arg::deref(Register::R15, -self.root_stack_space)
+        } else {
+            self.stack_space += 8;
+            Arg::deref(Register::Rbp, -(self.saved_space + self.stack_space))
+        };
+        self.slots.insert((color, on_root_stack), home.clone());
+        home
     }
 
     fn fold_arg(&mut self, arg: Box<Arg>) -> Box<Arg> {
@@ -38,37 +73,78 @@ impl Ctx {
         match instr {
             Instr::Addq { src, dst } => Instr::addq(self.fold_arg(src), self.fold_arg(dst)),
             Instr::Subq { src, dst } => Instr::subq(self.fold_arg(src), self.fold_arg(dst)),
+            Instr::Imulq { src, dst } => Instr::imulq(self.fold_arg(src), self.fold_arg(dst)),
+            Instr::Idivq(src) => Instr::idivq(self.fold_arg(src)),
+            Instr::Sbbq { src, dst } => Instr::sbbq(self.fold_arg(src), self.fold_arg(dst)),
+            Instr::Cmpq { src, dst } => Instr::cmpq(self.fold_arg(src), self.fold_arg(dst)),
+            Instr::Xorq { src, dst } => Instr::xorq(self.fold_arg(src), self.fold_arg(dst)),
+            Instr::Andq { src, dst } => Instr::andq(self.fold_arg(src), self.fold_arg(dst)),
+            Instr::Orq { src, dst } => Instr::orq(self.fold_arg(src), self.fold_arg(dst)),
+            Instr::Salq { src, dst } => Instr::salq(self.fold_arg(src), self.fold_arg(dst)),
+            Instr::Sarq { src, dst } => Instr::sarq(self.fold_arg(src), self.fold_arg(dst)),
+            Instr::Movzbq { src, dst } => Instr::movzbq(src, self.fold_arg(dst)),
             Instr::Movq { src, dst } => Instr::movq(self.fold_arg(src), self.fold_arg(dst)),
             Instr::Negq(dst) => Instr::negq(self.fold_arg(dst)),
+            Instr::Pushq(src) => Instr::pushq(self.fold_arg(src)),
+            Instr::IndirectCallq(f) => Instr::indirect_callq(self.fold_arg(f)),
+            Instr::Leaq { src, dst } => Instr::leaq(src, self.fold_arg(dst)),
             _ => instr,
         }
     }
-}
 
-fn fold_block(block: Block) -> Block {
-    let mut ctx = Ctx::new();
-    let instrs = block
-        .instrs
-        .into_iter()
-        .map(|i| ctx.fold_instr(i))
-        .collect();
+    fn fold_block(&mut self, block: Block) -> Block {
+        let instrs = block
+            .instrs
+            .into_iter()
+            .map(|i| self.fold_instr(i))
+            .collect();
 
-    Block {
-        info: BlockInfo {
-            stack_space: ctx.stack_space,
-        },
-        instrs,
+        Block { instrs }
     }
 }
 
-pub fn fold_program(program: Program) -> Program {
+/// Assigns homes to the variables of every block: a register from
+/// `ALLOCATABLE_REGISTERS` where the interference graph allows one, and
+/// otherwise a stack slot, shared by variables that do not interfere.
+/// Variables that are moved into one another get the same home where they
+/// can. Homes are assigned once for the whole program or function, so a
+/// variable keeps the same home in every block it appears in, and the size
+/// of the frame is recorded in the program's info. The callee-saved
+/// registers given out are recorded for the frame to save, and stack slots
+/// start below them.
+pub fn fold_program(mut program: Program) -> Program {
+    let live = uncover_live_program(&program);
+    let graph = build_interference(&program, &live);
+    let moves = build_move_graph(&program);
+    let colors = color_graph(&graph, &moves, &ALLOCATABLE_REGISTERS);
+    let callee_saved = CALLEE_SAVED_REGISTERS
+        .iter()
+        .copied()
+        .filter(|reg| {
+            colors
+                .values()
+                .any(|&color| ALLOCATABLE_REGISTERS.get(color) == Some(reg))
+        })
+        .collect::<Vec<Register>>();
+    let saved_space = 8 * callee_saved.len() as i64;
+    let mut ctx = Ctx::new(colors, program.info.vector_vars.clone(), saved_space);
+    let labels = program
+        .labels()
+        .into_iter()
+        .cloned()
+        .collect::<Vec<Label>>();
     let mut blocks = HashMap::new();
-    for (label, block) in program.blocks {
-        let block = fold_block(block);
-        blocks.insert(label, block);
+    for label in labels {
+        let block = program.blocks.remove(&label).unwrap();
+        blocks.insert(label, ctx.fold_block(block));
     }
     Program {
-        info: program.info,
+        info: ProgramInfo {
+            root_stack_space: ctx.root_stack_space,
+            stack_space: ctx.stack_space,
+            callee_saved,
+            ..program.info
+        },
         blocks,
     }
 }
@@ -76,7 +152,17 @@ pub fn fold_program(program: Program) -> Program {
 #[cfg(test)]
 mod tests {
     use super::super::*;
-    use super::fold_block;
+    use super::fold_program;
+
+    fn start_program(instrs: Vec<Instr>, vector_vars: &[&str]) -> Program {
+        let mut blocks = HashMap::new();
+        blocks.insert(*Label::new("start"), Block::new(instrs));
+        let info = ProgramInfo {
+            vector_vars: vector_vars.iter().map(|s| Symbol::new(s)).collect(),
+            ..ProgramInfo::default()
+        };
+        Program { info, blocks }
+    }
 
     #[test]
     fn basic_add_and_neg() {
@@ -85,49 +171,140 @@ mod tests {
             Instr::negq(Arg::var("v200000")),
             Instr::movq(Arg::int(52), Arg::reg(Register::Rax)),
             Instr::addq(Arg::var("v200000"), Arg::reg(Register::Rax)),
-            Instr::jumpq("basic_add_and_neg_conclusion"),
+            Instr::jumpq("conclusion"),
         ];
-        let block = Block {
-            info: BlockInfo::default(),
-            instrs,
-        };
         let expected_instrs = vec![
-            Instr::movq(Arg::int(10), Arg::deref(Register::Rbp, -8)),
-            Instr::negq(Arg::deref(Register::Rbp, -8)),
+            Instr::movq(Arg::int(10), Arg::reg(Register::Rcx)),
+            Instr::negq(Arg::reg(Register::Rcx)),
             Instr::movq(Arg::int(52), Arg::reg(Register::Rax)),
-            Instr::addq(Arg::deref(Register::Rbp, -8), Arg::reg(Register::Rax)),
-            Instr::jumpq("basic_add_and_neg_conclusion"),
+            Instr::addq(Arg::reg(Register::Rcx), Arg::reg(Register::Rax)),
+            Instr::jumpq("conclusion"),
         ];
-        let actual = fold_block(block);
-        assert_eq!(actual.instrs, expected_instrs);
-        assert_eq!(actual.info.stack_space, 8);
+        let program = fold_program(start_program(instrs, &[]));
+        let block = &program.blocks[&*Label::new("start")];
+        assert_eq!(block.instrs, expected_instrs);
+        assert_eq!(program.info.stack_space, 0);
     }
 
     #[test]
-    fn moves_and_neg() {
+    fn interfering_get_different_registers() {
         let instrs = vec![
             Instr::movq(Arg::int(10), Arg::var("x.1")),
-            Instr::negq(Arg::var("x.1")),
-            Instr::movq(Arg::var("x.1"), Arg::var("x.2")),
             Instr::movq(Arg::int(52), Arg::var("x.2")),
-            Instr::movq(Arg::var("x.2"), Arg::reg(Register::Rax)),
+            Instr::addq(Arg::var("x.1"), Arg::var("x.2")),
+            Instr::movq(Arg::var("x.2"), Arg::var("x.3")),
+            Instr::movq(Arg::var("x.3"), Arg::reg(Register::Rax)),
         ];
-        let block = Block {
-            info: BlockInfo::default(),
-            instrs,
-        };
         let expected_instrs = vec![
-            Instr::movq(Arg::int(10), Arg::deref(Register::Rbp, -8)),
-            Instr::negq(Arg::deref(Register::Rbp, -8)),
-            Instr::movq(
-                Arg::deref(Register::Rbp, -8),
-                Arg::deref(Register::Rbp, -16),
-            ),
-            Instr::movq(Arg::int(52), Arg::deref(Register::Rbp, -16)),
-            Instr::movq(Arg::deref(Register::Rbp, -16), Arg::reg(Register::Rax)),
+            Instr::movq(Arg::int(10), Arg::reg(Register::Rcx)),
+            Instr::movq(Arg::int(52), Arg::reg(Register::Rdx)),
+            Instr::addq(Arg::reg(Register::Rcx), Arg::reg(Register::Rdx)),
+            // x.3 is moved from x.2, so it shares its register.
+            Instr::movq(Arg::reg(Register::Rdx), Arg::reg(Register::Rdx)),
+            Instr::movq(Arg::reg(Register::Rdx), Arg::reg(Register::Rax)),
         ];
-        let actual = fold_block(block);
-        assert_eq!(actual.instrs, expected_instrs);
-        assert_eq!(actual.info.stack_space, 16);
+        let program = fold_program(start_program(instrs, &[]));
+        assert_eq!(
+            program.blocks[&*Label::new("start")].instrs,
+            expected_instrs
+        );
+    }
+
+    #[test]
+    fn shared_across_blocks() {
+        let mut blocks = HashMap::new();
+        blocks.insert(
+            *Label::new("start"),
+            Block::new(vec![
+                Instr::movq(Arg::int(1), Arg::var("x")),
+                Instr::jumpq("block_1"),
+            ]),
+        );
+        blocks.insert(
+            *Label::new("block_1"),
+            Block::new(vec![
+                Instr::movq(Arg::int(2), Arg::var("y")),
+                Instr::addq(Arg::var("y"), Arg::var("x")),
+                Instr::movq(Arg::var("x"), Arg::reg(Register::Rax)),
+            ]),
+        );
+        let program = fold_program(Program {
+            info: ProgramInfo::default(),
+            blocks,
+        });
+        let expected_instrs = vec![
+            Instr::movq(Arg::int(2), Arg::reg(Register::Rdx)),
+            Instr::addq(Arg::reg(Register::Rdx), Arg::reg(Register::Rcx)),
+            Instr::movq(Arg::reg(Register::Rcx), Arg::reg(Register::Rax)),
+        ];
+        assert_eq!(
+            program.blocks[&*Label::new("block_1")].instrs,
+            expected_instrs
+        );
+        assert_eq!(
+            program.blocks[&*Label::new("start")].instrs[0],
+            Instr::movq(Arg::int(1), Arg::reg(Register::Rcx))
+        );
+    }
+
+    #[test]
+    fn across_calls() {
+        let instrs = vec![
+            Instr::movq(Arg::int(1), Arg::var("x")),
+            Instr::movq(Arg::int(2), Arg::var("v")),
+            Instr::callq("read_int"),
+            Instr::movq(Arg::reg(Register::Rax), Arg::var("y")),
+            Instr::addq(Arg::var("v"), Arg::var("y")),
+            Instr::addq(Arg::var("x"), Arg::var("y")),
+            Instr::movq(Arg::var("y"), Arg::reg(Register::Rax)),
+        ];
+        // x is kept in a callee-saved register and the vector v on the root
+        // stack.
+        let expected_instrs = vec![
+            Instr::movq(Arg::int(1), Arg::reg(Register::Rbx)),
+            Instr::movq(Arg::int(2), Arg::deref(Register::R15, -8)),
+            Instr::callq("read_int"),
+            Instr::movq(Arg::reg(Register::Rax), Arg::reg(Register::Rcx)),
+            Instr::addq(Arg::deref(Register::R15, -8), Arg::reg(Register::Rcx)),
+            Instr::addq(Arg::reg(Register::Rbx), Arg::reg(Register::Rcx)),
+            Instr::movq(Arg::reg(Register::Rcx), Arg::reg(Register::Rax)),
+        ];
+        let program = fold_program(start_program(instrs, &["v"]));
+        let block = &program.blocks[&*Label::new("start")];
+        assert_eq!(block.instrs, expected_instrs);
+        assert_eq!(program.info.stack_space, 0);
+        assert_eq!(program.info.root_stack_space, 8);
+        assert_eq!(program.info.callee_saved, vec![Register::Rbx]);
+    }
+
+    #[test]
+    fn spills_when_registers_run_out() {
+        let count = ALLOCATABLE_REGISTERS.len() + 2;
+        let names = (0..count).map(|i| format!("x{}", i)).collect::<Vec<_>>();
+        let mut instrs = names
+            .iter()
+            .map(|name| Instr::movq(Arg::int(1), Arg::var(name)))
+            .collect::<Vec<Instr>>();
+        instrs.extend(
+            names
+                .iter()
+                .map(|name| Instr::addq(Arg::var(name), Arg::reg(Register::Rax))),
+        );
+        let program = fold_program(start_program(instrs, &[]));
+        let block = &program.blocks[&*Label::new("start")];
+        let homes = block.instrs[..count]
+            .iter()
+            .map(|instr| match instr {
+                Instr::Movq { dst, .. } => *dst.clone(),
+                _ => unreachable!(),
+            })
+            .collect::<HashSet<Arg>>();
+        assert_eq!(homes.len(), count);
+        // Every callee-saved register is used, so the slots are below them.
+        let saved = vec![Register::Rbx, Register::R12, Register::R13, Register::R14];
+        assert_eq!(program.info.callee_saved, saved);
+        assert!(homes.contains(&Arg::Deref(Register::Rbp, -40)));
+        assert!(homes.contains(&Arg::Deref(Register::Rbp, -48)));
+        assert_eq!(program.info.stack_space, 16);
     }
 }
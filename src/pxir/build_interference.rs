@@ -0,0 +1,285 @@
+use super::uncover_live::{regs_written, uncover_live_regs, vars_read, vars_written};
+use super::*;
+
+/// A vertex of the interference graph: a variable, or one of the
+/// `ALLOCATABLE_REGISTERS` that an instruction uses directly.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Location {
+    Var(Symbol),
+    Reg(Register),
+}
+
+/// Undirected graph joining locations that hold different values at the same
+/// time, so that a variable is never given the home of a location it is
+/// joined to.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Graph {
+    adjacent: HashMap<Location, HashSet<Location>>,
+}
+
+impl Graph {
+    pub fn add_vertex(&mut self, loc: Location) {
+        self.adjacent.entry(loc).or_default();
+    }
+
+    /// Joins the two locations. A location never interferes with itself.
+    pub fn add_edge(&mut self, a: Location, b: Location) {
+        if a == b {
+            return;
+        }
+        self.adjacent
+            .entry(a.clone())
+            .or_default()
+            .insert(b.clone());
+        self.adjacent.entry(b).or_default().insert(a);
+    }
+
+    pub fn interferes(&self, a: &Location, b: &Location) -> bool {
+        self.adjacent.get(a).is_some_and(|adj| adj.contains(b))
+    }
+
+    pub fn neighbors<'a>(&'a self, loc: &Location) -> impl Iterator<Item = &'a Location> {
+        self.adjacent.get(loc).into_iter().flatten()
+    }
+
+    /// Variables in the graph, including those that interfere with nothing.
+    pub fn vars(&self) -> impl Iterator<Item = &Symbol> {
+        self.adjacent.keys().filter_map(|loc| match loc {
+            Location::Var(sym) => Some(sym),
+            Location::Reg(_) => None,
+        })
+    }
+}
+
+fn is_allocatable(reg: Register) -> bool {
+    ALLOCATABLE_REGISTERS.contains(&reg)
+}
+
+/// Location of the graph the operand is, if it is a variable or an
+/// allocatable register.
+fn location(arg: &Arg) -> Option<Location> {
+    match arg {
+        Arg::Var(sym) => Some(Location::Var(*sym.clone())),
+        Arg::Reg(reg) if is_allocatable(*reg) => Some(Location::Reg(*reg)),
+        _ => None,
+    }
+}
+
+/// Location a `movq` copies from. The destination of a move need not
+/// interfere with its source, since after the move both hold the same value.
+fn move_source(instr: &Instr) -> Option<Location> {
+    match instr {
+        Instr::Movq { src, .. } => location(src),
+        _ => None,
+    }
+}
+
+/// Builds the interference graph of the program from the variables live
+/// after each instruction, as `uncover_live::uncover_live_program` finds
+/// them and the registers live after each instruction, as
+/// `uncover_live::uncover_live_regs` finds them. A location written by an
+/// instruction interferes with every variable live after it, and a variable
+/// written interferes with every register live after it, except for the
+/// source of a `movq`. Calls overwrite the caller-saved registers, so a
+/// variable live across one is kept in a callee-saved register or on the
+/// stack.
+pub fn build_interference(program: &Program, live: &HashMap<Label, Vec<HashSet<Symbol>>>) -> Graph {
+    let mut graph = Graph::default();
+    for label in program.labels() {
+        let block = &program.blocks[label];
+        let live_vars = &live[label][1..];
+        for ((instr, vars), regs) in block
+            .instrs
+            .iter()
+            .zip(live_vars)
+            .zip(uncover_live_regs(block))
+        {
+            for sym in vars_read(instr).into_iter().chain(vars_written(instr)) {
+                graph.add_vertex(Location::Var(sym));
+            }
+            let src = move_source(instr);
+            let written = vars_written(instr)
+                .into_iter()
+                .map(Location::Var)
+                .chain(
+                    regs_written(instr)
+                        .into_iter()
+                        .filter(|reg| is_allocatable(*reg))
+                        .map(Location::Reg),
+                )
+                .collect::<HashSet<Location>>();
+            // The collector moves vectors only through the root stack, so a
+            // vector variable live across a call, which may collect, gets no
+            // register at all.
+            if let Instr::Callq(_) | Instr::IndirectCallq(_) = instr {
+                for sym in vars.intersection(&program.info.vector_vars) {
+                    for reg in ALLOCATABLE_REGISTERS.iter() {
+                        graph.add_edge(Location::Var(sym.clone()), Location::Reg(*reg));
+                    }
+                }
+            }
+            for dst in &written {
+                for sym in vars {
+                    let loc = Location::Var(sym.clone());
+                    if Some(&loc) != src.as_ref() {
+                        graph.add_edge(dst.clone(), loc);
+                    }
+                }
+                if let Location::Var(_) = dst {
+                    for reg in regs.iter().filter(|reg| is_allocatable(**reg)) {
+                        let loc = Location::Reg(*reg);
+                        if Some(&loc) != src.as_ref() {
+                            graph.add_edge(dst.clone(), loc);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    graph
+}
+
+/// Builds the graph joining the locations that a `movq` copies between, at
+/// least one of them a variable. Giving both ends of a move the same home
+/// turns it into a self-move, which `patch` deletes.
+pub fn build_move_graph(program: &Program) -> Graph {
+    let mut graph = Graph::default();
+    for block in program.blocks.values() {
+        for instr in &block.instrs {
+            if let Instr::Movq { src, dst } = instr {
+                match (location(src), location(dst)) {
+                    (Some(Location::Reg(_)), Some(Location::Reg(_))) => {}
+                    (Some(a), Some(b)) => graph.add_edge(a, b),
+                    _ => {}
+                }
+            }
+        }
+    }
+    graph
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::uncover_live::uncover_live_program;
+    use super::super::*;
+    use super::{build_interference, build_move_graph, Graph, Location};
+
+    fn var(name: &str) -> Location {
+        Location::Var(Symbol::new(name))
+    }
+
+    fn graph_of(instrs: Vec<Instr>) -> Graph {
+        let mut blocks = HashMap::new();
+        blocks.insert(*Label::new("start"), Block::new(instrs));
+        let program = Program {
+            info: ProgramInfo::default(),
+            blocks,
+        };
+        build_interference(&program, &uncover_live_program(&program))
+    }
+
+    #[test]
+    fn variables() {
+        let graph = graph_of(vec![
+            Instr::movq(Arg::int(1), Arg::var("v")),
+            Instr::movq(Arg::int(46), Arg::var("w")),
+            Instr::movq(Arg::var("v"), Arg::var("x")),
+            Instr::addq(Arg::int(7), Arg::var("x")),
+            Instr::movq(Arg::var("x"), Arg::var("y")),
+            Instr::addq(Arg::var("w"), Arg::var("y")),
+            Instr::movq(Arg::var("y"), Arg::reg(Register::Rax)),
+            Instr::jumpq("conclusion"),
+        ]);
+        assert!(graph.interferes(&var("v"), &var("w")));
+        assert!(graph.interferes(&var("x"), &var("w")));
+        assert!(graph.interferes(&var("y"), &var("w")));
+        // Each is moved into the next and is not read again.
+        assert!(!graph.interferes(&var("v"), &var("x")));
+        assert!(!graph.interferes(&var("x"), &var("y")));
+        assert_eq!(graph.vars().count(), 4);
+    }
+
+    #[test]
+    fn registers() {
+        let graph = graph_of(vec![
+            Instr::movq(Arg::reg(Register::Rdi), Arg::var("x")),
+            Instr::movq(Arg::reg(Register::Rsi), Arg::var("y")),
+            Instr::movq(Arg::var("x"), Arg::reg(Register::Rax)),
+            Instr::cqto(),
+            Instr::idivq(Arg::var("y")),
+            Instr::movq(Arg::var("y"), Arg::reg(Register::Rdi)),
+            Instr::callq("print_int"),
+            Instr::jumpq("conclusion"),
+        ]);
+        let rsi = Location::Reg(Register::Rsi);
+        let rdx = Location::Reg(Register::Rdx);
+        // `%rsi` is still to be read when x is written.
+        assert!(graph.interferes(&var("x"), &rsi));
+        assert!(!graph.interferes(&var("y"), &rsi));
+        // `cqto` overwrites `%rdx` while y is live.
+        assert!(graph.interferes(&var("y"), &rdx));
+        assert!(!graph.interferes(&var("x"), &rdx));
+    }
+
+    #[test]
+    fn calls() {
+        let graph = graph_of(vec![
+            Instr::movq(Arg::int(1), Arg::var("x")),
+            Instr::callq("read_int"),
+            Instr::movq(Arg::reg(Register::Rax), Arg::var("y")),
+            Instr::addq(Arg::var("x"), Arg::var("y")),
+            Instr::jumpq("conclusion"),
+        ]);
+        for reg in ALLOCATABLE_REGISTERS.iter() {
+            let saved = CALLEE_SAVED_REGISTERS.contains(reg);
+            assert_eq!(graph.interferes(&var("x"), &Location::Reg(*reg)), !saved);
+            assert!(!graph.interferes(&var("y"), &Location::Reg(*reg)));
+        }
+    }
+
+    #[test]
+    fn vectors_across_calls() {
+        let mut blocks = HashMap::new();
+        blocks.insert(
+            *Label::new("start"),
+            Block::new(vec![
+                Instr::movq(Arg::int(1), Arg::var("v")),
+                Instr::callq("collect"),
+                Instr::movq(Arg::var("v"), Arg::reg(Register::Rax)),
+            ]),
+        );
+        let mut program = Program {
+            info: ProgramInfo::default(),
+            blocks,
+        };
+        program.info.vector_vars.insert(Symbol::new("v"));
+        let graph = build_interference(&program, &uncover_live_program(&program));
+        for reg in ALLOCATABLE_REGISTERS.iter() {
+            assert!(graph.interferes(&var("v"), &Location::Reg(*reg)));
+        }
+    }
+
+    #[test]
+    fn moves() {
+        let mut blocks = HashMap::new();
+        blocks.insert(
+            *Label::new("start"),
+            Block::new(vec![
+                Instr::movq(Arg::reg(Register::Rdi), Arg::var("x")),
+                Instr::movq(Arg::var("x"), Arg::var("y")),
+                Instr::movq(Arg::int(1), Arg::var("z")),
+                Instr::movq(Arg::var("y"), Arg::reg(Register::Rax)),
+            ]),
+        );
+        let program = Program {
+            info: ProgramInfo::default(),
+            blocks,
+        };
+        let graph = build_move_graph(&program);
+        assert!(graph.interferes(&var("x"), &Location::Reg(Register::Rdi)));
+        assert!(graph.interferes(&var("x"), &var("y")));
+        // `%rax` is never given to a variable.
+        assert_eq!(graph.neighbors(&var("y")).count(), 1);
+        assert_eq!(graph.neighbors(&var("z")).count(), 0);
+    }
+}
@@ -0,0 +1,136 @@
+use super::build_interference::{Graph, Location};
+use super::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Colors the variables of the graph so that no two adjacent locations share
+/// a color, with the DSatur heuristic: the next variable colored is the one
+/// adjacent to the most distinct colors, then the one with the most
+/// neighbors, and it takes the lowest color none of its neighbors has.
+///
+/// Color `i` stands for `registers[i]` when there is such a register, and a
+/// register in the graph has its color, so a variable never gets the color
+/// of a register it interferes with. Higher colors stand for stack slots.
+///
+/// A variable takes the color of a location it is joined to in `moves`
+/// instead, when that color is free and is a register or no register is
+/// free, so that the move between them can be deleted.
+pub fn color_graph(graph: &Graph, moves: &Graph, registers: &[Register]) -> HashMap<Symbol, usize> {
+    let reg_color = |reg: &Register| registers.iter().position(|r| r == reg);
+    // Variables are numbered in order of their names, so that ties go to the
+    // lowest number.
+    let mut vars = graph.vars().cloned().collect::<Vec<Symbol>>();
+    vars.sort_by(|a, b| a.value.cmp(&b.value));
+    let index = vars
+        .iter()
+        .enumerate()
+        .map(|(i, sym)| (sym, i))
+        .collect::<HashMap<&Symbol, usize>>();
+    let mut adjacent = vec![vec![]; vars.len()];
+    let mut related_vars: Vec<Vec<usize>> = vec![vec![]; vars.len()];
+    let mut related_colors = vec![vec![]; vars.len()];
+    let mut saturation = vec![HashSet::new(); vars.len()];
+    let mut degree = vec![0; vars.len()];
+    for (i, sym) in vars.iter().enumerate() {
+        let loc = Location::Var(sym.clone());
+        for neighbor in graph.neighbors(&loc) {
+            degree[i] += 1;
+            match neighbor {
+                Location::Var(n) => adjacent[i].push(index[n]),
+                Location::Reg(reg) => saturation[i].extend(reg_color(reg)),
+            }
+        }
+        for neighbor in moves.neighbors(&loc) {
+            match neighbor {
+                Location::Var(n) => related_vars[i].extend(index.get(n).copied()),
+                Location::Reg(reg) => related_colors[i].extend(reg_color(reg)),
+            }
+        }
+    }
+    // Uncolored variables by priority. A variable is pushed again whenever
+    // its saturation grows, and the entries it leaves behind are skipped.
+    let mut queue = (0..vars.len())
+        .map(|i| (saturation[i].len(), degree[i], Reverse(i)))
+        .collect::<BinaryHeap<_>>();
+    let mut colors: Vec<Option<usize>> = vec![None; vars.len()];
+    while let Some((len, _, Reverse(i))) = queue.pop() {
+        if colors[i].is_some() || len != saturation[i].len() {
+            continue;
+        }
+        let taken = &saturation[i];
+        let lowest = (0..).find(|c| !taken.contains(c)).unwrap();
+        let related = related_vars[i]
+            .iter()
+            .filter_map(|&n| colors[n])
+            .chain(related_colors[i].iter().copied())
+            .filter(|c| !taken.contains(c))
+            .min();
+        let color = match related {
+            Some(c) if c < registers.len() || lowest >= registers.len() => c,
+            _ => lowest,
+        };
+        colors[i] = Some(color);
+        for &n in &adjacent[i] {
+            if colors[n].is_none() && saturation[n].insert(color) {
+                queue.push((saturation[n].len(), degree[n], Reverse(n)));
+            }
+        }
+    }
+    vars.into_iter()
+        .zip(colors)
+        .map(|(sym, color)| (sym, color.unwrap()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::build_interference::{Graph, Location};
+    use super::super::*;
+    use super::color_graph;
+
+    fn var(name: &str) -> Location {
+        Location::Var(Symbol::new(name))
+    }
+
+    #[test]
+    fn triangle() {
+        let mut graph = Graph::default();
+        graph.add_edge(var("x"), var("y"));
+        graph.add_edge(var("y"), var("z"));
+        graph.add_edge(var("x"), var("z"));
+        graph.add_vertex(var("w"));
+        let colors = color_graph(&graph, &Graph::default(), &[Register::Rcx, Register::Rdx]);
+        assert_ne!(colors[&Symbol::new("x")], colors[&Symbol::new("y")]);
+        assert_ne!(colors[&Symbol::new("y")], colors[&Symbol::new("z")]);
+        assert_ne!(colors[&Symbol::new("x")], colors[&Symbol::new("z")]);
+        // Only two registers, so one of the three is spilled.
+        assert_eq!(colors.values().filter(|&&c| c >= 2).count(), 1);
+        assert_eq!(colors[&Symbol::new("w")], 0);
+    }
+
+    #[test]
+    fn avoids_registers() {
+        let mut graph = Graph::default();
+        graph.add_edge(var("x"), Location::Reg(Register::Rcx));
+        graph.add_edge(var("x"), var("y"));
+        let colors = color_graph(&graph, &Graph::default(), &[Register::Rcx, Register::Rdx]);
+        assert_eq!(colors[&Symbol::new("x")], 1);
+        assert_eq!(colors[&Symbol::new("y")], 0);
+    }
+
+    #[test]
+    fn prefers_move_related() {
+        let mut graph = Graph::default();
+        graph.add_edge(var("x"), var("y"));
+        graph.add_edge(var("x"), Location::Reg(Register::Rcx));
+        graph.add_vertex(var("z"));
+        let mut moves = Graph::default();
+        moves.add_edge(var("x"), var("z"));
+        moves.add_edge(var("y"), Location::Reg(Register::Rsi));
+        let registers = [Register::Rcx, Register::Rdx, Register::Rsi];
+        let colors = color_graph(&graph, &moves, &registers);
+        assert_eq!(colors[&Symbol::new("y")], 2);
+        assert_eq!(colors[&Symbol::new("z")], colors[&Symbol::new("x")]);
+        assert_eq!(colors[&Symbol::new("x")], 1);
+    }
+}
@@ -1,13 +1,15 @@
 // ! PXIR (Pseudo-x86 Intermediate Representation)
 
 pub mod assign_homes;
+pub mod build_interference;
+pub mod color_graph;
 pub mod patch;
 pub mod uncover_live;
 mod write;
 
-pub use write::write_block;
+pub use write::{write_block, write_block_live};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Register {
@@ -29,6 +31,59 @@ pub enum Register {
     R15,
 }
 
+/// Registers that pass the first arguments of a call, in order. Further
+/// arguments are pushed onto the stack, the last first.
+pub const ARG_REGISTERS: [Register; 6] = [
+    Register::Rdi,
+    Register::Rsi,
+    Register::Rdx,
+    Register::Rcx,
+    Register::R8,
+    Register::R9,
+];
+
+/// Registers that variables are assigned to, in the order the allocator
+/// prefers them. `%rax` and `%r11` are left to instruction selection and
+/// patching as scratch registers, `%r15` holds the root stack pointer, and
+/// `%rsp` and `%rbp` hold the frame.
+pub const ALLOCATABLE_REGISTERS: [Register; 11] = [
+    Register::Rcx,
+    Register::Rdx,
+    Register::Rsi,
+    Register::Rdi,
+    Register::R8,
+    Register::R9,
+    Register::R10,
+    Register::Rbx,
+    Register::R12,
+    Register::R13,
+    Register::R14,
+];
+
+/// Registers a call may overwrite. A function that needs a value after a
+/// call keeps it elsewhere.
+pub const CALLER_SAVED_REGISTERS: [Register; 9] = [
+    Register::Rax,
+    Register::Rcx,
+    Register::Rdx,
+    Register::Rsi,
+    Register::Rdi,
+    Register::R8,
+    Register::R9,
+    Register::R10,
+    Register::R11,
+];
+
+/// Registers a function must leave as it found them. A function that uses
+/// them saves them on entry and restores them on exit.
+pub const CALLEE_SAVED_REGISTERS: [Register; 5] = [
+    Register::Rbx,
+    Register::R12,
+    Register::R13,
+    Register::R14,
+    Register::R15,
+];
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Symbol {
     pub value: String,
@@ -46,7 +101,11 @@ impl Symbol {
 pub enum Arg {
     Int(i64),
     Reg(Register),
+    /// The low byte of a register, such as `%al` for `%rax`.
+    ByteReg(Register),
     Deref(Register, i64),
+    /// A global variable, addressed relative to the instruction pointer.
+    Global(String),
     Var(Box<Symbol>),
 }
 
@@ -59,19 +118,36 @@ impl Arg {
         Box::new(Arg::Reg(reg))
     }
 
+    pub fn byte_reg(reg: Register) -> Box<Arg> {
+        Box::new(Arg::ByteReg(reg))
+    }
+
     pub fn deref(reg: Register, offset: i64) -> Box<Arg> {
         Box::new(Arg::Deref(reg, offset))
     }
 
+    pub fn global(name: &str) -> Box<Arg> {
+        Box::new(Arg::Global(name.to_string()))
+    }
+
     pub fn var(s: &str) -> Box<Arg> {
         Box::new(Arg::Var(Box::new(Symbol::new(s))))
     }
 
-    pub fn is_dref(&self) -> bool {
-        match self {
-            Arg::Deref(_, _) => true,
-            _ => false,
-        }
+    /// Whether the argument is in memory. An instruction can have at most one
+    /// memory operand.
+    pub fn is_mem(&self) -> bool {
+        matches!(self, Arg::Deref(_, _) | Arg::Global(_))
+    }
+
+    pub fn is_int(&self) -> bool {
+        matches!(self, Arg::Int(_))
+    }
+
+    /// Whether the argument is an immediate that does not fit in the signed
+    /// 32 bits that most instructions can encode.
+    pub fn is_wide_int(&self) -> bool {
+        matches!(self, Arg::Int(i) if *i < i64::from(i32::MIN) || *i > i64::from(i32::MAX))
     }
 }
 
@@ -88,16 +164,104 @@ impl Label {
     }
 }
 
+/// Condition tested by a conditional jump.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CondCode {
+    /// The last arithmetic instruction overflowed.
+    O,
+    /// The operands of the last comparison were equal.
+    E,
+    /// The operands of the last comparison were not equal.
+    Ne,
+    /// The destination of the last comparison was less than its source.
+    L,
+    /// The destination of the last comparison was less than or equal to its
+    /// source.
+    Le,
+    /// The destination of the last comparison was greater than its source.
+    G,
+    /// The destination of the last comparison was greater than or equal to
+    /// its source.
+    Ge,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Instr {
-    Addq { src: Box<Arg>, dst: Box<Arg> },
-    Subq { src: Box<Arg>, dst: Box<Arg> },
-    Movq { src: Box<Arg>, dst: Box<Arg> },
+    Addq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
+    Subq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
+    Imulq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
+    /// Sign-extends `%rax` into `%rdx`.
+    Cqto,
+    /// Divides `%rdx:%rax` by the operand, leaving the quotient in `%rax` and
+    /// the remainder in `%rdx`.
+    Idivq(Box<Arg>),
+    /// Subtracts the source and the carry flag from the destination.
+    Sbbq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
+    /// Sets the flags by subtracting the source from the destination.
+    Cmpq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
+    Xorq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
+    Andq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
+    Orq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
+    /// Shifts the destination left by the number of bits the source gives.
+    Salq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
+    /// Shifts the destination right by the number of bits the source gives,
+    /// copying the sign bit into the bits shifted in.
+    Sarq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
+    /// Sets the byte operand to 1 if the condition holds and to 0 otherwise.
+    Set(CondCode, Box<Arg>),
+    /// Moves a byte into a register, filling the upper bytes with zeros.
+    Movzbq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
+    Movq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
     Negq(Box<Arg>),
     Pushq(Box<Arg>),
     Popq(Box<Arg>),
     Callq(Box<Label>),
+    /// Calls the function whose address the operand holds.
+    IndirectCallq(Box<Arg>),
+    /// Loads the address of the source, such as a function's `name(%rip)`,
+    /// into the destination.
+    Leaq {
+        src: Box<Arg>,
+        dst: Box<Arg>,
+    },
     Jumpq(Box<Label>),
+    JumpIf(CondCode, Box<Label>),
     Retq,
 }
 
@@ -110,6 +274,54 @@ impl Instr {
         Instr::Subq { src, dst }
     }
 
+    pub fn imulq(src: Box<Arg>, dst: Box<Arg>) -> Instr {
+        Instr::Imulq { src, dst }
+    }
+
+    pub fn cqto() -> Instr {
+        Instr::Cqto
+    }
+
+    pub fn idivq(src: Box<Arg>) -> Instr {
+        Instr::Idivq(src)
+    }
+
+    pub fn sbbq(src: Box<Arg>, dst: Box<Arg>) -> Instr {
+        Instr::Sbbq { src, dst }
+    }
+
+    pub fn cmpq(src: Box<Arg>, dst: Box<Arg>) -> Instr {
+        Instr::Cmpq { src, dst }
+    }
+
+    pub fn xorq(src: Box<Arg>, dst: Box<Arg>) -> Instr {
+        Instr::Xorq { src, dst }
+    }
+
+    pub fn andq(src: Box<Arg>, dst: Box<Arg>) -> Instr {
+        Instr::Andq { src, dst }
+    }
+
+    pub fn orq(src: Box<Arg>, dst: Box<Arg>) -> Instr {
+        Instr::Orq { src, dst }
+    }
+
+    pub fn salq(src: Box<Arg>, dst: Box<Arg>) -> Instr {
+        Instr::Salq { src, dst }
+    }
+
+    pub fn sarq(src: Box<Arg>, dst: Box<Arg>) -> Instr {
+        Instr::Sarq { src, dst }
+    }
+
+    pub fn set(cc: CondCode, dst: Box<Arg>) -> Instr {
+        Instr::Set(cc, dst)
+    }
+
+    pub fn movzbq(src: Box<Arg>, dst: Box<Arg>) -> Instr {
+        Instr::Movzbq { src, dst }
+    }
+
     pub fn movq(src: Box<Arg>, dst: Box<Arg>) -> Instr {
         Instr::Movq { src, dst }
     }
@@ -126,10 +338,22 @@ impl Instr {
         Instr::Callq(Label::new(label))
     }
 
+    pub fn indirect_callq(f: Box<Arg>) -> Instr {
+        Instr::IndirectCallq(f)
+    }
+
+    pub fn leaq(src: Box<Arg>, dst: Box<Arg>) -> Instr {
+        Instr::Leaq { src, dst }
+    }
+
     pub fn jumpq(label: &str) -> Instr {
         Instr::Jumpq(Label::new(label))
     }
 
+    pub fn jump_if(cc: CondCode, label: &str) -> Instr {
+        Instr::JumpIf(cc, Label::new(label))
+    }
+
     pub fn pushq(dst: Box<Arg>) -> Instr {
         Instr::Pushq(dst)
     }
@@ -139,38 +363,50 @@ impl Instr {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct BlockInfo {
-    /// Space needed for stack variables in bytes.
-    pub stack_space: i64,
-}
-
-impl Default for BlockInfo {
-    fn default() -> Self {
-        BlockInfo { stack_space: 0 }
-    }
-}
-
 #[derive(Clone, Debug)]
 pub struct Block {
-    pub info: BlockInfo,
     pub instrs: Vec<Instr>,
 }
 
 impl Block {
     pub fn new(instrs: Vec<Instr>) -> Block {
-        Block {
-            info: BlockInfo::default(),
-            instrs,
-        }
+        Block { instrs }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct ProgramInfo {}
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProgramInfo {
+    /// Variables that hold vectors, or `Any`s that may hold vectors. They
+    /// live on the root stack, where the garbage collector finds and updates
+    /// them.
+    pub vector_vars: HashSet<Symbol>,
+
+    /// Space needed for variables on the root stack in bytes.
+    pub root_stack_space: i64,
+
+    /// Space needed for variables in the stack frame in bytes, below the
+    /// saved callee-saved registers. It is the same for every block of the
+    /// program.
+    pub stack_space: i64,
+
+    /// Callee-saved registers that variables were assigned to, in the order
+    /// they are pushed below the saved `%rbp`.
+    pub callee_saved: Vec<Register>,
+}
 
 #[derive(Clone, Debug)]
 pub struct Program {
     pub info: ProgramInfo,
     pub blocks: HashMap<Label, Block>,
 }
+
+impl Program {
+    /// Labels of the program's blocks with the start block, `start` or
+    /// `NAME_start` for a function, first and the rest ordered by label, so
+    /// that passes and output are deterministic.
+    pub fn labels(&self) -> Vec<&Label> {
+        let mut labels = self.blocks.keys().collect::<Vec<&Label>>();
+        labels.sort_by_key(|l| (l.value != "start" && !l.value.ends_with("_start"), &l.value));
+        labels
+    }
+}
@@ -3,7 +3,13 @@ use super::*;
 fn fold_instr(instr: Instr) -> Vec<Instr> {
     match instr {
         Instr::Movq { src, dst } => {
-            if src.is_dref() && dst.is_dref() {
+            // Moves between variables given the same home do nothing.
+            if src == dst {
+                return vec![];
+            }
+            // Only a move to a register can take an immediate wider than 32
+            // bits.
+            if (src.is_mem() || src.is_wide_int()) && dst.is_mem() {
                 return vec![
                     Instr::movq(src, Arg::reg(Register::Rax)),
                     Instr::movq(Arg::reg(Register::Rax), dst),
@@ -12,7 +18,7 @@ fn fold_instr(instr: Instr) -> Vec<Instr> {
             vec![Instr::movq(src, dst)]
         }
         Instr::Addq { src, dst } => {
-            if src.is_dref() && dst.is_dref() {
+            if src.is_wide_int() || (src.is_mem() && dst.is_mem()) {
                 return vec![
                     Instr::movq(src, Arg::reg(Register::Rax)),
                     Instr::addq(Arg::reg(Register::Rax), dst),
@@ -21,7 +27,7 @@ fn fold_instr(instr: Instr) -> Vec<Instr> {
             vec![Instr::addq(src, dst)]
         }
         Instr::Subq { src, dst } => {
-            if src.is_dref() && dst.is_dref() {
+            if src.is_wide_int() || (src.is_mem() && dst.is_mem()) {
                 return vec![
                     Instr::movq(src, Arg::reg(Register::Rax)),
                     Instr::subq(Arg::reg(Register::Rax), dst),
@@ -29,14 +35,124 @@ fn fold_instr(instr: Instr) -> Vec<Instr> {
             }
             vec![Instr::subq(src, dst)]
         }
+        Instr::Imulq { src, dst } => {
+            // An immediate wider than 32 bits is loaded into `%rax`, and since
+            // multiplication commutes, the product is left there.
+            if src.is_wide_int() && dst.is_mem() {
+                return vec![
+                    Instr::movq(src, Arg::reg(Register::Rax)),
+                    Instr::imulq(dst.clone(), Arg::reg(Register::Rax)),
+                    Instr::movq(Arg::reg(Register::Rax), dst),
+                ];
+            }
+            if src.is_wide_int() {
+                return vec![
+                    Instr::movq(src, Arg::reg(Register::Rax)),
+                    Instr::imulq(Arg::reg(Register::Rax), dst),
+                ];
+            }
+            // The destination of `imulq` must be a register.
+            if dst.is_mem() {
+                return vec![
+                    Instr::movq(dst.clone(), Arg::reg(Register::Rax)),
+                    Instr::imulq(src, Arg::reg(Register::Rax)),
+                    Instr::movq(Arg::reg(Register::Rax), dst),
+                ];
+            }
+            vec![Instr::imulq(src, dst)]
+        }
+        Instr::Idivq(src) => {
+            // `idivq` takes no immediate and `%rax` and `%rdx` hold the
+            // dividend, so the divisor goes through `%rcx`, saved around it.
+            if src.is_int() {
+                return vec![
+                    Instr::pushq(Arg::reg(Register::Rcx)),
+                    Instr::movq(src, Arg::reg(Register::Rcx)),
+                    Instr::idivq(Arg::reg(Register::Rcx)),
+                    Instr::popq(Arg::reg(Register::Rcx)),
+                ];
+            }
+            vec![Instr::idivq(src)]
+        }
+        Instr::Xorq { src, dst } => {
+            if src.is_wide_int() || (src.is_mem() && dst.is_mem()) {
+                return vec![
+                    Instr::movq(src, Arg::reg(Register::Rax)),
+                    Instr::xorq(Arg::reg(Register::Rax), dst),
+                ];
+            }
+            vec![Instr::xorq(src, dst)]
+        }
+        Instr::Andq { src, dst } => {
+            if src.is_wide_int() || (src.is_mem() && dst.is_mem()) {
+                return vec![
+                    Instr::movq(src, Arg::reg(Register::Rax)),
+                    Instr::andq(Arg::reg(Register::Rax), dst),
+                ];
+            }
+            vec![Instr::andq(src, dst)]
+        }
+        Instr::Orq { src, dst } => {
+            if src.is_wide_int() || (src.is_mem() && dst.is_mem()) {
+                return vec![
+                    Instr::movq(src, Arg::reg(Register::Rax)),
+                    Instr::orq(Arg::reg(Register::Rax), dst),
+                ];
+            }
+            vec![Instr::orq(src, dst)]
+        }
+        Instr::Movzbq { src, dst } => {
+            // The destination of `movzbq` must be a register.
+            if dst.is_mem() {
+                return vec![
+                    Instr::movzbq(src, Arg::reg(Register::Rax)),
+                    Instr::movq(Arg::reg(Register::Rax), dst),
+                ];
+            }
+            vec![Instr::movzbq(src, dst)]
+        }
+        Instr::Cmpq { src, dst } => {
+            // An immediate wider than 32 bits is loaded into a register, and
+            // `%r11` is free for it when `%rax` holds the second operand.
+            if src.is_wide_int() && dst.is_int() {
+                return vec![
+                    Instr::movq(dst, Arg::reg(Register::Rax)),
+                    Instr::movq(src, Arg::reg(Register::R11)),
+                    Instr::cmpq(Arg::reg(Register::R11), Arg::reg(Register::Rax)),
+                ];
+            }
+            if src.is_wide_int() {
+                return vec![
+                    Instr::movq(src, Arg::reg(Register::Rax)),
+                    Instr::cmpq(Arg::reg(Register::Rax), dst),
+                ];
+            }
+            // The second operand of `cmpq` cannot be an immediate.
+            if dst.is_int() || (src.is_mem() && dst.is_mem()) {
+                return vec![
+                    Instr::movq(dst, Arg::reg(Register::Rax)),
+                    Instr::cmpq(src, Arg::reg(Register::Rax)),
+                ];
+            }
+            vec![Instr::cmpq(src, dst)]
+        }
+        Instr::Leaq { src, dst } => {
+            // The destination of `leaq` must be a register.
+            if dst.is_mem() {
+                return vec![
+                    Instr::leaq(src, Arg::reg(Register::Rax)),
+                    Instr::movq(Arg::reg(Register::Rax), dst),
+                ];
+            }
+            vec![Instr::leaq(src, dst)]
+        }
         _ => vec![instr],
     }
 }
 
 fn fold_block(block: Block) -> Block {
     Block {
-        info: block.info,
-        instrs: block.instrs.into_iter().map(fold_instr).flatten().collect(),
+        instrs: block.instrs.into_iter().flat_map(fold_instr).collect(),
     }
 }
 
@@ -66,14 +182,10 @@ mod tests {
             Instr::addq(Arg::deref(Register::Rbp, -8), Arg::reg(Register::Rax)),
             Instr::jumpq("basic_add_and_neg_conclusion"),
         ];
-        let block = Block {
-            info: BlockInfo { stack_space: 8 },
-            instrs: instrs.clone(),
-        };
+        let block = Block::new(instrs.clone());
         let expected_instrs = instrs;
         let actual = fold_block(block);
         assert_eq!(actual.instrs, expected_instrs);
-        assert_eq!(actual.info.stack_space, 8);
     }
 
     #[test]
@@ -86,10 +198,7 @@ mod tests {
             ),
             Instr::movq(Arg::deref(Register::Rbp, -16), Arg::reg(Register::Rax)),
         ];
-        let block = Block {
-            info: BlockInfo { stack_space: 16 },
-            instrs,
-        };
+        let block = Block::new(instrs);
         let expected_instrs = vec![
             Instr::movq(Arg::int(42), Arg::deref(Register::Rbp, -8)),
             Instr::movq(Arg::deref(Register::Rbp, -8), Arg::reg(Register::Rax)),
@@ -99,4 +208,151 @@ mod tests {
         let actual = fold_block(block);
         assert_eq!(actual.instrs, expected_instrs);
     }
+
+    #[test]
+    fn wide_int_to_mem() {
+        let wide = -2_147_483_649;
+        let block = Block::new(vec![
+            Instr::movq(Arg::int(wide), Arg::deref(Register::R15, -16)),
+            Instr::movq(Arg::int(wide), Arg::reg(Register::Rcx)),
+        ]);
+        let expected_instrs = vec![
+            Instr::movq(Arg::int(wide), Arg::reg(Register::Rax)),
+            Instr::movq(Arg::reg(Register::Rax), Arg::deref(Register::R15, -16)),
+            Instr::movq(Arg::int(wide), Arg::reg(Register::Rcx)),
+        ];
+        assert_eq!(fold_block(block).instrs, expected_instrs);
+    }
+
+    #[test]
+    fn self_moves() {
+        let block = Block::new(vec![
+            Instr::movq(Arg::reg(Register::Rcx), Arg::reg(Register::Rcx)),
+            Instr::movq(Arg::reg(Register::Rcx), Arg::reg(Register::Rdx)),
+        ]);
+        let expected_instrs = vec![Instr::movq(
+            Arg::reg(Register::Rcx),
+            Arg::reg(Register::Rdx),
+        )];
+        assert_eq!(fold_block(block).instrs, expected_instrs);
+    }
+
+    #[test]
+    fn imulq_to_mem() {
+        let block = Block::new(vec![Instr::imulq(
+            Arg::int(3),
+            Arg::deref(Register::Rbp, -8),
+        )]);
+        let expected_instrs = vec![
+            Instr::movq(Arg::deref(Register::Rbp, -8), Arg::reg(Register::Rax)),
+            Instr::imulq(Arg::int(3), Arg::reg(Register::Rax)),
+            Instr::movq(Arg::reg(Register::Rax), Arg::deref(Register::Rbp, -8)),
+        ];
+        assert_eq!(fold_block(block).instrs, expected_instrs);
+    }
+
+    #[test]
+    fn wide_int_operands() {
+        let wide = 5_000_000_000;
+        let block = Block::new(vec![
+            Instr::addq(Arg::int(wide), Arg::reg(Register::Rcx)),
+            Instr::subq(Arg::int(-wide), Arg::deref(Register::Rbp, -8)),
+            Instr::andq(Arg::int(wide), Arg::reg(Register::Rcx)),
+            Instr::orq(Arg::int(wide), Arg::reg(Register::Rcx)),
+            Instr::xorq(Arg::int(wide), Arg::reg(Register::Rcx)),
+            Instr::addq(Arg::int(i64::from(i32::MAX)), Arg::reg(Register::Rcx)),
+        ]);
+        let expected_instrs = vec![
+            Instr::movq(Arg::int(wide), Arg::reg(Register::Rax)),
+            Instr::addq(Arg::reg(Register::Rax), Arg::reg(Register::Rcx)),
+            Instr::movq(Arg::int(-wide), Arg::reg(Register::Rax)),
+            Instr::subq(Arg::reg(Register::Rax), Arg::deref(Register::Rbp, -8)),
+            Instr::movq(Arg::int(wide), Arg::reg(Register::Rax)),
+            Instr::andq(Arg::reg(Register::Rax), Arg::reg(Register::Rcx)),
+            Instr::movq(Arg::int(wide), Arg::reg(Register::Rax)),
+            Instr::orq(Arg::reg(Register::Rax), Arg::reg(Register::Rcx)),
+            Instr::movq(Arg::int(wide), Arg::reg(Register::Rax)),
+            Instr::xorq(Arg::reg(Register::Rax), Arg::reg(Register::Rcx)),
+            Instr::addq(Arg::int(i64::from(i32::MAX)), Arg::reg(Register::Rcx)),
+        ];
+        assert_eq!(fold_block(block).instrs, expected_instrs);
+    }
+
+    #[test]
+    fn imulq_by_wide_int() {
+        let wide = 10_000_000_000;
+        let block = Block::new(vec![
+            Instr::imulq(Arg::int(wide), Arg::reg(Register::Rcx)),
+            Instr::imulq(Arg::int(wide), Arg::deref(Register::Rbp, -8)),
+        ]);
+        let expected_instrs = vec![
+            Instr::movq(Arg::int(wide), Arg::reg(Register::Rax)),
+            Instr::imulq(Arg::reg(Register::Rax), Arg::reg(Register::Rcx)),
+            Instr::movq(Arg::int(wide), Arg::reg(Register::Rax)),
+            Instr::imulq(Arg::deref(Register::Rbp, -8), Arg::reg(Register::Rax)),
+            Instr::movq(Arg::reg(Register::Rax), Arg::deref(Register::Rbp, -8)),
+        ];
+        assert_eq!(fold_block(block).instrs, expected_instrs);
+    }
+
+    #[test]
+    fn idivq_by_int() {
+        let block = Block::new(vec![
+            Instr::cqto(),
+            Instr::idivq(Arg::int(7)),
+            Instr::idivq(Arg::deref(Register::Rbp, -8)),
+        ]);
+        let expected_instrs = vec![
+            Instr::cqto(),
+            Instr::pushq(Arg::reg(Register::Rcx)),
+            Instr::movq(Arg::int(7), Arg::reg(Register::Rcx)),
+            Instr::idivq(Arg::reg(Register::Rcx)),
+            Instr::popq(Arg::reg(Register::Rcx)),
+            Instr::idivq(Arg::deref(Register::Rbp, -8)),
+        ];
+        assert_eq!(fold_block(block).instrs, expected_instrs);
+    }
+
+    #[test]
+    fn cmpq_with_int() {
+        let block = Block::new(vec![Instr::cmpq(Arg::int(0), Arg::int(0))]);
+        let expected_instrs = vec![
+            Instr::movq(Arg::int(0), Arg::reg(Register::Rax)),
+            Instr::cmpq(Arg::int(0), Arg::reg(Register::Rax)),
+        ];
+        assert_eq!(fold_block(block).instrs, expected_instrs);
+    }
+
+    #[test]
+    fn cmpq_with_wide_int() {
+        let wide = 5_000_000_000;
+        let block = Block::new(vec![
+            Instr::cmpq(Arg::int(wide), Arg::deref(Register::Rbp, -8)),
+            Instr::cmpq(Arg::reg(Register::Rcx), Arg::int(-wide)),
+            Instr::cmpq(Arg::int(wide), Arg::int(1)),
+        ]);
+        let expected_instrs = vec![
+            Instr::movq(Arg::int(wide), Arg::reg(Register::Rax)),
+            Instr::cmpq(Arg::reg(Register::Rax), Arg::deref(Register::Rbp, -8)),
+            Instr::movq(Arg::int(-wide), Arg::reg(Register::Rax)),
+            Instr::cmpq(Arg::reg(Register::Rcx), Arg::reg(Register::Rax)),
+            Instr::movq(Arg::int(1), Arg::reg(Register::Rax)),
+            Instr::movq(Arg::int(wide), Arg::reg(Register::R11)),
+            Instr::cmpq(Arg::reg(Register::R11), Arg::reg(Register::Rax)),
+        ];
+        assert_eq!(fold_block(block).instrs, expected_instrs);
+    }
+
+    #[test]
+    fn movzbq_to_mem() {
+        let block = Block::new(vec![Instr::movzbq(
+            Arg::byte_reg(Register::Rax),
+            Arg::deref(Register::Rbp, -8),
+        )]);
+        let expected_instrs = vec![
+            Instr::movzbq(Arg::byte_reg(Register::Rax), Arg::reg(Register::Rax)),
+            Instr::movq(Arg::reg(Register::Rax), Arg::deref(Register::Rbp, -8)),
+        ];
+        assert_eq!(fold_block(block).instrs, expected_instrs);
+    }
 }
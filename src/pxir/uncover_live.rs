@@ -9,66 +9,255 @@ fn get_arg_var(arg: Arg) -> Option<Symbol> {
     }
 }
 
-fn vars_read(instr: &Instr) -> HashSet<Symbol> {
-    let args_read = match instr.clone() {
+/// Operands the instruction reads. Registers it reads without naming them,
+/// such as `%rax` for `cqto`, are not included.
+pub fn args_read(instr: &Instr) -> Vec<Box<Arg>> {
+    match instr.clone() {
         Instr::Addq { src, dst } => vec![src, dst],
         Instr::Subq { src, dst } => vec![src, dst],
+        Instr::Imulq { src, dst } => vec![src, dst],
+        Instr::Cqto => vec![],
+        Instr::Idivq(src) => vec![src],
+        Instr::Sbbq { src, dst } => vec![src, dst],
+        Instr::Cmpq { src, dst } => vec![src, dst],
+        Instr::Xorq { src, dst } => vec![src, dst],
+        Instr::Andq { src, dst } => vec![src, dst],
+        Instr::Orq { src, dst } => vec![src, dst],
+        Instr::Salq { src, dst } => vec![src, dst],
+        Instr::Sarq { src, dst } => vec![src, dst],
+        Instr::Set(_, _) => vec![],
+        Instr::Movzbq { src, .. } => vec![src],
         Instr::Movq { src, .. } => vec![src],
         Instr::Negq(dst) => vec![dst],
         Instr::Pushq(src) => vec![src],
         Instr::Popq(_) => vec![],
         Instr::Callq(_) => vec![],
+        Instr::IndirectCallq(f) => vec![f],
+        Instr::Leaq { .. } => vec![],
         Instr::Jumpq(_) => vec![],
+        Instr::JumpIf(_, _) => vec![],
         Instr::Retq => vec![],
-    };
-    HashSet::from_iter(args_read.into_iter().filter_map(|a| get_arg_var(*a)))
+    }
 }
 
-fn vars_written(instr: &Instr) -> HashSet<Symbol> {
-    let args_written = match instr.clone() {
+/// Operands the instruction writes. Registers it writes without naming them,
+/// such as `%rdx` for `cqto`, are not included.
+pub fn args_written(instr: &Instr) -> Vec<Box<Arg>> {
+    match instr.clone() {
         Instr::Addq { dst, .. } => vec![dst],
         Instr::Subq { dst, .. } => vec![dst],
+        Instr::Imulq { dst, .. } => vec![dst],
+        Instr::Cqto => vec![],
+        Instr::Idivq(_) => vec![],
+        Instr::Sbbq { dst, .. } => vec![dst],
+        Instr::Cmpq { .. } => vec![],
+        Instr::Xorq { dst, .. } => vec![dst],
+        Instr::Andq { dst, .. } => vec![dst],
+        Instr::Orq { dst, .. } => vec![dst],
+        Instr::Salq { dst, .. } => vec![dst],
+        Instr::Sarq { dst, .. } => vec![dst],
+        Instr::Set(_, dst) => vec![dst],
+        Instr::Movzbq { dst, .. } => vec![dst],
         Instr::Movq { dst, .. } => vec![dst],
         Instr::Negq(dst) => vec![dst],
         Instr::Pushq(_) => vec![],
         Instr::Popq(dst) => vec![dst],
         Instr::Callq(_) => vec![],
+        Instr::IndirectCallq(_) => vec![],
+        Instr::Leaq { dst, .. } => vec![dst],
         Instr::Jumpq(_) => vec![],
+        Instr::JumpIf(_, _) => vec![],
         Instr::Retq => vec![],
-    };
-    HashSet::from_iter(args_written.into_iter().filter_map(|a| get_arg_var(*a)))
+    }
 }
 
-fn live_before_instr(instr: &Instr, live_after: &HashSet<Symbol>) -> HashSet<Symbol> {
-    let written = vars_written(instr);
-    let read = vars_read(instr);
-    let mut live_before = live_after
-        .difference(&written)
-        .cloned()
-        .collect::<Vec<Symbol>>();
-    live_before.append(&mut read.into_iter().collect::<Vec<Symbol>>());
-    HashSet::from_iter(live_before.into_iter())
+pub fn vars_read(instr: &Instr) -> HashSet<Symbol> {
+    HashSet::from_iter(args_read(instr).into_iter().filter_map(|a| get_arg_var(*a)))
 }
 
-/// Gets the set of variables that are live after an instruction for each
-/// instruction in the block.
-pub fn uncover_live(block: &Block) -> Vec<HashSet<Symbol>> {
+pub fn vars_written(instr: &Instr) -> HashSet<Symbol> {
+    HashSet::from_iter(
+        args_written(instr)
+            .into_iter()
+            .filter_map(|a| get_arg_var(*a)),
+    )
+}
+
+/// Register the operand names, if it is one or its low byte.
+fn arg_reg(arg: &Arg) -> Option<Register> {
+    match arg {
+        Arg::Reg(reg) | Arg::ByteReg(reg) => Some(*reg),
+        _ => None,
+    }
+}
+
+/// Registers the instruction reads. The address of a memory operand is read
+/// whether the operand is read or written. The argument registers a call
+/// reads depend on the block, so `uncover_live_regs` adds them.
+pub fn regs_read(instr: &Instr) -> Vec<Register> {
+    let mut regs = args_read(instr)
+        .iter()
+        .filter_map(|arg| arg_reg(arg))
+        .collect::<Vec<Register>>();
+    for arg in args_read(instr).iter().chain(args_written(instr).iter()) {
+        if let Arg::Deref(reg, _) = **arg {
+            regs.push(reg);
+        }
+    }
+    match instr {
+        Instr::Cqto => regs.push(Register::Rax),
+        Instr::Idivq(_) => regs.extend(vec![Register::Rax, Register::Rdx]),
+        _ => {}
+    }
+    regs
+}
+
+/// Registers the instruction writes. A call may overwrite every
+/// caller-saved register, whichever function it calls.
+pub fn regs_written(instr: &Instr) -> Vec<Register> {
+    let mut regs = args_written(instr)
+        .iter()
+        .filter_map(|arg| arg_reg(arg))
+        .collect::<Vec<Register>>();
+    match instr {
+        Instr::Cqto => regs.push(Register::Rdx),
+        Instr::Idivq(_) => regs.extend(vec![Register::Rax, Register::Rdx]),
+        Instr::Callq(_) | Instr::IndirectCallq(_) => regs.extend(CALLER_SAVED_REGISTERS.iter()),
+        _ => {}
+    }
+    regs
+}
+
+/// Gets the set of registers live after each instruction of the block, as
+/// `uncover_live` does for variables. Values in registers never outlive the
+/// block that sets them, so nothing is live at its end. A call reads the
+/// argument registers written since the start of the block or the previous
+/// call, and overwrites the caller-saved ones.
+pub fn uncover_live_regs(block: &Block) -> Vec<HashSet<Register>> {
+    let mut call_args = vec![];
+    let mut set_up = HashSet::new();
+    for instr in &block.instrs {
+        match instr {
+            Instr::Callq(_) | Instr::IndirectCallq(_) => {
+                call_args.push(std::mem::take(&mut set_up));
+            }
+            _ => {
+                call_args.push(HashSet::new());
+                set_up.extend(
+                    regs_written(instr)
+                        .into_iter()
+                        .filter(|reg| ARG_REGISTERS.contains(reg)),
+                );
+            }
+        }
+    }
+    let mut live = HashSet::new();
+    let mut live_after = vec![];
+    for (instr, args) in block.instrs.iter().zip(call_args).rev() {
+        live_after.push(live.clone());
+        for reg in regs_written(instr) {
+            live.remove(&reg);
+        }
+        live.extend(regs_read(instr));
+        live.extend(args);
+    }
+    live_after.reverse();
+    live_after
+}
+
+/// Gets the variables live before the instruction. `label_live` gives the
+/// variables live at the start of the block with the given label.
+fn live_before_instr(
+    instr: &Instr,
+    live_after: &HashSet<Symbol>,
+    label_live: &dyn Fn(&Label) -> HashSet<Symbol>,
+) -> HashSet<Symbol> {
+    match instr {
+        Instr::Jumpq(label) => return label_live(label),
+        Instr::JumpIf(_, label) => return live_after.union(&label_live(label)).cloned().collect(),
+        _ => {}
+    }
+    let mut live_before = live_after.clone();
+    for sym in vars_written(instr) {
+        live_before.remove(&sym);
+    }
+    live_before.extend(vars_read(instr));
+    live_before
+}
+
+fn uncover_live_with(
+    block: &Block,
+    label_live: &dyn Fn(&Label) -> HashSet<Symbol>,
+) -> Vec<HashSet<Symbol>> {
     // We build the list of live after sets in reverse order.
     let mut live_after_sets = vec![];
     live_after_sets.push(HashSet::new());
     for instr in block.instrs.iter().rev() {
         // There will always be at least one set so unwrapping is ok.
         let prev_live_after = live_after_sets.last().unwrap();
-        let live_before = live_before_instr(instr, prev_live_after);
+        let live_before = live_before_instr(instr, prev_live_after, label_live);
         live_after_sets.push(live_before);
     }
     live_after_sets.into_iter().rev().collect()
 }
 
+/// Gets the set of variables that are live after an instruction for each
+/// instruction in the block. The first set holds the variables live before the
+/// first instruction. Nothing is taken to be live in the blocks it jumps to.
+pub fn uncover_live(block: &Block) -> Vec<HashSet<Symbol>> {
+    uncover_live_with(block, &|_| HashSet::new())
+}
+
+/// Gets the live after sets, as `uncover_live` does, for every block of the
+/// program, taking into account the variables live in the blocks each block
+/// jumps to. Labels without a block in the program, such as `conclusion`,
+/// have nothing live.
+///
+/// Loops jump back to blocks whose sets depend on their own, so the sets start
+/// empty and a block is swept again whenever the variables live at the start
+/// of a block it jumps to change. The sets only grow, so this ends.
+pub fn uncover_live_program(program: &Program) -> HashMap<Label, Vec<HashSet<Symbol>>> {
+    let labels = program.labels();
+    let mut preds: HashMap<&Label, Vec<&Label>> = HashMap::new();
+    for &label in &labels {
+        for instr in &program.blocks[label].instrs {
+            if let Instr::Jumpq(target) | Instr::JumpIf(_, target) = instr {
+                preds.entry(&**target).or_default().push(label);
+            }
+        }
+    }
+    let mut live = labels
+        .iter()
+        .map(|&label| (label.clone(), vec![HashSet::new()]))
+        .collect::<HashMap<Label, Vec<HashSet<Symbol>>>>();
+    // Blocks mostly jump to blocks later in label order, so sweeping
+    // backward needs fewer sweeps.
+    let mut pending = labels.clone();
+    let mut queued = labels.iter().copied().collect::<HashSet<&Label>>();
+    while let Some(label) = pending.pop() {
+        queued.remove(label);
+        let sets = uncover_live_with(&program.blocks[label], &|target| {
+            live.get(target)
+                .map(|sets| sets[0].clone())
+                .unwrap_or_default()
+        });
+        let changed = live[label][0] != sets[0];
+        live.insert(label.clone(), sets);
+        if changed {
+            for &pred in preds.get(label).into_iter().flatten() {
+                if queued.insert(pred) {
+                    pending.push(pred);
+                }
+            }
+        }
+    }
+    live
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::*;
-    use super::uncover_live;
+    use super::{uncover_live, uncover_live_program, uncover_live_regs};
     use std::collections::HashSet;
     use std::iter::FromIterator;
 
@@ -89,10 +278,7 @@ mod tests {
             Instr::addq(Arg::var("t.1"), Arg::reg(Register::Rax)),
             Instr::jumpq("conclusion"),
         ];
-        let block = Block {
-            info: BlockInfo { stack_space: 48 },
-            instrs,
-        };
+        let block = Block::new(instrs);
         let expected = vec![
             symbol_set(vec![]),
             symbol_set(vec!["v"]),
@@ -113,6 +299,123 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn across_blocks() {
+        let start = vec![
+            Instr::movq(Arg::int(1), Arg::var("x")),
+            Instr::movq(Arg::int(2), Arg::var("y")),
+            Instr::cmpq(Arg::int(0), Arg::var("x")),
+            Instr::jump_if(CondCode::E, "then"),
+            Instr::jumpq("else"),
+        ];
+        let then = vec![
+            Instr::movq(Arg::var("x"), Arg::reg(Register::Rax)),
+            Instr::jumpq("conclusion"),
+        ];
+        let els = vec![
+            Instr::movq(Arg::var("y"), Arg::reg(Register::Rax)),
+            Instr::jumpq("conclusion"),
+        ];
+        let mut blocks = HashMap::new();
+        blocks.insert(*Label::new("start"), Block::new(start));
+        blocks.insert(*Label::new("then"), Block::new(then));
+        blocks.insert(*Label::new("else"), Block::new(els));
+        let program = Program {
+            info: ProgramInfo::default(),
+            blocks,
+        };
+        let expected = vec![
+            symbol_set(vec![]),
+            symbol_set(vec!["x"]),
+            symbol_set(vec!["x", "y"]),
+            symbol_set(vec!["x", "y"]),
+            symbol_set(vec!["y"]),
+            symbol_set(vec![]),
+        ];
+        let actual = uncover_live_program(&program);
+        assert_eq!(actual[&*Label::new("start")], expected);
+        assert_eq!(actual[&*Label::new("then")][0], symbol_set(vec!["x"]));
+    }
+
+    #[test]
+    fn loops() {
+        // x = 0; while x < 10 { x += y }; return x
+        let start = vec![
+            Instr::movq(Arg::int(0), Arg::var("x")),
+            Instr::movq(Arg::int(3), Arg::var("y")),
+            Instr::jumpq("loop"),
+        ];
+        let header = vec![
+            Instr::cmpq(Arg::int(10), Arg::var("x")),
+            Instr::jump_if(CondCode::L, "body"),
+            Instr::jumpq("exit"),
+        ];
+        let body = vec![
+            Instr::addq(Arg::var("y"), Arg::var("x")),
+            Instr::jumpq("loop"),
+        ];
+        let exit = vec![
+            Instr::movq(Arg::var("x"), Arg::reg(Register::Rax)),
+            Instr::jumpq("conclusion"),
+        ];
+        let mut blocks = HashMap::new();
+        blocks.insert(*Label::new("start"), Block::new(start));
+        blocks.insert(*Label::new("loop"), Block::new(header));
+        blocks.insert(*Label::new("body"), Block::new(body));
+        blocks.insert(*Label::new("exit"), Block::new(exit));
+        let program = Program {
+            info: ProgramInfo::default(),
+            blocks,
+        };
+        let actual = uncover_live_program(&program);
+        // y is read only in the body, but it stays live around the loop.
+        let xy = symbol_set(vec!["x", "y"]);
+        assert_eq!(
+            actual[&*Label::new("loop")],
+            vec![
+                xy.clone(),
+                xy.clone(),
+                symbol_set(vec!["x"]),
+                symbol_set(vec![])
+            ]
+        );
+        assert_eq!(
+            actual[&*Label::new("body")],
+            vec![xy.clone(), xy.clone(), symbol_set(vec![])]
+        );
+        assert_eq!(
+            actual[&*Label::new("start")],
+            vec![
+                symbol_set(vec![]),
+                symbol_set(vec!["x"]),
+                xy,
+                symbol_set(vec![])
+            ]
+        );
+    }
+
+    #[test]
+    fn registers() {
+        let block = Block::new(vec![
+            Instr::movq(Arg::reg(Register::Rdi), Arg::var("x")),
+            Instr::movq(Arg::var("x"), Arg::reg(Register::Rsi)),
+            Instr::movq(Arg::int(8), Arg::reg(Register::Rdi)),
+            Instr::callq("collect"),
+            Instr::movq(Arg::reg(Register::Rax), Arg::var("y")),
+        ]);
+        let rdi = Register::Rdi;
+        let rsi = Register::Rsi;
+        let expected = vec![
+            HashSet::new(),
+            HashSet::from_iter(vec![rsi]),
+            HashSet::from_iter(vec![rdi, rsi]),
+            // `%rax` is overwritten by the call and read after it.
+            HashSet::from_iter(vec![Register::Rax]),
+            HashSet::new(),
+        ];
+        assert_eq!(uncover_live_regs(&block), expected);
+    }
+
     fn symbol_set(names: Vec<&str>) -> HashSet<Symbol> {
         HashSet::from_iter(names.iter().map(|s| Symbol::new(s)))
     }
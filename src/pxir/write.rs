@@ -1,4 +1,5 @@
 use super::*;
+use std::collections::HashSet;
 use std::fmt;
 use std::fmt::Write;
 
@@ -10,17 +11,53 @@ pub fn write_block(s: &mut String, label: &Label, block: &Block) -> fmt::Result
     Ok(())
 }
 
+/// Writes the block with each instruction followed by a comment listing the
+/// variables live after it. `live_after` has one set per instruction.
+pub fn write_block_live(
+    s: &mut String,
+    label: &Label,
+    block: &Block,
+    live_after: &[HashSet<Symbol>],
+) -> fmt::Result {
+    writeln!(s, "{}:", label)?;
+    for (instr, live) in block.instrs.iter().zip(live_after) {
+        let mut names = live
+            .iter()
+            .map(|sym| sym.value.as_str())
+            .collect::<Vec<&str>>();
+        names.sort_unstable();
+        let instr = instr.to_string();
+        writeln!(s, "\t{:<32}# live: {{{}}}", instr, names.join(", "))?;
+    }
+    Ok(())
+}
+
 impl fmt::Display for Instr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Instr::Addq { src, dst } => write!(f, "addq {}, {}", *src, *dst),
             Instr::Subq { src, dst } => write!(f, "subq {}, {}", *src, *dst),
+            Instr::Imulq { src, dst } => write!(f, "imulq {}, {}", *src, *dst),
+            Instr::Cqto => write!(f, "cqto"),
+            Instr::Idivq(src) => write!(f, "idivq {}", *src),
+            Instr::Sbbq { src, dst } => write!(f, "sbbq {}, {}", *src, *dst),
+            Instr::Cmpq { src, dst } => write!(f, "cmpq {}, {}", *src, *dst),
+            Instr::Xorq { src, dst } => write!(f, "xorq {}, {}", *src, *dst),
+            Instr::Andq { src, dst } => write!(f, "andq {}, {}", *src, *dst),
+            Instr::Orq { src, dst } => write!(f, "orq {}, {}", *src, *dst),
+            Instr::Salq { src, dst } => write!(f, "salq {}, {}", *src, *dst),
+            Instr::Sarq { src, dst } => write!(f, "sarq {}, {}", *src, *dst),
+            Instr::Set(cc, dst) => write!(f, "set{} {}", cc, *dst),
+            Instr::Movzbq { src, dst } => write!(f, "movzbq {}, {}", *src, *dst),
             Instr::Movq { src, dst } => write!(f, "movq {}, {}", *src, *dst),
             Instr::Negq(dst) => write!(f, "negq {}", *dst),
             Instr::Pushq(src) => write!(f, "pushq {}", *src),
             Instr::Popq(dst) => write!(f, "popq {}", *dst),
             Instr::Callq(label) => write!(f, "callq {}", *label),
+            Instr::IndirectCallq(arg) => write!(f, "callq *{}", *arg),
+            Instr::Leaq { src, dst } => write!(f, "leaq {}, {}", *src, *dst),
             Instr::Jumpq(label) => write!(f, "jmp {}", *label),
+            Instr::JumpIf(cc, label) => write!(f, "j{} {}", cc, *label),
             Instr::Retq => write!(f, "retq"),
         }
     }
@@ -31,7 +68,9 @@ impl fmt::Display for Arg {
         match self {
             Arg::Int(i) => write!(f, "${}", i),
             Arg::Reg(r) => write!(f, "{}", r),
+            Arg::ByteReg(r) => write!(f, "%{}", r.byte_name()),
             Arg::Deref(r, off) => write!(f, "{}({})", off, r),
+            Arg::Global(name) => write!(f, "{}(%rip)", name),
             Arg::Var(sym) => write!(f, "var<{}>", sym.value),
         }
     }
@@ -61,6 +100,44 @@ impl fmt::Display for Register {
     }
 }
 
+impl Register {
+    /// Name of the register's low byte.
+    fn byte_name(self) -> &'static str {
+        match self {
+            Register::Rsp => "spl",
+            Register::Rbp => "bpl",
+            Register::Rax => "al",
+            Register::Rbx => "bl",
+            Register::Rcx => "cl",
+            Register::Rdx => "dl",
+            Register::Rsi => "sil",
+            Register::Rdi => "dil",
+            Register::R8 => "r8b",
+            Register::R9 => "r9b",
+            Register::R10 => "r10b",
+            Register::R11 => "r11b",
+            Register::R12 => "r12b",
+            Register::R13 => "r13b",
+            Register::R14 => "r14b",
+            Register::R15 => "r15b",
+        }
+    }
+}
+
+impl fmt::Display for CondCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CondCode::O => write!(f, "o"),
+            CondCode::E => write!(f, "e"),
+            CondCode::Ne => write!(f, "ne"),
+            CondCode::L => write!(f, "l"),
+            CondCode::Le => write!(f, "le"),
+            CondCode::G => write!(f, "g"),
+            CondCode::Ge => write!(f, "ge"),
+        }
+    }
+}
+
 impl fmt::Display for Label {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.value)
@@ -0,0 +1,345 @@
+//! Interactive read-eval-print loop.
+//!
+//! Each entered expression is evaluated with the interpreter and, for every
+//! stage the user has switched on, compiled with the driver. The output of the
+//! shown stages is laid out side by side so that a change in the source can be
+//! followed through the pass pipeline.
+
+use super::driver::{drive_to, Stage};
+use super::options::{Options, Overflow, Typing};
+use super::rir;
+use super::rir::insert_casts::CastInserter;
+use super::rir::interp::{interp_with_output, InputSource, StdinInput};
+use super::rir::parse::parse_expr;
+use super::rir::typecheck::typecheck_program;
+
+const HELP: &str = "\
+Enter an R expression to evaluate it. Commands:
+  :STAGE   show or hide the output of a stage, one of rir, rir-casts,
+           rir-uniq, rir-assign, rir-closure, rir-alloc, rir-simple, cir,
+           pxir, pxir-live, pxir-homes, asm
+  :live    show or hide the variables live after each instruction
+  :shown   list the stages being shown
+  :trap    switch between wrapping and trapping on integer overflow
+  :dynamic switch between static and dynamic typing
+  :help    print this message
+  :quit    leave the REPL";
+
+/// Text separating columns of side-by-side output.
+const COLUMN_GAP: &str = "  |  ";
+
+/// What the REPL should do after handling an entry.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    /// Print the text and wait for the next entry.
+    Print(String),
+    /// Leave the REPL.
+    Quit,
+}
+
+/// State of a REPL session.
+pub struct Repl {
+    /// Stages whose output is shown after each evaluation.
+    shown: Vec<Stage>,
+
+    /// Where `(read)` takes its values from.
+    input: Box<dyn InputSource>,
+
+    /// Options for both evaluating and compiling entries.
+    options: Options,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Repl::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl::with_input(Box::new(StdinInput))
+    }
+
+    pub fn with_input(input: Box<dyn InputSource>) -> Repl {
+        Repl {
+            shown: vec![],
+            input,
+            options: Options::default(),
+        }
+    }
+
+    /// Whether the text forms a complete entry: a command, or an expression
+    /// whose parentheses are balanced.
+    pub fn is_complete(input: &str) -> bool {
+        let input = input.trim();
+        if input.starts_with(':') {
+            return true;
+        }
+        let mut depth = 0;
+        for line in input.lines() {
+            for c in line.chars() {
+                match c {
+                    ';' => break,
+                    '(' | '[' => depth += 1,
+                    ')' | ']' => depth -= 1,
+                    _ => (),
+                }
+            }
+        }
+        !input.is_empty() && depth <= 0
+    }
+
+    /// Handles one complete entry.
+    pub fn eval(&mut self, input: &str) -> Reply {
+        let input = input.trim();
+        match input.strip_prefix(':') {
+            Some(command) => self.command(command.trim()),
+            None => Reply::Print(self.eval_expr(input)),
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Reply {
+        let stage = match command {
+            "quit" | "q" => return Reply::Quit,
+            "help" | "h" => return Reply::Print(HELP.to_string()),
+            "shown" => return Reply::Print(self.shown_names()),
+            "trap" => return Reply::Print(self.toggle_overflow()),
+            "dynamic" => return Reply::Print(self.toggle_typing()),
+            "live" => Stage::PxirLive,
+            _ => match command.parse::<Stage>() {
+                Ok(stage) => stage,
+                Err(_) => {
+                    return Reply::Print(format!("unknown command `:{}`, try `:help`", command))
+                }
+            },
+        };
+        if let Some(i) = self.shown.iter().position(|s| *s == stage) {
+            self.shown.remove(i);
+        } else {
+            self.shown.push(stage);
+            self.shown.sort();
+        }
+        Reply::Print(self.shown_names())
+    }
+
+    fn toggle_overflow(&mut self) -> String {
+        self.options.overflow = match self.options.overflow {
+            Overflow::Wrapping => Overflow::Trapping,
+            Overflow::Trapping => Overflow::Wrapping,
+        };
+        match self.options.overflow {
+            Overflow::Wrapping => "overflow: wrap".to_string(),
+            Overflow::Trapping => "overflow: trap".to_string(),
+        }
+    }
+
+    fn toggle_typing(&mut self) -> String {
+        self.options.typing = match self.options.typing {
+            Typing::Static => Typing::Dynamic,
+            Typing::Dynamic => Typing::Static,
+        };
+        match self.options.typing {
+            Typing::Static => "typing: static".to_string(),
+            Typing::Dynamic => "typing: dynamic".to_string(),
+        }
+    }
+
+    fn shown_names(&self) -> String {
+        if self.shown.is_empty() {
+            return "showing: nothing".to_string();
+        }
+        let names = self
+            .shown
+            .iter()
+            .map(|stage| stage.name())
+            .collect::<Vec<&str>>();
+        format!("showing: {}", names.join(", "))
+    }
+
+    fn eval_expr(&mut self, input: &str) -> String {
+        let expr = match parse_expr(input) {
+            Ok(expr) => expr,
+            Err(e) => return e.render(input),
+        };
+        let prog = rir::Program::new(expr.clone());
+        // Ill-typed programs are rejected before they are run, as the
+        // compiler would. Dynamically typed programs are checked with their
+        // casts inserted.
+        let checked = match self.options.typing {
+            Typing::Static => typecheck_program(&prog),
+            Typing::Dynamic => typecheck_program(&CastInserter::new().fold_program(prog.clone())),
+        };
+        if let Err(e) = checked {
+            return e.render(input);
+        }
+        let mut printed = String::new();
+        let result =
+            match interp_with_output(&prog, self.input.as_mut(), &mut printed, &self.options) {
+                Ok(val) => val,
+                Err(e) => return e.render(input),
+            };
+        let mut columns = vec![];
+        for stage in self.shown.iter() {
+            let text = match drive_to((*expr).clone(), *stage, &self.options) {
+                Ok(text) => text,
+                Err(e) => return e.render(input),
+            };
+            columns.push((stage.name(), text));
+        }
+        // What the expression printed comes before its value.
+        let mut out = format!("{}=> {}", printed, result);
+        if !columns.is_empty() {
+            out.push_str("\n\n");
+            out.push_str(&side_by_side(&columns));
+        }
+        out
+    }
+}
+
+/// Lays out titled blocks of text as columns.
+pub fn side_by_side(columns: &[(&str, String)]) -> String {
+    let columns = columns
+        .iter()
+        .map(|(title, text)| {
+            let mut lines = vec![title.to_string(), "-".repeat(title.len())];
+            lines.extend(text.lines().map(|line| line.replace('\t', "    ")));
+            let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+            (lines, width)
+        })
+        .collect::<Vec<(Vec<String>, usize)>>();
+    let height = columns
+        .iter()
+        .map(|(lines, _)| lines.len())
+        .max()
+        .unwrap_or(0);
+    let mut rows = vec![];
+    for row in 0..height {
+        let cells = columns
+            .iter()
+            .map(|(lines, width)| {
+                let cell = lines.get(row).map_or("", |l| l.as_str());
+                format!("{:<width$}", cell, width = width)
+            })
+            .collect::<Vec<String>>();
+        rows.push(cells.join(COLUMN_GAP).trim_end().to_string());
+    }
+    rows.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::rir::interp::ScriptedInput;
+    use super::{side_by_side, Repl, Reply};
+
+    #[test]
+    fn completeness() {
+        assert!(Repl::is_complete("(+ 1 2)"));
+        assert!(Repl::is_complete("42"));
+        assert!(Repl::is_complete(":asm"));
+        assert!(!Repl::is_complete("(let ([x 1])"));
+        assert!(!Repl::is_complete("(+ 1 ; )\n"));
+        assert!(!Repl::is_complete("   "));
+    }
+
+    #[test]
+    fn evaluates() {
+        let mut repl = Repl::new();
+        let reply = repl.eval("(let ([x 10]) (+ x (- 3)))");
+        assert_eq!(reply, Reply::Print("=> 7".to_string()));
+    }
+
+    #[test]
+    fn reads_input() {
+        let mut repl = Repl::with_input(Box::new(ScriptedInput::ints(&[40, 2])));
+        assert_eq!(repl.eval("(read)"), Reply::Print("=> 40".to_string()));
+        assert_eq!(repl.eval("(+ 1 (read))"), Reply::Print("=> 3".to_string()));
+        let expected = "interp: `(read)` reached the end of the input\n";
+        assert_eq!(repl.eval("(read)"), Reply::Print(expected.to_string()));
+    }
+
+    #[test]
+    fn toggles_stages() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval(":asm"), Reply::Print("showing: asm".to_string()));
+        assert_eq!(
+            repl.eval(":cir"),
+            Reply::Print("showing: cir, asm".to_string())
+        );
+        assert_eq!(
+            repl.eval(":live"),
+            Reply::Print("showing: cir, pxir-live, asm".to_string())
+        );
+        assert_eq!(
+            repl.eval(":asm"),
+            Reply::Print("showing: cir, pxir-live".to_string())
+        );
+        assert_eq!(repl.eval(":quit"), Reply::Quit);
+    }
+
+    #[test]
+    fn shows_stages_side_by_side() {
+        let mut repl = Repl::new();
+        repl.eval(":rir-simple");
+        repl.eval(":cir");
+        let expected = "\
+=> 42
+
+rir-simple                             |  cir
+----------                             |  ---
+(let ([v12345 (- 10)]) (+ 52 v12345))  |  start:
+                                       |    v12345 = (- 10);
+                                       |    return (+ 52 v12345);";
+        assert_eq!(
+            repl.eval("(+ 52 (- 10))"),
+            Reply::Print(expected.to_string())
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        let mut repl = Repl::new();
+        let expected = "interp: unbound variable `x` at 1:6\n  |\n1 | (+ 1 x)\n  |      ^\n";
+        assert_eq!(repl.eval("(+ 1 x)"), Reply::Print(expected.to_string()));
+        let expected = "expected `)` at 1:7\n  |\n1 | (+ 1 2\n  |       ^\n";
+        assert_eq!(repl.eval("(+ 1 2"), Reply::Print(expected.to_string()));
+        let expected = "typecheck: expected Integer, found Boolean in argument 1 of - at 1:4\n  \
+                        |\n1 | (- #t)\n  |    ^^\n";
+        assert_eq!(repl.eval("(- #t)"), Reply::Print(expected.to_string()));
+    }
+
+    #[test]
+    fn traps_overflow() {
+        let mut repl = Repl::with_input(Box::new(ScriptedInput::ints(&[i64::MAX, i64::MAX])));
+        let expected = format!("=> {}", i64::MIN);
+        assert_eq!(repl.eval("(+ (read) 1)"), Reply::Print(expected));
+        assert_eq!(
+            repl.eval(":trap"),
+            Reply::Print("overflow: trap".to_string())
+        );
+        let expected = "interp: integer overflow at 1:1\n  |\n1 | (+ (read) 1)\n  | ^^^^^^^^^^^^\n";
+        assert_eq!(
+            repl.eval("(+ (read) 1)"),
+            Reply::Print(expected.to_string())
+        );
+    }
+
+    #[test]
+    fn dynamic_typing() {
+        let mut repl = Repl::new();
+        assert_eq!(
+            repl.eval(":dynamic"),
+            Reply::Print("typing: dynamic".to_string())
+        );
+        let reply = repl.eval("(vector-ref (vector 1 #t) 1)");
+        assert_eq!(reply, Reply::Print("=> #t".to_string()));
+        let expected =
+            "interp: expected Integer, found Boolean at 1:4\n  |\n1 | (- #t)\n  |    ^^\n";
+        assert_eq!(repl.eval("(- #t)"), Reply::Print(expected.to_string()));
+    }
+
+    #[test]
+    fn columns() {
+        let actual = side_by_side(&[("a", "1\n22".to_string()), ("b", "333\n4\n5".to_string())]);
+        assert_eq!(actual, "a   |  b\n-   |  -\n1   |  333\n22  |  4\n    |  5");
+    }
+}
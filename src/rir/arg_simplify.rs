@@ -1,12 +1,23 @@
-use super::{Expr, ExprFolder};
+use super::super::diagnostic::Span;
+use super::{Expr, ExprFolder, ExprKind, Symbol};
+use std::collections::HashSet;
 
 pub struct ExprArgSimplifier {
     pub counter: u64,
+
+    /// Variables assigned with `set!`, as `AssignmentConverter` finds them.
+    /// A read of one is complex: an operand evaluated after it may assign
+    /// the variable, so the read is copied into a new variable where it is
+    /// evaluated.
+    pub assigned: HashSet<Symbol>,
 }
 
 impl ExprArgSimplifier {
     pub fn new(counter: u64) -> ExprArgSimplifier {
-        ExprArgSimplifier { counter }
+        ExprArgSimplifier {
+            counter,
+            assigned: HashSet::new(),
+        }
     }
 
     pub fn new_sym_name(&mut self) -> String {
@@ -14,79 +25,154 @@ impl ExprArgSimplifier {
         self.counter += 1;
         name
     }
-}
 
-impl ExprFolder for ExprArgSimplifier {
-    fn fold(&mut self, e: Box<Expr>) -> Box<Expr> {
-        match *e {
-            Expr::Read => e,   // No args so just return
-            Expr::Lit(_) => e, // Primitive so no args so just return
-            Expr::Neg(op) => {
-                if is_complex_operand(&op) {
-                    let new_sym_name = self.new_sym_name();
-                    let folded_op = self.fold(op);
-                    Expr::let_bind(
-                        &new_sym_name,
-                        folded_op,
-                        Expr::neg(Expr::var(&new_sym_name)),
-                    )
-                } else {
-                    Expr::neg(op)
-                }
-            }
-            Expr::Add(op1, op2) => match (is_complex_operand(&op1), is_complex_operand(&op2)) {
-                (true, true) => {
-                    let new_sym_name1 = self.new_sym_name();
-                    let folded_op1 = self.fold(op1);
-                    let new_sym_name2 = self.new_sym_name();
-                    let folded_op2 = self.fold(op2);
-                    Expr::let_bind(
-                        &new_sym_name1,
-                        folded_op1,
-                        Expr::let_bind(
-                            &new_sym_name2,
-                            folded_op2,
-                            Expr::add(Expr::var(&new_sym_name1), Expr::var(&new_sym_name2)),
-                        ),
-                    )
-                }
-                (true, false) => {
-                    let new_sym_name = self.new_sym_name();
-                    let folded_op1 = self.fold(op1);
-                    Expr::let_bind(
-                        &new_sym_name,
-                        folded_op1,
-                        Expr::add(Expr::var(&new_sym_name), op2),
-                    )
-                }
-                (false, true) => {
-                    let new_sym_name = self.new_sym_name();
-                    let folded_op2 = self.fold(op2);
-                    Expr::let_bind(
-                        &new_sym_name,
-                        folded_op2,
-                        Expr::add(op1, Expr::var(&new_sym_name)),
-                    )
-                }
-                (false, false) => Expr::add(op1, op2),
-            },
-            Expr::Var(_) => e, // Return var
-            Expr::Let(sym, e, body) => Box::new(Expr::Let(sym, self.fold(e), self.fold(body))), // Recurse down e and body
+    /// Replaces a complex operand with a reference to a new variable. The
+    /// binding for the new variable is pushed onto `bindings`. Simple operands
+    /// are returned unchanged.
+    fn simplify_operand(
+        &mut self,
+        op: Box<Expr>,
+        bindings: &mut Vec<(String, Box<Expr>)>,
+    ) -> Box<Expr> {
+        if self.is_complex_operand(&op) {
+            let span = op.span.clone();
+            let new_sym_name = self.new_sym_name();
+            let folded_op = self.fold(op);
+            bindings.push((new_sym_name.clone(), folded_op));
+            Expr::var(&new_sym_name).at(span)
+        } else {
+            op
+        }
+    }
+
+    fn is_complex_operand(&self, op: &Expr) -> bool {
+        match &op.kind {
+            ExprKind::Lit(_) => false,
+            ExprKind::Var(sym) => self.assigned.contains(sym),
+            _ => true,
         }
     }
 }
 
-fn is_complex_operand(op: &Expr) -> bool {
-    match *op {
-        Expr::Lit(_) => false,
-        Expr::Var(_) => false,
-        _ => true,
+/// Wraps the expression in let bindings, with the first binding outermost.
+fn wrap_bindings(expr: Box<Expr>, bindings: Vec<(String, Box<Expr>)>, span: Span) -> Box<Expr> {
+    bindings.into_iter().rev().fold(expr, |body, (name, val)| {
+        Expr::let_bind(&name, val, body).at(span.clone())
+    })
+}
+
+impl ExprFolder for ExprArgSimplifier {
+    fn fold(&mut self, e: Box<Expr>) -> Box<Expr> {
+        let Expr { kind, span } = *e;
+        let mut bindings = vec![];
+        let kind = match kind {
+            ExprKind::Read => ExprKind::Read,         // No args so just return
+            ExprKind::Lit(lit) => ExprKind::Lit(lit), // Primitive so no args so just return
+            ExprKind::Neg(op) => ExprKind::Neg(self.simplify_operand(op, &mut bindings)),
+            ExprKind::Add(op1, op2) => {
+                let op1 = self.simplify_operand(op1, &mut bindings);
+                let op2 = self.simplify_operand(op2, &mut bindings);
+                ExprKind::Add(op1, op2)
+            }
+            ExprKind::Sub(op1, op2) => {
+                let op1 = self.simplify_operand(op1, &mut bindings);
+                let op2 = self.simplify_operand(op2, &mut bindings);
+                ExprKind::Sub(op1, op2)
+            }
+            ExprKind::Mul(op1, op2) => {
+                let op1 = self.simplify_operand(op1, &mut bindings);
+                let op2 = self.simplify_operand(op2, &mut bindings);
+                ExprKind::Mul(op1, op2)
+            }
+            ExprKind::Quotient(op1, op2) => {
+                let op1 = self.simplify_operand(op1, &mut bindings);
+                let op2 = self.simplify_operand(op2, &mut bindings);
+                ExprKind::Quotient(op1, op2)
+            }
+            ExprKind::Remainder(op1, op2) => {
+                let op1 = self.simplify_operand(op1, &mut bindings);
+                let op2 = self.simplify_operand(op2, &mut bindings);
+                ExprKind::Remainder(op1, op2)
+            }
+            ExprKind::Not(op) => ExprKind::Not(self.simplify_operand(op, &mut bindings)),
+            ExprKind::Cmp(cmp, op1, op2) => {
+                let op1 = self.simplify_operand(op1, &mut bindings);
+                let op2 = self.simplify_operand(op2, &mut bindings);
+                ExprKind::Cmp(cmp, op1, op2)
+            }
+            ExprKind::Vector(elems) => ExprKind::Vector(
+                elems
+                    .into_iter()
+                    .map(|e| *self.simplify_operand(Box::new(e), &mut bindings))
+                    .collect(),
+            ),
+            // Indices are always literals.
+            ExprKind::VectorRef(v, index) => {
+                ExprKind::VectorRef(self.simplify_operand(v, &mut bindings), index)
+            }
+            ExprKind::VectorSet(v, index, e) => {
+                let v = self.simplify_operand(v, &mut bindings);
+                let e = self.simplify_operand(e, &mut bindings);
+                ExprKind::VectorSet(v, index, e)
+            }
+            ExprKind::Inject(e, ty) => {
+                ExprKind::Inject(self.simplify_operand(e, &mut bindings), ty)
+            }
+            ExprKind::Project(e, ty) => {
+                ExprKind::Project(self.simplify_operand(e, &mut bindings), ty)
+            }
+            ExprKind::AnyVectorRef(v, index) => {
+                ExprKind::AnyVectorRef(self.simplify_operand(v, &mut bindings), index)
+            }
+            ExprKind::AnyVectorSet(v, index, e) => {
+                let v = self.simplify_operand(v, &mut bindings);
+                let e = self.simplify_operand(e, &mut bindings);
+                ExprKind::AnyVectorSet(v, index, e)
+            }
+            ExprKind::Print(e) => ExprKind::Print(self.simplify_operand(e, &mut bindings)),
+            ExprKind::Apply(f, args) => {
+                let f = self.simplify_operand(f, &mut bindings);
+                let args = args
+                    .into_iter()
+                    .map(|e| *self.simplify_operand(Box::new(e), &mut bindings))
+                    .collect();
+                ExprKind::Apply(f, args)
+            }
+            kind @ ExprKind::Void
+            | kind @ ExprKind::Collect(_)
+            | kind @ ExprKind::Allocate(_, _)
+            | kind @ ExprKind::GlobalValue(_)
+            | kind @ ExprKind::FunRef(_) => kind, // No args so just return
+            // The operands of `and`, `or` and `if` are evaluated conditionally
+            // so they are not lifted into bindings.
+            ExprKind::And(e1, e2) => ExprKind::And(self.fold(e1), self.fold(e2)),
+            ExprKind::Or(e1, e2) => ExprKind::Or(self.fold(e1), self.fold(e2)),
+            ExprKind::If(cond, then, els) => {
+                ExprKind::If(self.fold(cond), self.fold(then), self.fold(els))
+            }
+            ExprKind::Var(sym) => ExprKind::Var(sym), // Return var
+            ExprKind::Let(sym, e, body) => ExprKind::Let(sym, self.fold(e), self.fold(body)), // Recurse down e and body
+            ExprKind::Lambda(params, body) => ExprKind::Lambda(params, self.fold(body)),
+            // Like the value of a let, the value assigned by `set!` and the
+            // expressions of `begin` and `while` may be complex.
+            ExprKind::Set(sym, e) => ExprKind::Set(sym, self.fold(e)),
+            ExprKind::Begin(effects, e) => ExprKind::Begin(
+                effects
+                    .into_iter()
+                    .map(|e| *self.fold(Box::new(e)))
+                    .collect(),
+                self.fold(e),
+            ),
+            ExprKind::While(cond, body) => ExprKind::While(self.fold(cond), self.fold(body)),
+        };
+        wrap_bindings(Expr::new(kind, span.clone()), bindings, span)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::{Expr, ExprFolder};
+    use super::super::parse::parse_expr;
+    use super::super::{CmpOp, Expr, ExprFolder, ExprKind, Symbol};
     use super::ExprArgSimplifier;
 
     #[test]
@@ -108,17 +194,10 @@ mod tests {
 
     #[test]
     fn basic_add_and_neg() {
-        let expr = Expr::add(
-            Expr::int(52),
-            Expr::neg(
-                Expr::int(10)
-            ),
-        );
+        let expr = Expr::add(Expr::int(52), Expr::neg(Expr::int(10)));
         let expected = Expr::let_bind(
             "v200000",
-            Expr::neg(
-                Expr::int(10)
-            ),
+            Expr::neg(Expr::int(10)),
             Expr::add(Expr::int(52), Expr::var("v200000")),
         );
 
@@ -137,6 +216,24 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn simplify_sub_args() {
+        let expr = Expr::sub(Expr::read(), Expr::neg(Expr::int(10)));
+        let expected = Expr::let_bind(
+            "v200000",
+            Expr::read(),
+            Expr::let_bind(
+                "v200001",
+                Expr::neg(Expr::int(10)),
+                Expr::sub(Expr::var("v200000"), Expr::var("v200001")),
+            ),
+        );
+
+        let mut ctx = ExprArgSimplifier::new(200_000);
+        let actual = ctx.fold(expr);
+        assert_eq!(actual, expected);
+    }
+
     #[test]
     fn simplify_add_args() {
         let expr = Expr::add(
@@ -177,4 +274,57 @@ mod tests {
         let actual = ctx.fold(expr);
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn copies_assigned_variables() {
+        let expr = parse_expr("(+ x (begin (set! x 10) x))").unwrap();
+        let expected = parse_expr(
+            "(let ([v200000 x]) (let ([v200001 (begin (set! x 10) x)]) (+ v200000 v200001)))",
+        )
+        .unwrap();
+
+        let mut ctx = ExprArgSimplifier::new(200_000);
+        ctx.assigned.insert(Symbol::new("x"));
+        let actual = ctx.fold(expr);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn simplify_cmp_args_but_not_branches() {
+        let expr = Expr::if_expr(
+            Expr::cmp(CmpOp::Lt, Expr::read(), Expr::int(0)),
+            Expr::neg(Expr::read()),
+            Expr::int(1),
+        );
+        let expected = Expr::if_expr(
+            Expr::let_bind(
+                "v200000",
+                Expr::read(),
+                Expr::cmp(CmpOp::Lt, Expr::var("v200000"), Expr::int(0)),
+            ),
+            Expr::let_bind("v200001", Expr::read(), Expr::neg(Expr::var("v200001"))),
+            Expr::int(1),
+        );
+
+        let mut ctx = ExprArgSimplifier::new(200_000);
+        let actual = ctx.fold(expr);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn spans_are_carried() {
+        let expr = parse_expr("(- (read))").unwrap();
+        let mut ctx = ExprArgSimplifier::new(200_000);
+        let actual = ctx.fold(expr);
+        assert_eq!((actual.span.lo, actual.span.hi), (0, 10));
+        let (val, body) = match actual.kind {
+            ExprKind::Let(_, val, body) => (val, body),
+            _ => panic!("expected a let"),
+        };
+        assert_eq!((val.span.lo, val.span.hi), (3, 9));
+        match body.kind {
+            ExprKind::Neg(op) => assert_eq!((op.span.lo, op.span.hi), (3, 9)),
+            _ => panic!("expected a negation"),
+        }
+    }
 }
@@ -0,0 +1,220 @@
+//! Assignment conversion.
+//!
+//! Closures copy the values of the variables they capture, so a variable that
+//! is both assigned with `set!` and captured by a `lambda` is moved into a
+//! box, a vector of one element that the closures share instead. Reading the
+//! variable reads the box and assigning it writes the box, so
+//!
+//! ```text
+//! (let ([x 0]) (begin (set! x 1) (lambda () x)))
+//! ```
+//!
+//! becomes
+//!
+//! ```text
+//! (let ([b (vector 0)]) (begin (vector-set! b 0 1) (lambda () (vector-ref b 0))))
+//! ```
+//!
+//! A parameter that needs a box is copied into one at the start of the body of
+//! its function. Variables that are only assigned or only captured are left
+//! alone, and are recorded in `assigned` so that arg_simplify knows that
+//! reading one gives a value that a later `set!` can change.
+
+use super::super::diagnostic::Span;
+use super::convert_closures::free_vars;
+use super::typecheck::Type;
+use super::{Def, Expr, ExprFolder, ExprKind, Program, Symbol};
+use std::collections::{HashMap, HashSet};
+
+/// Runs after uniquify, so every variable of the program has its own name.
+pub struct AssignmentConverter {
+    pub counter: u64,
+
+    /// Maps each variable that needs a box to the new variable that holds
+    /// the box.
+    boxes: HashMap<Symbol, String>,
+
+    /// Variables assigned with `set!` that are left unboxed, found by
+    /// `fold_program`.
+    pub assigned: HashSet<Symbol>,
+}
+
+impl AssignmentConverter {
+    pub fn new(counter: u64) -> AssignmentConverter {
+        AssignmentConverter {
+            counter,
+            boxes: HashMap::new(),
+            assigned: HashSet::new(),
+        }
+    }
+
+    pub fn new_sym_name(&mut self) -> String {
+        let name = format!("v{}", self.counter);
+        self.counter += 1;
+        name
+    }
+
+    /// Boxes the variables of every function and the program's expression
+    /// that need it.
+    pub fn fold_program(&mut self, p: Program) -> Program {
+        let mut assigned = HashSet::new();
+        let mut captured = HashSet::new();
+        for def in &p.defs {
+            scan(&def.body, &mut assigned, &mut captured);
+        }
+        scan(&p.expr, &mut assigned, &mut captured);
+        let mut needs_box = assigned
+            .intersection(&captured)
+            .cloned()
+            .collect::<Vec<Symbol>>();
+        needs_box.sort_by(|a, b| a.value.cmp(&b.value));
+        for sym in needs_box {
+            assigned.remove(&sym);
+            let name = self.new_sym_name();
+            self.boxes.insert(sym, name);
+        }
+        self.assigned = assigned;
+
+        let defs = p
+            .defs
+            .into_iter()
+            .map(|def| {
+                let body = self.fold(def.body);
+                Def {
+                    body: self.box_params(&def.params, body),
+                    ..def
+                }
+            })
+            .collect();
+        Program {
+            defs,
+            expr: self.fold(p.expr),
+        }
+    }
+
+    /// Wraps the body of a function in bindings of boxes for the parameters
+    /// that need them.
+    fn box_params(&self, params: &[(Box<Symbol>, Type)], body: Box<Expr>) -> Box<Expr> {
+        let span = body.span.clone();
+        params
+            .iter()
+            .rev()
+            .fold(body, |body, (param, _)| match self.boxes.get(param) {
+                Some(name) => {
+                    let val = Expr::vector(vec![*Expr::var(&param.value).at(span.clone())]);
+                    Expr::let_bind(name, val.at(span.clone()), body).at(span.clone())
+                }
+                None => body,
+            })
+    }
+}
+
+/// Adds the variables assigned within the expression to `assigned` and those
+/// captured by a `lambda` within it to `captured`.
+fn scan(expr: &Expr, assigned: &mut HashSet<Symbol>, captured: &mut HashSet<Symbol>) {
+    match &expr.kind {
+        ExprKind::Set(sym, _) => {
+            assigned.insert((**sym).clone());
+        }
+        ExprKind::Lambda(_, _) => captured.extend(free_vars(expr)),
+        _ => {}
+    }
+    for child in expr.children() {
+        scan(child, assigned, captured);
+    }
+}
+
+impl ExprFolder for AssignmentConverter {
+    fn fold_var(&mut self, s: Box<Symbol>, span: Span) -> Box<Expr> {
+        match self.boxes.get(&s) {
+            Some(name) => Expr::vector_ref(Expr::var(name).at(span.clone()), 0).at(span),
+            None => Expr::new(ExprKind::Var(s), span),
+        }
+    }
+
+    fn fold_set(&mut self, sym: Box<Symbol>, e: Box<Expr>, span: Span) -> Box<Expr> {
+        let e = self.fold(e);
+        match self.boxes.get(&sym) {
+            Some(name) => Expr::vector_set(Expr::var(name).at(span.clone()), 0, e).at(span),
+            None => Expr::new(ExprKind::Set(sym, e), span),
+        }
+    }
+
+    fn fold_let(
+        &mut self,
+        sym: Box<Symbol>,
+        e: Box<Expr>,
+        body: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
+        let e = self.fold(e);
+        let body = self.fold(body);
+        match self.boxes.get(&sym) {
+            Some(name) => {
+                let val = Expr::vector(vec![*e]).at(span.clone());
+                Expr::let_bind(name, val, body).at(span)
+            }
+            None => Expr::new(ExprKind::Let(sym, e, body), span),
+        }
+    }
+
+    fn fold_lambda(
+        &mut self,
+        params: Vec<(Box<Symbol>, Type)>,
+        body: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
+        let body = self.fold(body);
+        let body = self.box_params(&params, body);
+        Expr::new(ExprKind::Lambda(params, body), span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse::parse_program;
+    use super::super::uniquify::ExprUniquifier;
+    use super::AssignmentConverter;
+
+    fn convert(src: &str) -> String {
+        let mut uniquify_ctx = ExprUniquifier::new(12345);
+        let prog = uniquify_ctx.fold_program(parse_program(src).unwrap());
+        assert!(uniquify_ctx.diagnostics.is_empty());
+        let mut ctx = AssignmentConverter::new(uniquify_ctx.counter);
+        ctx.fold_program(prog).to_string()
+    }
+
+    #[test]
+    fn boxes_assigned_captured_variables() {
+        let src = "\
+(let ([x 0])
+  (let ([y 1])
+    (let ([f (lambda () (+ x y))])
+      (begin (set! x 2) (set! f f) (f)))))";
+        let expected = "\
+(let ([v12348 (vector 0)])
+  (let ([v12346 1])
+    (let ([v12347 (lambda () (+ (vector-ref v12348 0) v12346))])
+      (begin (vector-set! v12348 0 2) (set! v12347 v12347) (v12347)))))
+";
+        assert_eq!(convert(src), expected);
+    }
+
+    #[test]
+    fn boxes_parameters() {
+        let src = "\
+(define (counter [n : Integer]) : (-> Integer)
+  (lambda () (begin (set! n (+ n 1)) n)))
+((counter 0))";
+        let expected = "\
+(define (f12345 [v12346 : Integer]) : (-> Integer)
+  (let ([v12347 (vector v12346)])
+    (lambda ()
+      (begin (vector-set! v12347 0 (+ (vector-ref v12347 0) 1))
+             (vector-ref v12347 0)))))
+
+(((fun-ref f12345) 0))
+";
+        assert_eq!(convert(src), expected);
+    }
+}
@@ -0,0 +1,437 @@
+//! Closure conversion.
+//!
+//! Every function value becomes a closure: a vector whose first element
+//! refers to the code of the function and whose other elements are the values
+//! of the variables the function captures. Each `lambda` is lifted to a new
+//! top-level function that takes its closure as an extra first parameter and
+//! reads the captured variables back out of it, so
+//!
+//! ```text
+//! (lambda ([x : Integer]) (+ x y))
+//! ```
+//!
+//! becomes `(vector (fun-ref f) y)` together with
+//!
+//! ```text
+//! (define (f [c : (Vector ((Vector) Integer -> Integer) Integer)] [x : Integer]) : Integer
+//!   (let ([y (vector-ref c 1)]) (+ x y)))
+//! ```
+//!
+//! Calling a function value passes the closure to the code it holds, so
+//! `(g 1)` becomes `((vector-ref g 0) g 1)`. Calls of functions defined at the
+//! top level by name stay direct calls. When such a function is used as a
+//! value, its closure refers to a lifted function that calls it directly.
+//!
+//! Function types change to match: `(Integer -> Integer)` becomes
+//! `(Vector ((Vector) Integer -> Integer))`, the type of a closure that
+//! captures nothing. The closure parameter of the code is written `(Vector)`,
+//! since the type of a closure cannot refer to itself.
+
+use super::super::diagnostic::Span;
+use super::typecheck::{Type, TypeChecker};
+use super::{Def, Expr, ExprFolder, ExprKind, Program, Symbol};
+use std::collections::HashMap;
+
+/// Runs after uniquify, so every variable it binds has a fresh name and the
+/// types of the variables in scope can be tracked by name.
+pub struct ClosureConverter {
+    pub counter: u64,
+
+    /// Types of the functions and variables in scope, as written before
+    /// conversion.
+    types: TypeChecker,
+
+    /// Functions lifted out of the program so far.
+    lifted: Vec<Def>,
+
+    /// Maps the name of each top-level function used as a value to the name
+    /// of the lifted function its closures refer to.
+    adapters: HashMap<String, String>,
+}
+
+impl ClosureConverter {
+    pub fn new(counter: u64) -> ClosureConverter {
+        ClosureConverter {
+            counter,
+            types: TypeChecker::new(),
+            lifted: vec![],
+            adapters: HashMap::new(),
+        }
+    }
+
+    pub fn new_sym_name(&mut self) -> String {
+        let name = format!("v{}", self.counter);
+        self.counter += 1;
+        name
+    }
+
+    fn new_fun_name(&mut self) -> String {
+        let name = format!("f{}", self.counter);
+        self.counter += 1;
+        name
+    }
+
+    /// Converts every function and the program's expression. The lifted
+    /// functions follow the functions of the program.
+    pub fn fold_program(&mut self, p: Program) -> Program {
+        for def in &p.defs {
+            self.types.bind(&def.name, Some(def.ty()));
+        }
+        let mut defs = p
+            .defs
+            .into_iter()
+            .map(|def| {
+                for (param, ty) in &def.params {
+                    self.types.bind(param, Some(ty.clone()));
+                }
+                let body = self.fold(def.body);
+                for _ in &def.params {
+                    self.types.unbind();
+                }
+                Def {
+                    params: convert_params(def.params),
+                    result: convert_type(&def.result),
+                    body,
+                    ..def
+                }
+            })
+            .collect::<Vec<Def>>();
+        let expr = self.fold(p.expr);
+        defs.append(&mut self.lifted);
+        Program { defs, expr }
+    }
+
+    /// Returns the name of a lifted function that ignores its closure and
+    /// calls the top-level function `name` with the rest of its arguments.
+    fn adapter(&mut self, name: &str, span: &Span) -> String {
+        if let Some(adapter) = self.adapters.get(name) {
+            return adapter.clone();
+        }
+        let (param_tys, result) = match self.types.lookup(&Symbol::new(name)) {
+            Some(Type::Function(params, result)) => (params, *result),
+            _ => unreachable!("fun-refs refer to functions of the program"),
+        };
+        let closure = self.new_sym_name();
+        let mut params = vec![];
+        for ty in &param_tys {
+            params.push((
+                Box::new(Symbol::new(&self.new_sym_name())),
+                convert_type(ty),
+            ));
+        }
+        let args = params
+            .iter()
+            .map(|(param, _)| *Expr::var(&param.value).at(span.clone()))
+            .collect();
+        let body = Expr::apply(Expr::fun_ref(name).at(span.clone()), args).at(span.clone());
+        let closure_ty = closure_type(&param_tys, &result, vec![]);
+        params.insert(0, (Box::new(Symbol::new(&closure)), closure_ty));
+        let adapter = self.new_fun_name();
+        self.lifted.push(Def {
+            name: Box::new(Symbol::new(&adapter)),
+            params,
+            result: convert_type(&result),
+            body,
+            span: span.clone(),
+        });
+        self.adapters.insert(name.to_string(), adapter.clone());
+        adapter
+    }
+}
+
+/// Type of a closure of a function with the given parameter and result types
+/// that captures variables of the `captured` types.
+fn closure_type(params: &[Type], result: &Type, captured: Vec<Type>) -> Type {
+    let mut code_params = vec![Type::Vector(vec![])];
+    code_params.extend(params.iter().map(convert_type));
+    let code = Type::Function(code_params, Box::new(convert_type(result)));
+    let mut elems = vec![code];
+    elems.extend(captured);
+    Type::Vector(elems)
+}
+
+/// Replaces each function type within the type by the type of a closure that
+/// captures nothing.
+fn convert_type(ty: &Type) -> Type {
+    match ty {
+        Type::Function(params, result) => closure_type(params, result, vec![]),
+        Type::Vector(elems) => Type::Vector(elems.iter().map(convert_type).collect()),
+        ty => ty.clone(),
+    }
+}
+
+fn convert_params(params: Vec<(Box<Symbol>, Type)>) -> Vec<(Box<Symbol>, Type)> {
+    params
+        .into_iter()
+        .map(|(param, ty)| (param, convert_type(&ty)))
+        .collect()
+}
+
+impl ExprFolder for ClosureConverter {
+    fn fold_let(
+        &mut self,
+        sym: Box<Symbol>,
+        e: Box<Expr>,
+        body: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
+        let ty = self.types.check(&e);
+        let e = self.fold(e);
+        self.types.bind(&sym, ty);
+        let body = self.fold(body);
+        self.types.unbind();
+        Expr::new(ExprKind::Let(sym, e, body), span)
+    }
+
+    fn fold_apply(&mut self, f: Box<Expr>, args: Vec<Expr>, span: Span) -> Box<Expr> {
+        if let ExprKind::FunRef(_) = f.kind {
+            let args = args.into_iter().map(|e| *self.fold(Box::new(e))).collect();
+            return Expr::new(ExprKind::Apply(f, args), span);
+        }
+        let f = self.fold(f);
+        let mut args = args
+            .into_iter()
+            .map(|e| *self.fold(Box::new(e)))
+            .collect::<Vec<Expr>>();
+        let (closure, binding) = match f.kind {
+            ExprKind::Var(sym) => (sym.value, None),
+            _ => (self.new_sym_name(), Some(f)),
+        };
+        let code = Expr::vector_ref(Expr::var(&closure).at(span.clone()), 0).at(span.clone());
+        args.insert(0, *Expr::var(&closure).at(span.clone()));
+        let call = Expr::new(ExprKind::Apply(code, args), span.clone());
+        match binding {
+            Some(f) => Expr::let_bind(&closure, f, call).at(span),
+            None => call,
+        }
+    }
+
+    fn fold_inject(&mut self, e: Box<Expr>, ty: Type, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Inject(self.fold(e), convert_type(&ty)), span)
+    }
+
+    fn fold_project(&mut self, e: Box<Expr>, ty: Type, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Project(self.fold(e), convert_type(&ty)), span)
+    }
+
+    fn fold_fun_ref(&mut self, name: String, span: Span) -> Box<Expr> {
+        let adapter = self.adapter(&name, &span);
+        Expr::vector(vec![*Expr::fun_ref(&adapter).at(span.clone())]).at(span)
+    }
+
+    fn fold_lambda(
+        &mut self,
+        params: Vec<(Box<Symbol>, Type)>,
+        body: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
+        let mut bound = params.iter().map(|(param, _)| &**param).collect();
+        let mut captured = vec![];
+        collect_free_vars(&body, &mut bound, &mut captured);
+        let captured_tys = captured
+            .iter()
+            .map(|sym| {
+                convert_type(
+                    &self
+                        .types
+                        .lookup(sym)
+                        .expect("captured variables are typed"),
+                )
+            })
+            .collect();
+
+        for (param, ty) in &params {
+            self.types.bind(param, Some(ty.clone()));
+        }
+        let result = self
+            .types
+            .check(&body)
+            .expect("convert_closures runs on well-typed programs");
+        let mut body = self.fold(body);
+        for _ in &params {
+            self.types.unbind();
+        }
+
+        let name = self.new_fun_name();
+        let closure = self.new_sym_name();
+        for (i, sym) in captured.iter().enumerate().rev() {
+            let elem = Expr::vector_ref(Expr::var(&closure).at(span.clone()), i as i64 + 1);
+            body = Expr::let_bind(&sym.value, elem.at(span.clone()), body).at(span.clone());
+        }
+        let param_tys = params.iter().map(|(_, ty)| ty.clone()).collect::<Vec<_>>();
+        let closure_ty = closure_type(&param_tys, &result, captured_tys);
+        let mut lifted_params = vec![(Box::new(Symbol::new(&closure)), closure_ty)];
+        lifted_params.extend(convert_params(params));
+        self.lifted.push(Def {
+            name: Box::new(Symbol::new(&name)),
+            params: lifted_params,
+            result: convert_type(&result),
+            body,
+            span: span.clone(),
+        });
+
+        let mut elems = vec![*Expr::fun_ref(&name).at(span.clone())];
+        elems.extend(
+            captured
+                .iter()
+                .map(|sym| *Expr::var(&sym.value).at(span.clone())),
+        );
+        Expr::vector(elems).at(span)
+    }
+}
+
+/// Variables that occur free in the expression, in the order of their first
+/// occurrence.
+pub fn free_vars(expr: &Expr) -> Vec<Symbol> {
+    let mut free = vec![];
+    collect_free_vars(expr, &mut vec![], &mut free);
+    free
+}
+
+/// Adds the variables that occur free in the expression to `free`, skipping
+/// those already there. `bound` holds the variables bound around the
+/// expression that do not count as free.
+fn collect_free_vars<'a>(expr: &'a Expr, bound: &mut Vec<&'a Symbol>, free: &mut Vec<Symbol>) {
+    match &expr.kind {
+        ExprKind::Var(sym) => {
+            if !bound.contains(&&**sym) && !free.contains(sym) {
+                free.push((**sym).clone());
+            }
+        }
+        ExprKind::Let(sym, e, body) => {
+            collect_free_vars(e, bound, free);
+            bound.push(sym);
+            collect_free_vars(body, bound, free);
+            bound.pop();
+        }
+        ExprKind::Lambda(params, body) => {
+            let len = bound.len();
+            bound.extend(params.iter().map(|(param, _)| &**param));
+            collect_free_vars(body, bound, free);
+            bound.truncate(len);
+        }
+        ExprKind::Neg(e)
+        | ExprKind::Not(e)
+        | ExprKind::Print(e)
+        | ExprKind::Inject(e, _)
+        | ExprKind::Project(e, _) => collect_free_vars(e, bound, free),
+        ExprKind::Add(e1, e2)
+        | ExprKind::Sub(e1, e2)
+        | ExprKind::Mul(e1, e2)
+        | ExprKind::Quotient(e1, e2)
+        | ExprKind::Remainder(e1, e2)
+        | ExprKind::And(e1, e2)
+        | ExprKind::Or(e1, e2)
+        | ExprKind::Cmp(_, e1, e2)
+        | ExprKind::VectorRef(e1, e2)
+        | ExprKind::AnyVectorRef(e1, e2) => {
+            collect_free_vars(e1, bound, free);
+            collect_free_vars(e2, bound, free);
+        }
+        ExprKind::If(e1, e2, e3)
+        | ExprKind::VectorSet(e1, e2, e3)
+        | ExprKind::AnyVectorSet(e1, e2, e3) => {
+            collect_free_vars(e1, bound, free);
+            collect_free_vars(e2, bound, free);
+            collect_free_vars(e3, bound, free);
+        }
+        ExprKind::Vector(elems) => {
+            for e in elems {
+                collect_free_vars(e, bound, free);
+            }
+        }
+        ExprKind::Apply(f, args) => {
+            collect_free_vars(f, bound, free);
+            for e in args {
+                collect_free_vars(e, bound, free);
+            }
+        }
+        ExprKind::Set(sym, e) => {
+            if !bound.contains(&&**sym) && !free.contains(sym) {
+                free.push((**sym).clone());
+            }
+            collect_free_vars(e, bound, free);
+        }
+        ExprKind::Begin(effects, e) => {
+            for effect in effects {
+                collect_free_vars(effect, bound, free);
+            }
+            collect_free_vars(e, bound, free);
+        }
+        ExprKind::While(cond, body) => {
+            collect_free_vars(cond, bound, free);
+            collect_free_vars(body, bound, free);
+        }
+        ExprKind::Read
+        | ExprKind::Lit(_)
+        | ExprKind::Void
+        | ExprKind::Collect(_)
+        | ExprKind::Allocate(_, _)
+        | ExprKind::GlobalValue(_)
+        | ExprKind::FunRef(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse::{parse_expr, parse_program};
+    use super::super::uniquify::ExprUniquifier;
+    use super::super::{ExprFolder, Program, Symbol};
+    use super::{free_vars, ClosureConverter};
+
+    #[test]
+    fn free_variables() {
+        let expr =
+            parse_expr("(let ([a b]) (lambda ([x : Integer]) (+ (+ x a) (+ c b))))").unwrap();
+        assert_eq!(free_vars(&expr), vec![Symbol::new("b"), Symbol::new("c")]);
+        let expr = parse_expr("(lambda ([x : Integer]) (let ([y x]) (f y z)))").unwrap();
+        assert_eq!(free_vars(&expr), vec![Symbol::new("f"), Symbol::new("z")]);
+    }
+
+    #[test]
+    fn lifts_lambdas() {
+        let expr = parse_expr("(let ([y 1]) ((lambda ([x : Integer]) (+ x y)) 2))").unwrap();
+        let mut ctx = ClosureConverter::new(1);
+        let actual = ctx.fold(expr);
+        let expected =
+            "(let ([y 1]) (let ([v3 (vector (fun-ref f1) y)]) ((vector-ref v3 0) v3 2)))";
+        assert_eq!(actual.to_string(), expected);
+        assert_eq!(ctx.lifted.len(), 1);
+        let expected = "\
+(define (f1 [v2 : (Vector ((Vector) Integer -> Integer) Integer)] [x : Integer]) : Integer
+  (let ([y (vector-ref v2 1)]) (+ x y)))
+
+1
+";
+        let prog = Program {
+            defs: ctx.lifted.clone(),
+            expr: parse_expr("1").unwrap(),
+        };
+        assert_eq!(prog.to_string(), expected);
+    }
+
+    #[test]
+    fn functions_as_values() {
+        let src = "\
+(define (inc [x : Integer]) : Integer (+ x 1))
+(define (twice [f : (Integer -> Integer)] [x : Integer]) : Integer (f (f x)))
+(twice inc (inc 1))";
+        let mut uniquify = ExprUniquifier::new(1);
+        let prog = uniquify.fold_program(parse_program(src).unwrap());
+        let mut ctx = ClosureConverter::new(uniquify.counter);
+        let actual = ctx.fold_program(prog);
+        let expected = "\
+(define (f1 [v3 : Integer]) : Integer
+  (+ v3 1))
+
+(define (f2 [v4 : (Vector ((Vector) Integer -> Integer))] [v5 : Integer]) : Integer
+  ((vector-ref v4 0) v4 ((vector-ref v4 0) v4 v5)))
+
+(define (f8 [v6 : (Vector ((Vector) Integer -> Integer))] [v7 : Integer]) : Integer
+  ((fun-ref f1) v7))
+
+((fun-ref f2) (vector (fun-ref f8)) ((fun-ref f1) 1))
+";
+        assert_eq!(actual.to_string(), expected);
+    }
+}
@@ -1,118 +1,381 @@
 use super::super::cir;
-use super::{Expr, Lit, Program};
+use super::super::diagnostic::{Diagnostic, Span};
+use super::super::error::CompileError;
+use super::{CmpOp, Expr, ExprKind, Lit, Program};
 use std::collections::HashMap;
 
-fn prepend_expr_to_tail(
-    expr: Box<cir::Expr>,
-    assign_to_with_tail: Option<(&str, Box<cir::Tail>)>,
-) -> Box<cir::Tail> {
-    match assign_to_with_tail {
-        None => cir::Tail::ret(expr),
-        Some((assign_to, tail)) => cir::Tail::seq(cir::Stmt::assign(assign_to, expr), tail),
+fn fold_op(expr: Expr) -> Result<Box<cir::Arg>, CompileError> {
+    match expr.kind {
+        ExprKind::Lit(Lit::Int(i)) => Ok(cir::Arg::int(i)),
+        ExprKind::Lit(Lit::Bool(b)) => Ok(cir::Arg::bool(b)),
+        ExprKind::Var(sym) => Ok(cir::Arg::var(&sym.value)),
+        // The arg_simplify pass should have converted all operands into vars
+        // or lits.
+        _ => Err(CompileError::Explicate(Diagnostic::new(
+            "expected a variable or literal operand".to_string(),
+            expr.span,
+        ))),
     }
 }
 
-fn fold_op(expr: Expr) -> Box<cir::Arg> {
-    match expr {
-        Expr::Lit(Lit::Int(i)) => cir::Arg::int(i),
-        Expr::Var(sym) => cir::Arg::var(&sym.value),
-        _ => panic!("uniquify pass should have converted all operands into vars or lits"),
+fn fold_index(expr: Expr) -> Result<usize, CompileError> {
+    match expr.kind {
+        ExprKind::Lit(Lit::Int(i)) if i >= 0 => Ok(i as usize),
+        _ => Err(CompileError::Explicate(Diagnostic::new(
+            "expected a literal index".to_string(),
+            expr.span,
+        ))),
     }
 }
 
-fn fold_let_assign(assign_to: &str, expr: Expr, tail: Box<cir::Tail>) -> Box<cir::Tail> {
-    match expr {
-        Expr::Read => {
-            let assign_val = cir::Expr::read();
-            cir::Tail::seq(cir::Stmt::assign(assign_to, assign_val), tail)
+/// Folds an expression that involves no control flow into a CIR expression.
+fn fold_simple_expr(expr: Expr) -> Result<Box<cir::Expr>, CompileError> {
+    let span = expr.span;
+    match expr.kind {
+        ExprKind::Read => Ok(cir::Expr::read()),
+        ExprKind::Lit(Lit::Int(i)) => Ok(cir::Expr::arg(cir::Arg::int(i))),
+        ExprKind::Lit(Lit::Bool(b)) => Ok(cir::Expr::arg(cir::Arg::bool(b))),
+        ExprKind::Var(sym) => Ok(cir::Expr::arg(cir::Arg::var(&sym.value))),
+        ExprKind::Neg(op) => Ok(cir::Expr::neg(fold_op(*op)?)),
+        ExprKind::Add(op1, op2) => Ok(cir::Expr::add(fold_op(*op1)?, fold_op(*op2)?)),
+        ExprKind::Sub(op1, op2) => Ok(cir::Expr::sub(fold_op(*op1)?, fold_op(*op2)?)),
+        ExprKind::Mul(op1, op2) => Ok(cir::Expr::mul(fold_op(*op1)?, fold_op(*op2)?)),
+        ExprKind::Quotient(op1, op2) => Ok(cir::Expr::quotient(fold_op(*op1)?, fold_op(*op2)?)),
+        ExprKind::Remainder(op1, op2) => Ok(cir::Expr::remainder(fold_op(*op1)?, fold_op(*op2)?)),
+        ExprKind::Not(op) => Ok(cir::Expr::not(fold_op(*op)?)),
+        ExprKind::Cmp(op, op1, op2) => Ok(cir::Expr::cmp(op, fold_op(*op1)?, fold_op(*op2)?)),
+        ExprKind::Void => Ok(cir::Expr::void()),
+        ExprKind::Collect(bytes) => Ok(cir::Expr::collect(bytes)),
+        ExprKind::Allocate(len, ty) => Ok(cir::Expr::allocate(len, ty)),
+        ExprKind::GlobalValue(name) => Ok(cir::Expr::global_value(&name)),
+        ExprKind::VectorRef(v, index) => {
+            Ok(cir::Expr::vector_ref(fold_op(*v)?, fold_index(*index)?))
         }
-        Expr::Lit(Lit::Int(i)) => {
-            let assign_val = cir::Expr::arg(cir::Arg::int(i));
-            cir::Tail::seq(cir::Stmt::assign(assign_to, assign_val), tail)
+        ExprKind::VectorSet(v, index, e) => {
+            let v = fold_op(*v)?;
+            let index = fold_index(*index)?;
+            Ok(cir::Expr::vector_set(v, index, fold_op(*e)?))
         }
-        Expr::Neg(op) => {
-            let assign_val = cir::Expr::neg(fold_op(*op));
-            cir::Tail::seq(cir::Stmt::assign(assign_to, assign_val), tail)
+        ExprKind::Inject(e, ty) => Ok(cir::Expr::inject(fold_op(*e)?, ty)),
+        ExprKind::Project(e, ty) => Ok(cir::Expr::project(fold_op(*e)?, ty)),
+        ExprKind::AnyVectorRef(v, index) => {
+            Ok(cir::Expr::any_vector_ref(fold_op(*v)?, fold_index(*index)?))
         }
-        Expr::Add(op1, op2) => {
-            let assign_val = cir::Expr::add(fold_op(*op1), fold_op(*op2));
-            cir::Tail::seq(cir::Stmt::assign(assign_to, assign_val), tail)
+        ExprKind::AnyVectorSet(v, index, e) => {
+            let v = fold_op(*v)?;
+            let index = fold_index(*index)?;
+            Ok(cir::Expr::any_vector_set(v, index, fold_op(*e)?))
         }
-        Expr::Var(sym) => {
-            let assign_val = cir::Expr::arg(cir::Arg::var(&sym.value));
-            cir::Tail::seq(cir::Stmt::assign(assign_to, assign_val), tail)
-        }
-        Expr::Let(sym, assn, body) => {
-            let tail_with_parent_assn = fold_let_body(*body, Some((assign_to, tail)));
-            fold_let_assign(&sym.value, *assn, tail_with_parent_assn)
+        ExprKind::Print(e) => Ok(cir::Expr::print(fold_op(*e)?)),
+        ExprKind::FunRef(name) => Ok(cir::Expr::fun_ref(&name)),
+        ExprKind::Apply(f, args) => {
+            let f = fold_op(*f)?;
+            let args = args
+                .into_iter()
+                .map(|arg| fold_op(arg).map(|arg| *arg))
+                .collect::<Result<Vec<cir::Arg>, CompileError>>()?;
+            Ok(cir::Expr::call(f, args))
         }
+        ExprKind::Vector(_) => Err(unexpected("`vector`", span)),
+        ExprKind::Let(_, _, _) => Err(unexpected("`let`", span)),
+        ExprKind::And(_, _) => Err(unexpected("`and`", span)),
+        ExprKind::Or(_, _) => Err(unexpected("`or`", span)),
+        ExprKind::If(_, _, _) => Err(unexpected("`if`", span)),
+        ExprKind::Lambda(_, _) => Err(unexpected("`lambda`", span)),
+        ExprKind::Set(_, _) => Err(unexpected("`set!`", span)),
+        ExprKind::Begin(_, _) => Err(unexpected("`begin`", span)),
+        ExprKind::While(_, _) => Err(unexpected("`while`", span)),
     }
 }
 
-fn fold_let_body(
-    expr: Expr,
-    assign_to_with_tail: Option<(&str, Box<cir::Tail>)>,
-) -> Box<cir::Tail> {
-    match expr {
-        Expr::Read => {
-            let c_expr = cir::Expr::read();
-            prepend_expr_to_tail(c_expr, assign_to_with_tail)
-        }
-        Expr::Lit(Lit::Int(i)) => {
-            let c_expr = cir::Expr::arg(cir::Arg::int(i));
-            prepend_expr_to_tail(c_expr, assign_to_with_tail)
+fn unexpected(what: &str, span: Span) -> CompileError {
+    CompileError::Explicate(Diagnostic::new(format!("unexpected {}", what), span))
+}
+
+/// Rewrites `and` and `or` as `if` so that only `if` needs to be explicated.
+fn desugar(expr: Expr) -> Expr {
+    let Expr { kind, span } = expr;
+    let kind = match kind {
+        ExprKind::And(e1, e2) => ExprKind::If(e1, e2, Expr::bool(false).at(span.clone())),
+        ExprKind::Or(e1, e2) => ExprKind::If(e1, Expr::bool(true).at(span.clone()), e2),
+        kind => kind,
+    };
+    Expr { kind, span }
+}
+
+/// Collects the blocks created while explicating control flow.
+struct Ctx {
+    tails: HashMap<cir::Label, cir::Tail>,
+    counter: u64,
+
+    /// Starts the label of each block, so that the blocks of different
+    /// functions have different labels.
+    prefix: String,
+}
+
+impl Ctx {
+    fn new(prefix: &str) -> Ctx {
+        Ctx {
+            tails: HashMap::new(),
+            counter: 0,
+            prefix: prefix.to_string(),
         }
-        Expr::Neg(op) => {
-            let c_expr = cir::Expr::neg(fold_op(*op));
-            prepend_expr_to_tail(c_expr, assign_to_with_tail)
+    }
+
+    /// Makes a name for a new block or variable that no other has.
+    fn new_name(&mut self, kind: &str) -> String {
+        self.counter += 1;
+        format!("{}{}_{}", self.prefix, kind, self.counter)
+    }
+
+    /// Returns a label for the tail, adding a new block for it unless it is
+    /// already just a jump.
+    #[allow(clippy::boxed_local)] // Tails are always built boxed.
+    fn create_block(&mut self, tail: Box<cir::Tail>) -> String {
+        if let cir::Tail::Goto(label) = *tail {
+            return label.value;
         }
-        Expr::Add(op1, op2) => {
-            let c_expr = cir::Expr::add(fold_op(*op1), fold_op(*op2));
-            prepend_expr_to_tail(c_expr, assign_to_with_tail)
+        let label = self.new_name("block");
+        self.tails.insert(cir::Label::new(&label), *tail);
+        label
+    }
+
+    /// Folds an expression in tail position, whose value is returned.
+    fn fold_tail(&mut self, expr: Expr) -> Result<Box<cir::Tail>, CompileError> {
+        let expr = desugar(expr);
+        match expr.kind {
+            ExprKind::Let(sym, assn, body) => {
+                let tail = self.fold_tail(*body)?;
+                self.fold_assign(&sym.value, *assn, tail)
+            }
+            ExprKind::If(cond, then, els) => {
+                let then = self.fold_tail(*then)?;
+                let els = self.fold_tail(*els)?;
+                self.fold_pred(*cond, then, els)
+            }
+            ExprKind::Begin(effects, e) => {
+                let tail = self.fold_tail(*e)?;
+                self.fold_effects(effects, tail)
+            }
+            ExprKind::Set(_, _) | ExprKind::While(_, _) => {
+                self.fold_effect(expr, cir::Tail::ret(cir::Expr::void()))
+            }
+            _ => Ok(cir::Tail::ret(fold_simple_expr(expr)?)),
         }
-        Expr::Var(sym) => {
-            let c_expr = cir::Expr::arg(cir::Arg::var(&sym.value));
-            prepend_expr_to_tail(c_expr, assign_to_with_tail)
+    }
+
+    /// Folds an expression that is evaluated only for its effects before
+    /// continuing with `tail`.
+    fn fold_effect(
+        &mut self,
+        expr: Expr,
+        tail: Box<cir::Tail>,
+    ) -> Result<Box<cir::Tail>, CompileError> {
+        let expr = desugar(expr);
+        match expr.kind {
+            ExprKind::Lit(_) | ExprKind::Var(_) | ExprKind::Void | ExprKind::FunRef(_) => Ok(tail),
+            ExprKind::Set(sym, e) => self.fold_assign(&sym.value, *e, tail),
+            ExprKind::Let(sym, assn, body) => {
+                let tail = self.fold_effect(*body, tail)?;
+                self.fold_assign(&sym.value, *assn, tail)
+            }
+            ExprKind::If(cond, then, els) => {
+                let join = self.create_block(tail);
+                let then = self.fold_effect(*then, cir::Tail::goto(&join))?;
+                let els = self.fold_effect(*els, cir::Tail::goto(&join))?;
+                self.fold_pred(*cond, then, els)
+            }
+            ExprKind::Begin(effects, e) => {
+                let tail = self.fold_effect(*e, tail)?;
+                self.fold_effects(effects, tail)
+            }
+            // The loop block tests the condition and either runs the body,
+            // which jumps back to the loop block, or leaves the loop.
+            ExprKind::While(cond, body) => {
+                let exit = self.create_block(tail);
+                let header = self.new_name("loop");
+                let body = self.fold_effect(*body, cir::Tail::goto(&header))?;
+                let test = self.fold_pred(*cond, body, cir::Tail::goto(&exit))?;
+                self.tails.insert(cir::Label::new(&header), *test);
+                Ok(cir::Tail::goto(&header))
+            }
+            // Other expressions may have effects, such as reading input or
+            // trapping on overflow, so their values are assigned to a new
+            // variable that is never read.
+            _ => {
+                let unused = self.new_name("unused");
+                self.fold_assign(&unused, expr, tail)
+            }
         }
-        Expr::Let(sym, assn, body) => {
-            let tail = fold_let_body(*body, assign_to_with_tail);
-            fold_let_assign(&sym.value, *assn, tail)
+    }
+
+    /// Folds the effects of a `begin` before continuing with `tail`.
+    fn fold_effects(
+        &mut self,
+        effects: Vec<Expr>,
+        tail: Box<cir::Tail>,
+    ) -> Result<Box<cir::Tail>, CompileError> {
+        effects
+            .into_iter()
+            .rev()
+            .try_fold(tail, |tail, effect| self.fold_effect(effect, tail))
+    }
+
+    /// Folds an expression whose value is assigned to `assign_to` before
+    /// continuing with `tail`.
+    fn fold_assign(
+        &mut self,
+        assign_to: &str,
+        expr: Expr,
+        tail: Box<cir::Tail>,
+    ) -> Result<Box<cir::Tail>, CompileError> {
+        let expr = desugar(expr);
+        match expr.kind {
+            ExprKind::Let(sym, assn, body) => {
+                let tail_with_parent_assn = self.fold_assign(assign_to, *body, tail)?;
+                self.fold_assign(&sym.value, *assn, tail_with_parent_assn)
+            }
+            ExprKind::If(cond, then, els) => {
+                let join = self.create_block(tail);
+                let then = self.fold_assign(assign_to, *then, cir::Tail::goto(&join))?;
+                let els = self.fold_assign(assign_to, *els, cir::Tail::goto(&join))?;
+                self.fold_pred(*cond, then, els)
+            }
+            ExprKind::Begin(effects, e) => {
+                let tail = self.fold_assign(assign_to, *e, tail)?;
+                self.fold_effects(effects, tail)
+            }
+            ExprKind::Set(_, _) | ExprKind::While(_, _) => {
+                let void = cir::Stmt::assign(assign_to, cir::Expr::void());
+                self.fold_effect(expr, cir::Tail::seq(void, tail))
+            }
+            _ => {
+                let assign_val = fold_simple_expr(expr)?;
+                Ok(cir::Tail::seq(
+                    cir::Stmt::assign(assign_to, assign_val),
+                    tail,
+                ))
+            }
         }
     }
-}
 
-fn fold_root_expr(expr: Expr) -> Box<cir::Tail> {
-    match expr {
-        Expr::Read => cir::Tail::ret(cir::Expr::read()),
-        Expr::Lit(Lit::Int(i)) => cir::Tail::ret(cir::Expr::arg(cir::Arg::int(i))),
-        Expr::Neg(op) => cir::Tail::ret(cir::Expr::neg(fold_op(*op))),
-        Expr::Add(op1, op2) => cir::Tail::ret(cir::Expr::add(fold_op(*op1), fold_op(*op2))),
-        Expr::Var(sym) => cir::Tail::ret(cir::Expr::arg(cir::Arg::var(&sym.value))),
-        Expr::Let(sym, assn, body) => {
-            let tail = fold_let_body(*body, None);
-            fold_let_assign(&sym.value, *assn, tail)
+    /// Folds the condition of an `if` into a tail that continues with `then`
+    /// when the condition is true and with `els` otherwise.
+    fn fold_pred(
+        &mut self,
+        cond: Expr,
+        then: Box<cir::Tail>,
+        els: Box<cir::Tail>,
+    ) -> Result<Box<cir::Tail>, CompileError> {
+        let Expr { kind, span } = desugar(cond);
+        match kind {
+            ExprKind::Lit(Lit::Bool(true)) => Ok(then),
+            ExprKind::Lit(Lit::Bool(false)) => Ok(els),
+            ExprKind::Var(sym) => {
+                let cond =
+                    cir::Expr::cmp(CmpOp::Eq, cir::Arg::var(&sym.value), cir::Arg::bool(true));
+                Ok(self.branch(cond, then, els))
+            }
+            ExprKind::Not(op) => {
+                let cond = cir::Expr::cmp(CmpOp::Eq, fold_op(*op)?, cir::Arg::bool(false));
+                Ok(self.branch(cond, then, els))
+            }
+            ExprKind::Cmp(op, op1, op2) => {
+                let cond = cir::Expr::cmp(op, fold_op(*op1)?, fold_op(*op2)?);
+                Ok(self.branch(cond, then, els))
+            }
+            // Boolean results of vector reads, calls and projections are
+            // compared against `#t` by instruction selection.
+            kind @ ExprKind::VectorRef(_, _)
+            | kind @ ExprKind::Apply(_, _)
+            | kind @ ExprKind::Project(_, _) => {
+                let cond = fold_simple_expr(Expr { kind, span })?;
+                Ok(self.branch(cond, then, els))
+            }
+            ExprKind::Let(sym, assn, body) => {
+                let tail = self.fold_pred(*body, then, els)?;
+                self.fold_assign(&sym.value, *assn, tail)
+            }
+            ExprKind::Begin(effects, e) => {
+                let tail = self.fold_pred(*e, then, els)?;
+                self.fold_effects(effects, tail)
+            }
+            ExprKind::If(cond, inner_then, inner_els) => {
+                let then = self.create_block(then);
+                let els = self.create_block(els);
+                let inner_then =
+                    self.fold_pred(*inner_then, cir::Tail::goto(&then), cir::Tail::goto(&els))?;
+                let inner_els =
+                    self.fold_pred(*inner_els, cir::Tail::goto(&then), cir::Tail::goto(&els))?;
+                self.fold_pred(*cond, inner_then, inner_els)
+            }
+            _ => Err(CompileError::Explicate(Diagnostic::new(
+                "expected a boolean condition".to_string(),
+                span,
+            ))),
         }
     }
+
+    fn branch(
+        &mut self,
+        cond: Box<cir::Expr>,
+        then: Box<cir::Tail>,
+        els: Box<cir::Tail>,
+    ) -> Box<cir::Tail> {
+        let then = self.create_block(then);
+        let els = self.create_block(els);
+        cir::Tail::if_goto(cond, &then, &els)
+    }
 }
 
-pub fn fold_program(p: Program) -> cir::Program {
-    let start_proc = fold_root_expr(*p.expr);
-    let tails = {
-        let mut tails = HashMap::new();
-        tails.insert(cir::Label::new("start"), *start_proc);
-        tails
-    };
-    cir::Program {
+/// Explicates a function body or the program's expression into blocks whose
+/// labels start with the given prefix.
+fn fold_body(body: Expr, prefix: &str) -> Result<HashMap<cir::Label, cir::Tail>, CompileError> {
+    let mut ctx = Ctx::new(prefix);
+    let start = ctx.fold_tail(body)?;
+    ctx.tails
+        .insert(cir::Label::new(&format!("{}start", prefix)), *start);
+    Ok(ctx.tails)
+}
+
+pub fn fold_program(p: Program) -> Result<cir::Program, CompileError> {
+    let defs = p
+        .defs
+        .into_iter()
+        .map(|def| {
+            let prefix = format!("{}_", def.name.value);
+            Ok(cir::Def {
+                name: def.name.value,
+                params: def
+                    .params
+                    .into_iter()
+                    .map(|(param, ty)| (cir::Symbol::new(&param.value), ty))
+                    .collect(),
+                result: def.result,
+                info: cir::Info::default(),
+                tails: fold_body(*def.body, &prefix)?,
+            })
+        })
+        .collect::<Result<Vec<cir::Def>, CompileError>>()?;
+    Ok(cir::Program {
         info: cir::Info::default(),
-        tails,
-    }
+        tails: fold_body(*p.expr, "")?,
+        defs,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::super::cir;
-    use super::super::Expr;
-    use super::fold_root_expr;
+    use super::super::super::error::CompileError;
+    use super::super::parse::{parse_expr, parse_program};
+    use super::super::typecheck::Type;
+    use super::super::{CmpOp, Expr, Program};
+    use super::{fold_program, Ctx};
+
+    fn fold_root_expr(expr: Expr) -> Result<Box<cir::Tail>, CompileError> {
+        Ctx::new("").fold_tail(expr)
+    }
 
     #[test]
     fn basic_add_and_neg() {
@@ -126,7 +389,23 @@ mod tests {
             cir::Tail::ret(cir::Expr::add(cir::Arg::int(52), cir::Arg::var("v200000"))),
         );
 
-        let actual = fold_root_expr(*expr);
+        let actual = fold_root_expr(*expr).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn sub() {
+        let expr = Expr::let_bind(
+            "x",
+            Expr::sub(Expr::int(52), Expr::int(10)),
+            Expr::sub(Expr::var("x"), Expr::int(1)),
+        );
+        let expected = cir::Tail::seq(
+            cir::Stmt::assign("x", cir::Expr::sub(cir::Arg::int(52), cir::Arg::int(10))),
+            cir::Tail::ret(cir::Expr::sub(cir::Arg::var("x"), cir::Arg::int(1))),
+        );
+
+        let actual = fold_root_expr(*expr).unwrap();
         assert_eq!(actual, expected);
     }
 
@@ -159,7 +438,7 @@ mod tests {
             ),
         );
 
-        let actual = fold_root_expr(*expr);
+        let actual = fold_root_expr(*expr).unwrap();
         assert_eq!(actual, expected);
     }
 
@@ -193,7 +472,135 @@ mod tests {
             ),
         );
 
-        let actual = fold_root_expr(*expr);
+        let actual = fold_root_expr(*expr).unwrap();
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn if_in_tail_position() {
+        let expr = parse_expr("(if (< x 1) 0 (not y))").unwrap();
+        let program = fold_program(Program::new(expr)).unwrap();
+        let tails = &program.tails;
+        assert_eq!(tails.len(), 3);
+        assert_eq!(
+            tails[&cir::Label::new("start")],
+            *cir::Tail::if_goto(
+                cir::Expr::cmp(CmpOp::Lt, cir::Arg::var("x"), cir::Arg::int(1)),
+                "block_1",
+                "block_2",
+            )
+        );
+        assert_eq!(
+            tails[&cir::Label::new("block_1")],
+            *cir::Tail::ret(cir::Expr::arg(cir::Arg::int(0)))
+        );
+        assert_eq!(
+            tails[&cir::Label::new("block_2")],
+            *cir::Tail::ret(cir::Expr::not(cir::Arg::var("y")))
+        );
+    }
+
+    #[test]
+    fn if_in_assignment() {
+        let expr = parse_expr("(let ([x (if (and y #t) 1 2)]) x)").unwrap();
+        let program = fold_program(Program::new(expr)).unwrap();
+        let tails = &program.tails;
+        // The join block is shared by both branches, and the constant second
+        // operand of `and` needs no test of its own.
+        assert_eq!(
+            tails[&cir::Label::new("start")],
+            *cir::Tail::if_goto(
+                cir::Expr::cmp(CmpOp::Eq, cir::Arg::var("y"), cir::Arg::bool(true)),
+                "block_2",
+                "block_3",
+            )
+        );
+        assert_eq!(
+            tails[&cir::Label::new("block_1")],
+            *cir::Tail::ret(cir::Expr::arg(cir::Arg::var("x")))
+        );
+        assert_eq!(
+            tails[&cir::Label::new("block_2")],
+            *cir::Tail::seq(
+                cir::Stmt::assign("x", cir::Expr::arg(cir::Arg::int(1))),
+                cir::Tail::goto("block_1"),
+            )
+        );
+    }
+
+    #[test]
+    fn while_loop() {
+        let expr = parse_expr("(begin (read) (while (< i 3) (set! i (+ i 1))) i)").unwrap();
+        let program = fold_program(Program::new(expr)).unwrap();
+        let tails = &program.tails;
+        // The value of `(read)` is dropped, and the body jumps back to the
+        // block that tests the condition.
+        assert_eq!(
+            tails[&cir::Label::new("start")],
+            *cir::Tail::seq(
+                cir::Stmt::assign("unused_4", cir::Expr::read()),
+                cir::Tail::goto("loop_2"),
+            )
+        );
+        assert_eq!(
+            tails[&cir::Label::new("loop_2")],
+            *cir::Tail::if_goto(
+                cir::Expr::cmp(CmpOp::Lt, cir::Arg::var("i"), cir::Arg::int(3)),
+                "block_3",
+                "block_1",
+            )
+        );
+        assert_eq!(
+            tails[&cir::Label::new("block_3")],
+            *cir::Tail::seq(
+                cir::Stmt::assign("i", cir::Expr::add(cir::Arg::var("i"), cir::Arg::int(1))),
+                cir::Tail::goto("loop_2"),
+            )
+        );
+        assert_eq!(
+            tails[&cir::Label::new("block_1")],
+            *cir::Tail::ret(cir::Expr::arg(cir::Arg::var("i")))
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let src = "(define (f [x : Integer]) : Integer (if (g x) 1 2)) (f 1)";
+        let program = fold_program(parse_program(src).unwrap()).unwrap();
+        assert_eq!(
+            program.tails[&cir::Label::new("start")],
+            *cir::Tail::ret(cir::Expr::call(cir::Arg::var("f"), vec![cir::Arg::Int(1)]))
+        );
+        let def = &program.defs[0];
+        assert_eq!(def.params, vec![(cir::Symbol::new("x"), Type::Integer)]);
+        let mut labels = def
+            .tails
+            .keys()
+            .map(|l| l.value.as_str())
+            .collect::<Vec<_>>();
+        labels.sort_unstable();
+        assert_eq!(labels, vec!["f_block_1", "f_block_2", "f_start"]);
+        assert_eq!(
+            def.tails[&cir::Label::new("f_start")],
+            *cir::Tail::if_goto(
+                cir::Expr::call(cir::Arg::var("g"), vec![*cir::Arg::var("x")]),
+                "f_block_1",
+                "f_block_2",
+            )
+        );
+    }
+
+    #[test]
+    fn non_boolean_condition() {
+        let expr = parse_expr("(if (+ 1 2) 3 4)").unwrap();
+        let actual = fold_program(Program::new(expr));
+        assert!(matches!(actual, Err(CompileError::Explicate(_))));
+    }
+
+    #[test]
+    fn complex_operand() {
+        let expr = Expr::neg(Expr::neg(Expr::int(1)));
+        let actual = fold_root_expr(*expr);
+        assert!(matches!(actual, Err(CompileError::Explicate(_))));
+    }
 }
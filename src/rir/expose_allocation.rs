@@ -0,0 +1,172 @@
+//! Makes the allocation of vectors explicit.
+//!
+//! Each `(vector e1 ... en)` becomes a chain of lets that evaluates the
+//! elements, collects garbage if the heap has no room for the vector,
+//! allocates it and fills it in:
+//!
+//! ```text
+//! (let ([x1 e1]) ... (let ([xn en])
+//!   (let ([_ (if (< (+ (global-value free_ptr) bytes)
+//!                   (global-value fromspace_end))
+//!                (void)
+//!                (collect bytes))])
+//!     (let ([v (allocate n T)])
+//!       (let ([_ (vector-set! v 0 x1)]) ... v)))))
+//! ```
+//!
+//! The elements are evaluated before the check because evaluating them may
+//! allocate too, which would use up the room the check made.
+
+use super::super::diagnostic::Span;
+use super::typecheck::{Type, TypeChecker};
+use super::{CmpOp, Def, Expr, ExprFolder, ExprKind, Program, Symbol};
+
+/// Runs after uniquify, so every variable it binds has a fresh name and the
+/// types of the variables in scope can be tracked by name.
+pub struct ExprAllocationExposer {
+    pub counter: u64,
+
+    /// Types of the variables in scope, from which the type of each
+    /// allocated vector is found.
+    types: TypeChecker,
+}
+
+impl ExprAllocationExposer {
+    pub fn new(counter: u64) -> ExprAllocationExposer {
+        ExprAllocationExposer {
+            counter,
+            types: TypeChecker::new(),
+        }
+    }
+
+    pub fn new_sym_name(&mut self) -> String {
+        let name = format!("v{}", self.counter);
+        self.counter += 1;
+        name
+    }
+
+    /// Exposes the allocations in every function and in the program's
+    /// expression, with the types of the functions and of the parameters in
+    /// scope.
+    pub fn fold_program(&mut self, p: Program) -> Program {
+        for def in &p.defs {
+            self.types.bind(&def.name, Some(def.ty()));
+        }
+        let defs = p
+            .defs
+            .into_iter()
+            .map(|def| {
+                for (param, ty) in &def.params {
+                    self.types.bind(param, Some(ty.clone()));
+                }
+                let body = self.fold(def.body);
+                for _ in &def.params {
+                    self.types.unbind();
+                }
+                Def { body, ..def }
+            })
+            .collect();
+        Program {
+            defs,
+            expr: self.fold(p.expr),
+        }
+    }
+}
+
+/// Reads a global variable of the runtime.
+fn global_value(name: &str, span: &Span) -> Box<Expr> {
+    Expr::new(ExprKind::GlobalValue(name.to_string()), span.clone())
+}
+
+impl ExprFolder for ExprAllocationExposer {
+    fn fold_vector(&mut self, elems: Vec<Expr>, span: Span) -> Box<Expr> {
+        let elem_tys = elems
+            .iter()
+            .map(|e| self.types.check(e))
+            .collect::<Option<Vec<Type>>>()
+            .expect("expose_allocation runs on well-typed programs");
+        let len = elems.len();
+        let bytes = 8 * (len as i64 + 1);
+        let elems = elems
+            .into_iter()
+            .map(|e| (self.new_sym_name(), self.fold(Box::new(e))))
+            .collect::<Vec<(String, Box<Expr>)>>();
+        let check_name = self.new_sym_name();
+        let v_name = self.new_sym_name();
+        let set_names = (0..len)
+            .map(|_| self.new_sym_name())
+            .collect::<Vec<String>>();
+
+        let mut body = Expr::var(&v_name).at(span.clone());
+        for (i, (elem_name, _)) in elems.iter().enumerate().rev() {
+            let set = Expr::vector_set(Expr::var(&v_name), i as i64, Expr::var(elem_name));
+            body = Expr::let_bind(&set_names[i], set.at(span.clone()), body).at(span.clone());
+        }
+        let allocate = Expr::new(
+            ExprKind::Allocate(len, Type::Vector(elem_tys)),
+            span.clone(),
+        );
+        body = Expr::let_bind(&v_name, allocate, body).at(span.clone());
+        let end = Expr::add(global_value("free_ptr", &span), Expr::int(bytes));
+        let has_room = Expr::cmp(CmpOp::Lt, end, global_value("fromspace_end", &span));
+        let check = Expr::if_expr(
+            has_room,
+            Expr::void(),
+            Expr::new(ExprKind::Collect(bytes), span.clone()),
+        );
+        body = Expr::let_bind(&check_name, check.at(span.clone()), body).at(span.clone());
+        for (elem_name, elem) in elems.into_iter().rev() {
+            body = Expr::let_bind(&elem_name, elem, body).at(span.clone());
+        }
+        body
+    }
+
+    fn fold_let(
+        &mut self,
+        sym: Box<Symbol>,
+        e: Box<Expr>,
+        body: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
+        let ty = self.types.check(&e);
+        let e = self.fold(e);
+        self.types.bind(&sym, ty);
+        let body = self.fold(body);
+        self.types.unbind();
+        Expr::new(ExprKind::Let(sym, e, body), span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse::parse_expr;
+    use super::super::ExprFolder;
+    use super::ExprAllocationExposer;
+
+    #[test]
+    fn expose_vector() {
+        let expr = parse_expr("(let ([x (vector 1 #t)]) (vector-ref x 0))").unwrap();
+        let actual = ExprAllocationExposer::new(1).fold(expr);
+        let expected = "\
+(let ([x (let ([v1 1])
+           (let ([v2 #t])
+             (let ([v3 (if (< (+ (global-value free_ptr) 24)
+                              (global-value fromspace_end))
+                           (void)
+                           (collect 24))])
+               (let ([v4 (allocate 2 (Vector Integer Boolean))])
+                 (let ([v5 (vector-set! v4 0 v1)])
+                   (let ([v6 (vector-set! v4 1 v2)]) v4))))))])
+  (vector-ref x 0))";
+        assert_eq!(actual.to_string(), expected);
+    }
+
+    #[test]
+    fn nested_vectors_use_variable_types() {
+        let expr = parse_expr("(let ([x (vector 1)]) (vector x))").unwrap();
+        let actual = ExprAllocationExposer::new(1).fold(expr);
+        assert!(actual
+            .to_string()
+            .contains("(allocate 1 (Vector (Vector Integer)))"));
+    }
+}
@@ -0,0 +1,285 @@
+//! Cast insertion, which turns a dynamically typed program into a statically
+//! typed one in which every value has type `Any`.
+//!
+//! Runs on the program as parsed, before type checking, and ignores its type
+//! annotations. Each value is injected into `Any` where it is made and
+//! projected out of it where an operation needs a particular type, so
+//!
+//! ```text
+//! (+ x 1)
+//! ```
+//!
+//! becomes
+//!
+//! ```text
+//! (inject (+ (project x Integer) (project (inject 1 Integer) Integer)) Integer)
+//! ```
+//!
+//! Vectors hold `Any`s and are read and written with `any-vector-ref` and
+//! `any-vector-set!`, which check the length at run time. Functions take and
+//! return `Any`s. Calls of functions defined at the top level by name stay
+//! direct calls, so only calls of function values check the arity.
+
+use super::super::diagnostic::Span;
+use super::typecheck::Type;
+use super::{CmpOp, Def, Expr, ExprFolder, ExprKind, Lit, Program, Symbol};
+use std::collections::HashMap;
+
+pub struct CastInserter {
+    /// Arity of each function defined at the top level, by name.
+    funs: HashMap<Symbol, usize>,
+
+    /// Variables in scope, innermost last. They shadow functions of the same
+    /// name.
+    locals: Vec<Symbol>,
+}
+
+fn inject(e: Box<Expr>, ty: Type, span: &Span) -> Box<Expr> {
+    Expr::new(ExprKind::Inject(e, ty), span.clone())
+}
+
+fn project(e: Box<Expr>, ty: Type) -> Box<Expr> {
+    let span = e.span.clone();
+    Expr::new(ExprKind::Project(e, ty), span)
+}
+
+impl CastInserter {
+    pub fn new() -> CastInserter {
+        CastInserter {
+            funs: HashMap::new(),
+            locals: vec![],
+        }
+    }
+
+    /// Inserts casts into every function and the program's expression.
+    pub fn fold_program(&mut self, p: Program) -> Program {
+        for def in &p.defs {
+            self.funs.insert((*def.name).clone(), def.params.len());
+        }
+        let defs = p
+            .defs
+            .into_iter()
+            .map(|def| {
+                let params = self.bind_params(def.params);
+                let body = self.fold(def.body);
+                self.locals.clear();
+                Def {
+                    params,
+                    result: Type::Any,
+                    body,
+                    ..def
+                }
+            })
+            .collect();
+        Program {
+            defs,
+            expr: self.fold(p.expr),
+        }
+    }
+
+    /// Brings the parameters into scope and gives them type `Any`.
+    fn bind_params(&mut self, params: Vec<(Box<Symbol>, Type)>) -> Vec<(Box<Symbol>, Type)> {
+        params
+            .into_iter()
+            .map(|(param, _)| {
+                self.locals.push((*param).clone());
+                (param, Type::Any)
+            })
+            .collect()
+    }
+
+    /// Arity of the function defined at the top level that the variable
+    /// refers to, if it is not shadowed.
+    fn fun_arity(&self, sym: &Symbol) -> Option<usize> {
+        if self.locals.contains(sym) {
+            None
+        } else {
+            self.funs.get(sym).copied()
+        }
+    }
+
+    /// Folds the expression and projects its value to the given type.
+    fn fold_to(&mut self, e: Box<Expr>, ty: Type) -> Box<Expr> {
+        project(self.fold(e), ty)
+    }
+
+    /// Folds an integer operation on the given operands.
+    fn fold_arith(
+        &mut self,
+        op: impl Fn(Box<Expr>, Box<Expr>) -> ExprKind,
+        e1: Box<Expr>,
+        e2: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
+        let e1 = self.fold_to(e1, Type::Integer);
+        let e2 = self.fold_to(e2, Type::Integer);
+        inject(Expr::new(op(e1, e2), span.clone()), Type::Integer, &span)
+    }
+}
+
+impl Default for CastInserter {
+    fn default() -> Self {
+        CastInserter::new()
+    }
+}
+
+impl ExprFolder for CastInserter {
+    fn fold(&mut self, e: Box<Expr>) -> Box<Expr> {
+        let Expr { kind, span } = *e;
+        let (kind, ty) = match kind {
+            ExprKind::Read => (ExprKind::Read, Type::Integer),
+            ExprKind::Lit(Lit::Int(i)) => (ExprKind::Lit(Lit::Int(i)), Type::Integer),
+            ExprKind::Lit(Lit::Bool(b)) => (ExprKind::Lit(Lit::Bool(b)), Type::Boolean),
+            ExprKind::Neg(e) => (ExprKind::Neg(self.fold_to(e, Type::Integer)), Type::Integer),
+            ExprKind::Add(e1, e2) => return self.fold_arith(ExprKind::Add, e1, e2, span),
+            ExprKind::Sub(e1, e2) => return self.fold_arith(ExprKind::Sub, e1, e2, span),
+            ExprKind::Mul(e1, e2) => return self.fold_arith(ExprKind::Mul, e1, e2, span),
+            ExprKind::Quotient(e1, e2) => return self.fold_arith(ExprKind::Quotient, e1, e2, span),
+            ExprKind::Remainder(e1, e2) => {
+                return self.fold_arith(ExprKind::Remainder, e1, e2, span)
+            }
+            ExprKind::Not(e) => (ExprKind::Not(self.fold_to(e, Type::Boolean)), Type::Boolean),
+            ExprKind::And(e1, e2) => {
+                let e1 = self.fold_to(e1, Type::Boolean);
+                let e2 = self.fold_to(e2, Type::Boolean);
+                (ExprKind::And(e1, e2), Type::Boolean)
+            }
+            ExprKind::Or(e1, e2) => {
+                let e1 = self.fold_to(e1, Type::Boolean);
+                let e2 = self.fold_to(e2, Type::Boolean);
+                (ExprKind::Or(e1, e2), Type::Boolean)
+            }
+            // Tagged values are the same exactly when the values are.
+            ExprKind::Cmp(CmpOp::Eq, e1, e2) => {
+                let kind = ExprKind::Cmp(CmpOp::Eq, self.fold(e1), self.fold(e2));
+                (kind, Type::Boolean)
+            }
+            ExprKind::Cmp(op, e1, e2) => {
+                let e1 = self.fold_to(e1, Type::Integer);
+                let e2 = self.fold_to(e2, Type::Integer);
+                (ExprKind::Cmp(op, e1, e2), Type::Boolean)
+            }
+            ExprKind::If(cond, then, els) => {
+                let cond = self.fold_to(cond, Type::Boolean);
+                let kind = ExprKind::If(cond, self.fold(then), self.fold(els));
+                return Expr::new(kind, span);
+            }
+            ExprKind::Vector(elems) => {
+                let ty = Type::Vector(vec![Type::Any; elems.len()]);
+                let elems = elems.into_iter().map(|e| *self.fold(Box::new(e))).collect();
+                (ExprKind::Vector(elems), ty)
+            }
+            ExprKind::VectorRef(v, index) | ExprKind::AnyVectorRef(v, index) => {
+                return Expr::new(ExprKind::AnyVectorRef(self.fold(v), index), span);
+            }
+            ExprKind::VectorSet(v, index, e) | ExprKind::AnyVectorSet(v, index, e) => {
+                let kind = ExprKind::AnyVectorSet(self.fold(v), index, self.fold(e));
+                (kind, Type::Void)
+            }
+            ExprKind::Void => (ExprKind::Void, Type::Void),
+            ExprKind::Var(sym) => {
+                let arity = self.fun_arity(&sym);
+                let var = Expr::new(ExprKind::Var(sym), span.clone());
+                return match arity {
+                    Some(arity) => inject(var, Type::any_function(arity), &span),
+                    None => var,
+                };
+            }
+            ExprKind::Let(sym, e, body) => {
+                let e = self.fold(e);
+                self.locals.push((*sym).clone());
+                let body = self.fold(body);
+                self.locals.pop();
+                return Expr::new(ExprKind::Let(sym, e, body), span);
+            }
+            ExprKind::Set(sym, e) => (ExprKind::Set(sym, self.fold(e)), Type::Void),
+            ExprKind::Begin(effects, e) => {
+                let effects = effects
+                    .into_iter()
+                    .map(|e| *self.fold(Box::new(e)))
+                    .collect();
+                return Expr::new(ExprKind::Begin(effects, self.fold(e)), span);
+            }
+            ExprKind::While(cond, body) => {
+                let cond = self.fold_to(cond, Type::Boolean);
+                (ExprKind::While(cond, self.fold(body)), Type::Void)
+            }
+            ExprKind::Print(e) => (ExprKind::Print(self.fold(e)), Type::Void),
+            ExprKind::Apply(f, args) => {
+                let args = args
+                    .into_iter()
+                    .map(|e| *self.fold(Box::new(e)))
+                    .collect::<Vec<Expr>>();
+                let f = match &f.kind {
+                    ExprKind::Var(sym) if self.fun_arity(sym).is_some() => f,
+                    _ => self.fold_to(f, Type::any_function(args.len())),
+                };
+                return Expr::new(ExprKind::Apply(f, args), span);
+            }
+            ExprKind::Lambda(params, body) => {
+                let ty = Type::any_function(params.len());
+                let len = self.locals.len();
+                let params = self.bind_params(params);
+                let body = self.fold(body);
+                self.locals.truncate(len);
+                (ExprKind::Lambda(params, body), ty)
+            }
+            // The operand is already an `Any`.
+            ExprKind::Inject(e, _) => return self.fold(e),
+            ExprKind::Project(e, ty) => (ExprKind::Project(self.fold(e), ty.clone()), ty),
+            // The forms added by later passes are left alone.
+            kind => return Expr::new(kind, span),
+        };
+        inject(Expr::new(kind, span.clone()), ty, &span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse::parse_program;
+    use super::super::typecheck::{typecheck_program, Type};
+    use super::CastInserter;
+
+    fn insert_casts(src: &str) -> String {
+        let prog = CastInserter::new().fold_program(parse_program(src).unwrap());
+        assert_eq!(typecheck_program(&prog), Ok(Some(Type::Any)));
+        prog.to_string()
+    }
+
+    #[test]
+    fn primitives() {
+        let actual = insert_casts("(if (< (read) 2) (vector-ref (vector #t (void)) 1) #f)");
+        let expected = "\
+(if (project (inject (< (project (inject (read) Integer) Integer)
+                        (project (inject 2 Integer) Integer))
+                     Boolean)
+             Boolean)
+    (any-vector-ref (inject (vector (inject #t Boolean) (inject (void) Void))
+                            (Vector Any Any))
+                    1)
+    (inject #f Boolean))
+";
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn functions() {
+        let src = "\
+(define (twice [f : (Integer -> Integer)] x) : Integer (f (f x)))
+(define (inc x) (+ x 1))
+(twice (lambda (inc) (inc 1)) (twice inc 0))";
+        let expected = "\
+(define (twice [f : Any] [x : Any]) : Any
+  ((project f (Any -> Any)) ((project f (Any -> Any)) x)))
+
+(define (inc [x : Any]) : Any
+  (inject (+ (project x Integer) (project (inject 1 Integer) Integer)) Integer))
+
+(twice (inject (lambda ([inc : Any])
+                 ((project inc (Any -> Any)) (inject 1 Integer)))
+               (Any -> Any))
+       (twice (inject inc (Any -> Any)) (inject 0 Integer)))
+";
+        assert_eq!(insert_casts(src), expected);
+    }
+}
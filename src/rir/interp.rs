@@ -1,10 +1,219 @@
-use super::{Expr, Lit, Program, Symbol};
-use std::collections::HashMap;
-use std::convert::TryFrom;
+use super::super::diagnostic::Diagnostic;
+use super::super::options::{Options, Overflow, Typing};
+use super::insert_casts::CastInserter;
+use super::typecheck::Type;
+use super::{CmpOp, Def, Expr, ExprKind, Lit, Program, Symbol};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
+use std::rc::Rc;
 
+/// Result of evaluating an expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    /// A vector shared by every value that refers to it, so that writes
+    /// through one are seen by the others. Values compare equal when their
+    /// elements do; `eq?` compares identity instead.
+    Vector(Rc<RefCell<Vec<Value>>>),
+    Void,
+    /// A function defined at the top level, by name.
+    Function(String),
+    /// A function created by a `lambda`.
+    Closure(Rc<Closure>),
+}
+
+/// The parameters and body of a `lambda` together with the environment it
+/// was evaluated in. Closures compare equal only to themselves.
+#[derive(Debug)]
+pub struct Closure {
+    params: Vec<Symbol>,
+    body: Expr,
+    env: Env,
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Closure) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Value {
+    /// Name of the value's type as written in diagnostics.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "Integer",
+            Value::Bool(_) => "Boolean",
+            Value::Vector(_) => "Vector",
+            Value::Void => "Void",
+            Value::Function(_) | Value::Closure(_) => "Function",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Bool(true) => write!(f, "#t"),
+            Value::Bool(false) => write!(f, "#f"),
+            Value::Vector(elems) => {
+                write!(f, "#(")?;
+                for (i, elem) in elems.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                write!(f, ")")
+            }
+            Value::Void => write!(f, "#<void>"),
+            // Compiled programs do not know the names of their functions.
+            Value::Function(_) | Value::Closure(_) => write!(f, "#<procedure>"),
+        }
+    }
+}
+
+/// Reason evaluation stopped before producing a value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InterpError {
+    /// A variable was referenced outside the scope of any binding.
+    UnboundVariable(Diagnostic),
+
+    /// `(read)` was evaluated after the input ran out.
+    EndOfInput,
+
+    /// The input given to `(read)` is not an integer.
+    InvalidInput(String),
+
+    /// Arithmetic overflowed 64 bits while trapping on overflow, or a
+    /// division overflowed in either mode.
+    Overflow(Diagnostic),
+
+    /// The divisor of `quotient` or `remainder` was zero.
+    DivideByZero(Diagnostic),
+
+    /// An operation was applied to a value of the wrong type.
+    TypeMismatch(Diagnostic),
+}
+
+impl InterpError {
+    /// Diagnostic that points at the expression that failed, if any.
+    pub fn diagnostic(&self) -> Option<&Diagnostic> {
+        match self {
+            InterpError::UnboundVariable(diagnostic)
+            | InterpError::Overflow(diagnostic)
+            | InterpError::DivideByZero(diagnostic)
+            | InterpError::TypeMismatch(diagnostic) => Some(diagnostic),
+            InterpError::EndOfInput | InterpError::InvalidInput(_) => None,
+        }
+    }
+
+    /// Renders the error as `CompileError::render` does, named after the
+    /// interpreter and with the offending line of `src` underlined.
+    pub fn render(&self, src: &str) -> String {
+        match self.diagnostic() {
+            Some(diagnostic) => format!("interp: {}", diagnostic.render(src)),
+            None => format!("interp: {}\n", self),
+        }
+    }
+}
+
+impl fmt::Display for InterpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpError::UnboundVariable(diagnostic) => write!(f, "{}", diagnostic),
+            InterpError::Overflow(diagnostic) => write!(f, "{}", diagnostic),
+            InterpError::DivideByZero(diagnostic) => write!(f, "{}", diagnostic),
+            InterpError::TypeMismatch(diagnostic) => write!(f, "{}", diagnostic),
+            InterpError::EndOfInput => write!(f, "`(read)` reached the end of the input"),
+            InterpError::InvalidInput(input) => {
+                write!(f, "`(read)` expected an integer but got `{}`", input)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InterpError {}
+
+/// Supplies the values returned by `(read)`.
+pub trait InputSource {
+    /// Returns the next line of input, or `None` once the input is exhausted.
+    fn next_input(&mut self) -> Option<String>;
+}
+
+/// Input given up front, returned one entry per `(read)`.
+#[derive(Clone, Debug, Default)]
+pub struct ScriptedInput {
+    queue: VecDeque<String>,
+}
+
+impl ScriptedInput {
+    pub fn new(inputs: &[&str]) -> ScriptedInput {
+        ScriptedInput {
+            queue: inputs.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    pub fn ints(inputs: &[i64]) -> ScriptedInput {
+        ScriptedInput {
+            queue: inputs.iter().map(|i| i.to_string()).collect(),
+        }
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn next_input(&mut self) -> Option<String> {
+        self.queue.pop_front()
+    }
+}
+
+/// Prompts for and reads each input from standard input.
+pub struct StdinInput;
+
+impl InputSource for StdinInput {
+    fn next_input(&mut self) -> Option<String> {
+        use std::io;
+        use std::io::prelude::*;
+        print!("Provide input: ");
+        io::stdout().flush().ok()?;
+        let mut input = String::new();
+        match io::stdin().read_line(&mut input) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(input),
+        }
+    }
+}
+
+/// Receives what `(print e)` writes.
+pub trait OutputSink {
+    fn write_output(&mut self, text: &str);
+}
+
+/// Collects the output in the string.
+impl OutputSink for String {
+    fn write_output(&mut self, text: &str) {
+        self.push_str(text);
+    }
+}
+
+/// Writes the output to standard output, for `interp`, which runs a program
+/// as the compiled one would.
+pub struct StdoutOutput;
+
+impl OutputSink for StdoutOutput {
+    fn write_output(&mut self, text: &str) {
+        print!("{}", text);
+    }
+}
+
+/// Binds each variable in scope to a cell holding its value. Environments
+/// made by `shallow_clone` share the cells, so that a `set!` is seen by every
+/// closure that captured the variable.
+#[derive(Debug)]
 struct Env {
-    bindings: HashMap<Box<Symbol>, Lit>,
+    bindings: HashMap<Box<Symbol>, Rc<RefCell<Value>>>,
 }
 
 impl Env {
@@ -14,75 +223,757 @@ impl Env {
         }
     }
 
-    fn set(&mut self, sym: Box<Symbol>, val: Lit) {
-        self.bindings.insert(sym, val);
+    /// Binds the variable to a new cell, shadowing any binding of the same
+    /// name.
+    fn set(&mut self, sym: Box<Symbol>, val: Value) {
+        self.bindings.insert(sym, Rc::new(RefCell::new(val)));
+    }
+
+    fn get(&self, sym: &Symbol) -> Option<Value> {
+        self.bindings.get(sym).map(|cell| cell.borrow().clone())
     }
 
-    fn get(&self, sym: &Symbol) -> Option<Lit> {
-        self.bindings.get(sym).copied()
+    /// Replaces the value in the cell the variable is bound to. Returns
+    /// `None` if the variable is unbound.
+    fn assign(&self, sym: &Symbol, val: Value) -> Option<()> {
+        *self.bindings.get(sym)?.borrow_mut() = val;
+        Some(())
     }
 
     fn shallow_clone(&self) -> Env {
-        let mut env = Env::new();
-        for (sym, val) in self.bindings.iter() {
-            env.set(sym.clone(), val.clone());
-        }
-        env
-    }
-}
-
-fn interp_expr(expr: &Expr, env: &Env) -> Lit {
-    match expr {
-        Expr::Read => {
-            use std::io;
-            use std::io::prelude::*;
-            print!("Provide input: ");
-            io::stdout().flush().unwrap();
-            let mut input = String::new();
-            io::stdin()
-                .read_line(&mut input)
-                .expect("error reading input");
-            input = input.trim().to_string();
-            TryFrom::try_from(input).expect("could not parse input")
-        }
-        Expr::Lit(lit) => *lit,
-        Expr::Neg(e) => match interp_expr(e, env) {
-            Lit::Int(i) => Lit::Int(-i),
-        },
-        Expr::Add(e1, e2) => {
-            let ipterpd1 = interp_expr(e1, env);
-            let interpd2 = interp_expr(e2, env);
-            match (ipterpd1, interpd2) {
-                (Lit::Int(i1), Lit::Int(i2)) => Lit::Int(i1 + i2),
+        Env {
+            bindings: self.bindings.clone(),
+        }
+    }
+}
+
+fn read_int(input: &mut dyn InputSource) -> Result<Value, InterpError> {
+    let line = input.next_input().ok_or(InterpError::EndOfInput)?;
+    let line = line.trim();
+    line.parse()
+        .map(Value::Int)
+        .map_err(|_| InterpError::InvalidInput(line.to_string()))
+}
+
+/// State shared by the whole evaluation of a program.
+struct Ctx<'a> {
+    input: &'a mut dyn InputSource,
+    output: &'a mut dyn OutputSink,
+    options: &'a Options,
+
+    /// Functions defined at the top level, by name.
+    defs: HashMap<&'a str, &'a Def>,
+
+    /// Binds the name of each function to the function, which is the
+    /// environment every function body starts from.
+    globals: Env,
+}
+
+impl Ctx<'_> {
+    /// Finishes an integer operation. `checked` is the result of the checked
+    /// operation and `wrapped` the result of the wrapping one.
+    fn arith(&self, expr: &Expr, checked: Option<i64>, wrapped: i64) -> Result<Value, InterpError> {
+        match (self.options.overflow, checked) {
+            (Overflow::Trapping, None) => Err(InterpError::Overflow(Diagnostic::new(
+                "integer overflow".to_string(),
+                expr.span.clone(),
+            ))),
+            _ => Ok(Value::Int(wrapped)),
+        }
+    }
+
+    /// Finishes a division given its divisor and the result of the checked
+    /// operation, which rounds toward zero like `idivq`. Dividing the most
+    /// negative integer by -1 is an overflow in both modes, since `idivq`
+    /// cannot produce a wrapped result for it.
+    fn division(
+        &self,
+        expr: &Expr,
+        divisor: i64,
+        checked: Option<i64>,
+    ) -> Result<Value, InterpError> {
+        let error = |message: &str| Diagnostic::new(message.to_string(), expr.span.clone());
+        match checked {
+            Some(i) => Ok(Value::Int(i)),
+            None if divisor == 0 => Err(InterpError::DivideByZero(error("division by zero"))),
+            None => Err(InterpError::Overflow(error("integer overflow"))),
+        }
+    }
+}
+
+/// Returns the integer held by the value of the given expression.
+fn expect_int(val: Value, expr: &Expr) -> Result<i64, InterpError> {
+    match val {
+        Value::Int(i) => Ok(i),
+        val => Err(type_mismatch("Integer", &val, expr)),
+    }
+}
+
+/// Returns the boolean held by the value of the given expression.
+fn expect_bool(val: Value, expr: &Expr) -> Result<bool, InterpError> {
+    match val {
+        Value::Bool(b) => Ok(b),
+        val => Err(type_mismatch("Boolean", &val, expr)),
+    }
+}
+
+/// Returns the vector held by the value of the given expression.
+fn expect_vector(val: Value, expr: &Expr) -> Result<Rc<RefCell<Vec<Value>>>, InterpError> {
+    match val {
+        Value::Vector(elems) => Ok(elems),
+        val => Err(type_mismatch("Vector", &val, expr)),
+    }
+}
+
+/// Returns the position given by an index operand if it is in range for the
+/// vector.
+fn expect_index(elems: &[Value], index: &Expr) -> Result<usize, InterpError> {
+    match index.kind {
+        ExprKind::Lit(Lit::Int(i)) if 0 <= i && (i as usize) < elems.len() => Ok(i as usize),
+        _ => Err(InterpError::TypeMismatch(Diagnostic::new(
+            format!(
+                "index is out of range for a vector of length {}",
+                elems.len()
+            ),
+            index.span.clone(),
+        ))),
+    }
+}
+
+/// Returns the value of the given expression if it has the given type, as
+/// `project` checks at run time: vectors must also have the type's length
+/// and functions its number of parameters.
+fn expect_type(val: Value, ty: &Type, expr: &Expr, ctx: &Ctx) -> Result<Value, InterpError> {
+    let matches = match (&val, ty) {
+        (Value::Int(_), Type::Integer)
+        | (Value::Bool(_), Type::Boolean)
+        | (Value::Void, Type::Void)
+        | (_, Type::Any) => true,
+        (Value::Vector(elems), Type::Vector(tys)) => elems.borrow().len() == tys.len(),
+        (Value::Function(name), Type::Function(params, _)) => {
+            ctx.defs[name.as_str()].params.len() == params.len()
+        }
+        (Value::Closure(closure), Type::Function(params, _)) => {
+            closure.params.len() == params.len()
+        }
+        _ => false,
+    };
+    if matches {
+        Ok(val)
+    } else {
+        Err(type_mismatch(&ty.to_string(), &val, expr))
+    }
+}
+
+/// Calls a function with the given parameters and body, binding the
+/// arguments in the environment the body starts from.
+fn call(
+    name: &str,
+    params: &[&Symbol],
+    body: &Expr,
+    mut body_env: Env,
+    args: Vec<Value>,
+    expr: &Expr,
+    ctx: &mut Ctx,
+) -> Result<Value, InterpError> {
+    if args.len() != params.len() {
+        return Err(InterpError::TypeMismatch(Diagnostic::new(
+            format!(
+                "`{}` expects {} argument(s) but got {}",
+                name,
+                params.len(),
+                args.len()
+            ),
+            expr.span.clone(),
+        )));
+    }
+    for (param, val) in params.iter().zip(args) {
+        body_env.set(Box::new((*param).clone()), val);
+    }
+    interp_expr(body, &body_env, ctx)
+}
+
+fn unbound_variable(sym: &Symbol, expr: &Expr) -> InterpError {
+    InterpError::UnboundVariable(Diagnostic::new(
+        format!("unbound variable `{}`", sym.value),
+        expr.span.clone(),
+    ))
+}
+
+fn type_mismatch(expected: &str, found: &Value, expr: &Expr) -> InterpError {
+    InterpError::TypeMismatch(Diagnostic::new(
+        format!("expected {}, found {}", expected, found.type_name()),
+        expr.span.clone(),
+    ))
+}
+
+/// Evaluates both operands of a binary integer operation.
+fn interp_ints(e1: &Expr, e2: &Expr, env: &Env, ctx: &mut Ctx) -> Result<(i64, i64), InterpError> {
+    let i1 = expect_int(interp_expr(e1, env, ctx)?, e1)?;
+    let i2 = expect_int(interp_expr(e2, env, ctx)?, e2)?;
+    Ok((i1, i2))
+}
+
+fn interp_bool(expr: &Expr, env: &Env, ctx: &mut Ctx) -> Result<bool, InterpError> {
+    expect_bool(interp_expr(expr, env, ctx)?, expr)
+}
+
+/// Compares two values with `eq?`, which compares vectors and closures by
+/// identity.
+fn interp_eq(e1: &Expr, e2: &Expr, env: &Env, ctx: &mut Ctx) -> Result<Value, InterpError> {
+    let val1 = interp_expr(e1, env, ctx)?;
+    let val2 = interp_expr(e2, env, ctx)?;
+    match (val1, val2) {
+        (Value::Int(i1), Value::Int(i2)) => Ok(Value::Bool(i1 == i2)),
+        (Value::Bool(b1), Value::Bool(b2)) => Ok(Value::Bool(b1 == b2)),
+        (Value::Vector(v1), Value::Vector(v2)) => Ok(Value::Bool(Rc::ptr_eq(&v1, &v2))),
+        (Value::Void, Value::Void) => Ok(Value::Bool(true)),
+        (Value::Function(f1), Value::Function(f2)) => Ok(Value::Bool(f1 == f2)),
+        (Value::Closure(c1), Value::Closure(c2)) => Ok(Value::Bool(Rc::ptr_eq(&c1, &c2))),
+        (Value::Function(_), Value::Closure(_)) | (Value::Closure(_), Value::Function(_)) => {
+            Ok(Value::Bool(false))
+        }
+        // Values of different types are distinct `Any`s.
+        _ if ctx.options.typing == Typing::Dynamic => Ok(Value::Bool(false)),
+        (val1, val2) => Err(type_mismatch(val1.type_name(), &val2, e2)),
+    }
+}
+
+/// Calls the function the expression `f` evaluates to. Kept out of
+/// `interp_expr`, whose frame is on the stack once per level of nesting.
+fn interp_apply(
+    f: &Expr,
+    args: &[Expr],
+    expr: &Expr,
+    env: &Env,
+    ctx: &mut Ctx,
+) -> Result<Value, InterpError> {
+    let callee = interp_expr(f, env, ctx)?;
+    let vals = args
+        .iter()
+        .map(|e| interp_expr(e, env, ctx))
+        .collect::<Result<Vec<Value>, InterpError>>()?;
+    match callee {
+        Value::Function(name) => {
+            let def = ctx.defs[name.as_str()];
+            let params = def.params.iter().map(|(p, _)| &**p).collect::<Vec<_>>();
+            let body_env = ctx.globals.shallow_clone();
+            call(
+                &def.name.value,
+                &params,
+                &def.body,
+                body_env,
+                vals,
+                expr,
+                ctx,
+            )
+        }
+        Value::Closure(closure) => {
+            let params = closure.params.iter().collect::<Vec<_>>();
+            let body_env = closure.env.shallow_clone();
+            call("lambda", &params, &closure.body, body_env, vals, expr, ctx)
+        }
+        val => Err(type_mismatch("Function", &val, f)),
+    }
+}
+
+/// Writes the value and a newline to the output, as the runtime would.
+fn print_value(val: &Value, ctx: &mut Ctx) {
+    ctx.output.write_output(&format!("{}\n", val));
+}
+
+/// Evaluates an arithmetic, logical or comparison operation.
+fn interp_prim(expr: &Expr, env: &Env, ctx: &mut Ctx) -> Result<Value, InterpError> {
+    match &expr.kind {
+        ExprKind::Neg(e) => {
+            let i = expect_int(interp_expr(e, env, ctx)?, e)?;
+            ctx.arith(expr, i.checked_neg(), i.wrapping_neg())
+        }
+        ExprKind::Add(e1, e2) => {
+            let (i1, i2) = interp_ints(e1, e2, env, ctx)?;
+            ctx.arith(expr, i1.checked_add(i2), i1.wrapping_add(i2))
+        }
+        ExprKind::Sub(e1, e2) => {
+            let (i1, i2) = interp_ints(e1, e2, env, ctx)?;
+            ctx.arith(expr, i1.checked_sub(i2), i1.wrapping_sub(i2))
+        }
+        ExprKind::Mul(e1, e2) => {
+            let (i1, i2) = interp_ints(e1, e2, env, ctx)?;
+            ctx.arith(expr, i1.checked_mul(i2), i1.wrapping_mul(i2))
+        }
+        ExprKind::Quotient(e1, e2) => {
+            let (i1, i2) = interp_ints(e1, e2, env, ctx)?;
+            ctx.division(expr, i2, i1.checked_div(i2))
+        }
+        ExprKind::Remainder(e1, e2) => {
+            let (i1, i2) = interp_ints(e1, e2, env, ctx)?;
+            ctx.division(expr, i2, i1.checked_rem(i2))
+        }
+        ExprKind::Not(e) => Ok(Value::Bool(!interp_bool(e, env, ctx)?)),
+        ExprKind::And(e1, e2) => {
+            if interp_bool(e1, env, ctx)? {
+                Ok(Value::Bool(interp_bool(e2, env, ctx)?))
+            } else {
+                Ok(Value::Bool(false))
+            }
+        }
+        ExprKind::Or(e1, e2) => {
+            if interp_bool(e1, env, ctx)? {
+                Ok(Value::Bool(true))
+            } else {
+                Ok(Value::Bool(interp_bool(e2, env, ctx)?))
             }
         }
-        Expr::Var(sym) => env.get(sym).expect("undefined variable"),
-        Expr::Let(sym, e, body) => {
-            let val = interp_expr(e, env);
+        ExprKind::Cmp(CmpOp::Eq, e1, e2) => interp_eq(e1, e2, env, ctx),
+        ExprKind::Cmp(op, e1, e2) => {
+            let (i1, i2) = interp_ints(e1, e2, env, ctx)?;
+            Ok(Value::Bool(op.apply(i1, i2)))
+        }
+        // Values carry their types already, so injecting only checks that an
+        // integer fits beside the tag.
+        ExprKind::Inject(e, ty) => match interp_expr(e, env, ctx)? {
+            Value::Int(i) if *ty == Type::Integer => {
+                let wrapped = i.wrapping_shl(3) >> 3;
+                ctx.arith(expr, Some(i).filter(|&i| i == wrapped), wrapped)
+            }
+            val => Ok(val),
+        },
+        ExprKind::Project(e, ty) => {
+            let val = interp_expr(e, env, ctx)?;
+            expect_type(val, ty, e, ctx)
+        }
+        _ => unreachable!("interp_prim is called on an operation"),
+    }
+}
+
+/// Evaluates an operation that creates, reads or writes a vector.
+fn interp_vector(expr: &Expr, env: &Env, ctx: &mut Ctx) -> Result<Value, InterpError> {
+    match &expr.kind {
+        ExprKind::Vector(elems) => {
+            let vals = elems
+                .iter()
+                .map(|e| interp_expr(e, env, ctx))
+                .collect::<Result<Vec<Value>, InterpError>>()?;
+            Ok(Value::Vector(Rc::new(RefCell::new(vals))))
+        }
+        ExprKind::VectorRef(v, index) | ExprKind::AnyVectorRef(v, index) => {
+            let elems = expect_vector(interp_expr(v, env, ctx)?, v)?;
+            let i = expect_index(&elems.borrow(), index)?;
+            let val = elems.borrow()[i].clone();
+            Ok(val)
+        }
+        ExprKind::VectorSet(v, index, e) | ExprKind::AnyVectorSet(v, index, e) => {
+            let elems = expect_vector(interp_expr(v, env, ctx)?, v)?;
+            let val = interp_expr(e, env, ctx)?;
+            let i = expect_index(&elems.borrow(), index)?;
+            elems.borrow_mut()[i] = val;
+            Ok(Value::Void)
+        }
+        _ => unreachable!("interp_vector is called on a vector operation"),
+    }
+}
+
+/// Evaluates a form that binds, assigns or sequences, or a loop.
+fn interp_control(expr: &Expr, env: &Env, ctx: &mut Ctx) -> Result<Value, InterpError> {
+    match &expr.kind {
+        ExprKind::Let(sym, e, body) => {
+            let val = interp_expr(e, env, ctx)?;
             let mut new_env = env.shallow_clone();
             new_env.set(sym.clone(), val);
-            interp_expr(body, &new_env)
+            interp_expr(body, &new_env, ctx)
+        }
+        ExprKind::Set(sym, e) => {
+            let val = interp_expr(e, env, ctx)?;
+            env.assign(sym, val)
+                .ok_or_else(|| unbound_variable(sym, expr))?;
+            Ok(Value::Void)
+        }
+        ExprKind::If(cond, then, els) => {
+            if interp_bool(cond, env, ctx)? {
+                interp_expr(then, env, ctx)
+            } else {
+                interp_expr(els, env, ctx)
+            }
+        }
+        ExprKind::Begin(effects, e) => {
+            for effect in effects {
+                interp_expr(effect, env, ctx)?;
+            }
+            interp_expr(e, env, ctx)
         }
+        ExprKind::While(cond, body) => {
+            while interp_bool(cond, env, ctx)? {
+                interp_expr(body, env, ctx)?;
+            }
+            Ok(Value::Void)
+        }
+        ExprKind::Print(e) => {
+            let val = interp_expr(e, env, ctx)?;
+            print_value(&val, ctx);
+            Ok(Value::Void)
+        }
+        _ => unreachable!("interp_control is called on a control form"),
     }
 }
 
-pub fn interp(p: &Program) {
-    println!("Result: {}", interp_expr(&p.expr, &Env::new()))
+/// Evaluates the expression. Its frame is on the stack once per level of
+/// nesting, so each kind of form is evaluated by a helper of its own.
+fn interp_expr(expr: &Expr, env: &Env, ctx: &mut Ctx) -> Result<Value, InterpError> {
+    match &expr.kind {
+        ExprKind::Read => read_int(ctx.input),
+        ExprKind::Lit(Lit::Int(i)) => Ok(Value::Int(*i)),
+        ExprKind::Lit(Lit::Bool(b)) => Ok(Value::Bool(*b)),
+        ExprKind::Neg(_)
+        | ExprKind::Add(..)
+        | ExprKind::Sub(..)
+        | ExprKind::Mul(..)
+        | ExprKind::Quotient(..)
+        | ExprKind::Remainder(..)
+        | ExprKind::Not(_)
+        | ExprKind::And(..)
+        | ExprKind::Or(..)
+        | ExprKind::Cmp(..)
+        | ExprKind::Inject(..)
+        | ExprKind::Project(..) => interp_prim(expr, env, ctx),
+        ExprKind::Vector(_)
+        | ExprKind::VectorRef(..)
+        | ExprKind::VectorSet(..)
+        | ExprKind::AnyVectorRef(..)
+        | ExprKind::AnyVectorSet(..) => interp_vector(expr, env, ctx),
+        ExprKind::Let(..)
+        | ExprKind::Set(..)
+        | ExprKind::If(..)
+        | ExprKind::Begin(..)
+        | ExprKind::While(..)
+        | ExprKind::Print(_) => interp_control(expr, env, ctx),
+        ExprKind::Void => Ok(Value::Void),
+        // The interpreter has no heap of its own: collection does nothing,
+        // allocation makes a vector of voids, and every global of the runtime
+        // reads as 0.
+        ExprKind::Collect(_) => Ok(Value::Void),
+        ExprKind::Allocate(len, _) => Ok(Value::Vector(Rc::new(RefCell::new(vec![
+            Value::Void;
+            *len
+        ])))),
+        ExprKind::GlobalValue(_) => Ok(Value::Int(0)),
+        ExprKind::Var(sym) => env.get(sym).ok_or_else(|| unbound_variable(sym, expr)),
+        ExprKind::Apply(f, args) => interp_apply(f, args, expr, env, ctx),
+        ExprKind::FunRef(name) => Ok(Value::Function(name.clone())),
+        ExprKind::Lambda(params, body) => Ok(Value::Closure(Rc::new(Closure {
+            params: params.iter().map(|(param, _)| (**param).clone()).collect(),
+            body: (**body).clone(),
+            env: env.shallow_clone(),
+        }))),
+    }
 }
 
-impl TryFrom<String> for Lit {
-    type Error = std::num::ParseIntError;
+/// Evaluates the program, taking the values of `(read)` from `input`. What it
+/// prints is discarded; `interp_with_output` collects it.
+pub fn interp_with(p: &Program, input: &mut dyn InputSource) -> Result<Value, InterpError> {
+    interp_with_options(p, input, &Options::default())
+}
 
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        let x = value.parse()?;
-        Ok(Lit::Int(x))
-    }
+/// Evaluates the program with the given options, taking the values of
+/// `(read)` from `input`. What it prints is discarded, as by `interp_with`.
+pub fn interp_with_options(
+    p: &Program,
+    input: &mut dyn InputSource,
+    options: &Options,
+) -> Result<Value, InterpError> {
+    interp_with_output(p, input, &mut String::new(), options)
 }
 
-impl fmt::Display for Lit {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
-            Lit::Int(i) => write!(f, "{}", i),
+/// Evaluates the program with the given options, taking the values of
+/// `(read)` from `input` and writing what it prints to `output`. A
+/// dynamically typed program is evaluated with its casts inserted, so that it
+/// fails where the compiled program would.
+pub fn interp_with_output(
+    p: &Program,
+    input: &mut dyn InputSource,
+    output: &mut dyn OutputSink,
+    options: &Options,
+) -> Result<Value, InterpError> {
+    let casted;
+    let p = match options.typing {
+        Typing::Static => p,
+        Typing::Dynamic => {
+            casted = CastInserter::new().fold_program(p.clone());
+            &casted
         }
+    };
+    let mut globals = Env::new();
+    for def in &p.defs {
+        globals.set(def.name.clone(), Value::Function(def.name.value.clone()));
+    }
+    let mut ctx = Ctx {
+        input,
+        output,
+        options,
+        defs: p
+            .defs
+            .iter()
+            .map(|def| (def.name.value.as_str(), def))
+            .collect(),
+        globals,
+    };
+    let env = ctx.globals.shallow_clone();
+    interp_expr(&p.expr, &env, &mut ctx)
+}
+
+/// Evaluates the program with input from standard input, writing what it
+/// prints and then the result to standard output.
+pub fn interp(p: &Program) {
+    let options = Options::default();
+    match interp_with_output(p, &mut StdinInput, &mut StdoutOutput, &options) {
+        Ok(val) => println!("Result: {}", val),
+        Err(e) => println!("Error: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::options::{Options, Overflow, Typing};
+    use super::super::parse::parse_program;
+    use super::{
+        interp_with, interp_with_options, interp_with_output, InterpError, ScriptedInput, Value,
+    };
+
+    #[test]
+    fn scripted_input() {
+        let p = parse_program("(let ([x (read)]) (+ x (- (read))))").unwrap();
+        let mut input = ScriptedInput::ints(&[50, 8]);
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(42)));
+    }
+
+    #[test]
+    fn sub() {
+        let p = parse_program("(let ([x (read)]) (- x (- 10 (read))))").unwrap();
+        let mut input = ScriptedInput::ints(&[50, 2]);
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(42)));
+    }
+
+    #[test]
+    fn mul_and_division() {
+        let p = parse_program("(* (quotient (read) 4) (remainder (read) 4))").unwrap();
+        let mut input = ScriptedInput::ints(&[-7, -7]);
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(3)));
+        let mut input = ScriptedInput::ints(&[7, 7]);
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(3)));
+    }
+
+    #[test]
+    fn division_errors() {
+        let p = parse_program("(quotient 1 (read))").unwrap();
+        let err = interp_with(&p, &mut ScriptedInput::ints(&[0])).unwrap_err();
+        assert_eq!(err.to_string(), "division by zero at 1:1");
+        let p = parse_program("(remainder (read) -1)").unwrap();
+        let actual = interp_with(&p, &mut ScriptedInput::ints(&[i64::MIN]));
+        assert!(matches!(actual, Err(InterpError::Overflow(_))));
+    }
+
+    #[test]
+    fn conditionals() {
+        let p = parse_program("(if (and (<= 0 (read)) (not (eq? #f (read)))) 1 2)").unwrap();
+        let err = interp_with(&p, &mut ScriptedInput::ints(&[1, 1])).unwrap_err();
+        assert_eq!(err.to_string(), "expected Boolean, found Integer at 1:37");
+        // `and` does not evaluate its second operand when the first is false.
+        let mut input = ScriptedInput::ints(&[-1]);
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(2)));
+        let p = parse_program("(let ([x (read)]) (or (> x 5) (>= (- x) 5)))").unwrap();
+        let mut input = ScriptedInput::ints(&[-5]);
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Bool(true)));
+        let mut input = ScriptedInput::ints(&[4]);
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Bool(false)));
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let p = parse_program("(+ 1 (< 1 2))").unwrap();
+        let err = interp_with(&p, &mut ScriptedInput::default()).unwrap_err();
+        assert_eq!(err.to_string(), "expected Integer, found Boolean at 1:6");
+        let p = parse_program("(if 0 1 2)").unwrap();
+        let actual = interp_with(&p, &mut ScriptedInput::default());
+        assert!(matches!(actual, Err(InterpError::TypeMismatch(_))));
+    }
+
+    #[test]
+    fn vectors() {
+        let src = "(let ([v (vector 1 (vector #t))]) \
+                   (let ([w v]) \
+                   (let ([_ (vector-set! w 0 (read))]) \
+                   (if (eq? v w) (vector-ref v 0) 0))))";
+        let p = parse_program(src).unwrap();
+        let mut input = ScriptedInput::ints(&[42]);
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(42)));
+        let p = parse_program("(eq? (vector 1) (vector 1))").unwrap();
+        let mut input = ScriptedInput::default();
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Bool(false)));
+        let p = parse_program("(vector 1 (vector #t (void)))").unwrap();
+        let val = interp_with(&p, &mut input).unwrap();
+        assert_eq!(val.to_string(), "#(1 #(#t #<void>))");
+    }
+
+    #[test]
+    fn functions() {
+        let src = "\
+(define (fact [n : Integer]) : Integer
+  (if (eq? n 0) 1 (* n (fact (- n 1)))))
+(define (twice [f : (Integer -> Integer)] [x : Integer]) : Integer
+  (f (f x)))
+(twice fact (read))";
+        let p = parse_program(src).unwrap();
+        let mut input = ScriptedInput::ints(&[3]);
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(720)));
+        let p = parse_program("(define (f) : Integer 1) (let ([f 2]) f)").unwrap();
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(2)));
+        let p = parse_program("(define (f) : Integer 1) (vector f (f 2))").unwrap();
+        let actual = interp_with(&p, &mut input).unwrap_err();
+        assert_eq!(
+            actual.to_string(),
+            "`f` expects 0 argument(s) but got 1 at 1:36"
+        );
+    }
+
+    #[test]
+    fn lambdas() {
+        let src = "\
+(define (map-vec [f : (Integer -> Integer)] [v : (Vector Integer Integer)])
+  : (Vector Integer Integer)
+  (vector (f (vector-ref v 0)) (f (vector-ref v 1))))
+(let ([y (read)])
+  (let ([add-y (lambda ([x : Integer]) (+ x y))])
+    (let ([y 100])
+      (vector-ref (map-vec add-y (vector 1 2)) 1))))";
+        let p = parse_program(src).unwrap();
+        let mut input = ScriptedInput::ints(&[40]);
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(42)));
+        let p = parse_program("((lambda ([x : Integer]) x))").unwrap();
+        let actual = interp_with(&p, &mut input).unwrap_err();
+        assert_eq!(
+            actual.to_string(),
+            "`lambda` expects 1 argument(s) but got 0 at 1:1"
+        );
+    }
+
+    #[test]
+    fn assignments_and_loops() {
+        let src = "\
+(let ([i 0])
+  (let ([inc (lambda () (set! i (+ i 1)))])
+    (begin (while (< i 5) (inc))
+           (let ([i 100]) (set! i 0))
+           i)))";
+        let p = parse_program(src).unwrap();
+        let mut input = ScriptedInput::default();
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(5)));
+    }
+
+    #[test]
+    fn prints() {
+        let src = "(begin (print (vector 1 (vector #t))) (print (- 5)) (print (lambda () 1)))";
+        let p = parse_program(src).unwrap();
+        let mut input = ScriptedInput::default();
+        let mut output = String::new();
+        let options = Options::default();
+        let actual = interp_with_output(&p, &mut input, &mut output, &options);
+        assert_eq!(actual, Ok(Value::Void));
+        assert_eq!(output, "#(1 #(#t))\n-5\n#<procedure>\n");
+        // Without a sink the output goes nowhere.
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Void));
+    }
+
+    #[test]
+    fn shadowing() {
+        let p = parse_program("(let ([x 1]) (+ (let ([x 10]) x) x))").unwrap();
+        let mut input = ScriptedInput::default();
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(11)));
+    }
+
+    #[test]
+    fn end_of_input() {
+        let p = parse_program("(+ (read) (read))").unwrap();
+        let mut input = ScriptedInput::ints(&[1]);
+        assert_eq!(interp_with(&p, &mut input), Err(InterpError::EndOfInput));
+    }
+
+    #[test]
+    fn invalid_input() {
+        let p = parse_program("(read)").unwrap();
+        let mut input = ScriptedInput::new(&[" forty-two\n"]);
+        let expected = InterpError::InvalidInput("forty-two".to_string());
+        assert_eq!(interp_with(&p, &mut input), Err(expected));
+    }
+
+    #[test]
+    fn unbound_variable() {
+        let p = parse_program("(let ([x 1]) (+ x y))").unwrap();
+        let err = interp_with(&p, &mut ScriptedInput::default()).unwrap_err();
+        assert_eq!(err.to_string(), "unbound variable `y` at 1:19");
+    }
+
+    #[test]
+    fn wrapping_overflow() {
+        let p = parse_program("(+ (read) 1)").unwrap();
+        let mut input = ScriptedInput::ints(&[i64::MAX]);
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(i64::MIN)));
+        let p = parse_program("(- (read))").unwrap();
+        let mut input = ScriptedInput::ints(&[i64::MIN]);
+        assert_eq!(interp_with(&p, &mut input), Ok(Value::Int(i64::MIN)));
+    }
+
+    #[test]
+    fn dynamic_typing() {
+        let options = Options {
+            typing: Typing::Dynamic,
+            ..Options::default()
+        };
+        let src = "\
+(define (first v) (vector-ref v 0))
+(let ([v (vector (read) #f)])
+  (if (eq? (first v) #f) 0 (+ (first v) 1)))";
+        let p = parse_program(src).unwrap();
+        let mut input = ScriptedInput::ints(&[41]);
+        let actual = interp_with_options(&p, &mut input, &options);
+        assert_eq!(actual, Ok(Value::Int(42)));
+        let p = parse_program("(vector-ref (vector 1) 1)").unwrap();
+        let err = interp_with_options(&p, &mut input, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "index is out of range for a vector of length 1 at 1:24"
+        );
+        let p = parse_program("((lambda (x) x) 1 2)").unwrap();
+        let err = interp_with_options(&p, &mut input, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "expected (Any Any -> Any), found Function at 1:2"
+        );
+        // Integers keep 61 bits beside their tag.
+        let p = parse_program("(read)").unwrap();
+        let mut input = ScriptedInput::ints(&[1 << 60]);
+        let actual = interp_with_options(&p, &mut input, &options);
+        assert_eq!(actual, Ok(Value::Int(-(1 << 60))));
+    }
+
+    #[test]
+    fn trapping_overflow() {
+        let options = Options {
+            overflow: Overflow::Trapping,
+            ..Options::default()
+        };
+        let p = parse_program("(let ([x (read)])\n  (+ x 1))").unwrap();
+        let mut input = ScriptedInput::ints(&[i64::MAX]);
+        let err = interp_with_options(&p, &mut input, &options).unwrap_err();
+        assert_eq!(err.to_string(), "integer overflow at 2:3");
+        let mut input = ScriptedInput::ints(&[i64::MAX - 1]);
+        let actual = interp_with_options(&p, &mut input, &options);
+        assert_eq!(actual, Ok(Value::Int(i64::MAX)));
+        let p = parse_program("(- (read))").unwrap();
+        let mut input = ScriptedInput::ints(&[i64::MIN]);
+        let actual = interp_with_options(&p, &mut input, &options);
+        assert!(matches!(actual, Err(InterpError::Overflow(_))));
+        let p = parse_program("(- -2 (read))").unwrap();
+        let mut input = ScriptedInput::ints(&[i64::MAX]);
+        let actual = interp_with_options(&p, &mut input, &options);
+        assert!(matches!(actual, Err(InterpError::Overflow(_))));
     }
 }
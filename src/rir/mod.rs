@@ -2,10 +2,22 @@
 //! Closely corresponsds to the AST of source code.
 
 pub mod arg_simplify;
+pub mod convert_assignments;
+pub mod convert_closures;
 pub mod explicate;
+pub mod expose_allocation;
+pub mod insert_casts;
 pub mod interp;
+pub mod parse;
+pub mod pretty;
+pub mod typecheck;
 pub mod uniquify;
 
+use super::diagnostic::Span;
+use typecheck::Type;
+
+pub use super::cir::CmpOp;
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Symbol {
     value: String,
@@ -22,54 +34,319 @@ impl Symbol {
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Lit {
     Int(i64),
+    Bool(bool),
+}
+
+/// An expression together with the location of the source text it was parsed
+/// from.
+#[derive(Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+/// Expressions compare equal when they have the same shape, regardless of
+/// where they came from.
+impl PartialEq for Expr {
+    fn eq(&self, other: &Expr) -> bool {
+        self.kind == other.kind
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub enum Expr {
+pub enum ExprKind {
     Read,
     Lit(Lit),
     Neg(Box<Expr>),
     Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Quotient(Box<Expr>, Box<Expr>),
+    Remainder(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// Short-circuiting conjunction.
+    And(Box<Expr>, Box<Expr>),
+    /// Short-circuiting disjunction.
+    Or(Box<Expr>, Box<Expr>),
+    Cmp(CmpOp, Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    Vector(Vec<Expr>),
+    /// Reads the element of a vector at an index, which is an integer
+    /// literal.
+    VectorRef(Box<Expr>, Box<Expr>),
+    /// Writes the element of a vector at an index, which is an integer
+    /// literal.
+    VectorSet(Box<Expr>, Box<Expr>, Box<Expr>),
+    Void,
+    /// Runs the garbage collector so that at least the given number of bytes
+    /// are free. Added by expose_allocation.
+    Collect(i64),
+    /// Reserves space on the heap for a vector of the given length and type,
+    /// with uninitialized elements. Added by expose_allocation.
+    Allocate(usize, Type),
+    /// Reads a global variable of the runtime. Added by expose_allocation.
+    GlobalValue(String),
     Var(Box<Symbol>),
     Let(Box<Symbol>, Box<Expr>, Box<Expr>),
+    /// Assigns the value of the expression to a variable in scope.
+    Set(Box<Symbol>, Box<Expr>),
+    /// Evaluates the expressions in order for their effects and then the
+    /// last one for its value.
+    Begin(Vec<Expr>, Box<Expr>),
+    /// Evaluates the body for its effects for as long as the condition is
+    /// true.
+    While(Box<Expr>, Box<Expr>),
+    /// Writes the value of the expression followed by a newline to standard
+    /// output.
+    Print(Box<Expr>),
+    /// Calls the function the first expression evaluates to with the values
+    /// of the rest as arguments.
+    Apply(Box<Expr>, Vec<Expr>),
+    /// Refers to a function defined at the top level of the program by its
+    /// unique name. Added by uniquify.
+    FunRef(String),
+    /// An anonymous function of the given parameters, which may refer to the
+    /// variables in scope where it is created. Removed by convert_closures.
+    Lambda(Vec<(Box<Symbol>, Type)>, Box<Expr>),
+    /// Tags the value of the expression, which has the given ground type, to
+    /// make a value of type `Any`.
+    Inject(Box<Expr>, Type),
+    /// Takes the value of the given ground type back out of an `Any`. Stops
+    /// the program if the value has another type.
+    Project(Box<Expr>, Type),
+    /// Reads the element of a vector held by an `Any` at an index, which is
+    /// an integer literal. Stops the program if the `Any` holds no vector or
+    /// one too short.
+    AnyVectorRef(Box<Expr>, Box<Expr>),
+    /// Writes the element of a vector held by an `Any`, checked like
+    /// `AnyVectorRef`.
+    AnyVectorSet(Box<Expr>, Box<Expr>, Box<Expr>),
 }
 
 impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Box<Expr> {
+        Box::new(Expr { kind, span })
+    }
+
+    /// Replaces the location of the expression.
+    pub fn at(mut self: Box<Expr>, span: Span) -> Box<Expr> {
+        self.span = span;
+        self
+    }
+
+    /// The expressions directly within the expression, in the order they
+    /// are written.
+    pub fn children(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::Read
+            | ExprKind::Lit(_)
+            | ExprKind::Void
+            | ExprKind::Collect(_)
+            | ExprKind::Allocate(_, _)
+            | ExprKind::GlobalValue(_)
+            | ExprKind::Var(_)
+            | ExprKind::FunRef(_) => vec![],
+            ExprKind::Neg(e)
+            | ExprKind::Not(e)
+            | ExprKind::Set(_, e)
+            | ExprKind::Print(e)
+            | ExprKind::Lambda(_, e)
+            | ExprKind::Inject(e, _)
+            | ExprKind::Project(e, _) => vec![e],
+            ExprKind::Add(e1, e2)
+            | ExprKind::Sub(e1, e2)
+            | ExprKind::Mul(e1, e2)
+            | ExprKind::Quotient(e1, e2)
+            | ExprKind::Remainder(e1, e2)
+            | ExprKind::And(e1, e2)
+            | ExprKind::Or(e1, e2)
+            | ExprKind::Cmp(_, e1, e2)
+            | ExprKind::VectorRef(e1, e2)
+            | ExprKind::AnyVectorRef(e1, e2)
+            | ExprKind::Let(_, e1, e2)
+            | ExprKind::While(e1, e2) => vec![e1, e2],
+            ExprKind::If(e1, e2, e3)
+            | ExprKind::VectorSet(e1, e2, e3)
+            | ExprKind::AnyVectorSet(e1, e2, e3) => vec![e1, e2, e3],
+            ExprKind::Vector(elems) => elems.iter().collect(),
+            ExprKind::Begin(effects, e) => effects.iter().chain(Some(&**e)).collect(),
+            ExprKind::Apply(f, args) => Some(&**f).into_iter().chain(args).collect(),
+        }
+    }
+
     pub fn read() -> Box<Expr> {
-        Box::new(Expr::Read)
+        Expr::new(ExprKind::Read, Span::default())
     }
 
     pub fn int(i: i64) -> Box<Expr> {
-        Box::new(Expr::Lit(Lit::Int(i)))
+        Expr::new(ExprKind::Lit(Lit::Int(i)), Span::default())
+    }
+
+    pub fn bool(b: bool) -> Box<Expr> {
+        Expr::new(ExprKind::Lit(Lit::Bool(b)), Span::default())
     }
 
     #[allow(clippy::should_implement_trait)] // Suggests to implement Neg trait.
     pub fn neg(e: Box<Expr>) -> Box<Expr> {
-        Box::new(Expr::Neg(e))
+        Expr::new(ExprKind::Neg(e), Span::default())
     }
 
     #[allow(clippy::should_implement_trait)] // Suggests to implement Add trait.
     pub fn add(e1: Box<Expr>, e2: Box<Expr>) -> Box<Expr> {
-        Box::new(Expr::Add(e1, e2))
+        Expr::new(ExprKind::Add(e1, e2), Span::default())
+    }
+
+    #[allow(clippy::should_implement_trait)] // Suggests to implement Sub trait.
+    pub fn sub(e1: Box<Expr>, e2: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::Sub(e1, e2), Span::default())
+    }
+
+    #[allow(clippy::should_implement_trait)] // Suggests to implement Mul trait.
+    pub fn mul(e1: Box<Expr>, e2: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::Mul(e1, e2), Span::default())
+    }
+
+    pub fn quotient(e1: Box<Expr>, e2: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::Quotient(e1, e2), Span::default())
+    }
+
+    pub fn remainder(e1: Box<Expr>, e2: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::Remainder(e1, e2), Span::default())
+    }
+
+    #[allow(clippy::should_implement_trait)] // Suggests to implement Not trait.
+    pub fn not(e: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::Not(e), Span::default())
+    }
+
+    pub fn and(e1: Box<Expr>, e2: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::And(e1, e2), Span::default())
+    }
+
+    pub fn or(e1: Box<Expr>, e2: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::Or(e1, e2), Span::default())
+    }
+
+    pub fn cmp(op: CmpOp, e1: Box<Expr>, e2: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::Cmp(op, e1, e2), Span::default())
+    }
+
+    pub fn if_expr(cond: Box<Expr>, then: Box<Expr>, els: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::If(cond, then, els), Span::default())
+    }
+
+    pub fn vector(elems: Vec<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::Vector(elems), Span::default())
+    }
+
+    pub fn vector_ref(v: Box<Expr>, index: i64) -> Box<Expr> {
+        Expr::new(ExprKind::VectorRef(v, Expr::int(index)), Span::default())
+    }
+
+    pub fn vector_set(v: Box<Expr>, index: i64, e: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::VectorSet(v, Expr::int(index), e), Span::default())
+    }
+
+    pub fn void() -> Box<Expr> {
+        Expr::new(ExprKind::Void, Span::default())
     }
 
     pub fn var(s: &str) -> Box<Expr> {
-        Box::new(Expr::Var(Box::new(Symbol::new(s))))
+        Expr::new(ExprKind::Var(Box::new(Symbol::new(s))), Span::default())
     }
 
     pub fn let_bind(s: &str, e: Box<Expr>, body: Box<Expr>) -> Box<Expr> {
-        Box::new(Expr::Let(Box::new(Symbol::new(s)), e, body))
+        Expr::new(
+            ExprKind::Let(Box::new(Symbol::new(s)), e, body),
+            Span::default(),
+        )
+    }
+
+    pub fn set(s: &str, e: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::Set(Box::new(Symbol::new(s)), e), Span::default())
+    }
+
+    pub fn begin(effects: Vec<Expr>, e: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::Begin(effects, e), Span::default())
+    }
+
+    pub fn while_loop(cond: Box<Expr>, body: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::While(cond, body), Span::default())
+    }
+
+    pub fn print(e: Box<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::Print(e), Span::default())
+    }
+
+    pub fn apply(f: Box<Expr>, args: Vec<Expr>) -> Box<Expr> {
+        Expr::new(ExprKind::Apply(f, args), Span::default())
+    }
+
+    pub fn fun_ref(name: &str) -> Box<Expr> {
+        Expr::new(ExprKind::FunRef(name.to_string()), Span::default())
+    }
+
+    pub fn lambda(params: Vec<(&str, Type)>, body: Box<Expr>) -> Box<Expr> {
+        let params = params
+            .into_iter()
+            .map(|(param, ty)| (Box::new(Symbol::new(param)), ty))
+            .collect();
+        Expr::new(ExprKind::Lambda(params, body), Span::default())
+    }
+
+    pub fn inject(e: Box<Expr>, ty: Type) -> Box<Expr> {
+        Expr::new(ExprKind::Inject(e, ty), Span::default())
+    }
+
+    pub fn project(e: Box<Expr>, ty: Type) -> Box<Expr> {
+        Expr::new(ExprKind::Project(e, ty), Span::default())
+    }
+
+    pub fn any_vector_ref(v: Box<Expr>, index: i64) -> Box<Expr> {
+        Expr::new(ExprKind::AnyVectorRef(v, Expr::int(index)), Span::default())
+    }
+
+    pub fn any_vector_set(v: Box<Expr>, index: i64, e: Box<Expr>) -> Box<Expr> {
+        Expr::new(
+            ExprKind::AnyVectorSet(v, Expr::int(index), e),
+            Span::default(),
+        )
     }
 }
 
 pub trait ExprFolder {
+    #[allow(clippy::boxed_local)] // Folders always take and return boxed nodes.
     fn fold(&mut self, e: Box<Expr>) -> Box<Expr> {
-        match *e {
-            Expr::Neg(e) => self.fold_neg(e),
-            Expr::Add(e1, e2) => self.fold_add(e1, e2),
-            Expr::Var(s) => self.fold_var(s),
-            Expr::Let(sym, e, body) => self.fold_let(sym, e, body),
-            _ => e, // By default leaf expressions just return identity.
+        let Expr { kind, span } = *e;
+        match kind {
+            ExprKind::Neg(e) => self.fold_neg(e, span),
+            ExprKind::Add(e1, e2) => self.fold_add(e1, e2, span),
+            ExprKind::Sub(e1, e2) => self.fold_sub(e1, e2, span),
+            ExprKind::Mul(e1, e2) => self.fold_mul(e1, e2, span),
+            ExprKind::Quotient(e1, e2) => self.fold_quotient(e1, e2, span),
+            ExprKind::Remainder(e1, e2) => self.fold_remainder(e1, e2, span),
+            ExprKind::Not(e) => self.fold_not(e, span),
+            ExprKind::And(e1, e2) => self.fold_and(e1, e2, span),
+            ExprKind::Or(e1, e2) => self.fold_or(e1, e2, span),
+            ExprKind::Cmp(op, e1, e2) => self.fold_cmp(op, e1, e2, span),
+            ExprKind::If(cond, then, els) => self.fold_if(cond, then, els, span),
+            ExprKind::Vector(elems) => self.fold_vector(elems, span),
+            ExprKind::VectorRef(v, index) => self.fold_vector_ref(v, index, span),
+            ExprKind::VectorSet(v, index, e) => self.fold_vector_set(v, index, e, span),
+            ExprKind::Var(s) => self.fold_var(s, span),
+            ExprKind::Let(sym, e, body) => self.fold_let(sym, e, body, span),
+            ExprKind::Set(sym, e) => self.fold_set(sym, e, span),
+            ExprKind::Begin(effects, e) => self.fold_begin(effects, e, span),
+            ExprKind::While(cond, body) => self.fold_while(cond, body, span),
+            ExprKind::Print(e) => self.fold_print(e, span),
+            ExprKind::Apply(f, args) => self.fold_apply(f, args, span),
+            ExprKind::Lambda(params, body) => self.fold_lambda(params, body, span),
+            ExprKind::FunRef(name) => self.fold_fun_ref(name, span),
+            ExprKind::Inject(e, ty) => self.fold_inject(e, ty, span),
+            ExprKind::Project(e, ty) => self.fold_project(e, ty, span),
+            ExprKind::AnyVectorRef(v, index) => self.fold_any_vector_ref(v, index, span),
+            ExprKind::AnyVectorSet(v, index, e) => self.fold_any_vector_set(v, index, e, span),
+            kind => Expr::new(kind, span), // By default leaf expressions just return identity.
         }
     }
 
@@ -77,31 +354,228 @@ pub trait ExprFolder {
         s
     }
 
-    fn fold_neg(&mut self, e: Box<Expr>) -> Box<Expr> {
-        Box::new(Expr::Neg(self.fold(e)))
+    fn fold_neg(&mut self, e: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Neg(self.fold(e)), span)
+    }
+
+    fn fold_add(&mut self, e1: Box<Expr>, e2: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Add(self.fold(e1), self.fold(e2)), span)
+    }
+
+    fn fold_sub(&mut self, e1: Box<Expr>, e2: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Sub(self.fold(e1), self.fold(e2)), span)
+    }
+
+    fn fold_mul(&mut self, e1: Box<Expr>, e2: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Mul(self.fold(e1), self.fold(e2)), span)
+    }
+
+    fn fold_quotient(&mut self, e1: Box<Expr>, e2: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Quotient(self.fold(e1), self.fold(e2)), span)
+    }
+
+    fn fold_remainder(&mut self, e1: Box<Expr>, e2: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Remainder(self.fold(e1), self.fold(e2)), span)
+    }
+
+    fn fold_not(&mut self, e: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Not(self.fold(e)), span)
+    }
+
+    fn fold_and(&mut self, e1: Box<Expr>, e2: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::And(self.fold(e1), self.fold(e2)), span)
+    }
+
+    fn fold_or(&mut self, e1: Box<Expr>, e2: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Or(self.fold(e1), self.fold(e2)), span)
+    }
+
+    fn fold_cmp(&mut self, op: CmpOp, e1: Box<Expr>, e2: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Cmp(op, self.fold(e1), self.fold(e2)), span)
+    }
+
+    fn fold_if(
+        &mut self,
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        els: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
+        Expr::new(
+            ExprKind::If(self.fold(cond), self.fold(then), self.fold(els)),
+            span,
+        )
+    }
+
+    fn fold_vector(&mut self, elems: Vec<Expr>, span: Span) -> Box<Expr> {
+        let elems = elems.into_iter().map(|e| *self.fold(Box::new(e))).collect();
+        Expr::new(ExprKind::Vector(elems), span)
+    }
+
+    fn fold_vector_ref(&mut self, v: Box<Expr>, index: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::VectorRef(self.fold(v), self.fold(index)), span)
+    }
+
+    fn fold_vector_set(
+        &mut self,
+        v: Box<Expr>,
+        index: Box<Expr>,
+        e: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
+        Expr::new(
+            ExprKind::VectorSet(self.fold(v), self.fold(index), self.fold(e)),
+            span,
+        )
+    }
+
+    fn fold_var(&mut self, s: Box<Symbol>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Var(self.fold_sym(s)), span)
     }
 
-    fn fold_add(&mut self, e1: Box<Expr>, e2: Box<Expr>) -> Box<Expr> {
-        Box::new(Expr::Add(self.fold(e1), self.fold(e2)))
+    fn fold_let(
+        &mut self,
+        sym: Box<Symbol>,
+        e: Box<Expr>,
+        body: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
+        Expr::new(
+            ExprKind::Let(self.fold_sym(sym), self.fold(e), self.fold(body)),
+            span,
+        )
     }
 
-    fn fold_var(&mut self, s: Box<Symbol>) -> Box<Expr> {
-        Box::new(Expr::Var(self.fold_sym(s)))
+    fn fold_set(&mut self, sym: Box<Symbol>, e: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Set(self.fold_sym(sym), self.fold(e)), span)
     }
 
-    fn fold_let(&mut self, sym: Box<Symbol>, e: Box<Expr>, body: Box<Expr>) -> Box<Expr> {
-        Box::new(Expr::Let(self.fold_sym(sym), self.fold(e), self.fold(body)))
+    fn fold_begin(&mut self, effects: Vec<Expr>, e: Box<Expr>, span: Span) -> Box<Expr> {
+        let effects = effects
+            .into_iter()
+            .map(|e| *self.fold(Box::new(e)))
+            .collect();
+        Expr::new(ExprKind::Begin(effects, self.fold(e)), span)
+    }
+
+    fn fold_while(&mut self, cond: Box<Expr>, body: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::While(self.fold(cond), self.fold(body)), span)
+    }
+
+    fn fold_print(&mut self, e: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Print(self.fold(e)), span)
+    }
+
+    fn fold_apply(&mut self, f: Box<Expr>, args: Vec<Expr>, span: Span) -> Box<Expr> {
+        let f = self.fold(f);
+        let args = args.into_iter().map(|e| *self.fold(Box::new(e))).collect();
+        Expr::new(ExprKind::Apply(f, args), span)
+    }
+
+    fn fold_fun_ref(&mut self, name: String, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::FunRef(name), span)
+    }
+
+    fn fold_lambda(
+        &mut self,
+        params: Vec<(Box<Symbol>, Type)>,
+        body: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
+        let params = params
+            .into_iter()
+            .map(|(param, ty)| (self.fold_sym(param), ty))
+            .collect();
+        Expr::new(ExprKind::Lambda(params, self.fold(body)), span)
+    }
+
+    fn fold_inject(&mut self, e: Box<Expr>, ty: Type, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Inject(self.fold(e), ty), span)
+    }
+
+    fn fold_project(&mut self, e: Box<Expr>, ty: Type, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::Project(self.fold(e), ty), span)
+    }
+
+    fn fold_any_vector_ref(&mut self, v: Box<Expr>, index: Box<Expr>, span: Span) -> Box<Expr> {
+        Expr::new(ExprKind::AnyVectorRef(self.fold(v), self.fold(index)), span)
+    }
+
+    fn fold_any_vector_set(
+        &mut self,
+        v: Box<Expr>,
+        index: Box<Expr>,
+        e: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
+        Expr::new(
+            ExprKind::AnyVectorSet(self.fold(v), self.fold(index), self.fold(e)),
+            span,
+        )
+    }
+}
+
+/// A function defined at the top level of a program with
+/// `(define (f [x : T] ...) : R body)`.
+#[derive(Clone, Debug)]
+pub struct Def {
+    pub name: Box<Symbol>,
+    pub params: Vec<(Box<Symbol>, Type)>,
+    pub result: Type,
+    pub body: Box<Expr>,
+    pub span: Span,
+}
+
+/// Like expressions, definitions compare equal regardless of where they came
+/// from.
+impl PartialEq for Def {
+    fn eq(&self, other: &Def) -> bool {
+        self.name == other.name
+            && self.params == other.params
+            && self.result == other.result
+            && self.body == other.body
     }
 }
 
+impl Def {
+    /// Type of the function.
+    pub fn ty(&self) -> Type {
+        let params = self.params.iter().map(|(_, ty)| ty.clone()).collect();
+        Type::Function(params, Box::new(self.result.clone()))
+    }
+}
+
+/// Functions, which may call each other, followed by the expression whose
+/// value is the value of the program.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Program {
+    pub defs: Vec<Def>,
     pub expr: Box<Expr>,
 }
 
 impl Program {
     pub fn new(e: Box<Expr>) -> Program {
-        Program { expr: e }
+        Program {
+            defs: vec![],
+            expr: e,
+        }
+    }
+
+    /// Folds the body of every function and the final expression with the
+    /// same folder.
+    pub fn fold_exprs(self, folder: &mut impl ExprFolder) -> Program {
+        let defs = self
+            .defs
+            .into_iter()
+            .map(|def| Def {
+                body: folder.fold(def.body),
+                ..def
+            })
+            .collect();
+        Program {
+            defs,
+            expr: folder.fold(self.expr),
+        }
     }
 }
 
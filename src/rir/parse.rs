@@ -0,0 +1,1099 @@
+//! Reader for R source text.
+//!
+//! Source is written in Racket-style S-expression syntax, e.g.
+//! `(let ([x (read)]) (+ x (- 10)))`. Parsing happens in two steps: the
+//! lexer splits the text into tokens and the reader builds an `Expr` from the
+//! token stream. Every expression is tagged with the span of the text it was
+//! read from.
+
+use super::super::diagnostic::{Diagnostic, Span};
+use super::typecheck::Type;
+use super::{CmpOp, Def, Expr, ExprKind, Lit, Program, Symbol};
+use std::rc::Rc;
+
+/// Error produced when source text is not a well-formed R program.
+pub type ParseError = Diagnostic;
+
+/// Most elements a vector can have. The runtime's vector tag has room for the
+/// length and pointer mask of no more.
+pub const MAX_VECTOR_LEN: usize = 50;
+
+/// Deepest that forms and types can be nested. The reader and the passes
+/// after it recurse once per level, so deeper input would overflow the stack.
+pub const MAX_NESTING: usize = 256;
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Open(char),
+    Close(char),
+    Int(i64),
+    Bool(bool),
+    Ident(String),
+    Eof,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    span: Span,
+}
+
+/// Splits source text into tokens.
+struct Lexer<'a> {
+    file: Rc<str>,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    len: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(file: Rc<str>, src: &'a str) -> Lexer<'a> {
+        Lexer {
+            file,
+            chars: src.char_indices().peekable(),
+            len: src.len(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    /// Byte offset of the next character.
+    fn offset(&mut self) -> usize {
+        let len = self.len;
+        self.chars.peek().map_or(len, |&(i, _)| i)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    /// Skips whitespace and `;` line comments.
+    fn skip_trivia(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.bump();
+            } else if c == ';' {
+                while let Some(c) = self.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    self.bump();
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Token, ParseError> {
+        self.skip_trivia();
+        let (line, column, lo) = (self.line, self.column, self.offset());
+        let kind = match self.peek() {
+            None => TokenKind::Eof,
+            Some(c) if c == '(' || c == '[' => {
+                self.bump();
+                TokenKind::Open(c)
+            }
+            Some(c) if c == ')' || c == ']' => {
+                self.bump();
+                TokenKind::Close(c)
+            }
+            Some(_) => {
+                let mut text = String::new();
+                while let Some(c) = self.peek() {
+                    if c.is_whitespace() || "()[];".contains(c) {
+                        break;
+                    }
+                    text.push(c);
+                    self.bump();
+                }
+                let span = Span::new(self.file.clone(), line, column, lo, self.offset());
+                atom_kind(text, span)?
+            }
+        };
+        let span = Span::new(self.file.clone(), line, column, lo, self.offset());
+        Ok(Token { kind, span })
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = vec![];
+        loop {
+            let token = self.next_token()?;
+            let is_eof = token.kind == TokenKind::Eof;
+            tokens.push(token);
+            if is_eof {
+                return Ok(tokens);
+            }
+        }
+    }
+}
+
+/// Classifies an atom as an integer literal, a boolean literal or an
+/// identifier.
+fn atom_kind(text: String, span: Span) -> Result<TokenKind, ParseError> {
+    match text.as_str() {
+        "#t" => return Ok(TokenKind::Bool(true)),
+        "#f" => return Ok(TokenKind::Bool(false)),
+        _ if text.starts_with('#') => {
+            return Err(ParseError::new(format!("unknown literal `{}`", text), span))
+        }
+        _ => {}
+    }
+    let digits = text.strip_prefix(|c| c == '-' || c == '+').unwrap_or(&text);
+    if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        return match text.parse() {
+            Ok(i) => Ok(TokenKind::Int(i)),
+            Err(_) => Err(ParseError::new(
+                format!("integer literal `{}` is out of range", text),
+                span,
+            )),
+        };
+    }
+    Ok(TokenKind::Ident(text))
+}
+
+fn closing_for(open: char) -> char {
+    if open == '[' {
+        ']'
+    } else {
+        ')'
+    }
+}
+
+/// Builds expressions from a stream of tokens.
+struct Reader {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Forms and types opened but not yet closed.
+    depth: usize,
+}
+
+impl Reader {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(token: &Token, message: String) -> ParseError {
+        ParseError::new(message, token.span.clone())
+    }
+
+    /// Reads a form or type with `read` one level deeper, failing at the
+    /// opening delimiter `token` if that is deeper than `MAX_NESTING`.
+    fn nested<T>(
+        &mut self,
+        token: &Token,
+        read: impl FnOnce(&mut Reader) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth == MAX_NESTING {
+            return Err(Reader::error(
+                token,
+                format!("forms are nested more than {} deep", MAX_NESTING),
+            ));
+        }
+        self.depth += 1;
+        let result = read(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn expect_open(&mut self) -> Result<char, ParseError> {
+        let token = self.next();
+        match token.kind {
+            TokenKind::Open(c) => Ok(c),
+            _ => Err(Reader::error(&token, "expected `(`".to_string())),
+        }
+    }
+
+    /// Consumes the delimiter that closes `open` and returns its span.
+    fn expect_close(&mut self, open: char) -> Result<Span, ParseError> {
+        let token = self.next();
+        let close = closing_for(open);
+        match token.kind {
+            TokenKind::Close(c) if c == close => Ok(token.span),
+            _ => Err(Reader::error(&token, format!("expected `{}`", close))),
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, ParseError> {
+        let token = self.next();
+        match token.kind {
+            TokenKind::Ident(name) if !is_keyword(&name) => Ok(name),
+            _ => Err(Reader::error(
+                &token,
+                "expected a variable name".to_string(),
+            )),
+        }
+    }
+
+    /// Reads the remaining operands of a form up to and including the closing
+    /// delimiter. Returns the operands and the span of the closing delimiter.
+    fn read_operands(&mut self, open: char) -> Result<(Vec<Expr>, Span), ParseError> {
+        let mut operands = vec![];
+        while let TokenKind::Open(_)
+        | TokenKind::Int(_)
+        | TokenKind::Bool(_)
+        | TokenKind::Ident(_) = self.peek().kind
+        {
+            operands.push(*self.read_expr()?);
+        }
+        let close_span = self.expect_close(open)?;
+        Ok((operands, close_span))
+    }
+
+    fn read_expr(&mut self) -> Result<Box<Expr>, ParseError> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Int(i) => Ok(Expr::int(*i).at(token.span)),
+            TokenKind::Bool(b) => Ok(Expr::bool(*b).at(token.span)),
+            TokenKind::Ident(name) => {
+                if is_keyword(name) {
+                    Err(Reader::error(
+                        &token,
+                        format!("`{}` cannot be used as a variable", name),
+                    ))
+                } else {
+                    Ok(Expr::var(name).at(token.span))
+                }
+            }
+            TokenKind::Open(open) => self.nested(&token, |r| r.read_form(*open, &token)),
+            TokenKind::Close(c) => Err(Reader::error(&token, format!("unexpected `{}`", c))),
+            TokenKind::Eof => Err(Reader::error(&token, "unexpected end of input".to_string())),
+        }
+    }
+
+    /// Reads a parenthesized form whose opening delimiter has been consumed.
+    fn read_form(&mut self, open: char, open_token: &Token) -> Result<Box<Expr>, ParseError> {
+        let head = self.next();
+        let name = match &head.kind {
+            TokenKind::Ident(name) => name.clone(),
+            TokenKind::Open(head_open) => {
+                // A call of the function a form evaluates to.
+                let f = self.nested(&head, |r| r.read_form(*head_open, &head))?;
+                let (operands, close_span) = self.read_operands(open)?;
+                return Ok(Expr::apply(f, operands).at(open_token.span.to(&close_span)));
+            }
+            _ => return Err(Reader::error(&head, "expected an operator".to_string())),
+        };
+        if name == "let" {
+            return self.read_let(open, open_token);
+        }
+        if name == "lambda" {
+            return self.read_lambda(open, open_token);
+        }
+        if name == "inject" || name == "project" {
+            return self.read_cast(&name, open, open_token);
+        }
+        if name == "set!" {
+            return self.read_set(open, open_token);
+        }
+        if is_runtime_form(&name) {
+            return self.read_runtime_form(&name, open, open_token);
+        }
+        if name == "define" {
+            return Err(Reader::error(
+                &head,
+                "`define` is only allowed at the top level".to_string(),
+            ));
+        }
+        let (operands, close_span) = self.read_operands(open)?;
+        let span = open_token.span.to(&close_span);
+        build_form(&name, head.span, operands, span)
+    }
+
+    /// Reads a type such as `Integer`, `(Vector Integer Boolean)`,
+    /// `(Integer -> Boolean)` or `Any`.
+    fn read_type(&mut self) -> Result<Type, ParseError> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Ident(name) if name == "Integer" => Ok(Type::Integer),
+            TokenKind::Ident(name) if name == "Boolean" => Ok(Type::Boolean),
+            TokenKind::Ident(name) if name == "Void" => Ok(Type::Void),
+            TokenKind::Ident(name) if name == "Any" => Ok(Type::Any),
+            TokenKind::Open(open) => self.nested(&token, |r| r.read_type_form(*open)),
+            _ => Err(Reader::error(&token, "expected a type".to_string())),
+        }
+    }
+
+    /// Reads the rest of a `(Vector T ...)` or `(T ... -> R)` type whose
+    /// opening delimiter has been consumed.
+    fn read_type_form(&mut self, open: char) -> Result<Type, ParseError> {
+        if let TokenKind::Ident(name) = &self.peek().kind {
+            if name == "Vector" {
+                self.next();
+                let mut elems = vec![];
+                while !matches!(self.peek().kind, TokenKind::Close(_)) {
+                    elems.push(self.read_type()?);
+                }
+                self.expect_close(open)?;
+                return Ok(Type::Vector(elems));
+            }
+        }
+        let mut params = vec![];
+        while !self.peek_ident("->") {
+            if let TokenKind::Close(_) | TokenKind::Eof = self.peek().kind {
+                return Err(Reader::error(self.peek(), "expected `->`".to_string()));
+            }
+            params.push(self.read_type()?);
+        }
+        self.next();
+        let result = self.read_type()?;
+        self.expect_close(open)?;
+        Ok(Type::Function(params, Box::new(result)))
+    }
+
+    fn peek_ident(&self, expected: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Ident(name) if name == expected)
+    }
+
+    fn expect_colon(&mut self) -> Result<(), ParseError> {
+        if self.peek_ident(":") {
+            self.next();
+            Ok(())
+        } else {
+            Err(Reader::error(self.peek(), "expected `:`".to_string()))
+        }
+    }
+
+    /// Whether the next tokens open a `define` form.
+    fn at_define(&self) -> bool {
+        matches!(self.peek().kind, TokenKind::Open(_))
+            && matches!(&self.tokens[self.pos + 1].kind, TokenKind::Ident(name) if name == "define")
+    }
+
+    /// Reads `(define (f [x : T] ...) : R body)`. A function without a result
+    /// type returns `Any`.
+    fn read_def(&mut self) -> Result<Def, ParseError> {
+        let open_token = self.next();
+        let open = match open_token.kind {
+            TokenKind::Open(c) => c,
+            _ => unreachable!("read_def is called at a `define` form"),
+        };
+        self.next();
+        let header_open = self.expect_open()?;
+        let name = self.expect_ident()?;
+        let params = self.read_params()?;
+        self.expect_close(header_open)?;
+        let result = if self.peek_ident(":") {
+            self.next();
+            self.read_type()?
+        } else {
+            Type::Any
+        };
+        let body = self.read_expr()?;
+        let close_span = self.expect_close(open)?;
+        Ok(Def {
+            name: Box::new(Symbol::new(&name)),
+            params,
+            result,
+            body,
+            span: open_token.span.to(&close_span),
+        })
+    }
+
+    /// Reads parameters written `[x : T]` up to the delimiter that closes
+    /// the list they are in. A parameter written `x` has type `Any`.
+    fn read_params(&mut self) -> Result<Vec<(Box<Symbol>, Type)>, ParseError> {
+        let mut params = vec![];
+        loop {
+            if let TokenKind::Ident(_) = self.peek().kind {
+                let param = self.expect_ident()?;
+                params.push((Box::new(Symbol::new(&param)), Type::Any));
+                continue;
+            }
+            if !matches!(self.peek().kind, TokenKind::Open(_)) {
+                break;
+            }
+            let param_open = self.expect_open()?;
+            let param = self.expect_ident()?;
+            self.expect_colon()?;
+            let ty = self.read_type()?;
+            self.expect_close(param_open)?;
+            params.push((Box::new(Symbol::new(&param)), ty));
+        }
+        Ok(params)
+    }
+
+    /// Reads the rest of `(lambda ([x : T] ...) body)`.
+    fn read_lambda(&mut self, open: char, open_token: &Token) -> Result<Box<Expr>, ParseError> {
+        let params_open = self.expect_open()?;
+        let params = self.read_params()?;
+        self.expect_close(params_open)?;
+        let body = self.read_expr()?;
+        let close_span = self.expect_close(open)?;
+        Ok(Expr::new(
+            ExprKind::Lambda(params, body),
+            open_token.span.to(&close_span),
+        ))
+    }
+
+    /// Reads the rest of `(inject e T)` or `(project e T)`.
+    fn read_cast(
+        &mut self,
+        name: &str,
+        open: char,
+        open_token: &Token,
+    ) -> Result<Box<Expr>, ParseError> {
+        let e = self.read_expr()?;
+        let ty = self.read_type()?;
+        let close_span = self.expect_close(open)?;
+        let kind = if name == "inject" {
+            ExprKind::Inject(e, ty)
+        } else {
+            ExprKind::Project(e, ty)
+        };
+        Ok(Expr::new(kind, open_token.span.to(&close_span)))
+    }
+
+    /// Reads the rest of `(set! x e)`.
+    fn read_set(&mut self, open: char, open_token: &Token) -> Result<Box<Expr>, ParseError> {
+        let name = self.expect_ident()?;
+        let e = self.read_expr()?;
+        let close_span = self.expect_close(open)?;
+        Ok(Expr::set(&name, e).at(open_token.span.to(&close_span)))
+    }
+
+    /// Reads the rest of `(collect N)`, `(allocate N T)`, `(global-value name)`
+    /// or `(fun-ref name)`, so that the dumps of later stages read back.
+    fn read_runtime_form(
+        &mut self,
+        name: &str,
+        open: char,
+        open_token: &Token,
+    ) -> Result<Box<Expr>, ParseError> {
+        let kind = match name {
+            "collect" => ExprKind::Collect(self.expect_int()?),
+            "allocate" => {
+                let token = self.peek().clone();
+                let len = self.expect_int()?;
+                if !(0..=MAX_VECTOR_LEN as i64).contains(&len) {
+                    return Err(Reader::error(
+                        &token,
+                        format!("expected a length from 0 to {}", MAX_VECTOR_LEN),
+                    ));
+                }
+                ExprKind::Allocate(len as usize, self.read_type()?)
+            }
+            "global-value" => ExprKind::GlobalValue(self.expect_ident()?),
+            _ => ExprKind::FunRef(self.expect_ident()?),
+        };
+        let close_span = self.expect_close(open)?;
+        Ok(Expr::new(kind, open_token.span.to(&close_span)))
+    }
+
+    fn expect_int(&mut self) -> Result<i64, ParseError> {
+        let token = self.next();
+        match token.kind {
+            TokenKind::Int(i) => Ok(i),
+            _ => Err(Reader::error(&token, "expected an integer".to_string())),
+        }
+    }
+
+    /// Reads the rest of `(let ([x e]) body)`.
+    fn read_let(&mut self, open: char, open_token: &Token) -> Result<Box<Expr>, ParseError> {
+        let bindings_open = self.expect_open()?;
+        let binding_open = self.expect_open()?;
+        let name = self.expect_ident()?;
+        let val = self.read_expr()?;
+        self.expect_close(binding_open)?;
+        if let TokenKind::Open(_) = self.peek().kind {
+            return Err(Reader::error(
+                self.peek(),
+                "`let` takes exactly one binding".to_string(),
+            ));
+        }
+        self.expect_close(bindings_open)?;
+        let body = self.read_expr()?;
+        let close_span = self.expect_close(open)?;
+        Ok(Expr::let_bind(&name, val, body).at(open_token.span.to(&close_span)))
+    }
+}
+
+/// Builds the expression of a form from its operator and operands. Kept out
+/// of `Reader::read_form` so that the frame of the recursive reader stays
+/// small on deeply nested input.
+#[inline(never)]
+fn build_form(
+    name: &str,
+    head_span: Span,
+    mut operands: Vec<Expr>,
+    span: Span,
+) -> Result<Box<Expr>, ParseError> {
+    let expr = match name {
+        "read" => {
+            check_arity(&span, "read", &operands, &[0])?;
+            Expr::read()
+        }
+        "+" | "*" | "quotient" | "remainder" => {
+            check_arity(&span, name, &operands, &[2])?;
+            let op2 = Box::new(operands.pop().unwrap());
+            let op1 = Box::new(operands.pop().unwrap());
+            match name {
+                "+" => Expr::add(op1, op2),
+                "*" => Expr::mul(op1, op2),
+                "quotient" => Expr::quotient(op1, op2),
+                _ => Expr::remainder(op1, op2),
+            }
+        }
+        "-" => {
+            check_arity(&span, "-", &operands, &[1, 2])?;
+            let op2 = Box::new(operands.pop().unwrap());
+            match operands.pop() {
+                Some(op1) => Expr::sub(Box::new(op1), op2),
+                None => Expr::neg(op2),
+            }
+        }
+        "not" => {
+            check_arity(&span, "not", &operands, &[1])?;
+            Expr::not(Box::new(operands.pop().unwrap()))
+        }
+        "and" | "or" => {
+            check_arity(&span, name, &operands, &[2])?;
+            let op2 = Box::new(operands.pop().unwrap());
+            let op1 = Box::new(operands.pop().unwrap());
+            if name == "and" {
+                Expr::and(op1, op2)
+            } else {
+                Expr::or(op1, op2)
+            }
+        }
+        "if" => {
+            check_arity(&span, "if", &operands, &[3])?;
+            let els = Box::new(operands.pop().unwrap());
+            let then = Box::new(operands.pop().unwrap());
+            let cond = Box::new(operands.pop().unwrap());
+            Expr::if_expr(cond, then, els)
+        }
+        "begin" => {
+            let e = operands.pop().ok_or_else(|| {
+                ParseError::new(
+                    "`begin` expects at least 1 operand(s) but got 0".to_string(),
+                    span.clone(),
+                )
+            })?;
+            Expr::begin(operands, Box::new(e))
+        }
+        "while" => {
+            check_arity(&span, "while", &operands, &[2])?;
+            let body = Box::new(operands.pop().unwrap());
+            let cond = Box::new(operands.pop().unwrap());
+            Expr::while_loop(cond, body)
+        }
+        "vector" => {
+            if operands.len() > MAX_VECTOR_LEN {
+                return Err(ParseError::new(
+                    format!(
+                        "`vector` takes at most {} operands but got {}",
+                        MAX_VECTOR_LEN,
+                        operands.len()
+                    ),
+                    span,
+                ));
+            }
+            Expr::vector(operands)
+        }
+        "vector-ref" => {
+            check_arity(&span, "vector-ref", &operands, &[2])?;
+            let index = check_index(operands.pop().unwrap())?;
+            let v = Box::new(operands.pop().unwrap());
+            Expr::new(ExprKind::VectorRef(v, index), Span::default())
+        }
+        "vector-set!" => {
+            check_arity(&span, "vector-set!", &operands, &[3])?;
+            let e = Box::new(operands.pop().unwrap());
+            let index = check_index(operands.pop().unwrap())?;
+            let v = Box::new(operands.pop().unwrap());
+            Expr::new(ExprKind::VectorSet(v, index, e), Span::default())
+        }
+        "any-vector-ref" => {
+            check_arity(&span, "any-vector-ref", &operands, &[2])?;
+            let index = check_index(operands.pop().unwrap())?;
+            let v = Box::new(operands.pop().unwrap());
+            Expr::new(ExprKind::AnyVectorRef(v, index), Span::default())
+        }
+        "any-vector-set!" => {
+            check_arity(&span, "any-vector-set!", &operands, &[3])?;
+            let e = Box::new(operands.pop().unwrap());
+            let index = check_index(operands.pop().unwrap())?;
+            let v = Box::new(operands.pop().unwrap());
+            Expr::new(ExprKind::AnyVectorSet(v, index, e), Span::default())
+        }
+        "void" => {
+            check_arity(&span, "void", &operands, &[0])?;
+            Expr::void()
+        }
+        "print" => {
+            check_arity(&span, "print", &operands, &[1])?;
+            Expr::print(Box::new(operands.pop().unwrap()))
+        }
+        _ if CmpOp::from_name(name).is_some() => {
+            check_arity(&span, name, &operands, &[2])?;
+            let op2 = Box::new(operands.pop().unwrap());
+            let op1 = Box::new(operands.pop().unwrap());
+            Expr::cmp(CmpOp::from_name(name).unwrap(), op1, op2)
+        }
+        _ => Expr::apply(Expr::var(name).at(head_span), operands),
+    };
+    Ok(expr.at(span))
+}
+
+fn is_keyword(name: &str) -> bool {
+    matches!(
+        name,
+        "let"
+            | "read"
+            | "+"
+            | "-"
+            | "*"
+            | "quotient"
+            | "remainder"
+            | "not"
+            | "and"
+            | "or"
+            | "if"
+            | "vector"
+            | "vector-ref"
+            | "vector-set!"
+            | "void"
+            | "define"
+            | "lambda"
+            | "inject"
+            | "project"
+            | "any-vector-ref"
+            | "any-vector-set!"
+            | "set!"
+            | "begin"
+            | "while"
+            | "print"
+    ) || CmpOp::from_name(name).is_some()
+        || is_runtime_form(name)
+}
+
+/// Whether the name is the operator of a form that passes write to refer to
+/// functions and to the runtime's heap.
+fn is_runtime_form(name: &str) -> bool {
+    matches!(name, "collect" | "allocate" | "global-value" | "fun-ref")
+}
+
+/// Checks that the index operand of `vector-ref` or `vector-set!` is an
+/// integer literal that can index a vector.
+fn check_index(operand: Expr) -> Result<Box<Expr>, ParseError> {
+    match operand.kind {
+        ExprKind::Lit(Lit::Int(i)) if (0..MAX_VECTOR_LEN as i64).contains(&i) => {
+            Ok(Box::new(operand))
+        }
+        _ => Err(ParseError::new(
+            format!(
+                "expected an index from 0 to {} as a literal",
+                MAX_VECTOR_LEN - 1
+            ),
+            operand.span,
+        )),
+    }
+}
+
+fn check_arity(
+    span: &Span,
+    op: &str,
+    operands: &[Expr],
+    expected: &[usize],
+) -> Result<(), ParseError> {
+    if expected.contains(&operands.len()) {
+        Ok(())
+    } else {
+        let expected = expected
+            .iter()
+            .map(|n| n.to_string())
+            .collect::<Vec<String>>();
+        Err(ParseError::new(
+            format!(
+                "`{}` expects {} operand(s) but got {}",
+                op,
+                expected.join(" or "),
+                operands.len()
+            ),
+            span.clone(),
+        ))
+    }
+}
+
+/// Parses a single R expression from source text read from the named file.
+pub fn parse_file_expr(file: &str, src: &str) -> Result<Box<Expr>, ParseError> {
+    let tokens = Lexer::new(file.into(), src).tokenize()?;
+    let mut reader = Reader {
+        tokens,
+        pos: 0,
+        depth: 0,
+    };
+    let expr = reader.read_expr()?;
+    let trailing = reader.next();
+    if trailing.kind != TokenKind::Eof {
+        return Err(Reader::error(
+            &trailing,
+            "unexpected input after expression".to_string(),
+        ));
+    }
+    Ok(expr)
+}
+
+/// Parses a single R expression from source text.
+pub fn parse_expr(src: &str) -> Result<Box<Expr>, ParseError> {
+    parse_file_expr("", src)
+}
+
+/// Parses an R program from source text read from the named file.
+/// A program is any number of `define` forms followed by an expression.
+pub fn parse_file_program(file: &str, src: &str) -> Result<Program, ParseError> {
+    let tokens = Lexer::new(file.into(), src).tokenize()?;
+    let mut reader = Reader {
+        tokens,
+        pos: 0,
+        depth: 0,
+    };
+    let mut defs = vec![];
+    while reader.at_define() {
+        defs.push(reader.read_def()?);
+    }
+    let expr = reader.read_expr()?;
+    let trailing = reader.next();
+    if trailing.kind != TokenKind::Eof {
+        return Err(Reader::error(
+            &trailing,
+            "unexpected input after expression".to_string(),
+        ));
+    }
+    Ok(Program { defs, expr })
+}
+
+/// Parses an R program from source text.
+pub fn parse_program(src: &str) -> Result<Program, ParseError> {
+    parse_file_program("", src)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::diagnostic::Span;
+    use super::super::typecheck::Type;
+    use super::super::{CmpOp, Expr, ExprKind};
+    use super::{parse_expr, parse_file_expr, parse_program, ParseError, MAX_NESTING};
+
+    #[test]
+    fn let_read_add_neg() {
+        let expected = Expr::let_bind(
+            "x",
+            Expr::read(),
+            Expr::add(Expr::var("x"), Expr::neg(Expr::int(10))),
+        );
+        let actual = parse_program("(let ([x (read)]) (+ x (- 10)))").unwrap();
+        assert_eq!(actual.expr, expected);
+    }
+
+    #[test]
+    fn neg_and_sub() {
+        let actual = parse_expr("(- (- x) (- 1 y))").unwrap();
+        let expected = Expr::sub(
+            Expr::neg(Expr::var("x")),
+            Expr::sub(Expr::int(1), Expr::var("y")),
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn mul_and_division() {
+        let actual = parse_expr("(* (quotient x 2) (remainder 7 y))").unwrap();
+        let expected = Expr::mul(
+            Expr::quotient(Expr::var("x"), Expr::int(2)),
+            Expr::remainder(Expr::int(7), Expr::var("y")),
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn booleans_and_conditionals() {
+        let actual = parse_expr("(if (and (not #f) (<= x 2)) (eq? y #t) (or #t (> 1 y)))").unwrap();
+        let expected = Expr::if_expr(
+            Expr::and(
+                Expr::not(Expr::bool(false)),
+                Expr::cmp(CmpOp::Le, Expr::var("x"), Expr::int(2)),
+            ),
+            Expr::cmp(CmpOp::Eq, Expr::var("y"), Expr::bool(true)),
+            Expr::or(
+                Expr::bool(true),
+                Expr::cmp(CmpOp::Gt, Expr::int(1), Expr::var("y")),
+            ),
+        );
+        assert_eq!(actual, expected);
+        let actual = parse_expr("(let ([< 1]) <)").unwrap_err();
+        assert_eq!(actual.message, "expected a variable name");
+        let actual = parse_expr("#true").unwrap_err();
+        assert_eq!(actual.message, "unknown literal `#true`");
+    }
+
+    #[test]
+    fn vectors() {
+        let actual = parse_expr("(vector-set! (vector 1 #t (void)) 2 (vector-ref v 0))").unwrap();
+        let expected = Expr::vector_set(
+            Expr::vector(vec![*Expr::int(1), *Expr::bool(true), *Expr::void()]),
+            2,
+            Expr::vector_ref(Expr::var("v"), 0),
+        );
+        assert_eq!(actual, expected);
+        let err = parse_expr("(vector-ref v x)").unwrap_err();
+        assert_eq!(err.message, "expected an index from 0 to 49 as a literal");
+        assert_eq!(err.span.column, 15);
+        assert!(parse_expr("(vector-ref v 50)").is_err());
+        assert!(parse_expr("(let ([void 1]) void)").is_err());
+    }
+
+    #[test]
+    fn literals_and_comments() {
+        let src = "; a comment\n(+ -7 ; trailing comment\n  +3)";
+        let expected = Expr::add(Expr::int(-7), Expr::int(3));
+        assert_eq!(parse_expr(src).unwrap(), expected);
+    }
+
+    #[test]
+    fn mismatched_delimiters() {
+        let actual = parse_expr("(let ([x 1)) x)");
+        let expected = ParseError::new(
+            "expected `]`".to_string(),
+            Span::new("".into(), 1, 11, 10, 11),
+        );
+        assert_eq!(actual, Err(expected));
+    }
+
+    #[test]
+    fn unexpected_end_of_input() {
+        let actual = parse_expr("(+ 1\n  (- 2)");
+        assert_eq!(actual.unwrap_err().span.line, 2);
+    }
+
+    #[test]
+    fn spans() {
+        let src = "(let ([x (read)])\n  (+ x 1))";
+        let expr = parse_file_expr("prog.rkt", src).unwrap();
+        assert_eq!(expr.span, Span::new("prog.rkt".into(), 1, 1, 0, src.len()));
+        let (val, body) = match expr.kind {
+            ExprKind::Let(_, val, body) => (val, body),
+            _ => panic!("expected a let"),
+        };
+        assert_eq!(val.span, Span::new("prog.rkt".into(), 1, 10, 9, 15));
+        assert_eq!(body.span, Span::new("prog.rkt".into(), 2, 3, 20, 27));
+        match body.kind {
+            ExprKind::Add(op1, _) => {
+                assert_eq!(op1.span, Span::new("prog.rkt".into(), 2, 6, 23, 24))
+            }
+            _ => panic!("expected an addition"),
+        }
+    }
+
+    #[test]
+    fn wrong_arity() {
+        let actual = parse_expr("(+ 1 2 3)").unwrap_err();
+        assert_eq!(actual.message, "`+` expects 2 operand(s) but got 3");
+        let actual = parse_expr("(- 1 2 3)").unwrap_err();
+        assert_eq!(actual.message, "`-` expects 1 or 2 operand(s) but got 3");
+    }
+
+    #[test]
+    fn applies_other_operators() {
+        let actual = parse_expr("(f 1 (g))").unwrap();
+        let expected = Expr::apply(
+            Expr::var("f"),
+            vec![*Expr::int(1), *Expr::apply(Expr::var("g"), vec![])],
+        );
+        assert_eq!(actual, expected);
+        let actual = parse_expr("((vector-ref v 0) 1)").unwrap();
+        let expected = Expr::apply(Expr::vector_ref(Expr::var("v"), 0), vec![*Expr::int(1)]);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn defines() {
+        let src = "\
+(define (f [x : Integer] [g : (Integer -> Boolean)]) : (Vector Boolean)
+  (vector (g x)))
+(f 1 h)";
+        let actual = parse_program(src).unwrap();
+        assert_eq!(actual.defs.len(), 1);
+        let def = &actual.defs[0];
+        assert_eq!(def.name.value, "f");
+        assert_eq!(
+            def.ty(),
+            Type::Function(
+                vec![
+                    Type::Integer,
+                    Type::Function(vec![Type::Integer], Box::new(Type::Boolean)),
+                ],
+                Box::new(Type::Vector(vec![Type::Boolean])),
+            )
+        );
+        assert_eq!(
+            def.body,
+            Expr::vector(vec![*Expr::apply(Expr::var("g"), vec![*Expr::var("x")])])
+        );
+        assert_eq!((def.span.lo, def.span.hi), (0, 89));
+        assert_eq!(
+            actual.expr,
+            Expr::apply(Expr::var("f"), vec![*Expr::int(1), *Expr::var("h")])
+        );
+    }
+
+    #[test]
+    fn lambdas() {
+        let actual =
+            parse_expr("((lambda ([x : Integer] [b : Boolean]) (if b x 0)) 1 #t)").unwrap();
+        let lambda = Expr::lambda(
+            vec![("x", Type::Integer), ("b", Type::Boolean)],
+            Expr::if_expr(Expr::var("b"), Expr::var("x"), Expr::int(0)),
+        );
+        let expected = Expr::apply(lambda, vec![*Expr::int(1), *Expr::bool(true)]);
+        assert_eq!(actual, expected);
+        let actual = parse_expr("(lambda () 1)").unwrap();
+        assert_eq!(actual, Expr::lambda(vec![], Expr::int(1)));
+        let actual = parse_expr("(lambda (x [y : Integer]) x)").unwrap();
+        let expected = Expr::lambda(vec![("x", Type::Any), ("y", Type::Integer)], Expr::var("x"));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn untyped_defines() {
+        let actual = parse_program("(define (f x y) (g x y))\n(f 1 2)").unwrap();
+        let def = &actual.defs[0];
+        assert_eq!(def.ty(), Type::any_function(2));
+        assert_eq!((def.span.lo, def.span.hi), (0, 24));
+    }
+
+    #[test]
+    fn casts() {
+        let actual = parse_expr("(project (inject #t Boolean) (Any -> Any))").unwrap();
+        let expected = Expr::project(
+            Expr::inject(Expr::bool(true), Type::Boolean),
+            Type::any_function(1),
+        );
+        assert_eq!(actual, expected);
+        let actual = parse_expr("(any-vector-set! v 1 (any-vector-ref v 0))").unwrap();
+        let expected =
+            Expr::any_vector_set(Expr::var("v"), 1, Expr::any_vector_ref(Expr::var("v"), 0));
+        assert_eq!(actual, expected);
+        let actual = parse_expr("(inject 1)").unwrap_err();
+        assert_eq!(actual.message, "expected a type");
+        let actual = parse_expr("(any-vector-ref v x)").unwrap_err();
+        assert_eq!(
+            actual.message,
+            "expected an index from 0 to 49 as a literal"
+        );
+    }
+
+    #[test]
+    fn loops() {
+        let actual = parse_expr("(begin (while (< i 10) (set! i (+ i 1))) i)").unwrap();
+        let expected = Expr::begin(
+            vec![*Expr::while_loop(
+                Expr::cmp(CmpOp::Lt, Expr::var("i"), Expr::int(10)),
+                Expr::set("i", Expr::add(Expr::var("i"), Expr::int(1))),
+            )],
+            Expr::var("i"),
+        );
+        assert_eq!(actual, expected);
+        let actual = parse_expr("(begin)").unwrap_err();
+        assert_eq!(
+            actual.message,
+            "`begin` expects at least 1 operand(s) but got 0"
+        );
+        let actual = parse_expr("(set! while 1)").unwrap_err();
+        assert_eq!(actual.message, "expected a variable name");
+    }
+
+    #[test]
+    fn print() {
+        let actual = parse_expr("(print (vector 1 #t))").unwrap();
+        let expected = Expr::print(Expr::vector(vec![*Expr::int(1), *Expr::bool(true)]));
+        assert_eq!(actual, expected);
+        let actual = parse_expr("(print)").unwrap_err();
+        assert_eq!(actual.message, "`print` expects 1 operand(s) but got 0");
+    }
+
+    #[test]
+    fn runtime_forms() {
+        let actual = parse_expr("(if (< (global-value free_ptr) 16) (void) (collect 16))").unwrap();
+        let expected = Expr::if_expr(
+            Expr::cmp(
+                CmpOp::Lt,
+                Expr::new(
+                    ExprKind::GlobalValue("free_ptr".to_string()),
+                    Span::default(),
+                ),
+                Expr::int(16),
+            ),
+            Expr::void(),
+            Expr::new(ExprKind::Collect(16), Span::default()),
+        );
+        assert_eq!(actual, expected);
+        let actual = parse_expr("(allocate 2 (Vector Integer Boolean))").unwrap();
+        let ty = Type::Vector(vec![Type::Integer, Type::Boolean]);
+        assert_eq!(actual.kind, ExprKind::Allocate(2, ty));
+        let actual = parse_expr("((fun-ref f) 1)").unwrap();
+        let expected = Expr::apply(Expr::fun_ref("f"), vec![*Expr::int(1)]);
+        assert_eq!(actual, expected);
+        let actual = parse_expr("(allocate 51 (Vector))").unwrap_err();
+        assert_eq!(actual.message, "expected a length from 0 to 50");
+        let actual = parse_expr("(let ([collect 1]) collect)").unwrap_err();
+        assert_eq!(actual.message, "expected a variable name");
+    }
+
+    #[test]
+    fn define_in_expression() {
+        let actual = parse_program("(+ 1 (define (f) : Integer 1))").unwrap_err();
+        assert_eq!(actual.message, "`define` is only allowed at the top level");
+        let actual = parse_program("(define (f [x Integer]) : Integer x) (f 1)").unwrap_err();
+        assert_eq!(actual.message, "expected `:`");
+        let actual = parse_program("(define (f) : Int 1) (f)").unwrap_err();
+        assert_eq!(actual.message, "expected a type");
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let actual = parse_expr(&"(".repeat(5000)).unwrap_err();
+        assert_eq!(actual.message, "forms are nested more than 256 deep");
+        assert_eq!((actual.span.lo, actual.span.hi), (256, 257));
+        let deep = format!("{}1{}", "(- ".repeat(MAX_NESTING), ")".repeat(MAX_NESTING));
+        assert!(parse_expr(&deep).is_ok());
+        let actual = parse_program(&format!(
+            "(define (f [x : {}Integer{}]) : Integer 1) (f 1)",
+            "(Vector ".repeat(5000),
+            ")".repeat(5000)
+        ))
+        .unwrap_err();
+        assert_eq!(actual.message, "forms are nested more than 256 deep");
+    }
+
+    #[test]
+    fn trailing_input() {
+        assert!(parse_expr("1 2").is_err());
+    }
+
+    #[test]
+    fn integer_out_of_range() {
+        assert!(parse_expr("99999999999999999999").is_err());
+    }
+}
@@ -0,0 +1,375 @@
+//! Pretty-printer that writes RIR back out as source text.
+//!
+//! Expressions that fit in the remaining width are written on one line.
+//! Longer expressions are broken Lisp style: the body of a `let` goes on its
+//! own line indented by two spaces and the operands of a primitive are aligned
+//! under the first operand. Parsing the output gives back the same expression,
+//! including the forms such as `(allocate N T)` and `(fun-ref f)` that only
+//! passes write.
+
+use super::typecheck::Type;
+use super::{Expr, ExprKind, Lit, Program, Symbol};
+use std::fmt;
+
+/// Line width the printer tries to stay within.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// Splits a primitive or function application into its operator and
+/// operands.
+fn app_parts(expr: &Expr) -> Option<(String, Vec<&Expr>)> {
+    match &expr.kind {
+        ExprKind::Apply(f, args) => return Some((flat(f), args.iter().collect())),
+        ExprKind::Set(sym, e) => return Some((format!("set! {}", sym.value), vec![&**e])),
+        ExprKind::Begin(effects, e) => {
+            let mut operands = effects.iter().collect::<Vec<&Expr>>();
+            operands.push(e);
+            return Some(("begin".to_string(), operands));
+        }
+        _ => {}
+    }
+    let (op, operands) = match &expr.kind {
+        ExprKind::Neg(e) => ("-", vec![&**e]),
+        ExprKind::Add(e1, e2) => ("+", vec![&**e1, e2]),
+        ExprKind::Sub(e1, e2) => ("-", vec![&**e1, e2]),
+        ExprKind::Mul(e1, e2) => ("*", vec![&**e1, e2]),
+        ExprKind::Quotient(e1, e2) => ("quotient", vec![&**e1, e2]),
+        ExprKind::Remainder(e1, e2) => ("remainder", vec![&**e1, e2]),
+        ExprKind::Not(e) => ("not", vec![&**e]),
+        ExprKind::And(e1, e2) => ("and", vec![&**e1, e2]),
+        ExprKind::Or(e1, e2) => ("or", vec![&**e1, e2]),
+        ExprKind::Cmp(op, e1, e2) => (op.name(), vec![&**e1, e2]),
+        ExprKind::If(cond, then, els) => ("if", vec![&**cond, then, els]),
+        ExprKind::While(cond, body) => ("while", vec![&**cond, body]),
+        ExprKind::Print(e) => ("print", vec![&**e]),
+        ExprKind::Vector(elems) => ("vector", elems.iter().collect()),
+        ExprKind::VectorRef(v, index) => ("vector-ref", vec![&**v, index]),
+        ExprKind::VectorSet(v, index, e) => ("vector-set!", vec![&**v, index, e]),
+        ExprKind::AnyVectorRef(v, index) => ("any-vector-ref", vec![&**v, index]),
+        ExprKind::AnyVectorSet(v, index, e) => ("any-vector-set!", vec![&**v, index, e]),
+        _ => return None,
+    };
+    Some((op.to_string(), operands))
+}
+
+/// Writes the opening of a lambda up to its body, e.g.
+/// `(lambda ([x : Integer])`.
+fn write_lambda_head(out: &mut String, params: &[(Box<Symbol>, Type)]) {
+    out.push_str("(lambda (");
+    for (i, (param, ty)) in params.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        out.push_str(&format!("[{} : {}]", param.value, ty));
+    }
+    out.push(')');
+}
+
+/// Splits a cast into its operator, operand and type.
+fn cast_parts(expr: &Expr) -> Option<(&str, &Expr, &Type)> {
+    match &expr.kind {
+        ExprKind::Inject(e, ty) => Some(("inject", e, ty)),
+        ExprKind::Project(e, ty) => Some(("project", e, ty)),
+        _ => None,
+    }
+}
+
+/// Writes the expression on a single line.
+fn write_flat(out: &mut String, expr: &Expr) {
+    if let Some((op, operands)) = app_parts(expr) {
+        out.push('(');
+        out.push_str(&op);
+        for operand in operands {
+            out.push(' ');
+            write_flat(out, operand);
+        }
+        out.push(')');
+        return;
+    }
+    if let Some((op, e, ty)) = cast_parts(expr) {
+        out.push_str(&format!("({} ", op));
+        write_flat(out, e);
+        out.push_str(&format!(" {})", ty));
+        return;
+    }
+    match &expr.kind {
+        ExprKind::Read => out.push_str("(read)"),
+        ExprKind::Void => out.push_str("(void)"),
+        ExprKind::Collect(bytes) => out.push_str(&format!("(collect {})", bytes)),
+        ExprKind::Allocate(len, ty) => out.push_str(&format!("(allocate {} {})", len, ty)),
+        ExprKind::GlobalValue(name) => out.push_str(&format!("(global-value {})", name)),
+        ExprKind::FunRef(name) => out.push_str(&format!("(fun-ref {})", name)),
+        ExprKind::Lit(lit) => out.push_str(&lit.to_string()),
+        ExprKind::Var(sym) => out.push_str(&sym.value),
+        ExprKind::Let(sym, e, body) => {
+            out.push_str("(let ([");
+            out.push_str(&sym.value);
+            out.push(' ');
+            write_flat(out, e);
+            out.push_str("]) ");
+            write_flat(out, body);
+            out.push(')');
+        }
+        ExprKind::Lambda(params, body) => {
+            write_lambda_head(out, params);
+            out.push(' ');
+            write_flat(out, body);
+            out.push(')');
+        }
+        _ => unreachable!("applications and casts are written above"),
+    }
+}
+
+fn flat(expr: &Expr) -> String {
+    let mut out = String::new();
+    write_flat(&mut out, expr);
+    out
+}
+
+fn newline(out: &mut String, column: usize) {
+    out.push('\n');
+    out.push_str(&" ".repeat(column));
+}
+
+/// Writes a primitive application with each operand aligned under the first.
+fn write_app(out: &mut String, op: &str, operands: &[&Expr], column: usize, width: usize) {
+    out.push('(');
+    out.push_str(op);
+    if operands.is_empty() {
+        out.push(')');
+        return;
+    }
+    out.push(' ');
+    let operand_column = column + op.len() + 2;
+    for (i, operand) in operands.iter().enumerate() {
+        if i > 0 {
+            newline(out, operand_column);
+        }
+        write_expr(out, operand, operand_column, width);
+    }
+    out.push(')');
+}
+
+/// Writes the expression starting at the given column, breaking lines so that
+/// the output stays within `width` where possible.
+fn write_expr(out: &mut String, expr: &Expr, column: usize, width: usize) {
+    let flat = flat(expr);
+    if column + flat.len() <= width {
+        out.push_str(&flat);
+        return;
+    }
+    if let Some((op, operands)) = app_parts(expr) {
+        write_app(out, &op, &operands, column, width);
+        return;
+    }
+    if let Some((op, e, ty)) = cast_parts(expr) {
+        // The type follows the operand if it fits after it on one line, and
+        // otherwise goes under it.
+        out.push_str(&format!("({} ", op));
+        let operand_column = column + op.len() + 2;
+        let start = out.len();
+        write_expr(out, e, operand_column, width);
+        let ty = ty.to_string();
+        let operand = &out[start..];
+        if !operand.contains('\n') && operand_column + operand.len() + ty.len() + 2 <= width {
+            out.push(' ');
+        } else {
+            newline(out, operand_column);
+        }
+        out.push_str(&ty);
+        out.push(')');
+        return;
+    }
+    match &expr.kind {
+        ExprKind::Let(sym, e, body) => {
+            out.push_str("(let ([");
+            out.push_str(&sym.value);
+            out.push(' ');
+            write_expr(out, e, column + sym.value.len() + 8, width);
+            out.push_str("])");
+            newline(out, column + 2);
+            write_expr(out, body, column + 2, width);
+            out.push(')');
+        }
+        ExprKind::Lambda(params, body) => {
+            write_lambda_head(out, params);
+            newline(out, column + 2);
+            write_expr(out, body, column + 2, width);
+            out.push(')');
+        }
+        _ => out.push_str(&flat),
+    }
+}
+
+/// Pretty-prints the expression, staying within `width` columns where
+/// possible.
+pub fn pretty(expr: &Expr, width: usize) -> String {
+    let mut out = String::new();
+    write_expr(&mut out, expr, 0, width);
+    out
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", pretty(self, DEFAULT_WIDTH))
+    }
+}
+
+/// Expressions are debugged as source text, which is far easier to compare in
+/// failed assertions than the nested node structure.
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", pretty(self, DEFAULT_WIDTH))
+    }
+}
+
+/// Writes each definition with its body on its own line, then the final
+/// expression, with a blank line between forms.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for def in &self.defs {
+            write!(f, "(define ({}", def.name.value)?;
+            for (param, ty) in &def.params {
+                write!(f, " [{} : {}]", param.value, ty)?;
+            }
+            let mut body = String::new();
+            write_expr(&mut body, &def.body, 2, DEFAULT_WIDTH);
+            writeln!(f, ") : {}\n  {})\n", def.result, body)?;
+        }
+        writeln!(f, "{}", self.expr)
+    }
+}
+
+impl fmt::Display for Lit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Lit::Int(i) => write!(f, "{}", i),
+            Lit::Bool(true) => write!(f, "#t"),
+            Lit::Bool(false) => write!(f, "#f"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse::{parse_expr, parse_program};
+    use super::super::Expr;
+    use super::pretty;
+
+    #[test]
+    fn short_expr_on_one_line() {
+        let expr = Expr::let_bind(
+            "x",
+            Expr::read(),
+            Expr::add(Expr::var("x"), Expr::neg(Expr::int(-10))),
+        );
+        assert_eq!(expr.to_string(), "(let ([x (read)]) (+ x (- -10)))");
+    }
+
+    #[test]
+    fn breaks_long_let() {
+        let expr = Expr::let_bind(
+            "first",
+            Expr::add(Expr::int(1), Expr::int(2)),
+            Expr::let_bind(
+                "second",
+                Expr::neg(Expr::var("first")),
+                Expr::add(Expr::var("first"), Expr::var("second")),
+            ),
+        );
+        let expected = "\
+(let ([first (+ 1 2)])
+  (let ([second (- first)])
+    (+ first second)))";
+        assert_eq!(pretty(&expr, 30), expected);
+    }
+
+    #[test]
+    fn aligns_operands() {
+        let expr = Expr::add(
+            Expr::let_bind("x", Expr::int(1), Expr::var("x")),
+            Expr::neg(Expr::read()),
+        );
+        let expected = "\
+(+ (let ([x 1])
+     x)
+   (- (read)))";
+        assert_eq!(pretty(&expr, 14), expected);
+    }
+
+    #[test]
+    fn booleans() {
+        let expr = parse_expr("(if (and #t (< x 1)) (not #f) (eq? x y))").unwrap();
+        assert_eq!(expr.to_string(), "(if (and #t (< x 1)) (not #f) (eq? x y))");
+        let expected = "\
+(if (and #t (< x 1))
+    (not #f)
+    (eq? x y))";
+        assert_eq!(pretty(&expr, 30), expected);
+    }
+
+    #[test]
+    fn vectors() {
+        let expr = parse_expr("(vector-ref (vector 1 (void)) 0)").unwrap();
+        assert_eq!(expr.to_string(), "(vector-ref (vector 1 (void)) 0)");
+    }
+
+    #[test]
+    fn programs() {
+        let src = "\
+(define (add [x : Integer] [y : Integer]) : Integer
+  (+ x y))
+
+(define (apply-twice [f : (Integer -> Integer)] [x : Integer]) : Integer
+  (f (f x)))
+
+((if #t apply-twice add) (add 1 2) 2)
+";
+        let prog = parse_program(src).unwrap();
+        assert_eq!(prog.to_string(), src);
+        assert_eq!(parse_program(&prog.to_string()).unwrap(), prog);
+        let expr = Expr::apply(Expr::fun_ref("f12345"), vec![]);
+        assert_eq!(pretty(&expr, 8), "((fun-ref f12345))");
+    }
+
+    #[test]
+    fn lambdas() {
+        let src = "(lambda ([x : Integer] [f : (Integer -> Integer)]) (f (+ x 1)))";
+        let expr = parse_expr(src).unwrap();
+        assert_eq!(expr.to_string(), src);
+        let expected = "\
+(lambda ([x : Integer] [f : (Integer -> Integer)])
+  (f (+ x 1)))";
+        assert_eq!(pretty(&expr, 40), expected);
+        assert_eq!(parse_expr(expected).unwrap(), expr);
+    }
+
+    #[test]
+    fn casts() {
+        let src = "(project (any-vector-ref (inject (vector 1 #t) (Vector Any Any)) 1) Boolean)";
+        let expr = parse_expr(src).unwrap();
+        assert_eq!(expr.to_string(), src);
+        let expected = "\
+(project (any-vector-ref (inject (vector 1 #t)
+                                 (Vector Any Any))
+                         1)
+         Boolean)";
+        assert_eq!(pretty(&expr, 60), expected);
+        assert_eq!(parse_expr(expected).unwrap(), expr);
+    }
+
+    #[test]
+    fn round_trip() {
+        let src = "\
+(let ([v12345 (let ([v12346 (read)]) (+ 3 v12346))])
+  (let ([v12347 (let ([v12348 (read)]) (+ v12348 4))])
+    (let ([x.1 (- v12347)])
+      (+ (let ([y -42]) (+ y (- (+ x.1 v12345))))
+         (let ([z (+ v12345 (- (- (- (+ 1 2)))))]) (+ z (read)))))))";
+        let expr = parse_expr(src).unwrap();
+        for width in 0..100 {
+            let printed = pretty(&expr, width);
+            assert_eq!(parse_expr(&printed).unwrap(), expr, "width {}", width);
+        }
+        assert_eq!(pretty(&expr, 80), src);
+    }
+}
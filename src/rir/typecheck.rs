@@ -0,0 +1,563 @@
+//! Static type checker for RIR.
+//!
+//! Runs before uniquify, so it resolves variables by name and follows `let`
+//! scoping itself. References to unbound variables are left for uniquify to
+//! report. Later passes can assume the program is well typed.
+
+use super::super::diagnostic::{Diagnostic, Span};
+use super::super::error::CompileError;
+use super::{CmpOp, Expr, ExprKind, Lit, Program, Symbol};
+use std::fmt;
+
+/// Type of an RIR expression.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Type {
+    Integer,
+    Boolean,
+    Void,
+    /// A vector with an element of the given type in each position.
+    Vector(Vec<Type>),
+    /// A function from parameters of the given types to a result.
+    Function(Vec<Type>, Box<Type>),
+    /// A value of any type, tagged with its type at run time.
+    Any,
+}
+
+impl Type {
+    /// Type of a function of the given arity that takes and returns `Any`.
+    pub fn any_function(arity: usize) -> Type {
+        Type::Function(vec![Type::Any; arity], Box::new(Type::Any))
+    }
+
+    /// Whether values of the type can be injected into `Any` and projected
+    /// back out of it. The tag of an `Any` tells only what kind of value it
+    /// holds, so vectors and functions must hold and take `Any`s.
+    pub fn is_ground(&self) -> bool {
+        match self {
+            Type::Integer | Type::Boolean | Type::Void => true,
+            Type::Vector(elems) => elems.iter().all(|elem| *elem == Type::Any),
+            Type::Function(params, result) => {
+                params.iter().all(|param| *param == Type::Any) && **result == Type::Any
+            }
+            Type::Any => false,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Integer => write!(f, "Integer"),
+            Type::Boolean => write!(f, "Boolean"),
+            Type::Void => write!(f, "Void"),
+            Type::Vector(elems) => {
+                write!(f, "(Vector")?;
+                for elem in elems {
+                    write!(f, " {}", elem)?;
+                }
+                write!(f, ")")
+            }
+            Type::Function(params, result) => {
+                write!(f, "(")?;
+                for param in params {
+                    write!(f, "{} ", param)?;
+                }
+                write!(f, "-> {})", result)
+            }
+            Type::Any => write!(f, "Any"),
+        }
+    }
+}
+
+/// Maintains the types of the variables in scope and the problems found while
+/// checking.
+pub struct TypeChecker {
+    /// Variables in scope with their types, innermost binding last. The type
+    /// of a variable bound to an ill-typed expression is unknown.
+    scopes: Vec<(Symbol, Option<Type>)>,
+
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl TypeChecker {
+    pub fn new() -> TypeChecker {
+        TypeChecker {
+            scopes: vec![],
+            diagnostics: vec![],
+        }
+    }
+
+    /// Brings a variable of the given type into scope.
+    pub fn bind(&mut self, sym: &Symbol, ty: Option<Type>) {
+        self.scopes.push((sym.clone(), ty));
+    }
+
+    /// Takes the innermost variable out of scope.
+    pub fn unbind(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Type of the innermost variable in scope with the given name.
+    pub fn lookup(&self, sym: &Symbol) -> Option<Type> {
+        self.scopes
+            .iter()
+            .rev()
+            .find(|(s, _)| s == sym)
+            .and_then(|(_, ty)| ty.clone())
+    }
+
+    /// Records a diagnostic unless the actual type is unknown, in which case
+    /// the problem has already been reported.
+    fn expect(&mut self, expected: &Type, actual: Option<Type>, context: &str, span: &Span) {
+        if let Some(actual) = actual {
+            if actual != *expected {
+                self.diagnostics.push(Diagnostic::new(
+                    format!("expected {}, found {} in {}", expected, actual, context),
+                    span.clone(),
+                ));
+            }
+        }
+    }
+
+    /// Checks the operands of a primitive that takes operands of the given
+    /// type.
+    fn check_args(&mut self, op: &str, expected: &Type, args: &[&Expr]) {
+        for (i, arg) in args.iter().enumerate() {
+            let actual = self.check(arg);
+            let context = format!("argument {} of {}", i + 1, op);
+            self.expect(expected, actual, &context, &arg.span);
+        }
+    }
+
+    /// Finds the type of the element of a vector of type `v_ty` at `index`.
+    fn check_index(
+        &mut self,
+        op: &str,
+        v_ty: Option<Type>,
+        v: &Expr,
+        index: &Expr,
+    ) -> Option<Type> {
+        let i = match index.kind {
+            ExprKind::Lit(Lit::Int(i)) => i,
+            _ => {
+                self.diagnostics.push(Diagnostic::new(
+                    format!("expected an integer literal in argument 2 of {}", op),
+                    index.span.clone(),
+                ));
+                return None;
+            }
+        };
+        match v_ty? {
+            Type::Vector(elems) => match elems.get(i as usize) {
+                Some(elem) if i >= 0 => Some(elem.clone()),
+                _ => {
+                    self.diagnostics.push(Diagnostic::new(
+                        format!("index {} is out of range for {}", i, Type::Vector(elems)),
+                        index.span.clone(),
+                    ));
+                    None
+                }
+            },
+            ty => {
+                self.diagnostics.push(Diagnostic::new(
+                    format!("expected a vector, found {} in argument 1 of {}", ty, op),
+                    v.span.clone(),
+                ));
+                None
+            }
+        }
+    }
+
+    /// Records a diagnostic if a cast to or from `Any` is to a type that is
+    /// not ground.
+    fn expect_ground(&mut self, op: &str, ty: &Type, span: &Span) {
+        if !ty.is_ground() {
+            self.diagnostics.push(Diagnostic::new(
+                format!("cannot {} {}, which is not a ground type", op, ty),
+                span.clone(),
+            ));
+        }
+    }
+
+    /// Infers the type of the expression. Returns `None` when the type cannot
+    /// be known, because of an error or an unbound variable.
+    pub fn check(&mut self, expr: &Expr) -> Option<Type> {
+        match &expr.kind {
+            ExprKind::Read => Some(Type::Integer),
+            ExprKind::Lit(Lit::Int(_)) => Some(Type::Integer),
+            ExprKind::Lit(Lit::Bool(_)) => Some(Type::Boolean),
+            ExprKind::Neg(e) => {
+                self.check_args("-", &Type::Integer, &[e]);
+                Some(Type::Integer)
+            }
+            ExprKind::Add(e1, e2) => {
+                self.check_args("+", &Type::Integer, &[e1, e2]);
+                Some(Type::Integer)
+            }
+            ExprKind::Sub(e1, e2) => {
+                self.check_args("-", &Type::Integer, &[e1, e2]);
+                Some(Type::Integer)
+            }
+            ExprKind::Mul(e1, e2) => {
+                self.check_args("*", &Type::Integer, &[e1, e2]);
+                Some(Type::Integer)
+            }
+            ExprKind::Quotient(e1, e2) => {
+                self.check_args("quotient", &Type::Integer, &[e1, e2]);
+                Some(Type::Integer)
+            }
+            ExprKind::Remainder(e1, e2) => {
+                self.check_args("remainder", &Type::Integer, &[e1, e2]);
+                Some(Type::Integer)
+            }
+            ExprKind::Not(e) => {
+                self.check_args("not", &Type::Boolean, &[e]);
+                Some(Type::Boolean)
+            }
+            ExprKind::And(e1, e2) => {
+                self.check_args("and", &Type::Boolean, &[e1, e2]);
+                Some(Type::Boolean)
+            }
+            ExprKind::Or(e1, e2) => {
+                self.check_args("or", &Type::Boolean, &[e1, e2]);
+                Some(Type::Boolean)
+            }
+            ExprKind::Cmp(CmpOp::Eq, e1, e2) => {
+                // `eq?` compares operands of any one type.
+                if let Some(ty1) = self.check(e1) {
+                    let ty2 = self.check(e2);
+                    self.expect(&ty1, ty2, "argument 2 of eq?", &e2.span);
+                } else {
+                    self.check(e2);
+                }
+                Some(Type::Boolean)
+            }
+            ExprKind::Cmp(op, e1, e2) => {
+                self.check_args(op.name(), &Type::Integer, &[e1, e2]);
+                Some(Type::Boolean)
+            }
+            ExprKind::If(cond, then, els) => {
+                let cond_ty = self.check(cond);
+                self.expect(&Type::Boolean, cond_ty, "condition of if", &cond.span);
+                let then_ty = self.check(then);
+                let els_ty = self.check(els);
+                match then_ty {
+                    Some(then_ty) => {
+                        self.expect(&then_ty, els_ty, "else branch of if", &els.span);
+                        Some(then_ty)
+                    }
+                    None => els_ty,
+                }
+            }
+            ExprKind::Vector(elems) => {
+                let tys = elems
+                    .iter()
+                    .map(|e| self.check(e))
+                    .collect::<Option<Vec<Type>>>();
+                tys.map(Type::Vector)
+            }
+            ExprKind::VectorRef(v, index) => {
+                let v_ty = self.check(v);
+                self.check_index("vector-ref", v_ty, v, index)
+            }
+            ExprKind::VectorSet(v, index, e) => {
+                let v_ty = self.check(v);
+                let elem_ty = self.check_index("vector-set!", v_ty, v, index);
+                let e_ty = self.check(e);
+                if let Some(elem_ty) = elem_ty {
+                    self.expect(&elem_ty, e_ty, "argument 3 of vector-set!", &e.span);
+                }
+                Some(Type::Void)
+            }
+            ExprKind::Inject(e, ty) => {
+                self.expect_ground("inject", ty, &expr.span);
+                let e_ty = self.check(e);
+                self.expect(ty, e_ty, "argument 1 of inject", &e.span);
+                Some(Type::Any)
+            }
+            ExprKind::Project(e, ty) => {
+                self.expect_ground("project", ty, &expr.span);
+                self.check_args("project", &Type::Any, &[e]);
+                Some(ty.clone())
+            }
+            ExprKind::AnyVectorRef(v, _) => {
+                self.check_args("any-vector-ref", &Type::Any, &[v]);
+                Some(Type::Any)
+            }
+            ExprKind::AnyVectorSet(v, _, e) => {
+                self.check_args("any-vector-set!", &Type::Any, &[v]);
+                let e_ty = self.check(e);
+                self.expect(&Type::Any, e_ty, "argument 3 of any-vector-set!", &e.span);
+                Some(Type::Void)
+            }
+            ExprKind::Void | ExprKind::Collect(_) => Some(Type::Void),
+            ExprKind::Allocate(_, ty) => Some(ty.clone()),
+            ExprKind::GlobalValue(_) => Some(Type::Integer),
+            // Unbound variables are reported by uniquify.
+            ExprKind::Var(sym) => self.lookup(sym),
+            ExprKind::Let(sym, e, body) => {
+                // A binding whose type is unknown leaves its uses unknown too,
+                // which keeps one error from being reported again at each use.
+                let ty = self.check(e);
+                self.bind(sym, ty);
+                let body_ty = self.check(body);
+                self.unbind();
+                body_ty
+            }
+            ExprKind::Set(sym, e) => {
+                let e_ty = self.check(e);
+                if let Some(ty) = self.lookup(sym) {
+                    self.expect(&ty, e_ty, "set!", &e.span);
+                }
+                Some(Type::Void)
+            }
+            ExprKind::Begin(effects, e) => {
+                for effect in effects {
+                    self.check(effect);
+                }
+                self.check(e)
+            }
+            ExprKind::While(cond, body) => {
+                let cond_ty = self.check(cond);
+                self.expect(&Type::Boolean, cond_ty, "condition of while", &cond.span);
+                self.check(body);
+                Some(Type::Void)
+            }
+            ExprKind::Print(e) => {
+                self.check(e);
+                Some(Type::Void)
+            }
+            ExprKind::Apply(f, args) => {
+                let f_ty = self.check(f);
+                let arg_tys = args.iter().map(|e| self.check(e)).collect::<Vec<_>>();
+                match f_ty? {
+                    Type::Function(params, result) => {
+                        if params.len() != args.len() {
+                            self.diagnostics.push(Diagnostic::new(
+                                format!(
+                                    "expected {} argument(s), found {} in call",
+                                    params.len(),
+                                    args.len()
+                                ),
+                                expr.span.clone(),
+                            ));
+                        } else {
+                            for (i, ((param, arg_ty), arg)) in
+                                params.iter().zip(arg_tys).zip(args).enumerate()
+                            {
+                                let context = format!("argument {} of call", i + 1);
+                                self.expect(param, arg_ty, &context, &arg.span);
+                            }
+                        }
+                        Some(*result)
+                    }
+                    ty => {
+                        self.diagnostics.push(Diagnostic::new(
+                            format!("expected a function, found {} in call", ty),
+                            f.span.clone(),
+                        ));
+                        None
+                    }
+                }
+            }
+            ExprKind::FunRef(name) => self.lookup(&Symbol::new(name)),
+            ExprKind::Lambda(params, body) => {
+                for (param, ty) in params {
+                    self.bind(param, Some(ty.clone()));
+                }
+                let body_ty = self.check(body);
+                for _ in params {
+                    self.unbind();
+                }
+                let param_tys = params.iter().map(|(_, ty)| ty.clone()).collect();
+                Some(Type::Function(param_tys, Box::new(body_ty?)))
+            }
+        }
+    }
+
+    /// Checks every function of the program, then infers the type of its
+    /// final expression. Every function is in scope everywhere, so functions
+    /// may call each other in any order.
+    pub fn check_program(&mut self, p: &Program) -> Option<Type> {
+        for def in &p.defs {
+            self.bind(&def.name, Some(def.ty()));
+        }
+        for def in &p.defs {
+            for (param, ty) in &def.params {
+                self.bind(param, Some(ty.clone()));
+            }
+            let body_ty = self.check(&def.body);
+            let context = format!("body of {}", def.name.value);
+            self.expect(&def.result, body_ty, &context, &def.body.span);
+            for _ in &def.params {
+                self.unbind();
+            }
+        }
+        let ty = self.check(&p.expr);
+        for _ in &p.defs {
+            self.unbind();
+        }
+        ty
+    }
+}
+
+impl Default for TypeChecker {
+    fn default() -> Self {
+        TypeChecker::new()
+    }
+}
+
+/// Checks that the program is well typed and returns the type of its value.
+/// The type is `None` when it depends on an unbound variable.
+pub fn typecheck_program(p: &Program) -> Result<Option<Type>, CompileError> {
+    let mut checker = TypeChecker::new();
+    let ty = checker.check_program(p);
+    if checker.diagnostics.is_empty() {
+        Ok(ty)
+    } else {
+        Err(CompileError::Typecheck(checker.diagnostics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::error::CompileError;
+    use super::super::parse::parse_program;
+    use super::{typecheck_program, Type};
+
+    fn check(src: &str) -> Result<Option<Type>, CompileError> {
+        typecheck_program(&parse_program(src).unwrap())
+    }
+
+    #[test]
+    fn well_typed() {
+        let src = "(let ([x (read)]) (if (and (< x 1) (eq? #t (not #f))) x (- x)))";
+        assert_eq!(check(src), Ok(Some(Type::Integer)));
+        assert_eq!(check("(eq? (> 1 2) #f)"), Ok(Some(Type::Boolean)));
+    }
+
+    #[test]
+    fn assignments_and_loops() {
+        let src = "(let ([x 1]) (begin (set! x 2) (while (< x 3) (set! x (+ x 1))) x))";
+        assert_eq!(check(src), Ok(Some(Type::Integer)));
+        let err = check("(let ([x 1]) (begin (set! x #t) (while x (void))))").unwrap_err();
+        let expected = "typecheck: expected Integer, found Boolean in set! at 1:29; \
+                        expected Boolean, found Integer in condition of while at 1:40";
+        assert_eq!(err.to_string(), expected);
+    }
+
+    #[test]
+    fn located_message() {
+        let err = check("(let ([b (< 1 2)])\n  (+ 1 b))").unwrap_err();
+        assert_eq!(err.pass(), "typecheck");
+        assert_eq!(
+            err.to_string(),
+            "typecheck: expected Integer, found Boolean in argument 2 of + at 2:8"
+        );
+    }
+
+    #[test]
+    fn conditions_and_branches() {
+        let err = check("(if 1 #t 2)").unwrap_err();
+        let expected = "typecheck: expected Boolean, found Integer in condition of if at 1:5; \
+                        expected Boolean, found Integer in else branch of if at 1:10";
+        assert_eq!(err.to_string(), expected);
+        let err = check("(eq? 1 #f)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "typecheck: expected Integer, found Boolean in argument 2 of eq? at 1:8"
+        );
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let err = check("(let ([x (if #t 1 #f)]) (+ x (- x #t)))").unwrap_err();
+        let expected = "typecheck: expected Integer, found Boolean in else branch of if at 1:19; \
+                        expected Integer, found Boolean in argument 2 of - at 1:35";
+        assert_eq!(err.to_string(), expected);
+    }
+
+    #[test]
+    fn unbound_variables_are_left_to_uniquify() {
+        assert_eq!(check("(+ y 1)"), Ok(Some(Type::Integer)));
+        assert_eq!(check("(let ([x 1]) y)"), Ok(None));
+    }
+
+    #[test]
+    fn vectors() {
+        let src = "(let ([v (vector 1 (vector #t))]) (vector-ref (vector-ref v 1) 0))";
+        assert_eq!(check(src), Ok(Some(Type::Boolean)));
+        assert_eq!(check("(vector-set! (vector 1) 0 2)"), Ok(Some(Type::Void)));
+        let err = check("(vector-ref (vector 1 #t) 2)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "typecheck: index 2 is out of range for (Vector Integer Boolean) at 1:27"
+        );
+        let err = check("(vector-set! (vector 1) 0 #f)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "typecheck: expected Integer, found Boolean in argument 3 of vector-set! at 1:27"
+        );
+        let err = check("(vector-ref 1 0)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "typecheck: expected a vector, found Integer in argument 1 of vector-ref at 1:13"
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let src = "\
+(define (even? [n : Integer]) : Boolean (if (eq? n 0) #t (odd? (- n 1))))
+(define (odd? [n : Integer]) : Boolean (if (eq? n 0) #f (even? (- n 1))))
+(define (compose [f : (Integer -> Boolean)] [x : Integer]) : (Vector Boolean)
+  (vector (f x)))
+(compose odd? 7)";
+        assert_eq!(check(src), Ok(Some(Type::Vector(vec![Type::Boolean]))));
+        let err = check("(define (f [x : Integer]) : Boolean x) (f 1)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "typecheck: expected Boolean, found Integer in body of f at 1:37"
+        );
+        let err = check("(define (f [x : Integer]) : Integer x) (f #t)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "typecheck: expected Integer, found Boolean in argument 1 of call at 1:43"
+        );
+        let err = check("(define (f [x : Integer]) : Integer x) (f)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "typecheck: expected 1 argument(s), found 0 in call at 1:40"
+        );
+        let err = check("(let ([x 1]) (x 2))").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "typecheck: expected a function, found Integer in call at 1:15"
+        );
+    }
+
+    #[test]
+    fn lambdas() {
+        let src = "(let ([y 1]) (lambda ([x : Integer]) (eq? x y)))";
+        let expected = Type::Function(vec![Type::Integer], Box::new(Type::Boolean));
+        assert_eq!(check(src), Ok(Some(expected)));
+        assert_eq!(
+            check("((lambda ([b : Boolean]) b) #t)"),
+            Ok(Some(Type::Boolean))
+        );
+        let err = check("((lambda ([x : Integer]) x) #t)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "typecheck: expected Integer, found Boolean in argument 1 of call at 1:29"
+        );
+    }
+
+    #[test]
+    fn type_names() {
+        let ty = Type::Function(
+            vec![Type::Integer, Type::Vector(vec![Type::Boolean, Type::Void])],
+            Box::new(Type::Integer),
+        );
+        assert_eq!(ty.to_string(), "(Integer (Vector Boolean Void) -> Integer)");
+    }
+}
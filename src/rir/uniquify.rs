@@ -1,4 +1,6 @@
-use super::{Expr, ExprFolder, Program, ProgramFolder, Symbol};
+use super::super::diagnostic::{Diagnostic, Span};
+use super::typecheck::Type;
+use super::{Def, Expr, ExprFolder, ExprKind, Program, ProgramFolder, Symbol};
 use std::collections::HashMap;
 
 /// Maintains state necessary for uniquify-ing the variable names in an AST.
@@ -8,6 +10,13 @@ pub struct ExprUniquifier {
     /// Maps variable names from source code to generated uniqued variable
     /// names. Contains only variables that are currently in scope.
     sym_table: HashMap<Box<Symbol>, Box<Symbol>>,
+
+    /// Maps the names of functions defined at the top level to generated
+    /// unique names, which are also valid assembly labels.
+    funs: HashMap<Symbol, String>,
+
+    /// Problems found while folding, such as references to unbound variables.
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 impl ExprUniquifier {
@@ -15,6 +24,8 @@ impl ExprUniquifier {
         ExprUniquifier {
             counter,
             sym_table: HashMap::new(),
+            funs: HashMap::new(),
+            diagnostics: vec![],
         }
     }
 
@@ -23,15 +34,115 @@ impl ExprUniquifier {
         self.counter += 1;
         sym
     }
+
+    fn new_fun_name(&mut self) -> String {
+        let name = format!("f{}", self.counter);
+        self.counter += 1;
+        name
+    }
+
+    /// Gives every function and variable of the program a unique name.
+    /// References to functions that no variable shadows become `fun-ref`s.
+    pub fn fold_program(&mut self, p: Program) -> Program {
+        for def in &p.defs {
+            if self.funs.contains_key(&def.name) {
+                self.diagnostics.push(Diagnostic::new(
+                    format!("function `{}` is defined more than once", def.name.value),
+                    def.span.clone(),
+                ));
+            } else {
+                let name = self.new_fun_name();
+                self.funs.insert(*def.name.clone(), name);
+            }
+        }
+        let defs = p.defs.into_iter().map(|def| self.fold_def(def)).collect();
+        Program {
+            defs,
+            expr: self.fold(p.expr),
+        }
+    }
+
+    fn fold_def(&mut self, def: Def) -> Def {
+        let mut params = vec![];
+        for (param, ty) in def.params {
+            if self.sym_table.contains_key(&param) {
+                self.diagnostics.push(Diagnostic::new(
+                    format!("parameter `{}` is bound more than once", param.value),
+                    def.span.clone(),
+                ));
+            }
+            let gen = self.new_sym();
+            self.sym_table.insert(param, gen.clone());
+            params.push((gen, ty));
+        }
+        let body = self.fold(def.body);
+        self.sym_table.clear();
+        Def {
+            name: Box::new(Symbol::new(&self.funs[&def.name])),
+            params,
+            body,
+            ..def
+        }
+    }
 }
 
 impl ExprFolder for ExprUniquifier {
-    fn fold_var(&mut self, s: Box<Symbol>) -> Box<Expr> {
-        let gen = self.sym_table.get(&s).expect("undefined variable");
-        Box::new(Expr::Var(gen.clone()))
+    fn fold_var(&mut self, s: Box<Symbol>, span: Span) -> Box<Expr> {
+        match self.sym_table.get(&s) {
+            Some(gen) => Expr::new(ExprKind::Var(gen.clone()), span),
+            None if self.funs.contains_key(&s) => {
+                Expr::new(ExprKind::FunRef(self.funs[&s].clone()), span)
+            }
+            None => {
+                self.diagnostics.push(Diagnostic::new(
+                    format!("unbound variable `{}`", s.value),
+                    span.clone(),
+                ));
+                Expr::new(ExprKind::Var(s), span)
+            }
+        }
     }
 
-    fn fold_let(&mut self, sym: Box<Symbol>, e: Box<Expr>, body: Box<Expr>) -> Box<Expr> {
+    /// A `fun-ref` read back from a dump names a function of the program,
+    /// which is renamed like the rest.
+    fn fold_fun_ref(&mut self, name: String, span: Span) -> Box<Expr> {
+        match self.funs.get(&Symbol::new(&name)) {
+            Some(gen) => Expr::new(ExprKind::FunRef(gen.clone()), span),
+            None => {
+                self.diagnostics.push(Diagnostic::new(
+                    format!("unknown function `{}`", name),
+                    span.clone(),
+                ));
+                Expr::new(ExprKind::FunRef(name), span)
+            }
+        }
+    }
+
+    fn fold_set(&mut self, sym: Box<Symbol>, e: Box<Expr>, span: Span) -> Box<Expr> {
+        let e = self.fold(e);
+        let sym = match self.sym_table.get(&sym) {
+            Some(gen) => gen.clone(),
+            None => {
+                let message = if self.funs.contains_key(&sym) {
+                    format!("cannot assign to function `{}`", sym.value)
+                } else {
+                    format!("unbound variable `{}`", sym.value)
+                };
+                self.diagnostics
+                    .push(Diagnostic::new(message, span.clone()));
+                sym
+            }
+        };
+        Expr::new(ExprKind::Set(sym, e), span)
+    }
+
+    fn fold_let(
+        &mut self,
+        sym: Box<Symbol>,
+        e: Box<Expr>,
+        body: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
         // Fold the value expression first.
         let folded_val = self.fold(e);
 
@@ -57,7 +168,41 @@ impl ExprFolder for ExprUniquifier {
             self.sym_table.remove(&sym);
         }
 
-        Box::new(Expr::Let(gen, folded_val, folded_body))
+        Expr::new(ExprKind::Let(gen, folded_val, folded_body), span)
+    }
+
+    fn fold_lambda(
+        &mut self,
+        params: Vec<(Box<Symbol>, Type)>,
+        body: Box<Expr>,
+        span: Span,
+    ) -> Box<Expr> {
+        // Like the symbol of a let, each parameter shadows the variable of the
+        // same name until the end of the body.
+        let mut shadowed = vec![];
+        let mut gen_params = vec![];
+        for (i, (param, ty)) in params.iter().enumerate() {
+            if params[..i].iter().any(|(p, _)| p == param) {
+                self.diagnostics.push(Diagnostic::new(
+                    format!("parameter `{}` is bound more than once", param.value),
+                    span.clone(),
+                ));
+            }
+            let gen = self.new_sym();
+            shadowed.push((
+                param.clone(),
+                self.sym_table.insert(param.clone(), gen.clone()),
+            ));
+            gen_params.push((gen, ty.clone()));
+        }
+        let folded_body = self.fold(body);
+        for (param, old_unq_sym) in shadowed.into_iter().rev() {
+            match old_unq_sym {
+                Some(old_unq_sym) => self.sym_table.insert(param, old_unq_sym),
+                None => self.sym_table.remove(&param),
+            };
+        }
+        Expr::new(ExprKind::Lambda(gen_params, folded_body), span)
     }
 }
 
@@ -66,13 +211,14 @@ pub struct ProgramUniquifier {}
 impl ProgramFolder for ProgramUniquifier {
     fn fold(&mut self, p: Program) -> Program {
         let mut ctx = ExprUniquifier::new(12345);
-        Program::new(ctx.fold(p.expr))
+        ctx.fold_program(p)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::{Expr, ExprFolder};
+    use super::super::parse::{parse_expr, parse_program};
+    use super::super::{Expr, ExprFolder, ExprKind};
     use super::ExprUniquifier;
 
     #[test]
@@ -108,6 +254,102 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn unbound_var() {
+        let src = "(let ([x 1])\n  (+ x y))";
+        let expr = parse_expr(src).unwrap();
+        let mut ctx = ExprUniquifier::new(12345);
+        ctx.fold(expr);
+        assert_eq!(ctx.diagnostics.len(), 1);
+        let expected = "unbound variable `y` at 2:8\n  |\n2 |   (+ x y))\n  |        ^\n";
+        assert_eq!(ctx.diagnostics[0].render(src), expected);
+    }
+
+    #[test]
+    fn spans_are_kept() {
+        let expr = parse_expr("(let ([x 1]) x)").unwrap();
+        let mut ctx = ExprUniquifier::new(12345);
+        let actual = ctx.fold(expr);
+        assert_eq!((actual.span.lo, actual.span.hi), (0, 15));
+        match actual.kind {
+            ExprKind::Let(_, _, body) => assert_eq!((body.span.lo, body.span.hi), (13, 14)),
+            _ => panic!("expected a let"),
+        }
+    }
+
+    #[test]
+    fn functions() {
+        let src = "\
+(define (f [x : Integer] [y : Integer]) : Integer (g x))
+(define (g [x : Integer]) : Integer (let ([f x]) f))
+(f 1 2)";
+        let mut ctx = ExprUniquifier::new(12345);
+        let actual = ctx.fold_program(parse_program(src).unwrap());
+        assert!(ctx.diagnostics.is_empty());
+        let expected = "\
+(define (f12345 [v12347 : Integer] [v12348 : Integer]) : Integer
+  ((fun-ref f12346) v12347))
+
+(define (f12346 [v12349 : Integer]) : Integer
+  (let ([v12350 v12349]) v12350))
+
+((fun-ref f12345) 1 2)
+";
+        assert_eq!(actual.to_string(), expected);
+    }
+
+    #[test]
+    fn lambdas() {
+        let src = "(let ([x 1]) (vector (lambda ([y : Integer] [x : Integer]) (+ x y)) x))";
+        let mut ctx = ExprUniquifier::new(12345);
+        let actual = ctx.fold(parse_expr(src).unwrap());
+        let expected = "\
+(let ([v12345 1])
+  (vector (lambda ([v12346 : Integer] [v12347 : Integer]) (+ v12347 v12346))
+          v12345))";
+        assert_eq!(actual.to_string(), expected);
+        let src = "(lambda ([x : Integer] [x : Integer]) x)";
+        let mut ctx = ExprUniquifier::new(12345);
+        ctx.fold(parse_expr(src).unwrap());
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(
+            ctx.diagnostics[0].message,
+            "parameter `x` is bound more than once"
+        );
+    }
+
+    #[test]
+    fn assignments() {
+        let src = "(define (f) : Integer 1)\n(let ([x 1]) (begin (set! x (+ x 1)) (set! f x) x))";
+        let mut ctx = ExprUniquifier::new(12345);
+        let actual = ctx.fold_program(parse_program(src).unwrap());
+        let expected =
+            "(let ([v12346 1]) (begin (set! v12346 (+ v12346 1)) (set! f v12346) v12346))";
+        assert_eq!(actual.expr.to_string().trim_end(), expected);
+        assert_eq!(ctx.diagnostics.len(), 1);
+        assert_eq!(ctx.diagnostics[0].message, "cannot assign to function `f`");
+    }
+
+    #[test]
+    fn duplicate_definitions() {
+        let src =
+            "(define (f [x : Integer] [x : Integer]) : Integer x)\n(define (f) : Integer 1)\n1";
+        let mut ctx = ExprUniquifier::new(12345);
+        ctx.fold_program(parse_program(src).unwrap());
+        let messages = ctx
+            .diagnostics
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            vec![
+                "function `f` is defined more than once",
+                "parameter `x` is bound more than once",
+            ]
+        );
+    }
+
     #[test]
     fn no_vars() {
         let expr = Expr::add(Expr::int(52), Expr::neg(Expr::int(10)));
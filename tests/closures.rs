@@ -0,0 +1,26 @@
+mod support;
+
+use eoc::rir::parse::parse_program;
+
+/// Closures and the vectors they capture must survive the collections
+/// triggered while a chain of closures is built and called. Each step
+/// allocates a short-lived vector of 49 elements holding another of 48, so
+/// twenty steps fill the heap.
+#[test]
+fn collects_captured_vectors() {
+    let elems = vec!["n"; 48].join(" ");
+    let junk = format!("{0} (vector {0})", elems);
+    let src = format!(
+        "\
+(define (chain [n : Integer] [f : (Integer -> Integer)]) : (Integer -> Integer)
+  (if (eq? n 0)
+      f
+      (let ([junk (vector {})])
+        (let ([v (vector (vector-ref junk 0))])
+          (chain (- n 1) (lambda ([x : Integer]) (f (+ x (vector-ref v 0)))))))))
+((chain (read) (lambda ([x : Integer]) x)) 1)",
+        junk
+    );
+    let prog = parse_program(&src).unwrap();
+    support::check_runs("collects_captured_vectors", &prog, &[vec![3], vec![25]]);
+}
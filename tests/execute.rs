@@ -0,0 +1,65 @@
+//! Runs every program in `tests/golden` natively and compares the result with
+//! the interpreter. Programs in `tests/golden/dynamic` have no type
+//! annotations and are only run dynamically typed.
+//!
+//! The inputs for `NAME.rkt` are read from `NAME.in`, one run per line with
+//! the values for `(read)` separated by spaces. A program without a `.in`
+//! file is run once with no input.
+
+mod support;
+
+use eoc::options::Typing;
+use eoc::rir::parse::parse_file_program;
+use std::fs;
+
+const DIR: &str = "./tests/golden";
+
+fn read_input_sets(text: &str) -> Vec<Vec<i64>> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with(';'))
+        .map(|line| {
+            line.split_whitespace()
+                .map(|word| word.parse().unwrap())
+                .collect()
+        })
+        .collect()
+}
+
+/// Runs every program in `dir` with the given typings. Returns the number of
+/// programs run.
+fn execute_dir(dir: &str, typings: &[Typing]) -> std::io::Result<usize> {
+    let mut ran = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "rkt") {
+            continue;
+        }
+        let src = fs::read_to_string(&path)?;
+        let prog = parse_file_program(&path.to_string_lossy(), &src)
+            .unwrap_or_else(|e| panic!("{}", e.render(&src)));
+        let input_path = path.with_extension("in");
+        let input_sets = if input_path.exists() {
+            read_input_sets(&fs::read_to_string(&input_path)?)
+        } else {
+            vec![vec![]]
+        };
+        let name = path.file_stem().unwrap().to_string_lossy();
+        support::check_runs_as(&name, &prog, &input_sets, typings);
+        ran += 1;
+    }
+    Ok(ran)
+}
+
+#[test]
+fn execute() -> std::io::Result<()> {
+    let ran = execute_dir(DIR, &[Typing::Static, Typing::Dynamic])?;
+    assert!(ran > 0, "no programs found");
+    Ok(())
+}
+
+#[test]
+fn execute_dynamic() -> std::io::Result<()> {
+    let ran = execute_dir(&format!("{}/dynamic", DIR), &[Typing::Dynamic])?;
+    assert!(ran > 0, "no programs found");
+    Ok(())
+}
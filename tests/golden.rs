@@ -0,0 +1,109 @@
+//! Golden tests for the text dumped after each pass.
+//!
+//! Every `tests/golden/NAME.rkt` is compiled up to each stage that has a
+//! `tests/golden/NAME.STAGE` file next to it, and the dump is compared with
+//! the contents of that file. Hand-written CIR programs in
+//! `tests/golden/cir/NAME.cir` are checked the same way, starting from the CIR
+//! stage, and programs in `tests/golden/dynamic` are compiled dynamically
+//! typed. Run with `BLESS=1` to rewrite the expected files from the current
+//! output.
+
+use eoc::cir;
+use eoc::driver::{drive_cir_to, drive_program_to, Stage};
+use eoc::options::{Options, Typing};
+use eoc::rir::parse::{parse_file_program, parse_program};
+use std::fs;
+use std::path::Path;
+
+/// Stages whose dumps are RIR source and so must parse back to a program
+/// that prints the same.
+const RIR_STAGES: [Stage; 7] = [
+    Stage::Rir,
+    Stage::RirCasts,
+    Stage::RirUniq,
+    Stage::RirAssign,
+    Stage::RirClosure,
+    Stage::RirAlloc,
+    Stage::RirSimple,
+];
+
+/// Compiles every input with the given extension in `dir` up to each stage
+/// that has an expected file. Returns the number of files checked.
+fn check_dir(
+    dir: &str,
+    input_ext: &str,
+    compile: impl Fn(&Path, &str, Stage) -> String,
+) -> std::io::Result<usize> {
+    let bless = std::env::var_os("BLESS").is_some();
+    let mut checked = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != input_ext) {
+            continue;
+        }
+        let src = fs::read_to_string(&path)?;
+        for stage in Stage::ALL.iter() {
+            let expected_path = path.with_extension(stage.name());
+            if stage.name() == input_ext || !expected_path.exists() {
+                continue;
+            }
+            let actual = compile(&path, &src, *stage);
+            if bless {
+                fs::write(&expected_path, &actual)?;
+            }
+            check(&expected_path, *stage, &actual)?;
+            checked += 1;
+        }
+    }
+    Ok(checked)
+}
+
+#[test]
+fn golden() -> std::io::Result<()> {
+    let checked = check_dir("./tests/golden", "rkt", |path, src, stage| {
+        let prog = parse_file_program(&path.to_string_lossy(), src)
+            .unwrap_or_else(|e| panic!("{}", e.render(src)));
+        drive_program_to(prog, stage, &Options::default())
+            .unwrap_or_else(|e| panic!("{}", e.render(src)))
+    })?;
+    assert!(checked > 0, "no golden files found");
+    Ok(())
+}
+
+#[test]
+fn golden_dynamic() -> std::io::Result<()> {
+    let options = Options {
+        typing: Typing::Dynamic,
+        ..Options::default()
+    };
+    let checked = check_dir("./tests/golden/dynamic", "rkt", |path, src, stage| {
+        let prog = parse_file_program(&path.to_string_lossy(), src)
+            .unwrap_or_else(|e| panic!("{}", e.render(src)));
+        drive_program_to(prog, stage, &options).unwrap_or_else(|e| panic!("{}", e.render(src)))
+    })?;
+    assert!(checked > 0, "no golden files found");
+    Ok(())
+}
+
+#[test]
+fn golden_cir() -> std::io::Result<()> {
+    let checked = check_dir("./tests/golden/cir", "cir", |path, src, stage| {
+        let prog = cir::parse::parse_file_program(&path.to_string_lossy(), src)
+            .unwrap_or_else(|e| panic!("{}", e.render(src)));
+        drive_cir_to(prog, stage, &Options::default())
+            .unwrap_or_else(|e| panic!("{}", e.render(src)))
+    })?;
+    assert!(checked > 0, "no golden files found");
+    Ok(())
+}
+
+fn check(expected_path: &Path, stage: Stage, actual: &str) -> std::io::Result<()> {
+    let expected = fs::read_to_string(expected_path)?;
+    assert_eq!(actual, expected, "{}", expected_path.display());
+    if RIR_STAGES.contains(&stage) {
+        let reparsed = parse_program(actual)
+            .unwrap_or_else(|e| panic!("{}: {}", expected_path.display(), e.render(actual)));
+        assert_eq!(reparsed.to_string(), actual, "{}", expected_path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,62 @@
+start:
+	callq read_int
+	movq %rax, %rdi
+	movq $0, %rax
+	cmpq $0, %rax
+	je divide_by_zero_error
+	movq %rdi, %rax
+	cqto
+	pushq %rcx
+	movq $0, %rcx
+	idivq %rcx
+	popq %rcx
+	movq %rax, %rsi
+	movq $-1, %rdx
+	addq $1, %rdx
+	cmpq $1, %rdx
+	sbbq %rdx, %rdx
+	addq $100, %rdx
+	jo overflow_error
+	movq $100, %rax
+	cqto
+	pushq %rcx
+	movq $-1, %rcx
+	idivq %rcx
+	popq %rcx
+	movq %rdx, %rcx
+	cmpq $0, %rdi
+	je divide_by_zero_error
+	movq %rdi, %rdx
+	addq $1, %rdx
+	cmpq $1, %rdx
+	sbbq %rdx, %rdx
+	addq $100, %rdx
+	jo overflow_error
+	movq $100, %rax
+	cqto
+	idivq %rdi
+	movq %rax, %rdi
+	imulq $3, %rdi
+	addq %rcx, %rsi
+	movq %rdi, %rax
+	addq %rsi, %rax
+	jmp conclusion
+
+	.globl main
+main:
+	pushq %rbp
+	movq %rsp, %rbp
+	subq $0, %rsp
+	jmp start
+conclusion:
+	movq %rax, %rdi
+	callq print_int
+	callq print_newline
+	movq $0, %rax
+	addq $0, %rsp
+	popq %rbp
+	retq
+overflow_error:
+	callq integer_overflow
+divide_by_zero_error:
+	callq divide_by_zero
@@ -0,0 +1,127 @@
+start:
+	callq read_int
+	movq %rax, %rbx
+	leaq f12345(%rip), %rcx
+	movq %rbx, %rdi
+	callq *%rcx
+	movq %rax, %rcx
+	movq %rbx, %rdx
+	imulq $1, %rdx
+	movq %rbx, %rsi
+	imulq $2, %rsi
+	movq %rbx, %rdi
+	imulq $3, %rdi
+	movq %rbx, %r8
+	imulq $4, %r8
+	movq %rbx, %r9
+	imulq $5, %r9
+	movq %rbx, %r10
+	imulq $6, %r10
+	movq %rbx, %r12
+	imulq $7, %r12
+	movq %rbx, %r13
+	imulq $8, %r13
+	movq %rbx, %r14
+	imulq $9, %r14
+	movq %rbx, -40(%rbp)
+	movq -40(%rbp), %rax
+	imulq $10, %rax
+	movq %rax, -40(%rbp)
+	movq %rbx, -48(%rbp)
+	movq -48(%rbp), %rax
+	imulq $11, %rax
+	movq %rax, -48(%rbp)
+	imulq $12, %rbx
+	addq %rbx, -48(%rbp)
+	movq -40(%rbp), %rbx
+	addq -48(%rbp), %rbx
+	addq %rbx, %r14
+	addq %r14, %r13
+	addq %r13, %r12
+	addq %r12, %r10
+	addq %r10, %r9
+	addq %r9, %r8
+	addq %r8, %rdi
+	addq %rdi, %rsi
+	addq %rsi, %rdx
+	movq %rcx, %rax
+	addq %rdx, %rax
+	jmp conclusion
+f12345:
+	pushq %rbp
+	movq %rsp, %rbp
+	pushq %rbx
+	pushq %r12
+	pushq %r13
+	pushq %r14
+	subq $16, %rsp
+	jmp f12345_start
+f12345_start:
+	movq %rdi, -40(%rbp)
+	movq -40(%rbp), %rcx
+	addq $1, %rcx
+	movq -40(%rbp), %rdx
+	addq $2, %rdx
+	movq -40(%rbp), %rsi
+	addq $3, %rsi
+	movq -40(%rbp), %rdi
+	addq $4, %rdi
+	movq -40(%rbp), %r8
+	addq $5, %r8
+	movq -40(%rbp), %r9
+	addq $6, %r9
+	movq -40(%rbp), %r10
+	addq $7, %r10
+	movq -40(%rbp), %rbx
+	addq $8, %rbx
+	movq -40(%rbp), %r12
+	addq $9, %r12
+	movq -40(%rbp), %r13
+	addq $10, %r13
+	movq -40(%rbp), %r14
+	addq $11, %r14
+	addq $12, -40(%rbp)
+	addq -40(%rbp), %r14
+	addq %r14, %r13
+	addq %r13, %r12
+	addq %r12, %rbx
+	addq %rbx, %r10
+	addq %r10, %r9
+	addq %r9, %r8
+	addq %r8, %rdi
+	addq %rdi, %rsi
+	addq %rsi, %rdx
+	movq %rcx, %rax
+	addq %rdx, %rax
+	jmp f12345_conclusion
+f12345_conclusion:
+	addq $16, %rsp
+	popq %r14
+	popq %r13
+	popq %r12
+	popq %rbx
+	popq %rbp
+	retq
+
+	.globl main
+main:
+	pushq %rbp
+	movq %rsp, %rbp
+	pushq %rbx
+	pushq %r12
+	pushq %r13
+	pushq %r14
+	subq $16, %rsp
+	jmp start
+conclusion:
+	movq %rax, %rdi
+	callq print_int
+	callq print_newline
+	movq $0, %rax
+	addq $16, %rsp
+	popq %r14
+	popq %r13
+	popq %r12
+	popq %rbx
+	popq %rbp
+	retq
@@ -0,0 +1,112 @@
+start:
+	callq read_int
+	movq %rax, %r12
+	movq $1, %rbx
+	movq $2, %r13
+	movq free_ptr(%rip), %rcx
+	addq $16, %rcx
+	movq fromspace_end(%rip), %rdx
+	cmpq %rdx, %rcx
+	jl block_7
+	jmp block_8
+block_1:
+	movq %rcx, %r11
+	movq 8(%r11), %rdx
+	movq %rcx, %r11
+	movq 24(%r11), %rcx
+	movq %rcx, %r11
+	movq 8(%r11), %rcx
+	movq %rdx, %rax
+	addq %rcx, %rax
+	jmp conclusion
+block_2:
+	movq $0, %rax
+	jmp conclusion
+block_3:
+	movq free_ptr(%rip), %r11
+	addq $32, free_ptr(%rip)
+	movq $519, 0(%r11)
+	movq %r11, %rcx
+	movq %rcx, %r11
+	movq %r12, 8(%r11)
+	movq $0, %rdx
+	movq %rcx, %r11
+	movq %rbx, 16(%r11)
+	movq $0, %rdx
+	movq %rcx, %r11
+	movq -8(%r15), %rax
+	movq %rax, 24(%r11)
+	movq $0, %rdx
+	movq %rcx, %r11
+	movq 24(%r11), %rdx
+	movq %rdx, %r11
+	movq $40, 8(%r11)
+	movq $0, %rdx
+	movq %rcx, %r11
+	movq 16(%r11), %rax
+	cmpq $1, %rax
+	je block_1
+	jmp block_2
+block_4:
+	movq $0, %rcx
+	jmp block_3
+block_5:
+	movq %r15, %rdi
+	movq $32, %rsi
+	callq collect
+	movq $0, %rcx
+	jmp block_3
+block_6:
+	movq free_ptr(%rip), %r11
+	addq $16, free_ptr(%rip)
+	movq $3, 0(%r11)
+	movq %r11, %rcx
+	movq %rcx, %r11
+	movq %r13, 8(%r11)
+	movq $0, %rdx
+	movq %rcx, -8(%r15)
+	movq free_ptr(%rip), %rcx
+	addq $32, %rcx
+	movq fromspace_end(%rip), %rdx
+	cmpq %rdx, %rcx
+	jl block_4
+	jmp block_5
+block_7:
+	movq $0, %rcx
+	jmp block_6
+block_8:
+	movq %r15, %rdi
+	movq $16, %rsi
+	callq collect
+	movq $0, %rcx
+	jmp block_6
+
+	.globl main
+main:
+	pushq %r15
+	pushq %rbp
+	movq %rsp, %rbp
+	pushq %rbx
+	pushq %r12
+	pushq %r13
+	subq $0, %rsp
+	movq $65536, %rdi
+	movq $16384, %rsi
+	callq initialize
+	movq rootstack_begin(%rip), %r15
+	movq $0, 0(%r15)
+	addq $8, %r15
+	jmp start
+conclusion:
+	movq %rax, %rdi
+	callq print_int
+	callq print_newline
+	movq $0, %rax
+	subq $8, %r15
+	addq $0, %rsp
+	popq %r13
+	popq %r12
+	popq %rbx
+	popq %rbp
+	popq %r15
+	retq
@@ -1,11 +1,9 @@
-use eoc::driver::drive;
-use eoc::rir::Expr;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+mod support;
+
+use eoc::rir::{Expr, Program};
 
 #[test]
-fn nested_let_assigns() -> std::io::Result<()> {
+fn nested_let_assigns() {
     let expr = Expr::let_bind(
         "y",
         Expr::let_bind(
@@ -19,13 +17,5 @@ fn nested_let_assigns() -> std::io::Result<()> {
         ),
         Expr::var("y"),
     );
-    let out = drive(*expr);
-
-    let path = Path::new("./tests/target/nestet_let_assigns.s");
-    // Open a file in write-only mode, returns `io::Result<File>`
-    let mut file = File::create(&path)?;
-    file.write_all(out.as_bytes())
+    support::check_runs("nested_let_assigns", &Program::new(expr), &[vec![]]);
 }
-
-// Link compiled code with runtime by running
-// gcc -g ./runtime/runtime.o ./target/nested_let_assigns.s -o ./target/nested_let_assigns.o
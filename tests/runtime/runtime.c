@@ -71,7 +71,7 @@ int is_ptr(int64_t* p) {
     return 0;
   } else {
     int t = any_tag(q);
-    return t == ANY_TAG_PTR || t == ANY_TAG_VEC;
+    return t == ANY_TAG_PTR || t == ANY_TAG_VEC || t == ANY_TAG_FUN;
   }
 }
 
@@ -504,6 +504,30 @@ int64_t read_int() {
   return i;
 }
 
+// Report integer overflow and stop the program. Compiled code jumps
+// here when arithmetic overflows while trapping on overflow.
+void integer_overflow() {
+  fflush(stdout);
+  fprintf(stderr, "error: integer overflow\n");
+  exit(255);
+}
+
+// Report division by zero and stop the program. Compiled code jumps
+// here instead of letting idivq raise SIGFPE.
+void divide_by_zero() {
+  fflush(stdout);
+  fprintf(stderr, "error: division by zero\n");
+  exit(255);
+}
+
+// Report a value whose tag is not the one an operation expects and stop
+// the program. Compiled code jumps here when a projection from Any fails.
+void type_mismatch() {
+  fflush(stdout);
+  fprintf(stderr, "error: type mismatch\n");
+  exit(255);
+}
+
 // print an integer to stdout
 void print_int(int64_t x) {
   printf("%" PRId64, x);
@@ -522,6 +546,14 @@ void print_void() {
   printf("#<void>");
 }
 
+void print_procedure() {
+  printf("#<procedure>");
+}
+
+void print_newline() {
+  printf("\n");
+}
+
 void print_vecbegin() {
   printf("#(");
 }
@@ -557,6 +589,9 @@ void print_any(int64_t any) {
     unsigned char len = get_length(tag);
     printf("#(");
     for (int i = 0; i != len; ++i) {
+      if (i != 0) {
+        printf(" ");
+      }
       print_any(vector_ptr[i + 1]); // this is wrong -Jeremy
     }
     printf(")");
@@ -32,12 +32,26 @@ void collect(int64_t** rootstack_ptr, uint64_t bytes_requested);
 // Read an integer from stdin.
 int64_t read_int();
 
+// Report integer overflow on stderr and exit with status 255.
+void integer_overflow();
+
+// Report division by zero on stderr and exit with status 255.
+void divide_by_zero();
+
+// Report a value of the wrong type on stderr and exit with status 255.
+void type_mismatch();
+
 // Print an integer to stdout.
 void print_int(int64_t x);
 
 // Print a boolean to stdout.
 void print_bool(int64_t x);
 
+// Print void, a function and a newline to stdout.
+void print_void();
+void print_procedure();
+void print_newline();
+
 void print_heap(int64_t** rootstack_ptr);
 void print_vector(int64_t* vector_ptr);
 void print_vecbegin();
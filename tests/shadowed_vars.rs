@@ -1,11 +1,9 @@
-use eoc::driver::drive;
-use eoc::rir::Expr;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+mod support;
+
+use eoc::rir::{Expr, Program};
 
 #[test]
-fn shadowed_vars() -> std::io::Result<()> {
+fn shadowed_vars() {
     let expr = Expr::let_bind(
         "my_var",
         Expr::int(42),
@@ -19,13 +17,6 @@ fn shadowed_vars() -> std::io::Result<()> {
             ),
         ),
     );
-    let out = drive(*expr);
-
-    let path = Path::new("./tests/target/shadowed_vars.s");
-    // Open a file in write-only mode, returns `io::Result<File>`
-    let mut file = File::create(&path)?;
-    file.write_all(out.as_bytes())
+    let input_sets = [vec![0], vec![42], vec![50], vec![-300], vec![i64::MIN]];
+    support::check_runs("shadowed_vars", &Program::new(expr), &input_sets);
 }
-
-// Link compiled code with runtime by running
-// gcc -g ./runtime/runtime.o ./target/shadowed_vars.s -o ./target/shadowed_vars.o
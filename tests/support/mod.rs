@@ -0,0 +1,150 @@
+//! Harness for running compiled programs.
+//!
+//! A program is compiled with `driver`, assembled and linked against
+//! `tests/runtime/runtime.c` with the local `gcc`, and run with scripted
+//! standard input. Its exit status and what it prints, including the
+//! program's value, are compared with what `rir::interp` gives for the same
+//! input.
+
+#![allow(dead_code)]
+
+use eoc::driver::{drive_program_to, Stage};
+use eoc::options::{Options, Overflow, Typing};
+use eoc::rir;
+use eoc::rir::interp::{interp_with_output, InterpError, ScriptedInput};
+use std::fs;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Directory that compiled programs are written to.
+const TARGET_DIR: &str = "./tests/target";
+
+/// Runtime that every program is linked against.
+const RUNTIME: &str = "./tests/runtime/runtime.c";
+
+/// Exit status of a program stopped by a runtime error such as
+/// `integer_overflow`, `divide_by_zero` or `type_mismatch`.
+const ERROR_STATUS: i32 = 255;
+
+/// What a run of a program did.
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub status: i32,
+    pub stdout: String,
+}
+
+/// A program compiled to an executable.
+pub struct Native {
+    exe: PathBuf,
+}
+
+impl Native {
+    /// Compiles the program and links it into `tests/target/NAME`.
+    pub fn build(name: &str, prog: &rir::Program, options: &Options) -> Native {
+        let asm = drive_program_to(prog.clone(), Stage::Asm, options)
+            .unwrap_or_else(|e| panic!("{}: {}", name, e));
+        fs::create_dir_all(TARGET_DIR).unwrap();
+        let exe = PathBuf::from(TARGET_DIR).join(name);
+        let asm_path = exe.with_extension("s");
+        fs::write(&asm_path, asm).unwrap();
+        let output = Command::new("gcc")
+            .arg("-Wl,-z,noexecstack")
+            .arg("-o")
+            .arg(&exe)
+            .arg(&asm_path)
+            .arg(RUNTIME)
+            .output()
+            .unwrap_or_else(|e| panic!("cannot run gcc: {}", e));
+        assert!(
+            output.status.success(),
+            "{}: gcc failed\n{}",
+            name,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        Native { exe }
+    }
+
+    /// Runs the executable with one input per line on standard input.
+    pub fn run(&self, inputs: &[i64]) -> Outcome {
+        let mut child = Command::new(&self.exe)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|e| panic!("cannot run {}: {}", self.exe.display(), e));
+        let stdin = inputs
+            .iter()
+            .map(|i| format!("{}\n", i))
+            .collect::<String>();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        Outcome {
+            status: output
+                .status
+                .code()
+                .expect("program was killed by a signal"),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        }
+    }
+}
+
+/// Evaluates the program with the interpreter and gives the outcome the
+/// compiled program is expected to have.
+pub fn interp(prog: &rir::Program, options: &Options, inputs: &[i64]) -> Outcome {
+    let mut input = ScriptedInput::ints(inputs);
+    let mut stdout = String::new();
+    match interp_with_output(prog, &mut input, &mut stdout, options) {
+        // `main` prints the value after what the program printed and exits
+        // with status 0.
+        Ok(val) => Outcome {
+            status: 0,
+            stdout: format!("{}{}\n", stdout, val),
+        },
+        Err(InterpError::Overflow(_))
+        | Err(InterpError::DivideByZero(_))
+        | Err(InterpError::TypeMismatch(_)) => Outcome {
+            status: ERROR_STATUS,
+            stdout,
+        },
+        Err(e) => panic!("interpreter failed: {}", e),
+    }
+}
+
+/// Checks that, for each list of inputs, the compiled program behaves the
+/// same as the interpreter. The program is compiled once per overflow mode,
+/// both statically and dynamically typed.
+pub fn check_runs(name: &str, prog: &rir::Program, input_sets: &[Vec<i64>]) {
+    check_runs_as(name, prog, input_sets, &[Typing::Static, Typing::Dynamic]);
+}
+
+/// Checks the program like `check_runs`, compiled with only the given
+/// typings.
+pub fn check_runs_as(name: &str, prog: &rir::Program, input_sets: &[Vec<i64>], typings: &[Typing]) {
+    for typing in typings {
+        for overflow in [Overflow::Wrapping, Overflow::Trapping].iter() {
+            let options = Options {
+                overflow: *overflow,
+                typing: *typing,
+            };
+            let mut exe_name = match overflow {
+                Overflow::Wrapping => format!("{}-wrap", name),
+                Overflow::Trapping => format!("{}-trap", name),
+            };
+            if *typing == Typing::Dynamic {
+                exe_name.push_str("-dyn");
+            }
+            let native = Native::build(&exe_name, prog, &options);
+            for inputs in input_sets {
+                let expected = interp(prog, &options, inputs);
+                let actual = native.run(inputs);
+                assert_eq!(actual, expected, "{} with input {:?}", exe_name, inputs);
+            }
+        }
+    }
+}
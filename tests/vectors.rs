@@ -0,0 +1,57 @@
+mod support;
+
+use eoc::rir::parse::parse_program;
+
+/// Builds a program that allocates a chain of `steps` vectors, each holding a
+/// running count and a vector created at the start, with a short-lived vector
+/// of 40 elements allocated at each step. The heap fills up several times
+/// over, so the collector has to copy the live vectors and update the root
+/// stack.
+fn allocation_chain(steps: usize) -> String {
+    let mut src = String::from("(let ([keep (vector (read))])\n(let ([acc (vector 0 keep)])\n");
+    for i in 0..steps {
+        let garbage = vec![i.to_string(); 40].join(" ");
+        src.push_str(&format!(
+            "(let ([acc (vector (+ (vector-ref acc 0) (vector-ref (vector {}) 0)) \
+             (vector-ref acc 1))])\n",
+            garbage
+        ));
+    }
+    src.push_str("(+ (vector-ref acc 0) (vector-ref (vector-ref acc 1) 0))");
+    src.push_str(&")".repeat(steps + 2));
+    src
+}
+
+#[test]
+fn collects_garbage() {
+    let prog = parse_program(&allocation_chain(100)).unwrap();
+    support::check_runs("collects_garbage", &prog, &[vec![3], vec![-4950]]);
+}
+
+#[test]
+fn returns_void() {
+    let prog = parse_program("(vector-set! (vector (read)) 0 1)").unwrap();
+    support::check_runs("returns_void", &prog, &[vec![5]]);
+}
+
+/// Vectors held by the frames of a recursion must survive the collections
+/// triggered by the calls below them. Each call allocates a short-lived vector
+/// of 50 elements that holds two more, so fifteen calls fill the heap.
+#[test]
+fn collects_garbage_across_calls() {
+    let elems = vec!["n"; 48].join(" ");
+    let junk = format!("{0} (vector {0}) (vector {0})", elems);
+    let src = format!(
+        "\
+(define (build [n : Integer] [acc : (Vector Integer)]) : (Vector Integer)
+  (if (eq? n 0)
+      acc
+      (let ([junk (vector {})])
+        (let ([r (build (- n 1) (vector (+ (vector-ref acc 0) (vector-ref junk 0))))])
+          (vector (+ (vector-ref r 0) (vector-ref acc 0)))))))
+(vector-ref (build (read) (vector 0)) 0)",
+        junk
+    );
+    let prog = parse_program(&src).unwrap();
+    support::check_runs("collects_garbage_across_calls", &prog, &[vec![3], vec![20]]);
+}